@@ -0,0 +1,209 @@
+//! Benchmarks of the server pipeline: framing, request conversion, and round
+//! trips through a server over an in-memory transport.
+//!
+//! Run with `cargo bench --features testing`, optionally with a filter on the
+//! benchmark names, e.g. `cargo bench --features testing -- round_trip`.
+//! Criterion compares each run with the last one saved in `target/criterion`,
+//! and reports the changes.
+
+use std::{hint::black_box, time::Instant};
+
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tendermint::{
+    abci::{request, response, types::ExecTxResult},
+    v0_38::abci::{Request, Response},
+};
+use tendermint_proto::v0_38::abci as pb;
+use tokio_util::codec::{Decoder, Encoder};
+use tower_abci::{
+    apps::EchoApp,
+    testing::genesis,
+    v038::{
+        codec::{Decode, Encode},
+        testing, Server,
+    },
+};
+
+/// The size of the transactions of the benchmarks, typical of a transfer.
+const TX_SIZE: usize = 250;
+/// The number of transactions of a block.
+const BLOCK_TXS: usize = 100;
+
+fn codec(c: &mut Criterion) {
+    let check_tx = pb::Request::from(check_tx_request());
+    let check_tx_frame = frame(check_tx.clone());
+    let finalize_block_frame = frame(pb::Request::from(finalize_block_request()));
+    let finalize_block_response = pb::Response::from(finalize_block_response());
+
+    let mut group = c.benchmark_group("encode");
+    group.bench_function("check_tx", |b| encode(b, &check_tx));
+    group.bench_function("finalize_block", |b| encode(b, &finalize_block_response));
+    group.finish();
+
+    let mut group = c.benchmark_group("decode");
+    group.bench_function("check_tx", |b| decode::<pb::Request>(b, &check_tx_frame));
+    group.bench_function("finalize_block", |b| {
+        decode::<pb::Request>(b, &finalize_block_frame)
+    });
+    group.finish();
+}
+
+fn convert(c: &mut Criterion) {
+    let check_tx = check_tx_request();
+    let finalize_block_response = finalize_block_response();
+
+    let mut group = c.benchmark_group("convert");
+    group.bench_function("check_tx", |b| {
+        b.iter(|| {
+            let request = pb::Request::from(black_box(check_tx.clone()));
+            Request::try_from(request).unwrap()
+        })
+    });
+    group.bench_function("finalize_block", |b| {
+        b.iter(|| {
+            let response = pb::Response::from(black_box(finalize_block_response.clone()));
+            Response::try_from(response).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn round_trip(c: &mut Criterion) {
+    let check_tx = check_tx_request();
+    let finalize_block = finalize_block_request();
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let server = Server::builder()
+        .consensus(EchoApp)
+        .mempool(EchoApp)
+        .info(EchoApp)
+        .snapshot(EchoApp)
+        .finish()
+        .unwrap();
+    let mut mempool = rt.block_on(async { testing::connect(&server) });
+    let mut consensus = rt.block_on(async {
+        let mut consensus = testing::connect(&server);
+        consensus
+            .call(Request::InitChain(genesis("bench")))
+            .await
+            .unwrap();
+        consensus
+    });
+
+    // The driver is async, so each sample runs its iterations on the runtime
+    // and times them there.
+    let mut group = c.benchmark_group("round_trip");
+    group.bench_function("check_tx", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    black_box(mempool.call(check_tx.clone()).await.unwrap());
+                }
+                start.elapsed()
+            })
+        })
+    });
+    group.throughput(Throughput::Elements(BLOCK_TXS as u64));
+    group.bench_function("check_tx_batch", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    for _ in 0..BLOCK_TXS {
+                        mempool.send(check_tx.clone()).await.unwrap();
+                    }
+                    black_box(mempool.flush().await.unwrap());
+                }
+                start.elapsed()
+            })
+        })
+    });
+    group.bench_function("finalize_block", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    black_box(consensus.call(finalize_block.clone()).await.unwrap());
+                    black_box(consensus.call(Request::Commit).await.unwrap());
+                }
+                start.elapsed()
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, codec, convert, round_trip);
+criterion_main!(benches);
+
+fn encode<M: prost::Message + std::fmt::Debug + Clone>(b: &mut criterion::Bencher, message: &M) {
+    let mut encoder = Encode::default();
+    let mut dst = BytesMut::new();
+    b.iter(|| {
+        encoder
+            .encode(black_box(message.clone()), &mut dst)
+            .unwrap();
+        dst.clear();
+    });
+}
+
+fn decode<M: prost::Message + Default>(b: &mut criterion::Bencher, frame: &[u8]) {
+    let mut decoder = Decode::<M>::default();
+    b.iter_batched(
+        || BytesMut::from(frame),
+        |mut src| decoder.decode(&mut src).unwrap().unwrap(),
+        BatchSize::SmallInput,
+    );
+}
+
+fn frame<M: prost::Message + std::fmt::Debug>(message: M) -> Bytes {
+    let mut dst = BytesMut::new();
+    Encode::default().encode(message, &mut dst).unwrap();
+    dst.freeze()
+}
+
+fn tx(i: usize) -> Bytes {
+    let mut tx = vec![0; TX_SIZE];
+    tx[..8].copy_from_slice(&(i as u64).to_be_bytes());
+    tx.into()
+}
+
+fn check_tx_request() -> Request {
+    Request::CheckTx(request::CheckTx {
+        tx: tx(0),
+        kind: request::CheckTxKind::New,
+    })
+}
+
+fn finalize_block_request() -> Request {
+    Request::FinalizeBlock(request::FinalizeBlock {
+        txs: (0..BLOCK_TXS).map(tx).collect(),
+        decided_last_commit: tendermint::abci::types::CommitInfo {
+            round: 0u8.into(),
+            votes: vec![],
+        },
+        misbehavior: vec![],
+        hash: tendermint::Hash::Sha256([1; 32]),
+        height: 1u32.into(),
+        time: genesis("bench").time,
+        next_validators_hash: tendermint::Hash::Sha256([2; 32]),
+        proposer_address: tendermint::account::Id::new([3; 20]),
+    })
+}
+
+fn finalize_block_response() -> Response {
+    Response::FinalizeBlock(response::FinalizeBlock {
+        events: vec![],
+        tx_results: (0..BLOCK_TXS)
+            .map(|i| ExecTxResult {
+                data: tx(i),
+                ..Default::default()
+            })
+            .collect(),
+        validator_updates: vec![],
+        consensus_param_updates: None,
+        app_hash: vec![4; 32].try_into().unwrap(),
+    })
+}
@@ -0,0 +1,65 @@
+//! A dry-run ABCI server, checking the requests of a node against the
+//! specification, and answering them with minimal valid responses.
+//!
+//! Point a node, proxy or ABCI client at it, stop it with Ctrl-C, and it
+//! lists the violations it found. See [`tower_abci::lint`].
+
+use structopt::StructOpt;
+
+use tower_abci::{lint::Linter, v034, v037, v038, BoxError};
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Bind the TCP server to this host.
+    #[structopt(short, long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// Bind the TCP server to this port.
+    #[structopt(short, long, default_value = "26658")]
+    port: u16,
+
+    /// Bind the UDS server to this path
+    #[structopt(long)]
+    uds: Option<String>,
+
+    /// The ABCI version to check against: 0.34, 0.37 or 0.38.
+    #[structopt(long, default_value = "0.38")]
+    abci: String,
+}
+
+async fn listen(opt: Opt, linter: Linter) -> Result<(), BoxError> {
+    macro_rules! listen {
+        ($version:ident) => {{
+            let server = $version::LintServer::new(linter);
+            match opt.uds {
+                Some(path) => server.listen_unix(path).await,
+                None => server.listen_tcp((opt.host.as_str(), opt.port)).await,
+            }
+        }};
+    }
+    match opt.abci.as_str() {
+        "0.34" => listen!(v034),
+        "0.37" => listen!(v037),
+        "0.38" => listen!(v038),
+        version => Err(format!("unsupported ABCI version {version}").into()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    tracing_subscriber::fmt::init();
+    let opt = Opt::from_args();
+
+    let linter = Linter::new();
+    tokio::select! {
+        result = listen(opt, linter.clone()) => result?,
+        result = tokio::signal::ctrl_c() => result?,
+    }
+
+    let violations = linter.violations();
+    println!("{} violations", violations.len());
+    for violation in violations {
+        println!("{violation}");
+    }
+    Ok(())
+}
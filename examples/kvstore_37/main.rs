@@ -1,141 +1,16 @@
 //! Example ABCI application, an in-memory key-value store.
+//!
+//! The application itself is [`tower_abci::apps::kvstore`], so run with
+//! `--features kvstore`.
 
-use std::{
-    collections::HashMap,
-    future::Future,
-    pin::Pin,
-    task::{Context, Poll},
-};
-
-use bytes::Bytes;
-use futures::future::FutureExt;
 use structopt::StructOpt;
-use tower::{Service, ServiceBuilder};
-
-use tendermint::abci::{
-    response::{self, PrepareProposal},
-    Event, EventAttributeIndexExt,
-};
+use tower::ServiceBuilder;
 
 use tower_abci::{
+    apps::kvstore::KVStore,
     v037::{split, Server},
-    BoxError,
 };
 
-use tendermint::v0_37::abci::{Request, Response};
-
-/// In-memory, hashmap-backed key-value store ABCI application.
-#[derive(Clone, Debug, Default)]
-pub struct KVStore {
-    store: HashMap<String, String>,
-    height: u32,
-    app_hash: [u8; 8],
-}
-
-impl Service<Request> for KVStore {
-    type Response = Response;
-    type Error = BoxError;
-    type Future = Pin<Box<dyn Future<Output = Result<Response, BoxError>> + Send + 'static>>;
-
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
-    }
-
-    fn call(&mut self, req: Request) -> Self::Future {
-        tracing::info!(?req);
-
-        let rsp = match req {
-            // handled messages
-            Request::Info(_) => Response::Info(self.info()),
-            Request::Query(query) => Response::Query(self.query(query.data)),
-            Request::DeliverTx(deliver_tx) => Response::DeliverTx(self.deliver_tx(deliver_tx.tx)),
-            Request::Commit => Response::Commit(self.commit()),
-            // unhandled messages
-            Request::Flush => Response::Flush,
-            Request::Echo(_) => Response::Echo(Default::default()),
-            Request::InitChain(_) => Response::InitChain(Default::default()),
-            Request::BeginBlock(_) => Response::BeginBlock(Default::default()),
-            Request::CheckTx(_) => Response::CheckTx(Default::default()),
-            Request::EndBlock(_) => Response::EndBlock(Default::default()),
-            Request::ListSnapshots => Response::ListSnapshots(Default::default()),
-            Request::OfferSnapshot(_) => Response::OfferSnapshot(Default::default()),
-            Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
-            Request::ApplySnapshotChunk(_) => Response::ApplySnapshotChunk(Default::default()),
-
-            // Note: https://github.com/tendermint/tendermint/blob/v0.37.x/spec/abci/abci%2B%2B_tmint_expected_behavior.md#adapting-existing-applications-that-use-abci
-            Request::PrepareProposal(prepare_prop) => Response::PrepareProposal(PrepareProposal {
-                txs: prepare_prop.txs,
-            }),
-            Request::ProcessProposal(..) => {
-                Response::ProcessProposal(response::ProcessProposal::Accept)
-            }
-        };
-        tracing::info!(?rsp);
-        async move { Ok(rsp) }.boxed()
-    }
-}
-
-impl KVStore {
-    fn info(&self) -> response::Info {
-        response::Info {
-            data: "tower-abci-kvstore-example".to_string(),
-            version: "0.1.0".to_string(),
-            app_version: 1,
-            last_block_height: self.height.into(),
-            last_block_app_hash: self.app_hash.to_vec().try_into().unwrap(),
-        }
-    }
-
-    fn query(&self, query: Bytes) -> response::Query {
-        let key = String::from_utf8(query.to_vec()).unwrap();
-        let (value, log) = match self.store.get(&key) {
-            Some(value) => (value.clone(), "exists".to_string()),
-            None => ("".to_string(), "does not exist".to_string()),
-        };
-
-        response::Query {
-            log,
-            key: key.into_bytes().into(),
-            value: value.into_bytes().into(),
-            ..Default::default()
-        }
-    }
-
-    fn deliver_tx(&mut self, tx: Bytes) -> response::DeliverTx {
-        let tx = String::from_utf8(tx.to_vec()).unwrap();
-        let tx_parts = tx.split('=').collect::<Vec<_>>();
-        let (key, value) = match (tx_parts.first(), tx_parts.get(1)) {
-            (Some(key), Some(value)) => (*key, *value),
-            _ => (tx.as_ref(), tx.as_ref()),
-        };
-        self.store.insert(key.to_string(), value.to_string());
-
-        response::DeliverTx {
-            events: vec![Event::new(
-                "app",
-                vec![
-                    ("key", key).index(),
-                    ("index_key", "index is working").index(),
-                    ("noindex_key", "index is working").no_index(),
-                ],
-            )],
-            ..Default::default()
-        }
-    }
-
-    fn commit(&mut self) -> response::Commit {
-        let retain_height = self.height.into();
-        // As in the other kvstore examples, just use store.len() as the "hash"
-        self.app_hash = (self.store.len() as u64).to_be_bytes();
-        self.height += 1;
-
-        response::Commit {
-            data: self.app_hash.to_vec().into(),
-            retain_height,
-        }
-    }
-}
-
 #[derive(Debug, StructOpt)]
 struct Opt {
     /// Bind the TCP server to this host.
@@ -157,7 +32,7 @@ async fn main() {
     let opt = Opt::from_args();
 
     // Construct our ABCI application.
-    let service = KVStore::default();
+    let service = KVStore::new();
 
     // Split it into components.
     let (consensus, mempool, snapshot, info) = split::service(service, 1);
@@ -0,0 +1,435 @@
+//! A local control socket for operators.
+//!
+//! [`listen_unix`] serves a line-based text protocol over a Unix socket, so
+//! that operators can inspect and manage the ABCI server with, e.g.,
+//! `socat - UNIX-CONNECT:/path/to/admin.sock`, without restarting the
+//! application. Each line is a command:
+//!
+//! - `status` reports the server's version, ABCI protocol version, uptime and
+//!   open connections, with the number of pending requests of each kind and
+//!   the oldest pending request of each connection;
+//! - `close <id>` asks the connection with the given id to close, after it has
+//!   sent its pending responses;
+//! - `drain` stops the server from accepting connections, and asks every open
+//!   connection to close;
+//! - `health` sends synthetic requests through each component service, and
+//!   reports their status and latency, as with
+//!   [`ServerHandle::health_check`];
+//! - `options` prints the current
+//!   [`ConnectionOptions`](crate::ConnectionOptions);
+//! - `set <setting> <value>` changes a connection option on every connection
+//!   without restarting the server, as with
+//!   [`ServerHandle::update_options`]. The settings are:
+//!   - `max_in_flight <n|none>`;
+//!   - `max_request_len <bytes|none>`;
+//!   - `pipeline_depth <consensus|mempool|snapshot|info> <n|none>`;
+//!   - `slow_request_threshold <seconds>`, e.g. `slow_request_threshold 0.5`,
+//!     for the threshold attached with
+//!     [`ServerHandle::manage_slow_request_threshold`];
+//!   - `summary_log <true|false>`;
+//!   - `log_level <method> <level|default>`, e.g. `log_level CheckTx trace`;
+//! - `filter` lists the rules of the transaction filter attached with
+//!   [`ServerHandle::set_tx_filter`], one per line;
+//! - `filter <change>` changes them for every service the
+//!   [transaction filter](crate::middleware::filter) applies to. The changes
+//!   are:
+//!   - `block pattern <hex>` and `unblock pattern <hex>`;
+//!   - `block sender <sender>` and `unblock sender <sender>`;
+//!   - `allow sender <sender>` and `disallow sender <sender>`, for the senders
+//!     accepted when any is allowed;
+//!   - `clear`, removing every rule.
+//!
+//! Each reply ends with a line that is either `ok`, or `error` followed by a
+//! message. A line longer than 64 KiB is answered with an error, and closes
+//! the connection. The lines of a `status` reply are `key=value` pairs:
+//!
+//! ```text
+//! server version=0.14.0 protocol=0.38 uptime_secs=12.003 draining=false connections=1
+//! connection id=0 kind=consensus age_secs=11.950 closing=false in_flight=1 consensus=1 mempool=0 snapshot=0 info=0 oldest=Commit oldest_height=10 oldest_pending_secs=3.042
+//! ok
+//! ```
+//!
+//! The socket grants control over the server to anyone who can connect to it,
+//! so it should be created somewhere only the operator can access.
+
+use std::{fmt::Write, time::Duration};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::Instrument;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{middleware::filter, task, BoxError, Category, ServerHandle};
+
+/// The longest command line read, so that a client can't make the server
+/// buffer without bound.
+const MAX_LINE_LEN: u64 = 64 * 1024;
+
+/// Serves the admin protocol for `handle` on a Unix socket at `path`.
+pub async fn listen_unix(
+    handle: ServerHandle,
+    path: impl AsRef<std::path::Path>,
+) -> Result<(), BoxError> {
+    let listener = tokio::net::UnixListener::bind(path)?;
+    let addr = listener.local_addr()?;
+    tracing::info!(?addr, "ABCI admin socket starting on uds");
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, _addr)) => {
+                let handle = handle.clone();
+                let span = tracing::info_span!("abci_admin_connection");
+                task::spawn(
+                    "abci-admin-connection",
+                    async move {
+                        if let Err(e) = serve(handle, socket).await {
+                            tracing::warn!(error = %e, "admin connection failed");
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                tracing::error!({ %e }, "error accepting new admin connection");
+            }
+        }
+    }
+}
+
+/// Answers commands read from `socket` until it is closed.
+async fn serve(
+    handle: ServerHandle,
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+) -> Result<(), BoxError> {
+    let (read, mut write) = tokio::io::split(socket);
+    let mut read = BufReader::new(read);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let len = (&mut read)
+            .take(MAX_LINE_LEN + 1)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if len == 0 {
+            return Ok(());
+        }
+        if !line.ends_with(b"\n") && len as u64 > MAX_LINE_LEN {
+            write.write_all(b"error command too long\n").await?;
+            return Err(format!("admin command longer than {MAX_LINE_LEN} bytes").into());
+        }
+        let line = std::str::from_utf8(&line)?;
+        let reply = match line.trim() {
+            "health" => health(&handle).await,
+            _ => execute(&handle, line),
+        };
+        write.write_all(reply.as_bytes()).await?;
+    }
+}
+
+/// Runs a `command`, returning the reply.
+fn execute(handle: &ServerHandle, command: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        [] => String::new(),
+        ["status"] => status(handle) + "ok\n",
+        ["close", id] => match id.parse() {
+            Ok(id) if handle.close_connection(id) => {
+                tracing::info!(id, "closing connection on admin request");
+                "ok\n".to_string()
+            }
+            Ok(id) => format!("error no connection {}\n", id),
+            Err(_) => format!("error invalid connection id {:?}\n", id),
+        },
+        ["drain"] => {
+            handle.drain();
+            "ok\n".to_string()
+        }
+        ["options"] => format!("{:?}\nok\n", handle.options()),
+        ["set", setting @ ..] => match set(handle, setting) {
+            Ok(()) => "ok\n".to_string(),
+            Err(e) => format!("error {}\n", e),
+        },
+        ["filter", change @ ..] => match change_filter(handle, change) {
+            Ok(reply) => reply + "ok\n",
+            Err(e) => format!("error {}\n", e),
+        },
+        _ => format!("error unknown command {:?}\n", command.trim()),
+    }
+}
+
+/// Runs the health checks, with a final `error` line if any of them failed.
+async fn health(handle: &ServerHandle) -> String {
+    let report = handle.health_check().await;
+    if report.is_healthy() {
+        format!("{report}ok\n")
+    } else {
+        format!("{report}error unhealthy\n")
+    }
+}
+
+/// Changes a connection option, given as the words following `set`.
+fn set(handle: &ServerHandle, setting: &[&str]) -> Result<(), String> {
+    match setting {
+        ["max_in_flight", limit] => {
+            let limit = parse_limit(limit)?;
+            handle.update_options(|options| options.max_in_flight = limit);
+        }
+        ["max_request_len", limit] => {
+            let limit = parse_limit(limit)?;
+            handle.update_options(|options| options.buffer_sizes.max_request_len = limit);
+        }
+        ["slow_request_threshold", seconds] => {
+            let threshold = seconds
+                .parse()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .ok_or_else(|| format!("invalid duration {:?}", seconds))?;
+            if !handle.set_slow_request_threshold(threshold) {
+                return Err("no slow request threshold".to_string());
+            }
+        }
+        ["pipeline_depth", category, limit] => {
+            let category = parse_category(category)?;
+            let limit = parse_limit(limit)?;
+            handle.update_options(|options| {
+                let depth = &mut options.pipeline_depth;
+                match category {
+                    Category::Consensus => depth.consensus = limit,
+                    Category::Mempool => depth.mempool = limit,
+                    Category::Snapshot => depth.snapshot = limit,
+                    Category::Info => depth.info = limit,
+                }
+            });
+        }
+        ["summary_log", enabled] => {
+            let enabled = enabled
+                .parse()
+                .map_err(|_| format!("invalid boolean {:?}", enabled))?;
+            handle.update_options(|options| options.summary_log = enabled);
+        }
+        ["log_level", method, "default"] => {
+            handle.update_options(|options| {
+                options.log_levels.remove(*method);
+            });
+        }
+        ["log_level", method, level] => {
+            let level: tracing::Level = level
+                .parse()
+                .map_err(|_| format!("invalid level {:?}", level))?;
+            handle.update_options(|options| {
+                options.log_levels.insert(method.to_string(), level);
+            });
+        }
+        _ => return Err(format!("unknown setting {:?}", setting.join(" "))),
+    }
+    Ok(())
+}
+
+/// Parses a limit, where `none` means unlimited.
+fn parse_limit(limit: &str) -> Result<Option<usize>, String> {
+    match limit {
+        "none" => Ok(None),
+        _ => limit
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid limit {:?}", limit)),
+    }
+}
+
+fn parse_category(category: &str) -> Result<Category, String> {
+    match category {
+        "consensus" => Ok(Category::Consensus),
+        "mempool" => Ok(Category::Mempool),
+        "snapshot" => Ok(Category::Snapshot),
+        "info" => Ok(Category::Info),
+        _ => Err(format!("unknown service {:?}", category)),
+    }
+}
+
+/// Lists the rules of the transaction filter, or changes them, given as the
+/// words following `filter`, returning the reply without its final `ok` line.
+fn change_filter(handle: &ServerHandle, change: &[&str]) -> Result<String, String> {
+    let filter = handle
+        .tx_filter()
+        .ok_or_else(|| "no transaction filter".to_string())?;
+    let pattern =
+        |hex: &str| filter::parse_pattern(hex).map_err(|_| format!("invalid hex pattern {hex:?}"));
+    match change {
+        [] => {
+            let rules = filter.rules();
+            let mut out = String::new();
+            for pattern in &rules.blocked_patterns {
+                writeln!(out, "blocked_pattern {}", hex::encode(pattern)).unwrap();
+            }
+            for sender in &rules.blocked_senders {
+                writeln!(out, "blocked_sender {sender}").unwrap();
+            }
+            for sender in &rules.allowed_senders {
+                writeln!(out, "allowed_sender {sender}").unwrap();
+            }
+            for name in filter.predicates() {
+                writeln!(out, "predicate {name}").unwrap();
+            }
+            return Ok(out);
+        }
+        ["block", "pattern", hex] => {
+            let pattern = pattern(hex)?;
+            filter.update_rules(|rules| {
+                if !rules.blocked_patterns.contains(&pattern) {
+                    rules.blocked_patterns.push(pattern);
+                }
+            });
+        }
+        ["unblock", "pattern", hex] => {
+            let pattern = pattern(hex)?;
+            filter.update_rules(|rules| rules.blocked_patterns.retain(|p| *p != pattern));
+        }
+        ["block", "sender", sender] => filter.update_rules(|rules| {
+            rules.blocked_senders.insert(sender.to_string());
+        }),
+        ["unblock", "sender", sender] => filter.update_rules(|rules| {
+            rules.blocked_senders.remove(*sender);
+        }),
+        ["allow", "sender", sender] => filter.update_rules(|rules| {
+            rules.allowed_senders.insert(sender.to_string());
+        }),
+        ["disallow", "sender", sender] => filter.update_rules(|rules| {
+            rules.allowed_senders.remove(*sender);
+        }),
+        ["clear"] => filter.set_rules(Default::default()),
+        _ => return Err(format!("unknown filter change {:?}", change.join(" "))),
+    }
+    Ok(String::new())
+}
+
+/// Formats the `status` reply, without its final `ok` line.
+fn status(handle: &ServerHandle) -> String {
+    let connections = handle.connections();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "server version={} protocol={} uptime_secs={:.3} draining={} connections={}",
+        env!("CARGO_PKG_VERSION"),
+        handle.protocol_version(),
+        handle.uptime().as_secs_f64(),
+        handle.is_draining(),
+        connections.len(),
+    )
+    .unwrap();
+    for conn in connections {
+        write!(
+            out,
+            "connection id={} kind={} age_secs={:.3} closing={} in_flight={} consensus={} mempool={} snapshot={} info={}",
+            conn.id,
+            conn.kind.map_or("unknown", Category::name),
+            conn.age.as_secs_f64(),
+            conn.closing,
+            conn.in_flight.len(),
+            conn.count(Category::Consensus),
+            conn.count(Category::Mempool),
+            conn.count(Category::Snapshot),
+            conn.count(Category::Info),
+        )
+        .unwrap();
+        if let Some(oldest) = conn.oldest() {
+            write!(out, " oldest={}", oldest.method).unwrap();
+            if let Some(height) = oldest.height {
+                write!(out, " oldest_height={}", height).unwrap();
+            }
+            write!(
+                out,
+                " oldest_pending_secs={:.3}",
+                oldest.pending.as_secs_f64()
+            )
+            .unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::{apps::NoopApp, middleware::slow::SlowRequestLayer, v038::Server};
+
+    fn handle() -> ServerHandle {
+        Server::builder()
+            .consensus(NoopApp)
+            .mempool(NoopApp)
+            .info(NoopApp)
+            .snapshot(NoopApp)
+            .finish()
+            .unwrap()
+            .handle()
+    }
+
+    /// Serves the admin protocol for `handle` on an in-memory connection.
+    fn connect(
+        handle: &ServerHandle,
+    ) -> (DuplexStream, tokio::task::JoinHandle<Result<(), BoxError>>) {
+        let (client, server) = tokio::io::duplex(1024);
+        (client, tokio::spawn(serve(handle.clone(), server)))
+    }
+
+    #[test]
+    fn sets_the_max_request_len() {
+        let handle = handle();
+        assert_eq!(execute(&handle, "set max_request_len 1024"), "ok\n");
+        assert_eq!(handle.options().buffer_sizes.max_request_len, Some(1024));
+        assert_eq!(execute(&handle, "set max_request_len none"), "ok\n");
+        assert_eq!(handle.options().buffer_sizes.max_request_len, None);
+    }
+
+    #[test]
+    fn sets_the_slow_request_threshold() {
+        let handle = handle();
+        assert_eq!(
+            execute(&handle, "set slow_request_threshold 0.5"),
+            "error no slow request threshold\n"
+        );
+
+        let (_layer, threshold) = SlowRequestLayer::adjustable(Duration::from_secs(1));
+        let mut changes = threshold.subscribe();
+        handle.manage_slow_request_threshold(threshold);
+        assert_eq!(execute(&handle, "set slow_request_threshold 0.5"), "ok\n");
+        assert_eq!(*changes.borrow_and_update(), Duration::from_millis(500));
+        assert_eq!(
+            handle.slow_request_threshold(),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            execute(&handle, "set slow_request_threshold -1"),
+            "error invalid duration \"-1\"\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_commands_line_by_line() {
+        let handle = handle();
+        let (mut client, _server) = connect(&handle);
+        client
+            .write_all(b"set max_in_flight 3\ndrain\n")
+            .await
+            .unwrap();
+        let mut reply = [0; 6];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ok\nok\n");
+        assert_eq!(handle.options().max_in_flight, Some(3));
+    }
+
+    #[tokio::test]
+    async fn closes_the_connection_on_a_line_too_long() {
+        let handle = handle();
+        let (mut client, server) = connect(&handle);
+        let line = vec![b'x'; MAX_LINE_LEN as usize + 1];
+        // The server stops reading partway through, so the write may fail.
+        let _ = client.write_all(&line).await;
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).await.unwrap();
+        assert_eq!(reply, "error command too long\n");
+        assert!(server.await.unwrap().is_err());
+    }
+}
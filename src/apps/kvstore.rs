@@ -0,0 +1,411 @@
+//! An in-memory key-value store.
+//!
+//! [`KVStore`] is the application of the examples, and a known-good reference
+//! for tests and benchmarks. It implements the ABCI of every supported
+//! protocol version, so the same value can be split and served by a
+//! [`v034::Server`](crate::v034::Server), a
+//! [`v037::Server`](crate::v037::Server) or a
+//! [`v038::Server`](crate::v038::Server):
+//!
+//! ```
+//! # use tower_abci::{apps::kvstore::KVStore, v038};
+//! # #[cfg(feature = "split")]
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let (consensus, mempool, snapshot, info) = v038::split::service(KVStore::new(), 1);
+//! # }
+//! # #[cfg(not(feature = "split"))]
+//! # fn main() {}
+//! ```
+//!
+//! Transactions are either `key=value`, setting `key` to `value`, or a bare
+//! `key`, setting `key` to itself; empty transactions are invalid. Queries
+//! look up the key in their data in the state as of the last commit. The app
+//! hash is the SHA-256 hash of the length-prefixed keys and values, in order
+//! of the keys. The store does not take snapshots.
+
+use std::{
+    collections::BTreeMap,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::future::{ready, Ready};
+use sha2::{Digest, Sha256};
+use tendermint::{
+    abci::{request, response, types::ExecTxResult, Code, Event, EventAttributeIndexExt},
+    block, AppHash,
+};
+use tower::Service;
+
+use crate::{reply::CodeResponse, BoxError};
+
+/// The code of the `CheckTx` and `DeliverTx` responses to invalid
+/// transactions.
+pub const CODE_INVALID_TX: u32 = 1;
+
+/// An in-memory, `BTreeMap`-backed key-value store application.
+#[derive(Clone, Debug)]
+pub struct KVStore {
+    /// The state as of the last commit.
+    committed: BTreeMap<Bytes, Bytes>,
+    /// The state including the transactions of the block being executed.
+    working: BTreeMap<Bytes, Bytes>,
+    /// The height of the last committed block.
+    height: block::Height,
+    /// The height of the block being executed.
+    executing: block::Height,
+    app_hash: AppHash,
+}
+
+impl Default for KVStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KVStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        // The default height is 1, but a fresh application is at height 0.
+        Self {
+            committed: BTreeMap::new(),
+            working: BTreeMap::new(),
+            height: block::Height::from(0u32),
+            executing: block::Height::from(0u32),
+            app_hash: AppHash::default(),
+        }
+    }
+
+    /// The value of `key` as of the last commit.
+    pub fn get(&self, key: &[u8]) -> Option<&Bytes> {
+        self.committed.get(key)
+    }
+
+    /// The height of the last committed block.
+    pub fn height(&self) -> block::Height {
+        self.height
+    }
+
+    /// The app hash as of the last commit.
+    pub fn app_hash(&self) -> &AppHash {
+        &self.app_hash
+    }
+
+    fn echo(&self, request: request::Echo) -> response::Echo {
+        response::Echo {
+            message: request.message,
+        }
+    }
+
+    fn info(&self) -> response::Info {
+        response::Info {
+            data: "tower-abci-kvstore".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            app_version: 1,
+            last_block_height: self.height,
+            last_block_app_hash: self.app_hash.clone(),
+        }
+    }
+
+    fn query(&self, query: request::Query) -> response::Query {
+        let (value, log) = match self.committed.get(&query.data) {
+            Some(value) => (value.clone(), "exists"),
+            None => (Bytes::new(), "does not exist"),
+        };
+        response::Query {
+            log: log.to_string(),
+            key: query.data,
+            value,
+            height: self.height,
+            ..Default::default()
+        }
+    }
+
+    fn check_tx(&self, request: request::CheckTx) -> response::CheckTx {
+        match parse_tx(&request.tx) {
+            Some(_) => response::CheckTx::ok(),
+            None => response::CheckTx::err(CODE_INVALID_TX, "empty transaction"),
+        }
+    }
+
+    /// Includes valid transactions in order, up to the size limit.
+    fn prepare_proposal(&self, request: request::PrepareProposal) -> response::PrepareProposal {
+        let mut size = 0;
+        let txs = request
+            .txs
+            .into_iter()
+            .filter(|tx| parse_tx(tx).is_some())
+            .take_while(|tx| {
+                size += tx.len() as i64;
+                size <= request.max_tx_bytes
+            })
+            .collect();
+        response::PrepareProposal { txs }
+    }
+
+    fn process_proposal(&self, txs: &[Bytes]) -> response::ProcessProposal {
+        if txs.iter().all(|tx| parse_tx(tx).is_some()) {
+            response::ProcessProposal::Accept
+        } else {
+            response::ProcessProposal::Reject
+        }
+    }
+
+    fn extend_vote(&self) -> response::ExtendVote {
+        response::ExtendVote {
+            vote_extension: Bytes::new(),
+        }
+    }
+
+    fn verify_vote_extension(
+        &self,
+        request: request::VerifyVoteExtension,
+    ) -> response::VerifyVoteExtension {
+        if request.vote_extension.is_empty() {
+            response::VerifyVoteExtension::Accept
+        } else {
+            response::VerifyVoteExtension::Reject
+        }
+    }
+
+    fn begin_block(&mut self, height: block::Height) {
+        self.executing = height;
+        self.working.clone_from(&self.committed);
+    }
+
+    fn execute_tx(&mut self, tx: Bytes) -> ExecTxResult {
+        let Some((key, value)) = parse_tx(&tx) else {
+            return ExecTxResult::err(CODE_INVALID_TX, "empty transaction");
+        };
+        let event = Event::new(
+            "app",
+            vec![
+                ("key", String::from_utf8_lossy(&key).as_ref()).index(),
+                ("index_key", "index is working").index(),
+                ("noindex_key", "noindex is working").no_index(),
+            ],
+        );
+        self.working.insert(key, value);
+        ExecTxResult {
+            events: vec![event],
+            ..Default::default()
+        }
+    }
+
+    fn deliver_tx(&mut self, request: request::DeliverTx) -> response::DeliverTx {
+        let result = self.execute_tx(request.tx);
+        response::DeliverTx {
+            code: result.code,
+            data: result.data,
+            log: result.log,
+            info: result.info,
+            gas_wanted: result.gas_wanted,
+            gas_used: result.gas_used,
+            events: result.events,
+            codespace: result.codespace,
+        }
+    }
+
+    fn finalize_block(&mut self, request: request::FinalizeBlock) -> response::FinalizeBlock {
+        self.begin_block(request.height);
+        let tx_results: Vec<_> = request
+            .txs
+            .into_iter()
+            .map(|tx| self.execute_tx(tx))
+            .collect();
+        response::FinalizeBlock {
+            events: vec![Event::new(
+                "app",
+                vec![("num_tx", tx_results.len().to_string()).index()],
+            )],
+            tx_results,
+            validator_updates: vec![],
+            consensus_param_updates: None,
+            app_hash: hash(&self.working),
+        }
+    }
+
+    /// Commits the block being executed, returning the new app hash.
+    fn commit(&mut self) -> AppHash {
+        self.committed.clone_from(&self.working);
+        self.height = self.executing;
+        self.app_hash = hash(&self.committed);
+        self.app_hash.clone()
+    }
+
+    fn offer_snapshot(&self) -> response::OfferSnapshot {
+        response::OfferSnapshot::Reject
+    }
+
+    fn apply_snapshot_chunk(&self) -> response::ApplySnapshotChunk {
+        response::ApplySnapshotChunk {
+            result: response::ApplySnapshotChunkResult::Abort,
+            ..Default::default()
+        }
+    }
+}
+
+/// Splits a transaction into its key and value, or returns `None` if it is
+/// invalid.
+fn parse_tx(tx: &Bytes) -> Option<(Bytes, Bytes)> {
+    if tx.is_empty() {
+        return None;
+    }
+    match tx.iter().position(|&b| b == b'=') {
+        Some(i) => Some((tx.slice(..i), tx.slice(i + 1..))),
+        None => Some((tx.clone(), tx.clone())),
+    }
+}
+
+fn hash(store: &BTreeMap<Bytes, Bytes>) -> AppHash {
+    let mut hasher = Sha256::new();
+    for (key, value) in store {
+        hasher.update((key.len() as u64).to_be_bytes());
+        hasher.update(key);
+        hasher.update((value.len() as u64).to_be_bytes());
+        hasher.update(value);
+    }
+    AppHash::try_from(hasher.finalize().to_vec()).expect("any bytes are an app hash")
+}
+
+impl Service<tendermint::v0_34::abci::Request> for KVStore {
+    type Response = tendermint::v0_34::abci::Response;
+    type Error = BoxError;
+    type Future = Ready<Result<Self::Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tendermint::v0_34::abci::Request) -> Self::Future {
+        use tendermint::v0_34::abci::{Request, Response};
+
+        let rsp = match req {
+            Request::Echo(echo) => Response::Echo(self.echo(echo)),
+            Request::Flush => Response::Flush,
+            Request::Info(_) => Response::Info(self.info()),
+            // See: https://github.com/informalsystems/tendermint-rs/blob/c2b5c9e01eab1c740598aa14375a7453f3bfa436/tendermint/src/abci/doc/request-setoption.md#L1
+            Request::SetOption(_) => Response::SetOption(response::SetOption {
+                code: Code::Ok,
+                log: "undocumented".to_string(),
+                info: "removed from tendermint in 0.35".to_string(),
+            }),
+            Request::InitChain(_) => Response::InitChain(Default::default()),
+            Request::Query(query) => Response::Query(self.query(query)),
+            Request::BeginBlock(begin_block) => {
+                self.begin_block(begin_block.header.height);
+                Response::BeginBlock(Default::default())
+            }
+            Request::CheckTx(check_tx) => Response::CheckTx(self.check_tx(check_tx)),
+            Request::DeliverTx(deliver_tx) => Response::DeliverTx(self.deliver_tx(deliver_tx)),
+            Request::EndBlock(_) => Response::EndBlock(Default::default()),
+            Request::Commit => Response::Commit(response::Commit {
+                data: self.commit().into(),
+                retain_height: 0u32.into(),
+            }),
+            Request::ListSnapshots => Response::ListSnapshots(Default::default()),
+            Request::OfferSnapshot(_) => Response::OfferSnapshot(self.offer_snapshot()),
+            Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
+            Request::ApplySnapshotChunk(_) => {
+                Response::ApplySnapshotChunk(self.apply_snapshot_chunk())
+            }
+        };
+        ready(Ok(rsp))
+    }
+}
+
+impl Service<tendermint::v0_37::abci::Request> for KVStore {
+    type Response = tendermint::v0_37::abci::Response;
+    type Error = BoxError;
+    type Future = Ready<Result<Self::Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tendermint::v0_37::abci::Request) -> Self::Future {
+        use tendermint::v0_37::abci::{Request, Response};
+
+        let rsp = match req {
+            Request::Echo(echo) => Response::Echo(self.echo(echo)),
+            Request::Flush => Response::Flush,
+            Request::Info(_) => Response::Info(self.info()),
+            Request::InitChain(_) => Response::InitChain(Default::default()),
+            Request::Query(query) => Response::Query(self.query(query)),
+            Request::PrepareProposal(proposal) => {
+                Response::PrepareProposal(self.prepare_proposal(proposal))
+            }
+            Request::ProcessProposal(proposal) => {
+                Response::ProcessProposal(self.process_proposal(&proposal.txs))
+            }
+            Request::BeginBlock(begin_block) => {
+                self.begin_block(begin_block.header.height);
+                Response::BeginBlock(Default::default())
+            }
+            Request::CheckTx(check_tx) => Response::CheckTx(self.check_tx(check_tx)),
+            Request::DeliverTx(deliver_tx) => Response::DeliverTx(self.deliver_tx(deliver_tx)),
+            Request::EndBlock(_) => Response::EndBlock(Default::default()),
+            Request::Commit => Response::Commit(response::Commit {
+                data: self.commit().into(),
+                retain_height: 0u32.into(),
+            }),
+            Request::ListSnapshots => Response::ListSnapshots(Default::default()),
+            Request::OfferSnapshot(_) => Response::OfferSnapshot(self.offer_snapshot()),
+            Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
+            Request::ApplySnapshotChunk(_) => {
+                Response::ApplySnapshotChunk(self.apply_snapshot_chunk())
+            }
+        };
+        ready(Ok(rsp))
+    }
+}
+
+impl Service<tendermint::v0_38::abci::Request> for KVStore {
+    type Response = tendermint::v0_38::abci::Response;
+    type Error = BoxError;
+    type Future = Ready<Result<Self::Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tendermint::v0_38::abci::Request) -> Self::Future {
+        use tendermint::v0_38::abci::{Request, Response};
+
+        let rsp = match req {
+            Request::Echo(echo) => Response::Echo(self.echo(echo)),
+            Request::Flush => Response::Flush,
+            Request::Info(_) => Response::Info(self.info()),
+            Request::InitChain(_) => Response::InitChain(Default::default()),
+            Request::Query(query) => Response::Query(self.query(query)),
+            Request::CheckTx(check_tx) => Response::CheckTx(self.check_tx(check_tx)),
+            Request::PrepareProposal(proposal) => {
+                Response::PrepareProposal(self.prepare_proposal(proposal))
+            }
+            Request::ProcessProposal(proposal) => {
+                Response::ProcessProposal(self.process_proposal(&proposal.txs))
+            }
+            Request::ExtendVote(_) => Response::ExtendVote(self.extend_vote()),
+            Request::VerifyVoteExtension(vote) => {
+                Response::VerifyVoteExtension(self.verify_vote_extension(vote))
+            }
+            Request::FinalizeBlock(block) => Response::FinalizeBlock(self.finalize_block(block)),
+            Request::Commit => {
+                // Since 0.38 the app hash is returned by FinalizeBlock.
+                self.commit();
+                Response::Commit(response::Commit {
+                    data: Bytes::new(),
+                    retain_height: 0u32.into(),
+                })
+            }
+            Request::ListSnapshots => Response::ListSnapshots(Default::default()),
+            Request::OfferSnapshot(_) => Response::OfferSnapshot(self.offer_snapshot()),
+            Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
+            Request::ApplySnapshotChunk(_) => {
+                Response::ApplySnapshotChunk(self.apply_snapshot_chunk())
+            }
+        };
+        ready(Ok(rsp))
+    }
+}
@@ -0,0 +1,7 @@
+//! Ready-made applications, for examples, tests and benchmarks.
+
+#[cfg(feature = "kvstore")]
+pub mod kvstore;
+mod stub;
+
+pub use stub::{EchoApp, NoopApp};
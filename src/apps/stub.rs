@@ -0,0 +1,240 @@
+//! Applications without state, which answer every request immediately.
+
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::future::{ready, Ready};
+use tendermint::abci::{request, response, types::ExecTxResult, Code};
+use tower::Service;
+
+use crate::BoxError;
+
+/// An application that accepts everything and does nothing.
+///
+/// It accepts all transactions and proposals, proposes the transactions of
+/// the mempool as they are, stays at height 0 with an empty app hash, and
+/// takes no snapshots. It implements the full ABCI and each category of
+/// requests of every protocol version, so it can stand in for any service
+/// given to a server, e.g. `Server::builder().snapshot(NoopApp)`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopApp;
+
+/// An application that answers with the data of its requests.
+///
+/// It behaves like [`NoopApp`], except that the results of `CheckTx`,
+/// `DeliverTx` and `FinalizeBlock` carry each transaction as their data, and
+/// queries return their data as both key and value. This makes it a baseline
+/// for measuring the overhead of the server on requests of a given size.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EchoApp;
+
+fn info(name: &str) -> response::Info {
+    response::Info {
+        data: name.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        app_version: 0,
+        last_block_height: 0u32.into(),
+        last_block_app_hash: Default::default(),
+    }
+}
+
+fn query(echo: bool, query: request::Query) -> response::Query {
+    let data = if echo { query.data } else { Bytes::new() };
+    response::Query {
+        key: data.clone(),
+        value: data,
+        height: 0u32.into(),
+        ..Default::default()
+    }
+}
+
+fn check_tx(echo: bool, check_tx: request::CheckTx) -> response::CheckTx {
+    response::CheckTx {
+        data: if echo { check_tx.tx } else { Bytes::new() },
+        ..Default::default()
+    }
+}
+
+fn deliver_tx(echo: bool, deliver_tx: request::DeliverTx) -> response::DeliverTx {
+    response::DeliverTx {
+        data: if echo { deliver_tx.tx } else { Bytes::new() },
+        ..Default::default()
+    }
+}
+
+fn commit() -> response::Commit {
+    response::Commit {
+        data: Bytes::new(),
+        retain_height: 0u32.into(),
+    }
+}
+
+/// Proposes the transactions in order, up to the size limit.
+fn prepare_proposal(proposal: request::PrepareProposal) -> response::PrepareProposal {
+    let mut size = 0;
+    let txs = proposal
+        .txs
+        .into_iter()
+        .take_while(|tx| {
+            size += tx.len() as i64;
+            size <= proposal.max_tx_bytes
+        })
+        .collect();
+    response::PrepareProposal { txs }
+}
+
+fn finalize_block(echo: bool, block: request::FinalizeBlock) -> response::FinalizeBlock {
+    response::FinalizeBlock {
+        events: vec![],
+        tx_results: block
+            .txs
+            .into_iter()
+            .map(|tx| ExecTxResult {
+                data: if echo { tx } else { Bytes::new() },
+                ..Default::default()
+            })
+            .collect(),
+        validator_updates: vec![],
+        consensus_param_updates: None,
+        app_hash: Default::default(),
+    }
+}
+
+fn apply_snapshot_chunk() -> response::ApplySnapshotChunk {
+    response::ApplySnapshotChunk {
+        result: response::ApplySnapshotChunkResult::Abort,
+        ..Default::default()
+    }
+}
+
+fn v034(
+    name: &str,
+    echo: bool,
+    req: tendermint::v0_34::abci::Request,
+) -> tendermint::v0_34::abci::Response {
+    use tendermint::v0_34::abci::{Request, Response};
+
+    match req {
+        Request::Echo(req) => Response::Echo(response::Echo {
+            message: req.message,
+        }),
+        Request::Flush => Response::Flush,
+        Request::Info(_) => Response::Info(info(name)),
+        Request::SetOption(_) => Response::SetOption(response::SetOption {
+            code: Code::Ok,
+            log: String::new(),
+            info: String::new(),
+        }),
+        Request::InitChain(_) => Response::InitChain(Default::default()),
+        Request::Query(req) => Response::Query(query(echo, req)),
+        Request::BeginBlock(_) => Response::BeginBlock(Default::default()),
+        Request::CheckTx(req) => Response::CheckTx(check_tx(echo, req)),
+        Request::DeliverTx(req) => Response::DeliverTx(deliver_tx(echo, req)),
+        Request::EndBlock(_) => Response::EndBlock(Default::default()),
+        Request::Commit => Response::Commit(commit()),
+        Request::ListSnapshots => Response::ListSnapshots(Default::default()),
+        Request::OfferSnapshot(_) => Response::OfferSnapshot(response::OfferSnapshot::Reject),
+        Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
+        Request::ApplySnapshotChunk(_) => Response::ApplySnapshotChunk(apply_snapshot_chunk()),
+    }
+}
+
+fn v037(
+    name: &str,
+    echo: bool,
+    req: tendermint::v0_37::abci::Request,
+) -> tendermint::v0_37::abci::Response {
+    use tendermint::v0_37::abci::{Request, Response};
+
+    match req {
+        Request::Echo(req) => Response::Echo(response::Echo {
+            message: req.message,
+        }),
+        Request::Flush => Response::Flush,
+        Request::Info(_) => Response::Info(info(name)),
+        Request::InitChain(_) => Response::InitChain(Default::default()),
+        Request::Query(req) => Response::Query(query(echo, req)),
+        Request::BeginBlock(_) => Response::BeginBlock(Default::default()),
+        Request::CheckTx(req) => Response::CheckTx(check_tx(echo, req)),
+        Request::DeliverTx(req) => Response::DeliverTx(deliver_tx(echo, req)),
+        Request::EndBlock(_) => Response::EndBlock(Default::default()),
+        Request::Commit => Response::Commit(commit()),
+        Request::ListSnapshots => Response::ListSnapshots(Default::default()),
+        Request::OfferSnapshot(_) => Response::OfferSnapshot(response::OfferSnapshot::Reject),
+        Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
+        Request::ApplySnapshotChunk(_) => Response::ApplySnapshotChunk(apply_snapshot_chunk()),
+        Request::PrepareProposal(req) => Response::PrepareProposal(prepare_proposal(req)),
+        Request::ProcessProposal(_) => Response::ProcessProposal(response::ProcessProposal::Accept),
+    }
+}
+
+fn v038(
+    name: &str,
+    echo: bool,
+    req: tendermint::v0_38::abci::Request,
+) -> tendermint::v0_38::abci::Response {
+    use tendermint::v0_38::abci::{Request, Response};
+
+    match req {
+        Request::Echo(req) => Response::Echo(response::Echo {
+            message: req.message,
+        }),
+        Request::Flush => Response::Flush,
+        Request::Info(_) => Response::Info(info(name)),
+        Request::InitChain(_) => Response::InitChain(Default::default()),
+        Request::Query(req) => Response::Query(query(echo, req)),
+        Request::CheckTx(req) => Response::CheckTx(check_tx(echo, req)),
+        Request::Commit => Response::Commit(commit()),
+        Request::ListSnapshots => Response::ListSnapshots(Default::default()),
+        Request::OfferSnapshot(_) => Response::OfferSnapshot(response::OfferSnapshot::Reject),
+        Request::LoadSnapshotChunk(_) => Response::LoadSnapshotChunk(Default::default()),
+        Request::ApplySnapshotChunk(_) => Response::ApplySnapshotChunk(apply_snapshot_chunk()),
+        Request::PrepareProposal(req) => Response::PrepareProposal(prepare_proposal(req)),
+        Request::ProcessProposal(_) => Response::ProcessProposal(response::ProcessProposal::Accept),
+        Request::ExtendVote(_) => Response::ExtendVote(response::ExtendVote {
+            vote_extension: Bytes::new(),
+        }),
+        Request::VerifyVoteExtension(_) => {
+            Response::VerifyVoteExtension(response::VerifyVoteExtension::Accept)
+        }
+        Request::FinalizeBlock(req) => Response::FinalizeBlock(finalize_block(echo, req)),
+    }
+}
+
+/// Implements `Service` for a stub application, for a request type that
+/// converts into the full request of a protocol version.
+macro_rules! impl_service {
+    ($app:ident, $name:literal, $echo:literal, $version:ident, $respond:ident, $request:ident => $response:ident) => {
+        impl Service<tendermint::$version::abci::$request> for $app {
+            type Response = tendermint::$version::abci::$response;
+            type Error = BoxError;
+            type Future = Ready<Result<Self::Response, BoxError>>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            #[allow(clippy::useless_conversion)]
+            fn call(&mut self, req: tendermint::$version::abci::$request) -> Self::Future {
+                let rsp = $respond($name, $echo, req.into());
+                ready(Ok(rsp
+                    .try_into()
+                    .expect("responses are of the category of their requests")))
+            }
+        }
+    };
+    ($app:ident, $name:literal, $echo:literal, $version:ident, $respond:ident) => {
+        impl_service!($app, $name, $echo, $version, $respond, Request => Response);
+        impl_service!($app, $name, $echo, $version, $respond, ConsensusRequest => ConsensusResponse);
+        impl_service!($app, $name, $echo, $version, $respond, MempoolRequest => MempoolResponse);
+        impl_service!($app, $name, $echo, $version, $respond, InfoRequest => InfoResponse);
+        impl_service!($app, $name, $echo, $version, $respond, SnapshotRequest => SnapshotResponse);
+    };
+}
+
+impl_service!(NoopApp, "tower-abci-noop", false, v0_34, v034);
+impl_service!(NoopApp, "tower-abci-noop", false, v0_37, v037);
+impl_service!(NoopApp, "tower-abci-noop", false, v0_38, v038);
+impl_service!(EchoApp, "tower-abci-echo", true, v0_34, v034);
+impl_service!(EchoApp, "tower-abci-echo", true, v0_37, v037);
+impl_service!(EchoApp, "tower-abci-echo", true, v0_38, v038);
@@ -1,4 +1,6 @@
 use super::error::ServiceError;
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
 use tokio::sync::{oneshot, OwnedSemaphorePermit};
 
 /// Message sent over buffer
@@ -7,6 +9,10 @@ pub(crate) struct Message<Request, Fut> {
     pub(crate) request: Request,
     pub(crate) tx: Tx<Fut>,
     pub(crate) span: tracing::Span,
+    pub(crate) request_id: Option<crate::RequestId>,
+    pub(crate) raw: Option<bytes::Bytes>,
+    pub(crate) deadline: Option<tokio::time::Instant>,
+    pub(crate) deliver_tx_batched: bool,
     pub(super) _permit: OwnedSemaphorePermit,
 }
 
@@ -4,12 +4,15 @@ use super::{
     worker::{Handle, Worker},
 };
 
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
 use futures::ready;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
 use tokio_util::sync::PollSemaphore;
 use tower::Service;
+use tracing::Instrument;
 
 /// Adds an mpsc buffer in front of an inner service.
 ///
@@ -79,7 +82,8 @@ where
         Request: Send + 'static,
     {
         let (svc1, svc2, svc3, svc4, worker) = Self::pair(service, bound);
-        tokio::spawn(worker.run());
+        let span = tracing::debug_span!("abci_buffer_worker");
+        crate::task::spawn("abci-buffer-worker", worker.run().instrument(span));
         (svc1, svc2, svc3, svc4)
     }
 
@@ -203,6 +207,11 @@ where
         // if we didn't do this, events on the worker related to this span wouldn't be counted
         // towards that span since the worker would have no way of entering it.
         let span = tracing::Span::current();
+        // Likewise for the request id, which is only available during `call`.
+        let request_id = crate::RequestId::current();
+        let raw = crate::middleware::raw::current();
+        let deadline = crate::middleware::deadline::current();
+        let deliver_tx_batched = crate::middleware::batch::deliver_tx_batched();
 
         // If we've made it here, then a semaphore permit has already been
         // acquired, so we can freely allocate a oneshot.
@@ -211,6 +220,10 @@ where
         match self.tx.send(Message {
             request,
             span,
+            request_id,
+            raw,
+            deadline,
+            deliver_tx_batched,
             tx,
             _permit,
         }) {
@@ -2,6 +2,8 @@ use super::{
     error::{Closed, ServiceError},
     message::Message,
 };
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
 use futures::stream::StreamExt;
 use std::sync::{Arc, Mutex, Weak};
 use tokio::{
@@ -131,7 +133,15 @@ where
         match self.service.ready().await {
             Ok(svc) => {
                 tracing::trace!("dispatching request to service");
-                let response = svc.call(msg.request);
+                let response = crate::RequestId::scope(msg.request_id, || {
+                    crate::middleware::raw::scope(msg.raw, || {
+                        crate::middleware::deadline::scope(msg.deadline, || {
+                            crate::middleware::batch::scope(msg.deliver_tx_batched, || {
+                                svc.call(msg.request)
+                            })
+                        })
+                    })
+                });
                 tracing::trace!("returning response future");
                 let _ = msg.tx.send(Ok(response));
             }
@@ -0,0 +1,311 @@
+//! Command-line flags for ABCI application binaries.
+//!
+//! [`ServerArgs`] parses the flags node operators expect of an ABCI
+//! application, with addresses written as in the `proxy_app` setting of
+//! CometBFT, into a [`ServerConfig`]. It is meant to be flattened into the
+//! binary's own options:
+//!
+//! ```ignore
+//! #[derive(Parser)]
+//! struct Opt {
+//!     #[command(flatten)]
+//!     server: ServerArgs,
+//!     #[arg(long)]
+//!     home: PathBuf,
+//! }
+//!
+//! let opt = Opt::parse();
+//! let config = opt.server.to_config()?;
+//! let server = ServerBuilder::from_config(&config)
+//!     // ...
+//!     .finish()
+//!     .unwrap();
+//! server.listen(&config.listen).await?;
+//! ```
+//!
+//! e.g. `app --abci-laddr unix:///run/app.sock --abci-max-in-flight 64`.
+//! Flags left out keep the values of the configuration they are applied to,
+//! so that flags can override a configuration file with
+//! [`ServerArgs::apply`].
+
+use std::{fmt, str::FromStr};
+
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    Args, ValueEnum,
+};
+
+use crate::{
+    config::{ListenAddress, ServerConfig, StallConfig},
+    BoxError, ErrorPolicy,
+};
+
+/// The transport of a listen address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    Tcp,
+    Unix,
+}
+
+impl Transport {
+    fn of(address: &ListenAddress) -> Self {
+        match address {
+            ListenAddress::Tcp { .. } => Transport::Tcp,
+            ListenAddress::Unix { .. } => Transport::Unix,
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Transport::Tcp),
+            "unix" => Ok(Transport::Unix),
+            _ => Err(format!("invalid transport {s:?}: expected tcp or unix")),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transport::Tcp => "tcp",
+            Transport::Unix => "unix",
+        })
+    }
+}
+
+/// The standard flags of an ABCI server. See the [module documentation](self).
+#[derive(Clone, Debug, Default, Args)]
+pub struct ServerArgs {
+    /// The address to listen on, e.g. `tcp://0.0.0.0:26658` or
+    /// `unix:///run/app.sock`.
+    #[arg(long = "abci-laddr", value_name = "ADDRESS")]
+    pub laddr: Option<String>,
+
+    /// The transport of `--abci-laddr`, when the address has no `tcp://` or
+    /// `unix://` prefix.
+    #[arg(long = "abci-transport", value_name = "TRANSPORT", value_enum)]
+    pub transport: Option<Transport>,
+
+    /// What to do when a non-consensus service fails: `disconnect`,
+    /// `exception` or `error_response`.
+    #[arg(
+        long = "abci-error-policy",
+        value_name = "POLICY",
+        value_parser = PossibleValuesParser::new(["disconnect", "exception", "error_response"])
+            .try_map(|s| parse_error_policy(&s))
+    )]
+    pub error_policy: Option<ErrorPolicy>,
+
+    /// Wait for each consensus response before calling the consensus service
+    /// with the next request.
+    #[arg(long = "abci-serial-consensus")]
+    pub serial_consensus: bool,
+
+    /// Stop reading requests after a `Commit` until its response is flushed.
+    #[arg(long = "abci-commit-barrier")]
+    pub commit_barrier: bool,
+
+    /// Stop reading requests while this many responses are pending.
+    #[arg(long = "abci-max-in-flight", value_name = "N")]
+    pub max_in_flight: Option<usize>,
+
+    /// Write responses at most this many milliseconds after they resolve.
+    #[arg(long = "abci-flush-interval-ms", value_name = "MS")]
+    pub flush_interval_ms: Option<u64>,
+
+    /// Fail connections sending requests longer than this many bytes.
+    #[arg(long = "abci-max-request-len", value_name = "BYTES")]
+    pub max_request_len: Option<usize>,
+
+    /// Process up to this many `CheckTx` requests in parallel.
+    #[arg(long = "abci-check-tx-concurrency", value_name = "N")]
+    pub check_tx_concurrency: Option<usize>,
+
+    /// Process up to this many `Query` requests in parallel.
+    #[arg(long = "abci-query-concurrency", value_name = "N")]
+    pub query_concurrency: Option<usize>,
+
+    /// Warn when a service makes no progress for this many milliseconds.
+    #[arg(long = "abci-stall-interval-ms", value_name = "MS")]
+    pub stall_interval_ms: Option<u64>,
+
+    /// Give each request a deadline this many milliseconds after it is read.
+    #[arg(long = "abci-request-timeout-ms", value_name = "MS")]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Log a one-line summary of each request served.
+    #[arg(long = "abci-summary-log")]
+    pub summary_log: bool,
+}
+
+fn parse_error_policy(s: &str) -> Result<ErrorPolicy, String> {
+    match s {
+        "disconnect" => Ok(ErrorPolicy::Disconnect),
+        "exception" => Ok(ErrorPolicy::Exception),
+        "error_response" => Ok(ErrorPolicy::ErrorResponse),
+        _ => Err(format!("invalid error policy {s:?}")),
+    }
+}
+
+impl ServerArgs {
+    /// The listen address given by `--abci-laddr` and `--abci-transport`, if
+    /// any.
+    ///
+    /// Fails if the address is invalid, if its prefix contradicts
+    /// `--abci-transport`, or if `--abci-transport` is given without an
+    /// address.
+    pub fn listen_address(&self) -> Result<Option<ListenAddress>, BoxError> {
+        let laddr = match (&self.laddr, self.transport) {
+            (Some(laddr), _) => laddr,
+            (None, None) => return Ok(None),
+            (None, Some(_)) => return Err("--abci-transport requires --abci-laddr".into()),
+        };
+        let address: ListenAddress = match self.transport {
+            Some(transport) if !laddr.contains("://") => {
+                format!("{transport}://{laddr}").parse()?
+            }
+            _ => laddr.parse()?,
+        };
+        match self.transport {
+            Some(transport) if transport != Transport::of(&address) => Err(format!(
+                "--abci-laddr {laddr} is not a {transport} address, as --abci-transport requires"
+            )
+            .into()),
+            _ => Ok(Some(address)),
+        }
+    }
+
+    /// Overrides the settings of `config` given by these flags.
+    pub fn apply(&self, config: &mut ServerConfig) -> Result<(), BoxError> {
+        if let Some(address) = self.listen_address()? {
+            config.listen = address;
+        }
+        if let Some(error_policy) = self.error_policy {
+            config.error_policy = error_policy;
+        }
+        config.serial_consensus |= self.serial_consensus;
+        config.commit_barrier |= self.commit_barrier;
+        config.summary_log |= self.summary_log;
+        if let Some(max_in_flight) = self.max_in_flight {
+            config.max_in_flight = Some(max_in_flight);
+        }
+        if let Some(flush_interval_ms) = self.flush_interval_ms {
+            config.flush_interval_ms = Some(flush_interval_ms);
+        }
+        if let Some(max_request_len) = self.max_request_len {
+            config.max_request_len = Some(max_request_len);
+        }
+        if let Some(concurrency) = self.check_tx_concurrency {
+            config.check_tx_concurrency = Some(concurrency);
+        }
+        if let Some(concurrency) = self.query_concurrency {
+            config.query_concurrency = Some(concurrency);
+        }
+        if let Some(timeout_ms) = self.request_timeout_ms {
+            config.request_timeout_ms = Some(timeout_ms);
+        }
+        if let Some(interval_ms) = self.stall_interval_ms {
+            let close = config.stall_detection.is_some_and(|stall| stall.close);
+            config.stall_detection = Some(StallConfig { interval_ms, close });
+        }
+        Ok(())
+    }
+
+    /// The default configuration, with the settings given by these flags.
+    pub fn to_config(&self) -> Result<ServerConfig, BoxError> {
+        let mut config = ServerConfig::default();
+        self.apply(&mut config)?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Opt {
+        #[command(flatten)]
+        server: ServerArgs,
+    }
+
+    fn parse(args: &[&str]) -> Result<ServerArgs, clap::Error> {
+        Opt::try_parse_from(std::iter::once("app").chain(args.iter().copied()))
+            .map(|opt| opt.server)
+    }
+
+    #[test]
+    fn flags_override_the_config() {
+        let args = parse(&[
+            "--abci-laddr",
+            "unix:///run/app.sock",
+            "--abci-error-policy",
+            "error_response",
+            "--abci-serial-consensus",
+            "--abci-max-in-flight",
+            "64",
+            "--abci-stall-interval-ms",
+            "500",
+        ])
+        .unwrap();
+        let config = args.to_config().unwrap();
+        assert_eq!(
+            config.listen,
+            ListenAddress::Unix {
+                path: "/run/app.sock".into()
+            }
+        );
+        assert_eq!(config.error_policy, ErrorPolicy::ErrorResponse);
+        assert!(config.serial_consensus);
+        assert!(!config.commit_barrier);
+        assert_eq!(config.max_in_flight, Some(64));
+        assert_eq!(
+            config.stall_detection,
+            Some(StallConfig {
+                interval_ms: 500,
+                close: false
+            })
+        );
+    }
+
+    #[test]
+    fn flags_left_out_keep_the_config() {
+        let config = parse(&[]).unwrap().to_config().unwrap();
+        assert_eq!(config, ServerConfig::default());
+    }
+
+    #[test]
+    fn transport_applies_to_an_address_without_a_prefix() {
+        let args = parse(&["--abci-laddr", "/run/app.sock", "--abci-transport", "unix"]).unwrap();
+        assert_eq!(
+            args.listen_address().unwrap(),
+            Some(ListenAddress::Unix {
+                path: "/run/app.sock".into()
+            })
+        );
+
+        let args = parse(&[
+            "--abci-laddr",
+            "tcp://0.0.0.0:26658",
+            "--abci-transport",
+            "unix",
+        ])
+        .unwrap();
+        assert!(args.listen_address().is_err());
+        let args = parse(&["--abci-transport", "tcp"]).unwrap();
+        assert!(args.listen_address().is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(parse(&["--abci-transport", "udp"]).is_err());
+        assert!(parse(&["--abci-error-policy", "ignore"]).is_err());
+        assert!(parse(&["--abci-max-in-flight", "many"]).is_err());
+    }
+}
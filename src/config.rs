@@ -0,0 +1,388 @@
+//! Server settings loaded from a configuration file.
+//!
+//! [`ServerConfig`] holds where the server listens and the settings of its
+//! connections, as plain values that deserialize with `serde` from any
+//! format, e.g. TOML:
+//!
+//! ```toml
+//! serial_consensus = true
+//! max_in_flight = 64
+//! flush_interval_ms = 5
+//! error_policy = "error_response"
+//!
+//! [listen]
+//! transport = "unix"
+//! path = "/run/app/abci.sock"
+//!
+//! [stall_detection]
+//! interval_ms = 10000
+//! close = true
+//!
+//! [log_levels]
+//! CheckTx = "trace"
+//!
+//! [tx_filter]
+//! blocked_patterns = ["deadbeef"]
+//! ```
+//!
+//! The server is then built and started from it:
+//!
+//! ```ignore
+//! let config: ServerConfig = toml::from_str(&std::fs::read_to_string(path)?)?;
+//! let server = ServerBuilder::from_config(&config)
+//!     .consensus(consensus)
+//!     .mempool(mempool)
+//!     .info(info)
+//!     .snapshot(snapshot)
+//!     .finish()
+//!     .unwrap();
+//! server.listen(&config.listen).await?;
+//! ```
+//!
+//! Fields left out keep the defaults of [`ConnectionOptions`], and unknown
+//! fields are rejected, so that a misspelled setting is not silently ignored.
+//!
+//! The connection settings, and the rules of the transaction filter, can be
+//! changed without restarting the server, by applying a new configuration
+//! with [`ServerConfig::apply`], or by reloading the configuration file
+//! whenever the process receives `SIGHUP` with [`reload_on_sighup`]:
+//!
+//! ```ignore
+//! let handle = server.handle();
+//! tokio::spawn(reload_on_sighup(handle, config.listen.clone(), move || {
+//!     Ok(toml::from_str(&std::fs::read_to_string(&path)?)?)
+//! }));
+//! ```
+
+use std::{collections::BTreeMap, fmt, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::Level;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{
+    middleware::filter::TxRules, BufferSizes, ConnectionOptions, ErrorPolicy, Overload,
+    PipelineDepth, ServerHandle, StallDetection,
+};
+
+/// The address CometBFT connects to, e.g. the `proxy_app` of its config.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "transport", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ListenAddress {
+    /// A TCP socket, e.g. `127.0.0.1:26658`.
+    Tcp { address: String },
+    /// A Unix domain socket at `path`.
+    Unix { path: PathBuf },
+}
+
+impl Default for ListenAddress {
+    /// The default `proxy_app` of CometBFT, `127.0.0.1:26658`.
+    fn default() -> Self {
+        Self::Tcp {
+            address: "127.0.0.1:26658".to_string(),
+        }
+    }
+}
+
+/// Parses addresses as CometBFT writes its `proxy_app`: `tcp://host:port`,
+/// `unix:///path/to/socket`, or a bare `host:port` for TCP.
+impl FromStr for ListenAddress {
+    type Err = InvalidListenAddress;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidListenAddress {
+            address: s.to_string(),
+        };
+        let (transport, rest) = match s.split_once("://") {
+            Some((transport, rest)) => (Some(transport), rest),
+            None => (None, s),
+        };
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        match transport {
+            Some("tcp") | None => {
+                if !rest.contains(':') {
+                    return Err(invalid());
+                }
+                Ok(Self::Tcp {
+                    address: rest.to_string(),
+                })
+            }
+            Some("unix") => Ok(Self::Unix { path: rest.into() }),
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { address } => write!(f, "tcp://{address}"),
+            Self::Unix { path } => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// An address that is not of the form `tcp://host:port`,
+/// `unix:///path/to/socket` or `host:port`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidListenAddress {
+    pub address: String,
+}
+
+impl fmt::Display for InvalidListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid listen address {:?}: expected tcp://host:port or unix:///path",
+            self.address
+        )
+    }
+}
+
+impl std::error::Error for InvalidListenAddress {}
+
+/// The configuration of a server. See the [module documentation](self).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Where the server listens.
+    pub listen: ListenAddress,
+    /// See [`ConnectionOptions::error_policy`].
+    pub error_policy: ErrorPolicy,
+    /// See [`ConnectionOptions::serial_consensus`].
+    pub serial_consensus: bool,
+    /// See [`ConnectionOptions::commit_barrier`].
+    pub commit_barrier: bool,
+    /// See [`ConnectionOptions::stall_detection`].
+    pub stall_detection: Option<StallConfig>,
+    /// See [`ConnectionOptions::max_in_flight`].
+    pub max_in_flight: Option<usize>,
+    /// See [`ConnectionOptions::overload`].
+    pub overload: Option<OverloadConfig>,
+    /// [`ConnectionOptions::flush_interval`], in milliseconds.
+    pub flush_interval_ms: Option<u64>,
+    /// [`ConnectionOptions::read_timeout`], in milliseconds.
+    pub read_timeout_ms: Option<u64>,
+    /// [`ConnectionOptions::write_timeout`], in milliseconds.
+    pub write_timeout_ms: Option<u64>,
+    /// [`ConnectionOptions::idle_timeout`], in milliseconds.
+    pub idle_timeout_ms: Option<u64>,
+    /// [`ConnectionOptions::tcp_keepalive`], in milliseconds.
+    pub tcp_keepalive_ms: Option<u64>,
+    /// See [`BufferSizes::read_capacity`].
+    pub read_capacity: Option<usize>,
+    /// See [`BufferSizes::max_request_len`].
+    pub max_request_len: Option<usize>,
+    /// See [`BufferSizes::write_capacity`].
+    pub write_capacity: Option<usize>,
+    /// See [`ConnectionOptions::blocking_codec_len`].
+    pub blocking_codec_len: Option<usize>,
+    /// See [`ConnectionOptions::pipeline_depth`].
+    pub pipeline_depth: PipelineDepthConfig,
+    /// See [`ConnectionOptions::check_tx_concurrency`].
+    pub check_tx_concurrency: Option<usize>,
+    /// See [`ConnectionOptions::query_concurrency`].
+    pub query_concurrency: Option<usize>,
+    /// [`ConnectionOptions::request_timeout`], in milliseconds.
+    pub request_timeout_ms: Option<u64>,
+    /// See [`ConnectionOptions::summary_log`].
+    pub summary_log: bool,
+    /// See [`ConnectionOptions::log_levels`]. Levels are named as in
+    /// `tracing`, e.g. `"info"` or `"TRACE"`.
+    #[serde(with = "levels")]
+    pub log_levels: BTreeMap<String, Level>,
+    /// The rules of the transaction filter attached with
+    /// [`ServerHandle::set_tx_filter`]. If left out, the current rules are
+    /// kept.
+    pub tx_filter: Option<TxRules>,
+}
+
+/// The settings of [`StallDetection`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StallConfig {
+    /// [`StallDetection::interval`], in milliseconds.
+    pub interval_ms: u64,
+    /// See [`StallDetection::close`].
+    #[serde(default)]
+    pub close: bool,
+}
+
+/// The settings of [`Overload`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverloadConfig {
+    /// See [`Overload::max_in_flight`].
+    pub max_in_flight: usize,
+    /// See [`Overload::resume_below`]. Defaults to `max_in_flight`.
+    #[serde(default)]
+    pub resume_below: Option<usize>,
+    /// See [`Overload::throttle_info`].
+    #[serde(default)]
+    pub throttle_info: bool,
+}
+
+/// The settings of [`PipelineDepth`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineDepthConfig {
+    pub consensus: Option<usize>,
+    pub mempool: Option<usize>,
+    pub snapshot: Option<usize>,
+    pub info: Option<usize>,
+}
+
+impl ServerConfig {
+    /// The connection settings of this configuration.
+    pub fn connection_options(&self) -> ConnectionOptions {
+        let defaults = BufferSizes::default();
+        ConnectionOptions {
+            error_policy: self.error_policy,
+            serial_consensus: self.serial_consensus,
+            commit_barrier: self.commit_barrier,
+            stall_detection: self.stall_detection.map(|stall| {
+                StallDetection::new(Duration::from_millis(stall.interval_ms)).close(stall.close)
+            }),
+            max_in_flight: self.max_in_flight,
+            overload: self.overload.map(|overload| Overload {
+                max_in_flight: overload.max_in_flight,
+                resume_below: overload.resume_below.unwrap_or(overload.max_in_flight),
+                throttle_info: overload.throttle_info,
+            }),
+            flush_interval: self.flush_interval_ms.map(Duration::from_millis),
+            read_timeout: self.read_timeout_ms.map(Duration::from_millis),
+            write_timeout: self.write_timeout_ms.map(Duration::from_millis),
+            idle_timeout: self.idle_timeout_ms.map(Duration::from_millis),
+            tcp_keepalive: self.tcp_keepalive_ms.map(Duration::from_millis),
+            buffer_sizes: BufferSizes {
+                read_capacity: self.read_capacity.unwrap_or(defaults.read_capacity),
+                max_request_len: self.max_request_len,
+                write_capacity: self.write_capacity.unwrap_or(defaults.write_capacity),
+            },
+            blocking_codec_len: self.blocking_codec_len,
+            pipeline_depth: PipelineDepth {
+                consensus: self.pipeline_depth.consensus,
+                mempool: self.pipeline_depth.mempool,
+                snapshot: self.pipeline_depth.snapshot,
+                info: self.pipeline_depth.info,
+            },
+            check_tx_concurrency: self.check_tx_concurrency,
+            query_concurrency: self.query_concurrency,
+            request_timeout: self.request_timeout_ms.map(Duration::from_millis),
+            summary_log: self.summary_log,
+            log_levels: self.log_levels.clone(),
+            ..ConnectionOptions::default()
+        }
+    }
+
+    /// Applies the connection settings of this configuration to a running
+    /// server, as with [`ServerHandle::update_options`]: open connections
+    /// apply them to the requests they read next, except for the initial
+    /// capacity of the read buffer, which only applies to new connections.
+    ///
+    /// The settings that a configuration doesn't cover, the `CheckTx` error
+    /// responses and the per-kind overrides, are kept. The listen address
+    /// can't be changed without restarting the server.
+    ///
+    /// The rules of the transaction filter, if any, replace those of the
+    /// filter attached to the server.
+    pub fn apply(&self, handle: &ServerHandle) {
+        let mut options = self.connection_options();
+        handle.update_options(|current| {
+            options.check_tx_error = current.check_tx_error.take();
+            options.overrides = std::mem::take(&mut current.overrides);
+            *current = options;
+        });
+        if let Some(rules) = &self.tx_filter {
+            match handle.tx_filter() {
+                Some(filter) => filter.set_rules(rules.clone()),
+                None => tracing::warn!("no transaction filter to apply the configured rules to"),
+            }
+        }
+    }
+}
+
+/// Reloads the configuration with `load` and [applies](ServerConfig::apply)
+/// it to the server of `handle` whenever the process receives `SIGHUP`,
+/// until the server drains.
+///
+/// A configuration that fails to load is logged and ignored, leaving the
+/// settings unchanged, and a change from the server's `listen` address is
+/// logged as requiring a restart. Fails if the signal handler can't be
+/// installed.
+#[cfg(all(target_family = "unix", feature = "net"))]
+pub async fn reload_on_sighup<F>(
+    handle: ServerHandle,
+    listen: ListenAddress,
+    mut load: F,
+) -> Result<(), crate::BoxError>
+where
+    F: FnMut() -> Result<ServerConfig, crate::BoxError>,
+{
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    loop {
+        tokio::select! {
+            hangup = hangups.recv() => {
+                if hangup.is_none() {
+                    return Ok(());
+                }
+            }
+            () = handle.drained() => return Ok(()),
+        }
+        tracing::info!("reloading configuration on SIGHUP");
+        match load() {
+            Ok(config) => {
+                if config.listen != listen {
+                    tracing::warn!(
+                        current = %listen,
+                        configured = %config.listen,
+                        "changing the listen address requires a restart"
+                    );
+                }
+                config.apply(&handle);
+            }
+            Err(error) => {
+                tracing::error!(%error, "failed to reload configuration, keeping current settings");
+            }
+        }
+    }
+}
+
+/// (De)serializes log levels by name, as `tracing::Level` doesn't implement
+/// the `serde` traits.
+mod levels {
+    use std::collections::BTreeMap;
+
+    use crate::Level;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        levels: &BTreeMap<String, Level>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            levels
+                .iter()
+                .map(|(method, level)| (method, level.as_str())),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Level>, D::Error> {
+        BTreeMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(method, level)| {
+                let level = level
+                    .parse()
+                    .map_err(|_| D::Error::custom(format!("invalid log level {level:?}")))?;
+                Ok((method, level))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,76 @@
+//! Information about individual ABCI connections.
+
+use std::time::Duration;
+
+use tendermint::block;
+
+use crate::{Category, RequestId};
+
+/// A block whose execution was interrupted by its connection closing.
+///
+/// If the consensus connection closes after the node has started executing a
+/// block (with `BeginBlock` or `FinalizeBlock`) but before the `Commit`
+/// response was sent, the application is left with a partially executed
+/// block. The node will re-execute the block after it reconnects, so the
+/// application should discard the partial state; `ServerBuilder::on_interrupted_block`
+/// registers a callback that is told when this happens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterruptedBlock {
+    /// The id of the connection that closed.
+    pub connection: u64,
+    /// The height of the interrupted block, if it was known.
+    pub height: Option<block::Height>,
+    /// The method of the last request read before the connection closed.
+    pub last_method: &'static str,
+}
+
+/// The state of an open connection, as reported by
+/// [`ServerHandle::connections`](crate::ServerHandle::connections).
+#[derive(Clone, Debug)]
+pub struct ConnectionStatus {
+    /// The id of the connection.
+    pub id: u64,
+    /// The kind of the connection, once it has been detected.
+    pub kind: Option<Category>,
+    /// How long ago the connection was accepted.
+    pub age: Duration,
+    /// Whether the connection was asked to close, and is sending its pending
+    /// responses before it does.
+    pub closing: bool,
+    /// The requests read from the connection whose responses have not been
+    /// sent yet, oldest first.
+    pub in_flight: Vec<PendingRequest>,
+}
+
+impl ConnectionStatus {
+    /// The number of pending requests dispatched to the given service.
+    pub fn count(&self, category: Category) -> usize {
+        self.in_flight
+            .iter()
+            .filter(|r| r.category == category)
+            .count()
+    }
+
+    /// The request that has been pending the longest, if any.
+    ///
+    /// Responses are sent in request order, so this is the request holding up
+    /// every other response on the connection.
+    pub fn oldest(&self) -> Option<&PendingRequest> {
+        self.in_flight.first()
+    }
+}
+
+/// A request whose response has not been sent yet.
+#[derive(Clone, Debug)]
+pub struct PendingRequest {
+    /// The id of the request.
+    pub id: RequestId,
+    /// Which component service the request was dispatched to.
+    pub category: Category,
+    /// The ABCI method of the request, e.g. `"Commit"`.
+    pub method: &'static str,
+    /// The block height carried by the request, if it has one.
+    pub height: Option<block::Height>,
+    /// How long ago the request was read from the connection.
+    pub pending: Duration,
+}
@@ -0,0 +1,188 @@
+//! Per-connection information available to services.
+//!
+//! Every connection has a [`ConnectionContext`], with its id, the address of
+//! its peer, its kind once detected, and [`Extensions`] attached by the
+//! application when the connection is accepted, e.g. an identity derived from
+//! the peer address. Services and middleware read it with
+//! [`ConnectionContext::current`] while the server is calling
+//! [`Service::call`](tower::Service::call), as with
+//! [`RequestId::current`](crate::RequestId::current):
+//!
+//! ```ignore
+//! let server = Server::builder()
+//!     .on_connection(|context| {
+//!         if let Some(PeerAddr::Tcp(addr)) = context.peer() {
+//!             context.extensions_mut().insert(Trusted(addr.ip().is_loopback()));
+//!         }
+//!     })
+//!     // ...
+//!
+//! // In the mempool service's `call`:
+//! let trusted = ConnectionContext::current()
+//!     .and_then(|context| context.extensions().get::<Trusted>().copied());
+//! ```
+//!
+//! The context follows the request through [`split`](crate::v038::split)
+//! and the server's buffers, like the request id.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::{Category, RequestId};
+
+/// The contexts of the open connections, by connection id, which is unique
+/// within the process.
+static CONTEXTS: OnceLock<Mutex<HashMap<u64, Arc<ConnectionContext>>>> = OnceLock::new();
+
+fn contexts() -> &'static Mutex<HashMap<u64, Arc<ConnectionContext>>> {
+    CONTEXTS.get_or_init(Mutex::default)
+}
+
+/// A callback attaching extensions to the context of each new connection.
+pub(crate) type ConnectionCallback = Arc<dyn Fn(&mut ConnectionContext) + Send + Sync + 'static>;
+
+/// The address of a connection's peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// A Unix socket peer, with its path if it is bound to one.
+    Unix(Option<PathBuf>),
+}
+
+/// A map of values keyed by their type.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts `value`, returning the value of the same type it replaces.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// The value of type `T`, if one was inserted.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Removes the value of type `T`, returning it.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Information about a connection. See the [module documentation](self).
+#[derive(Debug)]
+pub struct ConnectionContext {
+    id: u64,
+    peer: Option<PeerAddr>,
+    kind: Arc<OnceLock<Category>>,
+    extensions: Extensions,
+}
+
+impl ConnectionContext {
+    /// The context of the connection of the request being passed to
+    /// [`Service::call`], if called from within `call` on a service invoked
+    /// by the server.
+    ///
+    /// [`Service::call`]: tower::Service::call
+    pub fn current() -> Option<Arc<ConnectionContext>> {
+        let id = RequestId::current()?;
+        contexts().lock().unwrap().get(&id.connection()).cloned()
+    }
+
+    /// The id of the connection, as in [`RequestId::connection`].
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The address of the peer, if the connection was accepted by a
+    /// `listen_*` method rather than opened in-process.
+    pub fn peer(&self) -> Option<&PeerAddr> {
+        self.peer.as_ref()
+    }
+
+    /// The kind of the connection, once detected from its first request.
+    pub fn kind(&self) -> Option<Category> {
+        self.kind.get().copied()
+    }
+
+    /// The extensions attached to the connection.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// The extensions attached to the connection, to attach more while the
+    /// connection is being accepted.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Makes the context of a new connection current for its requests, until
+    /// the returned guard is dropped.
+    pub(crate) fn register(
+        id: u64,
+        peer: Option<PeerAddr>,
+        kind: Arc<OnceLock<Category>>,
+        on_connection: Option<&ConnectionCallback>,
+    ) -> Registered {
+        let mut context = ConnectionContext {
+            id,
+            peer,
+            kind,
+            extensions: Extensions::new(),
+        };
+        if let Some(on_connection) = on_connection {
+            on_connection(&mut context);
+        }
+        contexts().lock().unwrap().insert(id, Arc::new(context));
+        Registered { id }
+    }
+}
+
+/// Keeps the context of a connection current while the connection is open.
+#[derive(Debug)]
+pub(crate) struct Registered {
+    id: u64,
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        contexts().lock().unwrap().remove(&self.id);
+    }
+}
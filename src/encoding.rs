@@ -0,0 +1,131 @@
+//! `serde` encodings of ABCI requests and responses.
+//!
+//! The request and response enums of every protocol version, and their
+//! per-category variants such as `ConsensusRequest`, implement [`Encode`], so
+//! that capture files, golden tests and debugging tools can persist ABCI
+//! traffic with any `serde` format. A field is encoded with
+//! `#[serde(with = "tower_abci::encoding")]`, and a message on its own, e.g.
+//! one per line of a capture file, by wrapping it in [`Encoded`]:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Exchange {
+//!     id: RequestId,
+//!     #[serde(with = "tower_abci::encoding")]
+//!     request: Request,
+//!     #[serde(with = "tower_abci::encoding")]
+//!     response: Response,
+//! }
+//!
+//! let line = serde_json::to_string(&Encoded(request))?;
+//! ```
+//!
+//! A message is encoded as its protobuf type from `tendermint_proto`, so the
+//! encoding follows the ABCI schema of its version rather than the layout of
+//! the `tendermint` types: each message is a map of its fields by protobuf
+//! name, with its method as the variant of the `value` field, e.g.
+//! `{"value":{"Echo":{"message":"hello"}}}` in JSON. Byte strings are encoded
+//! as sequences of bytes.
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::BoxError;
+
+/// An ABCI message with a `serde` encoding. See the [module
+/// documentation](self).
+pub trait Encode: Sized {
+    /// The protobuf type the message is encoded as.
+    type Proto: Serialize + for<'de> Deserialize<'de>;
+
+    fn to_proto(&self) -> Self::Proto;
+
+    /// Fails if the protobuf message is invalid, or is not of the message's
+    /// category.
+    fn from_proto(proto: Self::Proto) -> Result<Self, BoxError>;
+}
+
+/// Serializes `message`, for `#[serde(with = "tower_abci::encoding")]`.
+pub fn serialize<T: Encode, S: Serializer>(message: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    message.to_proto().serialize(serializer)
+}
+
+/// Deserializes a message, for `#[serde(with = "tower_abci::encoding")]`.
+pub fn deserialize<'de, T: Encode, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    T::from_proto(T::Proto::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// A message serialized and deserialized with its [`Encode`] encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Encoded<T>(pub T);
+
+impl<T: Encode> Serialize for Encoded<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de, T: Encode> Deserialize<'de> for Encoded<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(Encoded)
+    }
+}
+
+/// Implements [`Encode`] for the enum `$message` of a version, and for its
+/// per-category variants, through the conversions to and from `$message`.
+macro_rules! impl_encode {
+    ($version:ident, $message:ident { $($category:ident),* }) => {
+        impl Encode for tendermint::$version::abci::$message {
+            type Proto = tendermint_proto::$version::abci::$message;
+
+            fn to_proto(&self) -> Self::Proto {
+                self.clone().into()
+            }
+
+            fn from_proto(proto: Self::Proto) -> Result<Self, BoxError> {
+                Ok(proto.try_into()?)
+            }
+        }
+
+        $(
+            impl Encode for tendermint::$version::abci::$category {
+                type Proto = tendermint_proto::$version::abci::$message;
+
+                fn to_proto(&self) -> Self::Proto {
+                    tendermint::$version::abci::$message::from(self.clone()).into()
+                }
+
+                fn from_proto(proto: Self::Proto) -> Result<Self, BoxError> {
+                    let message = tendermint::$version::abci::$message::try_from(proto)?;
+                    Ok(message.try_into()?)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_encode_version {
+    ($version:ident) => {
+        impl_encode!(
+            $version,
+            Request {
+                ConsensusRequest,
+                MempoolRequest,
+                InfoRequest,
+                SnapshotRequest
+            }
+        );
+        impl_encode!(
+            $version,
+            Response {
+                ConsensusResponse,
+                MempoolResponse,
+                InfoResponse,
+                SnapshotResponse
+            }
+        );
+    };
+}
+
+impl_encode_version!(v0_34);
+impl_encode_version!(v0_37);
+impl_encode_version!(v0_38);
@@ -0,0 +1,175 @@
+//! Handling of errors returned by component services.
+
+/// What the server does when a component service returns an error.
+///
+/// Errors from the consensus service always close the connection, because the
+/// application's state may no longer match what the node expects, and the
+/// node can't meaningfully continue the block. The policy only applies to
+/// errors from the mempool, info and snapshot services.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ErrorPolicy {
+    /// Close the connection.
+    #[default]
+    Disconnect,
+    /// Respond with an `Exception` carrying the error message, and keep
+    /// serving the connection.
+    ///
+    /// Note that nodes generally treat an `Exception` as fatal to the
+    /// connection themselves.
+    Exception,
+    /// Respond with a method-appropriate error response, and keep serving the
+    /// connection: a nonzero code for `CheckTx` and `Query`, an empty list or
+    /// chunk for `ListSnapshots` and `LoadSnapshotChunk`, and a rejection for
+    /// `OfferSnapshot` and `ApplySnapshotChunk`. Methods without an error
+    /// representation (`Info` and `Echo`) get an `Exception`.
+    ErrorResponse,
+}
+
+/// The code used for method-appropriate error responses.
+pub(crate) const ERROR_RESPONSE_CODE: u32 = 1;
+
+/// Reports mempool service errors to the node as rejected transactions.
+///
+/// When set with `ServerBuilder::check_tx_error`, any error returned by the
+/// mempool service is answered with a `CheckTx` response carrying this code,
+/// regardless of the [`ErrorPolicy`]. A single bad transaction then only
+/// causes that transaction to be rejected, rather than an `Exception` or the
+/// loss of the mempool connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckTxError {
+    code: u32,
+    codespace: String,
+    log: Option<String>,
+}
+
+impl CheckTxError {
+    /// Reports mempool errors with the given response `code`, and the error's
+    /// message as the log.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is zero, which would mark the transaction as valid.
+    pub fn new(code: u32) -> Self {
+        assert!(code != 0, "CheckTx error code must be nonzero");
+        Self {
+            code,
+            codespace: String::new(),
+            log: None,
+        }
+    }
+
+    /// Sets the codespace of the response.
+    pub fn codespace(mut self, codespace: impl Into<String>) -> Self {
+        self.codespace = codespace.into();
+        self
+    }
+
+    /// Uses a fixed `log` message instead of the error's message, for
+    /// applications that don't want internal errors exposed to clients.
+    pub fn log(mut self, log: impl Into<String>) -> Self {
+        self.log = Some(log.into());
+        self
+    }
+
+    /// The `CheckTx` response reporting `error`.
+    pub fn response(&self, error: &crate::BoxError) -> tendermint::abci::response::CheckTx {
+        tendermint::abci::response::CheckTx {
+            code: self.code.into(),
+            codespace: self.codespace.clone(),
+            log: self.log.clone().unwrap_or_else(|| error.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for CheckTxError {
+    fn default() -> Self {
+        Self::new(ERROR_RESPONSE_CODE)
+    }
+}
+
+/// An error that closed an ABCI connection, with context about where it happened.
+#[derive(Debug)]
+pub struct ConnectionError {
+    connection: u64,
+    kind: Option<crate::Category>,
+    method: Option<&'static str>,
+    height: Option<tendermint::block::Height>,
+    source: crate::BoxError,
+}
+
+impl ConnectionError {
+    pub(crate) fn new(
+        connection: u64,
+        kind: Option<crate::Category>,
+        method: Option<&'static str>,
+        height: Option<tendermint::block::Height>,
+        source: crate::BoxError,
+    ) -> Self {
+        Self {
+            connection,
+            kind,
+            method,
+            height,
+            source,
+        }
+    }
+
+    /// The id of the connection that failed.
+    pub fn connection(&self) -> u64 {
+        self.connection
+    }
+
+    /// The kind of the connection, if it was detected before it failed.
+    pub fn kind(&self) -> Option<crate::Category> {
+        self.kind
+    }
+
+    /// The method of the last request read from the connection, if any.
+    pub fn last_method(&self) -> Option<&'static str> {
+        self.method
+    }
+
+    /// The last block height seen in a request on the connection, if any.
+    pub fn last_height(&self) -> Option<tendermint::block::Height> {
+        self.height
+    }
+
+    /// The underlying error.
+    pub fn inner(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        &*self.source
+    }
+
+    /// Consumes the context, returning the underlying error.
+    pub fn into_inner(self) -> crate::BoxError {
+        self.source
+    }
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "connection {}", self.connection)?;
+        if let Some(kind) = self.kind {
+            write!(f, " ({})", kind.name())?;
+        }
+        write!(f, " failed")?;
+        if let Some(method) = self.method {
+            write!(f, " after {} request", method)?;
+        }
+        if let Some(height) = self.height {
+            write!(f, " at height {}", height)?;
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl std::error::Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
@@ -0,0 +1,327 @@
+//! Builders for ABCI events.
+//!
+//! Events are sets of key-value attributes, each flagged for indexing by the
+//! node. Since CometBFT 0.37, keys and values are strings; with 0.34 they are
+//! bytes, and the `Event`s of `tendermint` only keep, when encoded for 0.34,
+//! the attributes built as bytes. [`EventBuilder`] takes values of the usual
+//! types, and builds the event for either:
+//!
+//! ```
+//! # use tower_abci::event::EventBuilder;
+//! # let (sender, memo) = (String::from("alice"), "rent");
+//! let event = EventBuilder::new("transfer")
+//!     .attr("sender", &sender)
+//!     .attr("amount", 100u64)
+//!     .attr_unindexed("memo", memo.as_bytes())
+//!     .build();
+//! ```
+//!
+//! [`build`](EventBuilder::build) writes values that are not UTF-8 in hex,
+//! while [`build_v034`](EventBuilder::build_v034) keeps them as they are.
+//! [`EventLog`] collects the events of a transaction or of a block, and
+//! [`BlockEvents`] those of a block and of each of its transactions.
+
+use bytes::Bytes;
+use tendermint::{
+    abci::{self, v0_34, Event},
+    block, AppHash, Hash,
+};
+
+/// A value of an event attribute.
+pub trait AttributeValue {
+    /// The bytes of the value. Text values are UTF-8 encoded, and numbers
+    /// are written in decimal.
+    fn into_bytes(self) -> Vec<u8>;
+}
+
+impl AttributeValue for &str {
+    fn into_bytes(self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl AttributeValue for String {
+    fn into_bytes(self) -> Vec<u8> {
+        self.into_bytes()
+    }
+}
+
+impl AttributeValue for &String {
+    fn into_bytes(self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl AttributeValue for &[u8] {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> AttributeValue for [u8; N] {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl AttributeValue for Vec<u8> {
+    fn into_bytes(self) -> Vec<u8> {
+        self
+    }
+}
+
+impl AttributeValue for Bytes {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+/// Written in upper case hex, as CometBFT shows hashes.
+impl AttributeValue for Hash {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+/// Written in upper case hex, as CometBFT shows hashes.
+impl AttributeValue for AppHash {
+    fn into_bytes(self) -> Vec<u8> {
+        hex::encode_upper(self.as_bytes()).into_bytes()
+    }
+}
+
+macro_rules! impl_display_value {
+    ($($ty:ty),*) => {
+        $(
+            impl AttributeValue for $ty {
+                fn into_bytes(self) -> Vec<u8> {
+                    self.to_string().into_bytes()
+                }
+            }
+        )*
+    };
+}
+
+impl_display_value!(
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    block::Height
+);
+
+/// An attribute of an event, as bytes until the event is built.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Attribute {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    index: bool,
+}
+
+/// The text of `bytes`, or their hex if they are not UTF-8.
+fn text(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| hex::encode(e.into_bytes()))
+}
+
+/// Builds an [`Event`]. See the [module documentation](self) for details.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventBuilder {
+    kind: String,
+    attributes: Vec<Attribute>,
+}
+
+impl EventBuilder {
+    /// An event of type `kind`, without attributes.
+    pub fn new(kind: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Adds an attribute, indexed by the node.
+    pub fn attr(self, key: impl AsRef<str>, value: impl AttributeValue) -> Self {
+        self.attr_with_index(key, value, true)
+    }
+
+    /// Adds an attribute not indexed by the node.
+    pub fn attr_unindexed(self, key: impl AsRef<str>, value: impl AttributeValue) -> Self {
+        self.attr_with_index(key, value, false)
+    }
+
+    /// Adds an attribute, indexed by the node if `index` is set.
+    pub fn attr_with_index(
+        mut self,
+        key: impl AsRef<str>,
+        value: impl AttributeValue,
+        index: bool,
+    ) -> Self {
+        self.attributes.push(Attribute {
+            key: key.as_ref().as_bytes().to_vec(),
+            value: value.into_bytes(),
+            index,
+        });
+        self
+    }
+
+    /// Adds an attribute if `value` is set.
+    pub fn attr_opt(self, key: impl AsRef<str>, value: Option<impl AttributeValue>) -> Self {
+        match value {
+            Some(value) => self.attr(key, value),
+            None => self,
+        }
+    }
+
+    /// Sets whether the node indexes every attribute added so far.
+    pub fn index_all(mut self, index: bool) -> Self {
+        for attribute in &mut self.attributes {
+            attribute.index = index;
+        }
+        self
+    }
+
+    /// Builds the event for CometBFT 0.37 and later, with string attributes.
+    /// Values that are not UTF-8 are written in lower case hex.
+    pub fn build(self) -> Event {
+        Event::new(
+            self.kind,
+            self.attributes
+                .into_iter()
+                .map(|attribute| (text(attribute.key), text(attribute.value), attribute.index)),
+        )
+    }
+
+    /// Builds the event for CometBFT 0.34, with byte attributes.
+    pub fn build_v034(self) -> Event {
+        Event {
+            kind: self.kind,
+            attributes: self
+                .attributes
+                .into_iter()
+                .map(|attribute| {
+                    abci::EventAttribute::V034(v0_34::EventAttribute {
+                        key: attribute.key,
+                        value: attribute.value,
+                        index: attribute.index,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<EventBuilder> for Event {
+    fn from(builder: EventBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// The events emitted while executing a transaction or a block, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventLog {
+    events: Vec<EventBuilder>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emits `event`.
+    pub fn emit(&mut self, event: EventBuilder) {
+        self.events.push(event);
+    }
+
+    /// Emits the events of `other`, after those emitted so far.
+    pub fn append(&mut self, other: &mut EventLog) {
+        self.events.append(&mut other.events);
+    }
+
+    /// Drops the events emitted so far, e.g. because the transaction failed.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Builds the events for CometBFT 0.37 and later.
+    pub fn into_events(self) -> Vec<Event> {
+        self.events.into_iter().map(EventBuilder::build).collect()
+    }
+
+    /// Builds the events for CometBFT 0.34.
+    pub fn into_events_v034(self) -> Vec<Event> {
+        self.events
+            .into_iter()
+            .map(EventBuilder::build_v034)
+            .collect()
+    }
+}
+
+/// The events of a block: those of the block itself, and those of each of its
+/// transactions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockEvents {
+    block: EventLog,
+    txs: Vec<EventLog>,
+}
+
+impl BlockEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The events of the block itself, i.e. of `BeginBlock` and `EndBlock`,
+    /// or of `FinalizeBlock`.
+    pub fn block(&mut self) -> &mut EventLog {
+        &mut self.block
+    }
+
+    /// Starts the events of the next transaction of the block.
+    pub fn next_tx(&mut self) -> &mut EventLog {
+        self.txs.push(EventLog::new());
+        self.txs.last_mut().expect("just pushed")
+    }
+
+    /// The events of the transaction at `index`, if it was started.
+    pub fn tx(&mut self, index: usize) -> Option<&mut EventLog> {
+        self.txs.get_mut(index)
+    }
+
+    /// Builds the events of the block, and of each transaction in order, for
+    /// CometBFT 0.37 and later.
+    pub fn into_events(self) -> (Vec<Event>, Vec<Vec<Event>>) {
+        (
+            self.block.into_events(),
+            self.txs.into_iter().map(EventLog::into_events).collect(),
+        )
+    }
+
+    /// Builds the events of the block, and of each transaction in order, for
+    /// CometBFT 0.34.
+    pub fn into_events_v034(self) -> (Vec<Event>, Vec<Vec<Event>>) {
+        (
+            self.block.into_events_v034(),
+            self.txs
+                .into_iter()
+                .map(EventLog::into_events_v034)
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,432 @@
+//! A consensus service for applications that execute whole blocks.
+//!
+//! ABCI delivers a block as a sequence of requests: `BeginBlock`, one
+//! `DeliverTx` per transaction and `EndBlock` before 0.38, or a single
+//! `FinalizeBlock` since, followed by `Commit`. [`BlockExecutor`] assembles
+//! these requests into a [`Block`], and hands it to an [`ExecuteBlock`]
+//! implementation, e.g. a closure, so that the application executes each
+//! block in one function instead of keeping track of where it is in the
+//! sequence:
+//!
+//! ```ignore
+//! let consensus = BlockExecutor::new(move |block: Block| {
+//!     let tx_results = block.txs.iter().map(|tx| state.apply(tx)).collect();
+//!     Ok(response::FinalizeBlock {
+//!         tx_results,
+//!         app_hash: state.hash(),
+//!         events: vec![],
+//!         validator_updates: vec![],
+//!         consensus_param_updates: None,
+//!     })
+//! });
+//! let server = v038::Server::builder().consensus(consensus)
+//! ```
+//!
+//! The executor implements the consensus service of every supported protocol
+//! version. Before 0.38, the responses to `DeliverTx` requests resolve once
+//! the block is executed, when the `EndBlock` request arrives, so the server
+//! must not wait for them before calling the service with `EndBlock`: don't
+//! combine the executor with `serial_consensus` or a consensus pipeline depth
+//! on those versions.
+//!
+//! Proposals are prepared by taking the transactions of the mempool in order,
+//! up to the size limit, and all proposals are accepted, unless the
+//! [`ExecuteBlock`] implementation overrides this. Vote extensions are empty.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use bytes::Bytes;
+use tendermint::{
+    abci::{
+        request, response,
+        types::{CommitInfo, ExecTxResult, Misbehavior},
+    },
+    account, block, AppHash, Hash, Time,
+};
+use tokio::sync::oneshot;
+use tower::Service;
+
+use crate::BoxError;
+
+/// A block to execute.
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub height: block::Height,
+    pub time: Time,
+    pub hash: Hash,
+    pub proposer_address: account::Id,
+    pub txs: Vec<Bytes>,
+    /// The votes for the previous block.
+    pub last_commit: CommitInfo,
+    pub misbehavior: Vec<Misbehavior>,
+}
+
+/// An application that executes whole blocks.
+///
+/// It is implemented by closures taking a [`Block`], for applications that
+/// only need to execute blocks.
+pub trait ExecuteBlock {
+    /// Executes `block`, returning the result of each of its transactions,
+    /// in order, and the app hash of the resulting state.
+    fn execute_block(&mut self, block: Block) -> Result<response::FinalizeBlock, BoxError>;
+
+    /// Initializes the application from the genesis. By default, the
+    /// genesis validators and consensus parameters are kept, and the app
+    /// hash is empty.
+    fn init_chain(&mut self, request: request::InitChain) -> Result<response::InitChain, BoxError> {
+        let _ = request;
+        Ok(Default::default())
+    }
+
+    /// Persists the state resulting from the last executed block, returning
+    /// the height of the oldest block the node must keep. By default, all
+    /// blocks are kept.
+    fn commit(&mut self) -> Result<block::Height, BoxError> {
+        Ok(0u32.into())
+    }
+
+    /// Chooses the transactions of a block proposed by this node. By default,
+    /// these are the transactions of the mempool in order, up to the size
+    /// limit.
+    fn prepare_proposal(
+        &mut self,
+        request: request::PrepareProposal,
+    ) -> Result<response::PrepareProposal, BoxError> {
+        let mut size = 0;
+        let txs = request
+            .txs
+            .into_iter()
+            .take_while(|tx| {
+                size += tx.len() as i64;
+                size <= request.max_tx_bytes
+            })
+            .collect();
+        Ok(response::PrepareProposal { txs })
+    }
+
+    /// Decides whether to vote for a proposed block. By default, all
+    /// proposals are accepted.
+    fn process_proposal(
+        &mut self,
+        request: request::ProcessProposal,
+    ) -> Result<response::ProcessProposal, BoxError> {
+        let _ = request;
+        Ok(response::ProcessProposal::Accept)
+    }
+}
+
+impl<F> ExecuteBlock for F
+where
+    F: FnMut(Block) -> Result<response::FinalizeBlock, BoxError>,
+{
+    fn execute_block(&mut self, block: Block) -> Result<response::FinalizeBlock, BoxError> {
+        self(block)
+    }
+}
+
+/// A consensus service executing whole blocks with an [`ExecuteBlock`]
+/// implementation. See the [module documentation](self) for details.
+pub struct BlockExecutor<E> {
+    state: Arc<Mutex<State<E>>>,
+}
+
+// Implementing Clone manually avoids an (incorrect) derived E: Clone bound
+impl<E> Clone for BlockExecutor<E> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+struct State<E> {
+    executor: E,
+    /// The block being assembled from `BeginBlock` and `DeliverTx` requests.
+    block: Option<Block>,
+    /// The senders of the results of the transactions of `block`.
+    tx_results: Vec<oneshot::Sender<Result<ExecTxResult, BoxError>>>,
+    /// The app hash of the last executed block, or of the genesis state.
+    app_hash: AppHash,
+}
+
+impl<E: ExecuteBlock> BlockExecutor<E> {
+    pub fn new(executor: E) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                executor,
+                block: None,
+                tx_results: Vec::new(),
+                app_hash: AppHash::default(),
+            })),
+        }
+    }
+}
+
+impl<E: ExecuteBlock> State<E> {
+    fn init_chain(&mut self, request: request::InitChain) -> Result<response::InitChain, BoxError> {
+        let response = self.executor.init_chain(request)?;
+        self.app_hash = response.app_hash.clone();
+        Ok(response)
+    }
+
+    fn begin_block(&mut self, request: request::BeginBlock) -> response::BeginBlock {
+        // A block left unfinished is abandoned, along with the responses to
+        // its transactions.
+        self.tx_results.clear();
+        self.block = Some(Block {
+            height: request.header.height,
+            time: request.header.time,
+            hash: request.hash,
+            proposer_address: request.header.proposer_address,
+            txs: Vec::new(),
+            last_commit: request.last_commit_info,
+            misbehavior: request.byzantine_validators,
+        });
+        Default::default()
+    }
+
+    fn deliver_tx(
+        &mut self,
+        request: request::DeliverTx,
+    ) -> Result<oneshot::Receiver<Result<ExecTxResult, BoxError>>, BoxError> {
+        let block = self.block.as_mut().ok_or("DeliverTx outside of a block")?;
+        block.txs.push(request.tx);
+        let (sender, receiver) = oneshot::channel();
+        self.tx_results.push(sender);
+        Ok(receiver)
+    }
+
+    fn end_block(&mut self) -> Result<response::EndBlock, BoxError> {
+        let block = self.block.take().ok_or("EndBlock outside of a block")?;
+        let senders = std::mem::take(&mut self.tx_results);
+        let response = match self.execute(block) {
+            Ok(response) => response,
+            Err(e) => {
+                for sender in senders {
+                    let _ = sender.send(Err(format!("block execution failed: {}", e).into()));
+                }
+                return Err(e);
+            }
+        };
+        for (sender, tx_result) in senders.into_iter().zip(response.tx_results) {
+            // The receiver is gone if the connection closed.
+            let _ = sender.send(Ok(tx_result));
+        }
+        Ok(response::EndBlock {
+            validator_updates: response.validator_updates,
+            consensus_param_updates: response.consensus_param_updates,
+            events: response.events,
+        })
+    }
+
+    fn finalize_block(
+        &mut self,
+        request: request::FinalizeBlock,
+    ) -> Result<response::FinalizeBlock, BoxError> {
+        self.execute(Block {
+            height: request.height,
+            time: request.time,
+            hash: request.hash,
+            proposer_address: request.proposer_address,
+            txs: request.txs,
+            last_commit: request.decided_last_commit,
+            misbehavior: request.misbehavior,
+        })
+    }
+
+    fn execute(&mut self, block: Block) -> Result<response::FinalizeBlock, BoxError> {
+        let txs = block.txs.len();
+        let response = self.executor.execute_block(block)?;
+        if response.tx_results.len() != txs {
+            return Err(format!(
+                "block execution returned {} results for {} transactions",
+                response.tx_results.len(),
+                txs
+            )
+            .into());
+        }
+        self.app_hash = response.app_hash.clone();
+        Ok(response)
+    }
+
+    fn commit(&mut self) -> Result<response::Commit, BoxError> {
+        let retain_height = self.executor.commit()?;
+        Ok(response::Commit {
+            data: Bytes::copy_from_slice(self.app_hash.as_bytes()),
+            retain_height,
+        })
+    }
+}
+
+fn deliver_tx_response(result: ExecTxResult) -> response::DeliverTx {
+    response::DeliverTx {
+        code: result.code,
+        data: result.data,
+        log: result.log,
+        info: result.info,
+        gas_wanted: result.gas_wanted,
+        gas_used: result.gas_used,
+        events: result.events,
+        codespace: result.codespace,
+    }
+}
+
+/// The future of a response of a [`BlockExecutor`].
+pub struct ResponseFuture<R>(Inner<R>);
+
+enum Inner<R> {
+    Ready(Option<Result<R, BoxError>>),
+    /// The response to a `DeliverTx`, once its block is executed.
+    TxResult {
+        receiver: oneshot::Receiver<Result<ExecTxResult, BoxError>>,
+        response: fn(ExecTxResult) -> R,
+    },
+}
+
+impl<R> ResponseFuture<R> {
+    fn ready(response: Result<R, BoxError>) -> Self {
+        Self(Inner::Ready(Some(response)))
+    }
+
+    fn tx_result(
+        receiver: Result<oneshot::Receiver<Result<ExecTxResult, BoxError>>, BoxError>,
+        response: fn(ExecTxResult) -> R,
+    ) -> Self {
+        match receiver {
+            Ok(receiver) => Self(Inner::TxResult { receiver, response }),
+            Err(e) => Self::ready(Err(e)),
+        }
+    }
+}
+
+impl<R: Unpin> Future for ResponseFuture<R> {
+    type Output = Result<R, BoxError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.0 {
+            Inner::Ready(response) => {
+                Poll::Ready(response.take().expect("polled after completion"))
+            }
+            Inner::TxResult { receiver, response } => {
+                let result = ready!(Pin::new(receiver).poll(cx))
+                    .map_err(|_| "the block of the transaction was abandoned")??;
+                Poll::Ready(Ok(response(result)))
+            }
+        }
+    }
+}
+
+impl<E: ExecuteBlock> Service<tendermint::v0_34::abci::ConsensusRequest> for BlockExecutor<E> {
+    type Response = tendermint::v0_34::abci::ConsensusResponse;
+    type Error = BoxError;
+    type Future = ResponseFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tendermint::v0_34::abci::ConsensusRequest) -> Self::Future {
+        use tendermint::v0_34::abci::{ConsensusRequest as Request, ConsensusResponse as Response};
+
+        let mut state = self.state.lock().unwrap();
+        match req {
+            Request::InitChain(req) => {
+                ResponseFuture::ready(state.init_chain(req).map(Response::InitChain))
+            }
+            Request::BeginBlock(req) => {
+                ResponseFuture::ready(Ok(Response::BeginBlock(state.begin_block(req))))
+            }
+            Request::DeliverTx(req) => ResponseFuture::tx_result(state.deliver_tx(req), |result| {
+                Response::DeliverTx(deliver_tx_response(result))
+            }),
+            Request::EndBlock(_) => {
+                ResponseFuture::ready(state.end_block().map(Response::EndBlock))
+            }
+            Request::Commit => ResponseFuture::ready(state.commit().map(Response::Commit)),
+        }
+    }
+}
+
+impl<E: ExecuteBlock> Service<tendermint::v0_37::abci::ConsensusRequest> for BlockExecutor<E> {
+    type Response = tendermint::v0_37::abci::ConsensusResponse;
+    type Error = BoxError;
+    type Future = ResponseFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tendermint::v0_37::abci::ConsensusRequest) -> Self::Future {
+        use tendermint::v0_37::abci::{ConsensusRequest as Request, ConsensusResponse as Response};
+
+        let mut state = self.state.lock().unwrap();
+        match req {
+            Request::InitChain(req) => {
+                ResponseFuture::ready(state.init_chain(req).map(Response::InitChain))
+            }
+            Request::PrepareProposal(req) => ResponseFuture::ready(
+                state
+                    .executor
+                    .prepare_proposal(req)
+                    .map(Response::PrepareProposal),
+            ),
+            Request::ProcessProposal(req) => ResponseFuture::ready(
+                state
+                    .executor
+                    .process_proposal(req)
+                    .map(Response::ProcessProposal),
+            ),
+            Request::BeginBlock(req) => {
+                ResponseFuture::ready(Ok(Response::BeginBlock(state.begin_block(req))))
+            }
+            Request::DeliverTx(req) => ResponseFuture::tx_result(state.deliver_tx(req), |result| {
+                Response::DeliverTx(deliver_tx_response(result))
+            }),
+            Request::EndBlock(_) => {
+                ResponseFuture::ready(state.end_block().map(Response::EndBlock))
+            }
+            Request::Commit => ResponseFuture::ready(state.commit().map(Response::Commit)),
+        }
+    }
+}
+
+impl<E: ExecuteBlock> Service<tendermint::v0_38::abci::ConsensusRequest> for BlockExecutor<E> {
+    type Response = tendermint::v0_38::abci::ConsensusResponse;
+    type Error = BoxError;
+    type Future = ResponseFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tendermint::v0_38::abci::ConsensusRequest) -> Self::Future {
+        use tendermint::v0_38::abci::{ConsensusRequest as Request, ConsensusResponse as Response};
+
+        let mut state = self.state.lock().unwrap();
+        let response = match req {
+            Request::InitChain(req) => state.init_chain(req).map(Response::InitChain),
+            Request::PrepareProposal(req) => state
+                .executor
+                .prepare_proposal(req)
+                .map(Response::PrepareProposal),
+            Request::ProcessProposal(req) => state
+                .executor
+                .process_proposal(req)
+                .map(Response::ProcessProposal),
+            Request::ExtendVote(_) => Ok(Response::ExtendVote(response::ExtendVote {
+                vote_extension: Bytes::new(),
+            })),
+            Request::VerifyVoteExtension(_) => Ok(Response::VerifyVoteExtension(
+                response::VerifyVoteExtension::Accept,
+            )),
+            Request::FinalizeBlock(req) => state.finalize_block(req).map(Response::FinalizeBlock),
+            Request::Commit => state.commit().map(Response::Commit),
+        };
+        ResponseFuture::ready(response)
+    }
+}
@@ -0,0 +1,525 @@
+//! Inspection and control of a running server.
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{
+    connection::{ConnectionStatus, PendingRequest},
+    context::{self, ConnectionCallback, ConnectionContext, PeerAddr},
+    health::{self, HealthCheck, HealthReport},
+    middleware::filter::TxFilterLayer,
+    pipeline::InFlight,
+    Category, ConnectionOptions, Overload,
+};
+
+/// A handle for inspecting and controlling a running server, obtained with
+/// `Server::handle` before the server starts listening.
+///
+/// The handle can be cloned and used from any task, e.g., to dump the state of
+/// every connection when the node appears to be stuck on a `Commit`:
+///
+/// ```
+/// # use tower_abci::{apps::NoopApp, v038::Server};
+/// # #[cfg(feature = "net")]
+/// # async fn example() {
+/// # let server = Server::builder()
+/// #     .consensus(NoopApp)
+/// #     .mempool(NoopApp)
+/// #     .info(NoopApp)
+/// #     .snapshot(NoopApp)
+/// #     .finish()
+/// #     .unwrap();
+/// let handle = server.handle();
+/// tokio::spawn(server.listen_tcp("127.0.0.1:26658"));
+/// for conn in handle.connections() {
+///     println!("{conn:?}, oldest request: {:?}", conn.oldest());
+/// }
+/// # }
+/// ```
+///
+/// The same information and controls are available to operators over a local
+/// socket with [`admin::listen_unix`](crate::admin::listen_unix).
+#[derive(Clone, Debug)]
+pub struct ServerHandle {
+    inner: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    protocol: &'static str,
+    started: Instant,
+    connections: Mutex<BTreeMap<u64, Tracked>>,
+    draining: watch::Sender<bool>,
+    listening: watch::Sender<bool>,
+    state: watch::Sender<ServerState>,
+    options: watch::Sender<ConnectionOptions>,
+    /// The number of requests pending on every connection.
+    load: Arc<watch::Sender<usize>>,
+    health: HealthCheck,
+    /// The transaction filter managed through the handle, if any.
+    tx_filter: Mutex<Option<TxFilterLayer>>,
+    /// The threshold of the slow-request warnings managed through the
+    /// handle, if any.
+    slow_request_threshold: Mutex<Option<watch::Sender<Duration>>>,
+}
+
+/// The stage of its lifecycle a server is in, as published by
+/// [`ServerHandle::state`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerState {
+    /// The server was built, but isn't accepting connections yet.
+    Starting,
+    /// The server is accepting connections on `addr`.
+    Listening { addr: LocalAddr },
+    /// [`drain`](ServerHandle::drain) was called, and the listener or some
+    /// connections are still open.
+    Draining,
+    /// The server stopped, and won't accept connections again.
+    Stopped { reason: StopReason },
+}
+
+/// The address a server is listening on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LocalAddr {
+    Tcp(SocketAddr),
+    /// The path of the socket, unless it is unnamed.
+    Unix(Option<PathBuf>),
+}
+
+/// Why a server stopped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The server was drained, and its last connection closed.
+    Drained,
+    /// The server failed to listen.
+    Error(String),
+}
+
+#[derive(Debug)]
+struct Tracked {
+    accepted: Instant,
+    kind: Arc<OnceLock<Category>>,
+    in_flight: InFlight,
+    close: watch::Sender<bool>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        protocol: &'static str,
+        options: ConnectionOptions,
+        health: HealthCheck,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Shared {
+                protocol,
+                started: Instant::now(),
+                connections: Mutex::default(),
+                draining: watch::channel(false).0,
+                listening: watch::channel(false).0,
+                state: watch::channel(ServerState::Starting).0,
+                options: watch::channel(options).0,
+                load: Arc::new(watch::channel(0).0),
+                health,
+                tx_filter: Mutex::new(None),
+                slow_request_threshold: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// The ABCI protocol version spoken by the server, e.g. `"0.38"`.
+    pub fn protocol_version(&self) -> &'static str {
+        self.inner.protocol
+    }
+
+    /// How long ago the server was built.
+    pub fn uptime(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    /// The settings currently applied to every connection.
+    pub fn options(&self) -> ConnectionOptions {
+        self.inner.options.borrow().clone()
+    }
+
+    /// Changes the settings of every connection, including ones opened later,
+    /// without restarting the server.
+    ///
+    /// Open connections apply the new settings to the requests they read
+    /// after the change; requests already in flight are unaffected.
+    pub fn update_options(&self, update: impl FnOnce(&mut ConnectionOptions)) {
+        self.inner.options.send_modify(update);
+        tracing::info!(options = ?*self.inner.options.borrow(), "connection options updated");
+    }
+
+    /// Lets the rules of `filter`, applied to the server's services, be
+    /// changed through the handle, e.g. by the admin socket or a
+    /// configuration reload. Replaces the filter set before, if any.
+    pub fn set_tx_filter(&self, filter: TxFilterLayer) {
+        *self.inner.tx_filter.lock().unwrap() = Some(filter);
+    }
+
+    /// The transaction filter set with [`set_tx_filter`](Self::set_tx_filter).
+    pub fn tx_filter(&self) -> Option<TxFilterLayer> {
+        self.inner.tx_filter.lock().unwrap().clone()
+    }
+
+    /// Lets the threshold of a
+    /// [`SlowRequestLayer::adjustable`](crate::middleware::slow::SlowRequestLayer::adjustable),
+    /// applied to the server's services, be changed through the handle, with
+    /// [`set_slow_request_threshold`](Self::set_slow_request_threshold).
+    /// Replaces the threshold set before, if any.
+    pub fn manage_slow_request_threshold(&self, threshold: watch::Sender<Duration>) {
+        *self.inner.slow_request_threshold.lock().unwrap() = Some(threshold);
+    }
+
+    /// Changes the threshold given to
+    /// [`manage_slow_request_threshold`](Self::manage_slow_request_threshold),
+    /// for the calls starting from now on. Returns `false` if there is none.
+    pub fn set_slow_request_threshold(&self, threshold: Duration) -> bool {
+        match &*self.inner.slow_request_threshold.lock().unwrap() {
+            Some(sender) => {
+                sender.send_replace(threshold);
+                tracing::info!(?threshold, "slow request threshold updated");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The threshold given to
+    /// [`manage_slow_request_threshold`](Self::manage_slow_request_threshold),
+    /// if any.
+    pub fn slow_request_threshold(&self) -> Option<Duration> {
+        let threshold = self.inner.slow_request_threshold.lock().unwrap();
+        threshold.as_ref().map(|sender| *sender.borrow())
+    }
+
+    /// The status of every open connection, ordered by connection id.
+    pub fn connections(&self) -> Vec<ConnectionStatus> {
+        let connections = self.inner.connections.lock().unwrap();
+        connections
+            .iter()
+            .map(|(id, tracked)| tracked.status(*id))
+            .collect()
+    }
+
+    /// The status of the connection with the given id, if it is open.
+    pub fn connection(&self, id: u64) -> Option<ConnectionStatus> {
+        let connections = self.inner.connections.lock().unwrap();
+        connections.get(&id).map(|tracked| tracked.status(id))
+    }
+
+    /// The number of requests pending on every connection.
+    pub fn in_flight(&self) -> usize {
+        *self.inner.load.borrow()
+    }
+
+    /// Resolves once the server is overloaded by the standard of `overload`,
+    /// or never if it is `None`.
+    #[cfg(feature = "net")]
+    pub(crate) async fn overloaded(&self, overload: Option<Overload>) {
+        let Some(overload) = overload else {
+            return futures::future::pending().await;
+        };
+        let mut load = self.inner.load.subscribe();
+        // The sender lives as long as `self`, so this can't fail.
+        let _ = load.wait_for(|load| *load >= overload.max_in_flight).await;
+    }
+
+    /// Resolves once the server isn't overloaded by the standard of
+    /// `overload`: at once if fewer than its `max_in_flight` requests are
+    /// pending, and otherwise once fewer than its `resume_below` are. `paused`
+    /// says what waits, for the log.
+    pub(crate) async fn unloaded(&self, overload: Option<Overload>, paused: &'static str) {
+        let Some(overload) = overload else {
+            return;
+        };
+        let mut load = self.inner.load.subscribe();
+        let in_flight = *load.borrow_and_update();
+        if in_flight < overload.max_in_flight {
+            return;
+        }
+        tracing::warn!(in_flight, ?overload, paused, "server overloaded");
+        let resume_below = overload.resume_below.min(overload.max_in_flight);
+        // The sender lives as long as `self`, so this can't fail.
+        let _ = load.wait_for(|load| *load < resume_below).await;
+        tracing::info!(paused, "server no longer overloaded");
+    }
+
+    /// Sends synthetic requests through each component service, reporting
+    /// their status and latency; see the [`health`] module.
+    /// Each check times out after [`health::DEFAULT_TIMEOUT`].
+    pub async fn health_check(&self) -> HealthReport {
+        self.health_check_within(health::DEFAULT_TIMEOUT).await
+    }
+
+    /// Like [`health_check`](Self::health_check), with each check timing out
+    /// after `timeout`.
+    pub async fn health_check_within(&self, timeout: Duration) -> HealthReport {
+        self.inner.health.run(timeout).await
+    }
+
+    /// Asks the connection with the given id to close, returning `false` if
+    /// there is no such connection.
+    ///
+    /// The connection stops reading requests, sends the responses that are
+    /// still pending, and then closes. The node will usually reconnect.
+    pub fn close_connection(&self, id: u64) -> bool {
+        let connections = self.inner.connections.lock().unwrap();
+        match connections.get(&id) {
+            Some(tracked) => {
+                tracked.close.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops the server from accepting new connections, and asks every open
+    /// connection to close as with [`close_connection`](Self::close_connection).
+    ///
+    /// The server's `listen_*` method returns once it stops accepting
+    /// connections; [`connections`](Self::connections) is empty once all of
+    /// them have closed.
+    pub fn drain(&self) {
+        tracing::info!("draining server");
+        self.inner.draining.send_replace(true);
+        self.inner.state.send_if_modified(|state| match state {
+            ServerState::Stopped { .. } => false,
+            _ => {
+                *state = ServerState::Draining;
+                true
+            }
+        });
+        {
+            let connections = self.inner.connections.lock().unwrap();
+            for tracked in connections.values() {
+                tracked.close.send_replace(true);
+            }
+        }
+        self.stop_if_drained();
+    }
+
+    /// Returns `true` if [`drain`](Self::drain) was called.
+    pub fn is_draining(&self) -> bool {
+        *self.inner.draining.borrow()
+    }
+
+    /// Returns `true` while the server's `listen_*` method has its listener
+    /// bound and is accepting connections.
+    pub fn is_listening(&self) -> bool {
+        *self.inner.listening.borrow()
+    }
+
+    /// Subscribes to the lifecycle of the server, so that other components,
+    /// e.g. an RPC server or an orchestrator, can wait for it to listen or to
+    /// stop:
+    ///
+    /// ```
+    /// # use tower_abci::{apps::NoopApp, v038::Server, BoxError, ServerState};
+    /// # #[cfg(feature = "net")]
+    /// # async fn example() -> Result<(), BoxError> {
+    /// # let server = Server::builder()
+    /// #     .consensus(NoopApp)
+    /// #     .mempool(NoopApp)
+    /// #     .info(NoopApp)
+    /// #     .snapshot(NoopApp)
+    /// #     .finish()
+    /// #     .unwrap();
+    /// # let handle = server.handle();
+    /// let mut state = handle.state();
+    /// tokio::spawn(server.listen_tcp("127.0.0.1:26658"));
+    /// let state = state
+    ///     .wait_for(|state| !matches!(state, ServerState::Starting))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn state(&self) -> watch::Receiver<ServerState> {
+        self.inner.state.subscribe()
+    }
+
+    /// Records that the server is accepting connections on `addr`.
+    #[cfg(feature = "net")]
+    pub(crate) fn set_listening(&self, addr: LocalAddr) {
+        self.inner.listening.send_replace(true);
+        self.inner.state.send_if_modified(|state| match state {
+            ServerState::Starting | ServerState::Listening { .. } => {
+                *state = ServerState::Listening { addr };
+                true
+            }
+            _ => false,
+        });
+    }
+
+    /// Records that the server stopped accepting connections after being
+    /// drained.
+    #[cfg(feature = "net")]
+    pub(crate) fn stop_listening(&self) {
+        self.inner.listening.send_replace(false);
+        self.stop_if_drained();
+    }
+
+    /// Records that the server failed to listen, if `result` is an error.
+    #[cfg(feature = "net")]
+    pub(crate) fn fail_on_error<T>(&self, result: std::io::Result<T>) -> std::io::Result<T> {
+        if let Err(e) = &result {
+            self.inner.state.send_replace(ServerState::Stopped {
+                reason: StopReason::Error(e.to_string()),
+            });
+        }
+        result
+    }
+
+    /// Moves the server to [`ServerState::Stopped`] once it was drained, its
+    /// listener stopped and its last connection closed.
+    fn stop_if_drained(&self) {
+        if !self.is_draining() || self.is_listening() {
+            return;
+        }
+        if !self.inner.connections.lock().unwrap().is_empty() {
+            return;
+        }
+        self.inner.state.send_if_modified(|state| match state {
+            ServerState::Stopped { .. } => false,
+            _ => {
+                *state = ServerState::Stopped {
+                    reason: StopReason::Drained,
+                };
+                true
+            }
+        });
+    }
+
+    /// Resolves once [`drain`](Self::drain) is called.
+    #[cfg(feature = "net")]
+    pub(crate) async fn drained(&self) {
+        let mut draining = self.inner.draining.subscribe();
+        // The sender lives as long as `self`, so this can't fail.
+        let _ = draining.wait_for(|draining| *draining).await;
+    }
+
+    /// Starts tracking a new connection, and makes its context current for
+    /// its requests, until the returned registration is dropped.
+    pub(crate) fn register(
+        &self,
+        id: u64,
+        peer: Option<PeerAddr>,
+        on_connection: Option<&ConnectionCallback>,
+    ) -> Registration {
+        let in_flight = InFlight::new(self.inner.load.clone());
+        let kind = Arc::new(OnceLock::new());
+        let context = ConnectionContext::register(id, peer, kind.clone(), on_connection);
+        let (close, closing) = watch::channel(self.is_draining());
+        self.inner.connections.lock().unwrap().insert(
+            id,
+            Tracked {
+                accepted: Instant::now(),
+                kind: kind.clone(),
+                in_flight: in_flight.clone(),
+                close,
+            },
+        );
+        Registration {
+            handle: self.clone(),
+            id,
+            kind,
+            in_flight,
+            closing,
+            options: self.inner.options.subscribe(),
+            _context: context,
+        }
+    }
+}
+
+impl Tracked {
+    fn status(&self, id: u64) -> ConnectionStatus {
+        let now = Instant::now();
+        ConnectionStatus {
+            id,
+            kind: self.kind.get().copied(),
+            age: now - self.accepted,
+            closing: *self.close.borrow(),
+            in_flight: self
+                .in_flight
+                .requests()
+                .into_iter()
+                .map(|pending| PendingRequest {
+                    id: pending.id,
+                    category: pending.category,
+                    method: pending.method,
+                    height: pending.height,
+                    pending: now.saturating_duration_since(pending.received),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Keeps a connection listed by its [`ServerHandle`] while it is alive.
+pub(crate) struct Registration {
+    handle: ServerHandle,
+    id: u64,
+    kind: Arc<OnceLock<Category>>,
+    in_flight: InFlight,
+    closing: watch::Receiver<bool>,
+    options: watch::Receiver<ConnectionOptions>,
+    _context: context::Registered,
+}
+
+impl Registration {
+    /// Reports the kind of the connection, once detected.
+    pub(crate) fn set_kind(&self, kind: Category) {
+        let _ = self.kind.set(kind);
+    }
+
+    /// The connection's queue of pending requests, as seen by the handle.
+    pub(crate) fn in_flight(&self) -> InFlight {
+        self.in_flight.clone()
+    }
+
+    /// The connection's settings, updated by the handle.
+    pub(crate) fn options(&self) -> watch::Receiver<ConnectionOptions> {
+        self.options.clone()
+    }
+
+    /// A signal that the connection was asked to close.
+    pub(crate) fn close_signal(&self) -> CloseSignal {
+        CloseSignal(self.closing.clone())
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.handle
+            .inner
+            .connections
+            .lock()
+            .unwrap()
+            .remove(&self.id);
+        self.handle.stop_if_drained();
+    }
+}
+
+/// Tells a connection that it was asked to close.
+pub(crate) struct CloseSignal(watch::Receiver<bool>);
+
+impl CloseSignal {
+    /// Resolves once the connection is asked to close.
+    pub(crate) async fn requested(&mut self) {
+        // If the sender is gone, the connection is no longer tracked, and
+        // there is nobody left to ask it to close.
+        if self.0.wait_for(|close| *close).await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Helpers for the ABCI handshake performed when a node (re)connects.
+//!
+//! On startup, the node sends an `Info` request and compares the height and
+//! app hash reported by the application with its own block store and state.
+//! If the application is behind, the node replays the missing blocks to it;
+//! if it is ahead, or the hashes disagree, the node refuses to start.
+//!
+//! Applications report their state through the [`AppState`] trait, and can
+//! use [`info`] to build the `Info` response and [`replay`] to predict, before
+//! the node does, what it is going to do with that response. Given the node's
+//! state (e.g. read from its data directory or from its RPC endpoint), this
+//! turns a failed handshake into a [`HandshakeError`] that explains what is
+//! inconsistent, instead of a crash on the node side.
+
+use std::fmt;
+
+use tendermint::{abci::response, block, AppHash};
+
+/// The state an application has persisted, as reported in the handshake.
+pub trait AppState {
+    /// The height of the last block committed by the application, or zero if
+    /// it has not committed any block.
+    fn last_block_height(&self) -> block::Height;
+
+    /// The app hash returned by the last `Commit`.
+    fn last_block_app_hash(&self) -> AppHash;
+
+    /// The height and app hash of the last commit, read together so that a
+    /// commit in between cannot pair the height of one block with the app
+    /// hash of another.
+    fn last_block(&self) -> (block::Height, AppHash) {
+        (self.last_block_height(), self.last_block_app_hash())
+    }
+}
+
+/// Builds the `Info` response reporting the application's state to the node.
+pub fn info(
+    state: &impl AppState,
+    data: impl Into<String>,
+    version: impl Into<String>,
+    app_version: u64,
+) -> response::Info {
+    let (last_block_height, last_block_app_hash) = state.last_block();
+    response::Info {
+        data: data.into(),
+        version: version.into(),
+        app_version,
+        last_block_height,
+        last_block_app_hash,
+    }
+}
+
+/// What the node expects of the application when it connects.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeState {
+    /// The height of the last block in the node's block store.
+    pub store_height: block::Height,
+    /// The height of the last block the node's state was updated with.
+    pub state_height: block::Height,
+    /// The app hash the node's state expects after `state_height`.
+    pub app_hash: AppHash,
+}
+
+/// What the node will do after a successful handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Replay {
+    /// Whether the node will call `InitChain`, because the application has
+    /// not committed any block.
+    pub init_chain: bool,
+    /// The first and last heights of the blocks the node will replay to the
+    /// application, if it is behind the block store.
+    pub blocks: Option<(block::Height, block::Height)>,
+}
+
+impl Replay {
+    /// Returns `true` if the application is up to date with the node.
+    pub fn is_up_to_date(&self) -> bool {
+        !self.init_chain && self.blocks.is_none()
+    }
+}
+
+/// Computes how the node will bring the application up to date, following the
+/// same rules as the node's handshake.
+///
+/// Returns an error if the handshake will fail, in which case the node will
+/// not start until the application's or the node's state is repaired.
+pub fn replay(app: &impl AppState, node: &NodeState) -> Result<Replay, HandshakeError> {
+    let app_height = app.last_block_height();
+    let store = node.store_height.value();
+    let state = node.state_height.value();
+
+    if app_height.value() > store {
+        return Err(HandshakeError::AppAhead {
+            app: app_height,
+            store: node.store_height,
+        });
+    }
+    if store < state {
+        return Err(HandshakeError::StoreBehindState {
+            store: node.store_height,
+            state: node.state_height,
+        });
+    }
+    if store > state + 1 {
+        return Err(HandshakeError::StoreAheadOfState {
+            store: node.store_height,
+            state: node.state_height,
+        });
+    }
+
+    // The node only knows the app hash for its state height, so only then can
+    // a divergent application be detected before replaying any block.
+    let app_hash = app.last_block_app_hash();
+    if app_height.value() != 0 && app_height.value() == state && app_hash != node.app_hash {
+        return Err(HandshakeError::AppHashMismatch {
+            height: app_height,
+            expected: node.app_hash.clone(),
+            actual: app_hash,
+        });
+    }
+
+    Ok(Replay {
+        init_chain: app_height.value() == 0,
+        blocks: (app_height.value() < store).then(|| (app_height.increment(), node.store_height)),
+    })
+}
+
+/// The reason a handshake will fail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// The application has committed blocks the node does not have, e.g.
+    /// because the node's data was restored from an older backup.
+    AppAhead {
+        app: block::Height,
+        store: block::Height,
+    },
+    /// The node's block store is behind its state, which the node cannot
+    /// recover from on its own.
+    StoreBehindState {
+        store: block::Height,
+        state: block::Height,
+    },
+    /// The node's block store is more than one block ahead of its state,
+    /// which the node cannot recover from on its own.
+    StoreAheadOfState {
+        store: block::Height,
+        state: block::Height,
+    },
+    /// The application's app hash differs from the one the node expects at
+    /// the same height, i.e., the application's state has diverged.
+    AppHashMismatch {
+        height: block::Height,
+        expected: AppHash,
+        actual: AppHash,
+    },
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::AppAhead { app, store } => write!(
+                f,
+                "app is at height {app}, ahead of the node's block store at height {store}"
+            ),
+            HandshakeError::StoreBehindState { store, state } => write!(
+                f,
+                "node's block store at height {store} is behind its state at height {state}"
+            ),
+            HandshakeError::StoreAheadOfState { store, state } => write!(
+                f,
+                "node's block store at height {store} is more than one block ahead of its \
+                 state at height {state}"
+            ),
+            HandshakeError::AppHashMismatch {
+                height,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "app hash mismatch at height {height}: node expects {expected}, app has {actual}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl AppState for response::Info {
+    fn last_block_height(&self) -> block::Height {
+        self.last_block_height
+    }
+
+    fn last_block_app_hash(&self) -> AppHash {
+        self.last_block_app_hash.clone()
+    }
+}
+
+impl<T: AppState + ?Sized> AppState for std::sync::Arc<T> {
+    fn last_block_height(&self) -> block::Height {
+        (**self).last_block_height()
+    }
+
+    fn last_block_app_hash(&self) -> AppHash {
+        (**self).last_block_app_hash()
+    }
+
+    fn last_block(&self) -> (block::Height, AppHash) {
+        (**self).last_block()
+    }
+}
+
+/// Reads the state shared with the consensus service that commits it.
+impl<T: AppState> AppState for std::sync::Mutex<T> {
+    fn last_block_height(&self) -> block::Height {
+        self.lock().unwrap().last_block_height()
+    }
+
+    fn last_block_app_hash(&self) -> AppHash {
+        self.lock().unwrap().last_block_app_hash()
+    }
+
+    fn last_block(&self) -> (block::Height, AppHash) {
+        self.lock().unwrap().last_block()
+    }
+}
+
+/// Reads the state shared with the consensus service that commits it.
+impl<T: AppState> AppState for std::sync::RwLock<T> {
+    fn last_block_height(&self) -> block::Height {
+        self.read().unwrap().last_block_height()
+    }
+
+    fn last_block_app_hash(&self) -> AppHash {
+        self.read().unwrap().last_block_app_hash()
+    }
+
+    fn last_block(&self) -> (block::Height, AppHash) {
+        self.read().unwrap().last_block()
+    }
+}
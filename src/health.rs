@@ -0,0 +1,165 @@
+//! Self-checks of a server's component services.
+//!
+//! `Server::health_check` and
+//! [`ServerHandle::health_check`](crate::ServerHandle::health_check) send
+//! synthetic requests through each component service and report, for each of
+//! them, whether it answered and how long it took:
+//!
+//! - the info service is sent an `Echo` and an `Info` request;
+//! - the snapshot service is sent a `ListSnapshots` request;
+//! - the consensus and mempool services, which have no request without side
+//!   effects, are only checked to become ready.
+//!
+//! The report can validate the application at startup, before the server
+//! starts listening:
+//!
+//! ```
+//! # use tower_abci::{apps::NoopApp, v038::Server, BoxError};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), BoxError> {
+//! # let server = Server::builder()
+//! #     .consensus(NoopApp)
+//! #     .mempool(NoopApp)
+//! #     .info(NoopApp)
+//! #     .snapshot(NoopApp)
+//! #     .finish()
+//! #     .unwrap();
+//! let report = server.health_check().await;
+//! if !report.is_healthy() {
+//!     return Err(format!("application is unhealthy:\n{report}").into());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! It is also served by the `health` command of the [admin
+//! socket](crate::admin) and the `/health` endpoint of the
+//! [probe](crate::probe).
+
+use std::{
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+
+use crate::{BoxError, Category};
+
+/// How long each check may take, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The message of the synthetic `Echo` requests.
+pub(crate) const ECHO_MESSAGE: &str = "tower-abci health check";
+
+/// The outcome of a check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HealthStatus {
+    Healthy,
+    /// The service returned an error, or an unexpected response.
+    Failed(String),
+    /// The service did not answer within the timeout.
+    TimedOut,
+}
+
+/// The outcome of one check of a component service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComponentHealth {
+    pub component: Category,
+    /// What was checked, e.g. `"echo"` or `"ready"`.
+    pub check: &'static str,
+    pub status: HealthStatus,
+    /// How long the check took, up to the timeout.
+    pub latency: Duration,
+}
+
+impl ComponentHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.status == HealthStatus::Healthy
+    }
+}
+
+/// The outcome of the checks of every component service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthReport {
+    pub components: Vec<ComponentHealth>,
+}
+
+impl HealthReport {
+    /// Returns `true` if every check succeeded.
+    pub fn is_healthy(&self) -> bool {
+        self.components.iter().all(ComponentHealth::is_healthy)
+    }
+}
+
+/// One line per check, e.g.
+/// `info check=echo latency_secs=0.000 status=ok`.
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for component in &self.components {
+            write!(
+                f,
+                "{} check={} latency_secs={:.3} status=",
+                component.component.name(),
+                component.check,
+                component.latency.as_secs_f64(),
+            )?;
+            match &component.status {
+                HealthStatus::Healthy => writeln!(f, "ok")?,
+                HealthStatus::Failed(error) => writeln!(f, "failed error={error:?}")?,
+                HealthStatus::TimedOut => writeln!(f, "timed_out")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the checks of a server, with the given timeout.
+#[derive(Clone)]
+pub(crate) struct HealthCheck(
+    Arc<dyn Fn(Duration) -> BoxFuture<'static, HealthReport> + Send + Sync>,
+);
+
+impl HealthCheck {
+    pub(crate) fn new<F>(check: F) -> Self
+    where
+        F: Fn(Duration) -> BoxFuture<'static, HealthReport> + Send + Sync + 'static,
+    {
+        Self(Arc::new(check))
+    }
+
+    pub(crate) fn run(&self, timeout: Duration) -> BoxFuture<'static, HealthReport> {
+        (self.0)(timeout)
+    }
+}
+
+impl fmt::Debug for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HealthCheck").finish_non_exhaustive()
+    }
+}
+
+/// Runs the `check` of `component`, timing it.
+pub(crate) async fn timed<F>(
+    component: Category,
+    check: &'static str,
+    timeout: Duration,
+    future: F,
+) -> ComponentHealth
+where
+    F: Future<Output = Result<(), BoxError>>,
+{
+    let start = Instant::now();
+    let status = match tokio::time::timeout(timeout, future).await {
+        Ok(Ok(())) => HealthStatus::Healthy,
+        Ok(Err(e)) => HealthStatus::Failed(e.to_string()),
+        Err(_) => HealthStatus::TimedOut,
+    };
+    ComponentHealth {
+        component,
+        check,
+        status,
+        latency: start.elapsed(),
+    }
+}
@@ -0,0 +1,136 @@
+//! A stock info service reporting the committed state of the application.
+//!
+//! Every application answers `Info` with the height and app hash of its last
+//! commit, and the node checks them in the handshake. [`InfoService`] reads
+//! them from the same [`AppState`] the consensus service updates as it
+//! commits, so the handshake always sees what was actually committed:
+//!
+//! ```ignore
+//! let state = Arc::new(RwLock::new(MyState::load()?));
+//! let info = InfoService::new(state.clone())
+//!     .version(env!("CARGO_PKG_VERSION"))
+//!     .app_version(1);
+//! let server = v038::Server::builder()
+//!     .consensus(MyConsensus::new(state))
+//!     .info(QueryRouter::new(info).route("/store", store))
+//! ```
+//!
+//! It answers `Echo` with the message of the request, and `SetOption` with
+//! success. It serves no queries, answering them as
+//! [`QueryRouter`](crate::query::QueryRouter) answers queries whose path has
+//! no route, so that a router can add them.
+
+use std::task::{Context, Poll};
+
+use futures::future::{ready, Ready};
+use tendermint::abci::{request, response};
+use tower::Service;
+
+use crate::{
+    handshake::{self, AppState},
+    query, BoxError,
+};
+
+/// An info service answering `Info` from an [`AppState`]. See the [module
+/// documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct InfoService<A> {
+    state: A,
+    data: String,
+    version: String,
+    app_version: u64,
+}
+
+impl<A: AppState> InfoService<A> {
+    /// Reports the height and app hash of `state`, with empty data and
+    /// versions.
+    pub fn new(state: A) -> Self {
+        Self {
+            state,
+            data: String::new(),
+            version: String::new(),
+            app_version: 0,
+        }
+    }
+
+    /// Reports `data`, some arbitrary information about the application.
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = data.into();
+        self
+    }
+
+    /// Reports `version`, the semantic version of the application software.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Reports `app_version`, the version of the application protocol, which
+    /// the node checks against the one of the current block.
+    pub fn app_version(mut self, app_version: u64) -> Self {
+        self.app_version = app_version;
+        self
+    }
+
+    fn info(&self) -> response::Info {
+        handshake::info(
+            &self.state,
+            self.data.clone(),
+            self.version.clone(),
+            self.app_version,
+        )
+    }
+}
+
+impl<A: AppState> Service<request::Info> for InfoService<A> {
+    type Response = response::Info;
+    type Error = BoxError;
+    type Future = Ready<Result<response::Info, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: request::Info) -> Self::Future {
+        ready(Ok(self.info()))
+    }
+}
+
+macro_rules! impl_info_service {
+    ($version:ident $(, $pattern:pat => $response:expr)*) => {
+        impl<A: AppState> Service<tendermint::$version::abci::InfoRequest> for InfoService<A> {
+            type Response = tendermint::$version::abci::InfoResponse;
+            type Error = BoxError;
+            type Future = Ready<Result<Self::Response, BoxError>>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::InfoRequest) -> Self::Future {
+                use tendermint::$version::abci::{InfoRequest, InfoResponse};
+
+                let response = match req {
+                    InfoRequest::Info(_) => InfoResponse::Info(self.info()),
+                    InfoRequest::Query(query) => InfoResponse::Query(query::unknown_path(query)),
+                    InfoRequest::Echo(echo) => InfoResponse::Echo(response::Echo {
+                        message: echo.message,
+                    }),
+                    $($pattern => $response,)*
+                };
+                ready(Ok(response))
+            }
+        }
+    };
+}
+
+impl_info_service!(
+    v0_34,
+    InfoRequest::SetOption(_) => InfoResponse::SetOption(response::SetOption {
+        code: Default::default(),
+        log: String::new(),
+        info: String::new(),
+    })
+);
+impl_info_service!(v0_37);
+impl_info_service!(v0_38);
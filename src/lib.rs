@@ -1,30 +1,134 @@
 #![doc = include_str!("../README.md")]
 /// A fork of tower::buffer @ `e1760d38` that has four queues feeding
 /// the same worker task, with different priorities.
+#[cfg(feature = "split")]
 mod buffer4;
 
+#[cfg(all(target_family = "unix", feature = "net"))]
+pub mod admin;
+pub mod apps;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod connection;
+pub mod context;
+#[cfg(feature = "serde")]
+pub mod encoding;
+pub mod error;
+pub mod event;
+pub mod executor;
+pub mod handle;
+pub mod handshake;
+pub mod health;
+pub mod info;
+#[cfg(feature = "net")]
+pub mod lint;
+pub mod message;
+pub mod metrics;
+pub mod middleware;
+#[cfg(not(feature = "tracing"))]
+mod no_tracing;
+pub mod options;
+mod pipeline;
+pub mod probe;
+pub mod proof;
+pub mod query;
+pub mod redact;
+pub mod reply;
+pub mod request_id;
+pub mod set;
+pub mod snapshot;
+pub mod summary;
+mod task;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub use connection::{ConnectionStatus, InterruptedBlock, PendingRequest};
+pub use error::{CheckTxError, ConnectionError, ErrorPolicy};
+pub use handle::{ServerHandle, ServerState};
+pub use message::{BlockHeight, RequestExt, ResponseExt};
+#[cfg(not(feature = "tracing"))]
+pub use no_tracing::Level;
+pub use options::{BufferSizes, ConnectionOptions, Overload, PipelineDepth, StallDetection};
+pub use pipeline::Category;
+pub use redact::Redacted;
+pub use request_id::RequestId;
+#[cfg(feature = "tracing")]
+pub use tracing::Level;
+
 // #[cfg(feature = "v034")]
 pub mod v034 {
-    mod codec;
+    #[cfg(feature = "client")]
+    pub mod client;
+    // Public for the benchmarks, not part of the API.
+    #[doc(hidden)]
+    pub mod codec;
+    #[cfg(feature = "testing")]
+    pub mod conformance;
+    #[cfg(feature = "net")]
+    mod lint;
+    #[cfg(feature = "net")]
+    mod proto;
     mod server;
+    #[cfg(feature = "split")]
     pub mod split;
+    #[cfg(feature = "testing")]
+    pub mod testing;
+    #[cfg(feature = "net")]
+    pub use lint::LintServer;
+    #[cfg(feature = "net")]
+    pub use proto::{ProtoServer, ProtoServerBuilder};
     pub use server::Server;
     pub use server::ServerBuilder;
 }
 
 // #[cfg(feature = "v037")]
 pub mod v037 {
-    mod codec;
+    #[cfg(feature = "client")]
+    pub mod client;
+    // Public for the benchmarks, not part of the API.
+    #[doc(hidden)]
+    pub mod codec;
+    #[cfg(feature = "testing")]
+    pub mod conformance;
+    #[cfg(feature = "net")]
+    mod lint;
+    #[cfg(feature = "net")]
+    mod proto;
     mod server;
+    #[cfg(feature = "split")]
     pub mod split;
+    #[cfg(feature = "testing")]
+    pub mod testing;
+    #[cfg(feature = "net")]
+    pub use lint::LintServer;
+    #[cfg(feature = "net")]
+    pub use proto::{ProtoServer, ProtoServerBuilder};
     pub use server::Server;
     pub use server::ServerBuilder;
 }
 
 pub mod v038 {
-    mod codec;
+    #[cfg(feature = "client")]
+    pub mod client;
+    // Public for the benchmarks, not part of the API.
+    #[doc(hidden)]
+    pub mod codec;
+    #[cfg(feature = "testing")]
+    pub mod conformance;
+    #[cfg(feature = "net")]
+    mod lint;
+    #[cfg(feature = "net")]
+    mod proto;
     mod server;
+    #[cfg(feature = "split")]
     pub mod split;
+    #[cfg(feature = "testing")]
+    pub mod testing;
+    #[cfg(feature = "net")]
+    pub use lint::LintServer;
+    #[cfg(feature = "net")]
+    pub use proto::{ProtoServer, ProtoServerBuilder};
     pub use server::Server;
     pub use server::ServerBuilder;
 }
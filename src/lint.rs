@@ -0,0 +1,292 @@
+//! Checking the requests of a node against the ABCI specification.
+//!
+//! Custom node builds, proxies and ABCI clients in other languages can be
+//! tested without a real application by pointing them at a lint server, e.g.
+//! [`v038::LintServer`](crate::v038::LintServer), which answers every request
+//! with the minimal valid response of [`NoopApp`](crate::apps::NoopApp) and
+//! reports each deviation from the specification as a [`Violation`]:
+//!
+//! ```ignore
+//! let linter = Linter::new();
+//! tokio::spawn(LintServer::new(linter.clone()).listen_tcp("127.0.0.1:26658"));
+//! // Run the node against it, then:
+//! for violation in linter.violations() {
+//!     println!("{violation}");
+//! }
+//! ```
+//!
+//! The server holds the responses of each connection until the node sends a
+//! `Flush`, as the specification allows, so that a client that doesn't flush
+//! waits rather than working by accident. It checks that:
+//!
+//! - every request is a well-formed frame holding a valid request;
+//! - every request on a connection is of the category of its first one,
+//!   except `Echo` and `Flush`, which are sent on any connection;
+//! - the consensus connection follows the block lifecycle of its version,
+//!   e.g. `BeginBlock`, `DeliverTx`s, `EndBlock`, then `Commit`, with each
+//!   block one higher than the last committed one;
+//! - `ApplySnapshotChunk` follows an `OfferSnapshot`;
+//! - the node flushed every request it sent before closing the connection.
+//!
+//! Violations are also logged at `WARN`. A framing error closes the
+//! connection, since the rest of the stream can't be read; the server answers
+//! the other violations and carries on.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tendermint::block;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::Category;
+
+/// What a [`Violation`] breaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// A frame has an invalid length prefix or doesn't decode.
+    Framing,
+    /// A frame decodes to a request with invalid or missing fields.
+    Malformed,
+    /// A request isn't of the category of its connection.
+    Category,
+    /// A request arrived out of order.
+    Ordering,
+    /// A request is for an unexpected block height.
+    Height,
+    /// The connection closed with requests the node never flushed.
+    Flush,
+}
+
+/// A deviation of a node from the ABCI specification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// The id of the connection it happened on.
+    pub connection: u64,
+    /// The kind of the connection, once known.
+    pub kind: Option<Category>,
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection {}", self.connection)?;
+        if let Some(kind) = self.kind {
+            write!(f, " ({})", kind.name())?;
+        }
+        write!(f, ": {:?}: {}", self.rule, self.message)
+    }
+}
+
+/// Collects the violations found by the connections of a lint server. Clones
+/// share the same violations.
+#[derive(Clone, Debug, Default)]
+pub struct Linter {
+    violations: Arc<Mutex<Vec<Violation>>>,
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The violations found so far, in the order they were found.
+    pub fn violations(&self) -> Vec<Violation> {
+        self.violations.lock().unwrap().clone()
+    }
+
+    /// Returns `true` if no violation was found so far.
+    pub fn is_clean(&self) -> bool {
+        self.violations.lock().unwrap().is_empty()
+    }
+
+    /// Checks the requests of a new connection.
+    pub(crate) fn connection(&self, id: u64) -> ConnectionLint {
+        ConnectionLint {
+            linter: self.clone(),
+            id,
+            kind: None,
+            unflushed: 0,
+            stage: Stage::Start,
+            block: None,
+            committed: None,
+            offered: false,
+        }
+    }
+}
+
+/// Where the consensus connection is in the block lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    /// No consensus request was received yet.
+    Start,
+    /// Between blocks: after `InitChain` or `Commit`.
+    Idle,
+    /// Executing a block, after `BeginBlock`.
+    Executing,
+    /// The block was executed, and awaits its `Commit`.
+    Executed,
+}
+
+/// The checks of a single connection.
+pub(crate) struct ConnectionLint {
+    linter: Linter,
+    id: u64,
+    kind: Option<Category>,
+    /// The number of requests received since the last `Flush`.
+    unflushed: usize,
+    stage: Stage,
+    /// The height of the block being executed.
+    block: Option<block::Height>,
+    /// The height of the last committed block.
+    committed: Option<block::Height>,
+    /// Whether a snapshot was offered.
+    offered: bool,
+}
+
+impl ConnectionLint {
+    fn violation(&self, rule: Rule, message: String) {
+        tracing::warn!(connection = self.id, ?rule, %message, "ABCI violation");
+        self.linter.violations.lock().unwrap().push(Violation {
+            connection: self.id,
+            kind: self.kind,
+            rule,
+            message,
+        });
+    }
+
+    /// Records a frame that couldn't be decoded.
+    pub(crate) fn framing(&self, error: &dyn fmt::Display) {
+        self.violation(Rule::Framing, error.to_string());
+    }
+
+    /// Records a frame that didn't convert to a valid request.
+    pub(crate) fn malformed(&mut self, error: &dyn fmt::Display) {
+        self.unflushed += 1;
+        self.violation(Rule::Malformed, error.to_string());
+    }
+
+    pub(crate) fn flush(&mut self) {
+        self.unflushed = 0;
+    }
+
+    /// Checks a request of `category` for `method`, at `height` if it
+    /// carries one.
+    pub(crate) fn request(
+        &mut self,
+        method: &'static str,
+        category: Category,
+        height: Option<block::Height>,
+    ) {
+        self.unflushed += 1;
+        match self.kind {
+            None if method != "Echo" => self.kind = Some(category),
+            Some(kind) if kind != category && method != "Echo" => self.violation(
+                Rule::Category,
+                format!(
+                    "{method} sent on a {} connection, rather than a {} one",
+                    kind.name(),
+                    category.name()
+                ),
+            ),
+            _ => {}
+        }
+        match category {
+            Category::Consensus => self.consensus(method, height),
+            Category::Snapshot => match method {
+                "OfferSnapshot" => self.offered = true,
+                "ApplySnapshotChunk" if !self.offered => self.violation(
+                    Rule::Ordering,
+                    "ApplySnapshotChunk before any OfferSnapshot".to_string(),
+                ),
+                _ => {}
+            },
+            Category::Mempool | Category::Info => {}
+        }
+    }
+
+    fn consensus(&mut self, method: &'static str, height: Option<block::Height>) {
+        let expected = |stages: &[Stage]| stages.contains(&self.stage);
+        let (allowed, next) = match method {
+            "InitChain" => (expected(&[Stage::Start]), Stage::Idle),
+            "PrepareProposal" | "ProcessProposal" | "ExtendVote" | "VerifyVoteExtension" => {
+                (expected(&[Stage::Start, Stage::Idle]), self.stage)
+            }
+            "BeginBlock" => (expected(&[Stage::Start, Stage::Idle]), Stage::Executing),
+            "DeliverTx" => (expected(&[Stage::Executing]), Stage::Executing),
+            "EndBlock" => (expected(&[Stage::Executing]), Stage::Executed),
+            "FinalizeBlock" => (expected(&[Stage::Start, Stage::Idle]), Stage::Executed),
+            "Commit" => (expected(&[Stage::Executed]), Stage::Idle),
+            _ => (true, self.stage),
+        };
+        if !allowed {
+            self.violation(
+                Rule::Ordering,
+                format!("{method} received while {}", self.stage.describe()),
+            );
+        }
+
+        match method {
+            "BeginBlock" | "FinalizeBlock" => {
+                self.check_next_height(method, height);
+                self.block = height;
+            }
+            "PrepareProposal" | "ProcessProposal" | "ExtendVote" | "VerifyVoteExtension" => {
+                self.check_next_height(method, height)
+            }
+            "EndBlock" => {
+                if let (Some(height), Some(block)) = (height, self.block) {
+                    if height != block {
+                        self.violation(
+                            Rule::Height,
+                            format!("EndBlock at height {height}, in block {block}"),
+                        );
+                    }
+                }
+            }
+            "Commit" => self.committed = self.block.take().or(self.committed),
+            _ => {}
+        }
+        self.stage = next;
+    }
+
+    /// Checks that a request for the next block is one higher than the last
+    /// committed block.
+    fn check_next_height(&self, method: &str, height: Option<block::Height>) {
+        if let (Some(height), Some(committed)) = (height, self.committed) {
+            if height.value() != committed.value() + 1 {
+                self.violation(
+                    Rule::Height,
+                    format!("{method} at height {height}, after committing {committed}"),
+                );
+            }
+        }
+    }
+
+    /// Records that the connection closed.
+    pub(crate) fn closed(&self) {
+        if self.unflushed > 0 {
+            self.violation(
+                Rule::Flush,
+                format!(
+                    "closed with {} requests sent since the last Flush",
+                    self.unflushed
+                ),
+            );
+        }
+    }
+}
+
+impl Stage {
+    fn describe(self) -> &'static str {
+        match self {
+            Stage::Start => "no block was executed",
+            Stage::Idle => "between blocks",
+            Stage::Executing => "executing a block",
+            Stage::Executed => "awaiting Commit",
+        }
+    }
+}
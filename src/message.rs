@@ -0,0 +1,832 @@
+//! Version-independent accessors for ABCI requests and responses.
+//!
+//! Each protocol version has its own request and response enums, and each of
+//! those has its own split into per-category enums. Middleware that only needs
+//! to know which method a message is for, which block it pertains to, or
+//! whether the application accepted it, can be written once against
+//! [`RequestExt`] and [`ResponseExt`] instead of matching on every variant of
+//! every enum.
+
+use tendermint::{
+    abci::{response, Code},
+    block,
+};
+
+/// Version-independent accessors for ABCI request enums.
+pub trait RequestExt {
+    /// The name of the ABCI method this request is for, e.g. `"FinalizeBlock"`.
+    fn method(&self) -> &'static str;
+
+    /// The block height this request pertains to, if it carries one: the
+    /// height of the block being proposed, executed or voted on, the height
+    /// a `Query` is made at, unless it is made at the latest height, or the
+    /// height of the snapshot offered or loaded.
+    ///
+    /// `Commit` carries no height; [`BlockHeight`] tracks the height it
+    /// commits.
+    fn height(&self) -> Option<block::Height>;
+}
+
+/// The coarse outcome of an ABCI response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Outcome {
+    /// The application processed or accepted the request.
+    Success,
+    /// The application rejected the request, either with a nonzero code or
+    /// with a reject, abort, or retry result.
+    Failure,
+    /// The application responded with an `Exception`.
+    Exception,
+}
+
+impl Outcome {
+    /// Returns `true` if this is [`Outcome::Success`].
+    pub fn is_success(self) -> bool {
+        self == Outcome::Success
+    }
+
+    /// The outcome indicated by a response code.
+    pub fn from_code(code: Code) -> Self {
+        if code.is_ok() {
+            Outcome::Success
+        } else {
+            Outcome::Failure
+        }
+    }
+}
+
+/// Version-independent accessors for ABCI response enums.
+///
+/// Accessors return `None` for methods whose responses do not carry the
+/// corresponding field.
+pub trait ResponseExt {
+    /// The name of the ABCI method this response is for, e.g. `"FinalizeBlock"`.
+    fn method(&self) -> &'static str;
+
+    /// The response code, for `CheckTx`, `DeliverTx`, `Query` and
+    /// `SetOption` responses.
+    fn code(&self) -> Option<Code>;
+
+    /// The namespace of the response code, for `CheckTx`, `DeliverTx` and
+    /// `Query` responses.
+    fn codespace(&self) -> Option<&str>;
+
+    /// The log, for the responses with a code, or the error of an
+    /// `Exception`.
+    fn log(&self) -> Option<&str>;
+
+    /// Classifies the response as a success, failure, or exception.
+    fn outcome(&self) -> Outcome;
+
+    /// The gas wanted, for `CheckTx` and `DeliverTx` responses, or the total
+    /// over all transactions of a `FinalizeBlock` response.
+    fn gas_wanted(&self) -> Option<i64>;
+
+    /// The gas used, for `CheckTx` and `DeliverTx` responses, or the total
+    /// over all transactions of a `FinalizeBlock` response.
+    fn gas_used(&self) -> Option<i64>;
+
+    /// Returns `true` unless the application processed or accepted the
+    /// request, i.e. for failures and exceptions.
+    fn is_err(&self) -> bool {
+        !self.outcome().is_success()
+    }
+}
+
+/// The fields of the individual response structs, shared by every protocol
+/// version, from which [`ResponseExt`] is implemented for the enums.
+trait Fields {
+    fn code(&self) -> Option<Code> {
+        None
+    }
+
+    fn codespace(&self) -> Option<&str> {
+        None
+    }
+
+    fn log(&self) -> Option<&str> {
+        None
+    }
+
+    fn outcome(&self) -> Outcome {
+        self.code().map_or(Outcome::Success, Outcome::from_code)
+    }
+
+    fn gas_wanted(&self) -> Option<i64> {
+        None
+    }
+
+    fn gas_used(&self) -> Option<i64> {
+        None
+    }
+}
+
+macro_rules! impl_coded_fields {
+    ($($ty:ident),*) => {
+        $(
+            impl Fields for response::$ty {
+                fn code(&self) -> Option<Code> {
+                    Some(self.code)
+                }
+
+                fn codespace(&self) -> Option<&str> {
+                    Some(&self.codespace)
+                }
+
+                fn log(&self) -> Option<&str> {
+                    Some(&self.log)
+                }
+            }
+        )*
+    };
+}
+
+impl_coded_fields!(Query);
+
+macro_rules! impl_gas_fields {
+    ($($ty:ident),*) => {
+        $(
+            impl Fields for response::$ty {
+                fn code(&self) -> Option<Code> {
+                    Some(self.code)
+                }
+
+                fn codespace(&self) -> Option<&str> {
+                    Some(&self.codespace)
+                }
+
+                fn log(&self) -> Option<&str> {
+                    Some(&self.log)
+                }
+
+                fn gas_wanted(&self) -> Option<i64> {
+                    Some(self.gas_wanted)
+                }
+
+                fn gas_used(&self) -> Option<i64> {
+                    Some(self.gas_used)
+                }
+            }
+        )*
+    };
+}
+
+impl_gas_fields!(CheckTx, DeliverTx);
+
+impl Fields for response::SetOption {
+    fn code(&self) -> Option<Code> {
+        Some(self.code)
+    }
+
+    fn log(&self) -> Option<&str> {
+        Some(&self.log)
+    }
+}
+
+impl Fields for response::Exception {
+    fn log(&self) -> Option<&str> {
+        Some(&self.error)
+    }
+
+    fn outcome(&self) -> Outcome {
+        Outcome::Exception
+    }
+}
+
+impl Fields for response::OfferSnapshot {
+    fn outcome(&self) -> Outcome {
+        match self {
+            response::OfferSnapshot::Accept => Outcome::Success,
+            _ => Outcome::Failure,
+        }
+    }
+}
+
+impl Fields for response::ApplySnapshotChunk {
+    fn outcome(&self) -> Outcome {
+        match self.result {
+            response::ApplySnapshotChunkResult::Accept => Outcome::Success,
+            _ => Outcome::Failure,
+        }
+    }
+}
+
+impl Fields for response::ProcessProposal {
+    fn outcome(&self) -> Outcome {
+        match self {
+            response::ProcessProposal::Accept => Outcome::Success,
+            _ => Outcome::Failure,
+        }
+    }
+}
+
+impl Fields for response::VerifyVoteExtension {
+    fn outcome(&self) -> Outcome {
+        match self {
+            response::VerifyVoteExtension::Accept => Outcome::Success,
+            _ => Outcome::Failure,
+        }
+    }
+}
+
+impl Fields for response::FinalizeBlock {
+    fn gas_wanted(&self) -> Option<i64> {
+        Some(self.tx_results.iter().map(|r| r.gas_wanted).sum())
+    }
+
+    fn gas_used(&self) -> Option<i64> {
+        Some(self.tx_results.iter().map(|r| r.gas_used).sum())
+    }
+}
+
+macro_rules! impl_plain_fields {
+    ($($ty:ident),*) => {
+        $(impl Fields for response::$ty {})*
+    };
+}
+
+impl_plain_fields!(
+    Echo,
+    Info,
+    InitChain,
+    BeginBlock,
+    EndBlock,
+    Commit,
+    ListSnapshots,
+    LoadSnapshotChunk,
+    PrepareProposal,
+    ExtendVote
+);
+
+/// Implements [`ResponseExt`] for a response enum with the given variants,
+/// each named after its method, and variants without a payload.
+macro_rules! impl_response_ext {
+    ($ty:ident { $($variant:ident),* $(,)? } $(unit { $($unit:ident),* })?) => {
+        impl ResponseExt for $ty {
+            fn method(&self) -> &'static str {
+                match self {
+                    $($ty::$variant(_) => stringify!($variant),)*
+                    $($($ty::$unit => stringify!($unit),)*)?
+                }
+            }
+
+            fn code(&self) -> Option<Code> {
+                match self {
+                    $($ty::$variant(x) => Fields::code(x),)*
+                    $($($ty::$unit => None,)*)?
+                }
+            }
+
+            fn codespace(&self) -> Option<&str> {
+                match self {
+                    $($ty::$variant(x) => Fields::codespace(x),)*
+                    $($($ty::$unit => None,)*)?
+                }
+            }
+
+            fn log(&self) -> Option<&str> {
+                match self {
+                    $($ty::$variant(x) => Fields::log(x),)*
+                    $($($ty::$unit => None,)*)?
+                }
+            }
+
+            fn outcome(&self) -> Outcome {
+                match self {
+                    $($ty::$variant(x) => Fields::outcome(x),)*
+                    $($($ty::$unit => Outcome::Success,)*)?
+                }
+            }
+
+            fn gas_wanted(&self) -> Option<i64> {
+                match self {
+                    $($ty::$variant(x) => Fields::gas_wanted(x),)*
+                    $($($ty::$unit => None,)*)?
+                }
+            }
+
+            fn gas_used(&self) -> Option<i64> {
+                match self {
+                    $($ty::$variant(x) => Fields::gas_used(x),)*
+                    $($($ty::$unit => None,)*)?
+                }
+            }
+        }
+    };
+}
+
+fn end_block_height(height: i64) -> Option<block::Height> {
+    block::Height::try_from(height).ok()
+}
+
+/// A height of zero queries the latest height.
+fn query_height(height: block::Height) -> Option<block::Height> {
+    (height.value() != 0).then_some(height)
+}
+
+/// Tracks the height of the block being executed on a consensus connection,
+/// so that requests that don't carry it, `DeliverTx` and `Commit`, can be
+/// attributed to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockHeight {
+    current: Option<block::Height>,
+}
+
+impl BlockHeight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Observes a request of the consensus connection, returning the height
+    /// of the block it pertains to, if known.
+    pub fn observe(&mut self, request: &impl RequestExt) -> Option<block::Height> {
+        match request.method() {
+            "InitChain" => {
+                self.current = None;
+                None
+            }
+            "DeliverTx" | "Commit" => self.current,
+            _ => {
+                let height = request.height();
+                self.current = height.or(self.current);
+                height
+            }
+        }
+    }
+
+    /// The height of the block being executed, if known.
+    pub fn get(&self) -> Option<block::Height> {
+        self.current
+    }
+}
+
+// ===== impl v0_34 =====
+
+mod v0_34 {
+    use super::*;
+    use tendermint::v0_34::abci::{
+        ConsensusRequest, ConsensusResponse, InfoRequest, InfoResponse, MempoolRequest,
+        MempoolResponse, Request, Response, SnapshotRequest, SnapshotResponse,
+    };
+
+    impl RequestExt for Request {
+        fn method(&self) -> &'static str {
+            match self {
+                Request::Echo(_) => "Echo",
+                Request::Flush => "Flush",
+                Request::Info(_) => "Info",
+                Request::SetOption(_) => "SetOption",
+                Request::InitChain(_) => "InitChain",
+                Request::Query(_) => "Query",
+                Request::BeginBlock(_) => "BeginBlock",
+                Request::CheckTx(_) => "CheckTx",
+                Request::DeliverTx(_) => "DeliverTx",
+                Request::EndBlock(_) => "EndBlock",
+                Request::Commit => "Commit",
+                Request::ListSnapshots => "ListSnapshots",
+                Request::OfferSnapshot(_) => "OfferSnapshot",
+                Request::LoadSnapshotChunk(_) => "LoadSnapshotChunk",
+                Request::ApplySnapshotChunk(_) => "ApplySnapshotChunk",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                Request::BeginBlock(x) => Some(x.header.height),
+                Request::EndBlock(x) => end_block_height(x.height),
+                Request::Query(x) => query_height(x.height),
+                Request::OfferSnapshot(x) => Some(x.snapshot.height),
+                Request::LoadSnapshotChunk(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for ConsensusRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                ConsensusRequest::InitChain(_) => "InitChain",
+                ConsensusRequest::BeginBlock(_) => "BeginBlock",
+                ConsensusRequest::DeliverTx(_) => "DeliverTx",
+                ConsensusRequest::EndBlock(_) => "EndBlock",
+                ConsensusRequest::Commit => "Commit",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                ConsensusRequest::BeginBlock(x) => Some(x.header.height),
+                ConsensusRequest::EndBlock(x) => end_block_height(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for MempoolRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                MempoolRequest::CheckTx(_) => "CheckTx",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            None
+        }
+    }
+
+    impl RequestExt for InfoRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                InfoRequest::Info(_) => "Info",
+                InfoRequest::Query(_) => "Query",
+                InfoRequest::Echo(_) => "Echo",
+                InfoRequest::SetOption(_) => "SetOption",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                InfoRequest::Query(x) => query_height(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for SnapshotRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                SnapshotRequest::ListSnapshots => "ListSnapshots",
+                SnapshotRequest::OfferSnapshot(_) => "OfferSnapshot",
+                SnapshotRequest::LoadSnapshotChunk(_) => "LoadSnapshotChunk",
+                SnapshotRequest::ApplySnapshotChunk(_) => "ApplySnapshotChunk",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                SnapshotRequest::OfferSnapshot(x) => Some(x.snapshot.height),
+                SnapshotRequest::LoadSnapshotChunk(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl_response_ext!(Response {
+            Exception,
+            Echo,
+            Info,
+            SetOption,
+            InitChain,
+            Query,
+            BeginBlock,
+            CheckTx,
+            DeliverTx,
+            EndBlock,
+            Commit,
+            ListSnapshots,
+            OfferSnapshot,
+            LoadSnapshotChunk,
+            ApplySnapshotChunk,
+        }
+        unit { Flush }
+    );
+    impl_response_ext!(ConsensusResponse {
+        InitChain,
+        BeginBlock,
+        DeliverTx,
+        EndBlock,
+        Commit,
+    });
+    impl_response_ext!(MempoolResponse { CheckTx });
+    impl_response_ext!(InfoResponse {
+        Echo,
+        Info,
+        Query,
+        SetOption,
+    });
+    impl_response_ext!(SnapshotResponse {
+        ListSnapshots,
+        OfferSnapshot,
+        LoadSnapshotChunk,
+        ApplySnapshotChunk,
+    });
+}
+
+// ===== impl v0_37 =====
+
+mod v0_37 {
+    use super::*;
+    use tendermint::v0_37::abci::{
+        ConsensusRequest, ConsensusResponse, InfoRequest, InfoResponse, MempoolRequest,
+        MempoolResponse, Request, Response, SnapshotRequest, SnapshotResponse,
+    };
+
+    impl RequestExt for Request {
+        fn method(&self) -> &'static str {
+            match self {
+                Request::Echo(_) => "Echo",
+                Request::Flush => "Flush",
+                Request::Info(_) => "Info",
+                Request::InitChain(_) => "InitChain",
+                Request::Query(_) => "Query",
+                Request::BeginBlock(_) => "BeginBlock",
+                Request::CheckTx(_) => "CheckTx",
+                Request::DeliverTx(_) => "DeliverTx",
+                Request::EndBlock(_) => "EndBlock",
+                Request::Commit => "Commit",
+                Request::ListSnapshots => "ListSnapshots",
+                Request::OfferSnapshot(_) => "OfferSnapshot",
+                Request::LoadSnapshotChunk(_) => "LoadSnapshotChunk",
+                Request::ApplySnapshotChunk(_) => "ApplySnapshotChunk",
+                Request::PrepareProposal(_) => "PrepareProposal",
+                Request::ProcessProposal(_) => "ProcessProposal",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                Request::BeginBlock(x) => Some(x.header.height),
+                Request::EndBlock(x) => end_block_height(x.height),
+                Request::PrepareProposal(x) => Some(x.height),
+                Request::ProcessProposal(x) => Some(x.height),
+                Request::Query(x) => query_height(x.height),
+                Request::OfferSnapshot(x) => Some(x.snapshot.height),
+                Request::LoadSnapshotChunk(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for ConsensusRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                ConsensusRequest::InitChain(_) => "InitChain",
+                ConsensusRequest::PrepareProposal(_) => "PrepareProposal",
+                ConsensusRequest::ProcessProposal(_) => "ProcessProposal",
+                ConsensusRequest::BeginBlock(_) => "BeginBlock",
+                ConsensusRequest::DeliverTx(_) => "DeliverTx",
+                ConsensusRequest::EndBlock(_) => "EndBlock",
+                ConsensusRequest::Commit => "Commit",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                ConsensusRequest::BeginBlock(x) => Some(x.header.height),
+                ConsensusRequest::EndBlock(x) => end_block_height(x.height),
+                ConsensusRequest::PrepareProposal(x) => Some(x.height),
+                ConsensusRequest::ProcessProposal(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for MempoolRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                MempoolRequest::CheckTx(_) => "CheckTx",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            None
+        }
+    }
+
+    impl RequestExt for InfoRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                InfoRequest::Info(_) => "Info",
+                InfoRequest::Query(_) => "Query",
+                InfoRequest::Echo(_) => "Echo",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                InfoRequest::Query(x) => query_height(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for SnapshotRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                SnapshotRequest::ListSnapshots => "ListSnapshots",
+                SnapshotRequest::OfferSnapshot(_) => "OfferSnapshot",
+                SnapshotRequest::LoadSnapshotChunk(_) => "LoadSnapshotChunk",
+                SnapshotRequest::ApplySnapshotChunk(_) => "ApplySnapshotChunk",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                SnapshotRequest::OfferSnapshot(x) => Some(x.snapshot.height),
+                SnapshotRequest::LoadSnapshotChunk(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl_response_ext!(Response {
+            Exception,
+            Echo,
+            Info,
+            InitChain,
+            Query,
+            BeginBlock,
+            CheckTx,
+            DeliverTx,
+            EndBlock,
+            Commit,
+            ListSnapshots,
+            OfferSnapshot,
+            LoadSnapshotChunk,
+            ApplySnapshotChunk,
+            PrepareProposal,
+            ProcessProposal,
+        }
+        unit { Flush }
+    );
+    impl_response_ext!(ConsensusResponse {
+        InitChain,
+        PrepareProposal,
+        ProcessProposal,
+        BeginBlock,
+        DeliverTx,
+        EndBlock,
+        Commit,
+    });
+    impl_response_ext!(MempoolResponse { CheckTx });
+    impl_response_ext!(InfoResponse { Echo, Info, Query });
+    impl_response_ext!(SnapshotResponse {
+        ListSnapshots,
+        OfferSnapshot,
+        LoadSnapshotChunk,
+        ApplySnapshotChunk,
+    });
+}
+
+// ===== impl v0_38 =====
+
+mod v0_38 {
+    use super::*;
+    use tendermint::v0_38::abci::{
+        ConsensusRequest, ConsensusResponse, InfoRequest, InfoResponse, MempoolRequest,
+        MempoolResponse, Request, Response, SnapshotRequest, SnapshotResponse,
+    };
+
+    impl RequestExt for Request {
+        fn method(&self) -> &'static str {
+            match self {
+                Request::Echo(_) => "Echo",
+                Request::Flush => "Flush",
+                Request::Info(_) => "Info",
+                Request::InitChain(_) => "InitChain",
+                Request::Query(_) => "Query",
+                Request::CheckTx(_) => "CheckTx",
+                Request::Commit => "Commit",
+                Request::ListSnapshots => "ListSnapshots",
+                Request::OfferSnapshot(_) => "OfferSnapshot",
+                Request::LoadSnapshotChunk(_) => "LoadSnapshotChunk",
+                Request::ApplySnapshotChunk(_) => "ApplySnapshotChunk",
+                Request::PrepareProposal(_) => "PrepareProposal",
+                Request::ProcessProposal(_) => "ProcessProposal",
+                Request::ExtendVote(_) => "ExtendVote",
+                Request::VerifyVoteExtension(_) => "VerifyVoteExtension",
+                Request::FinalizeBlock(_) => "FinalizeBlock",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                Request::PrepareProposal(x) => Some(x.height),
+                Request::ProcessProposal(x) => Some(x.height),
+                Request::ExtendVote(x) => Some(x.height),
+                Request::VerifyVoteExtension(x) => Some(x.height),
+                Request::FinalizeBlock(x) => Some(x.height),
+                Request::Query(x) => query_height(x.height),
+                Request::OfferSnapshot(x) => Some(x.snapshot.height),
+                Request::LoadSnapshotChunk(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for ConsensusRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                ConsensusRequest::InitChain(_) => "InitChain",
+                ConsensusRequest::PrepareProposal(_) => "PrepareProposal",
+                ConsensusRequest::ProcessProposal(_) => "ProcessProposal",
+                ConsensusRequest::Commit => "Commit",
+                ConsensusRequest::ExtendVote(_) => "ExtendVote",
+                ConsensusRequest::VerifyVoteExtension(_) => "VerifyVoteExtension",
+                ConsensusRequest::FinalizeBlock(_) => "FinalizeBlock",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                ConsensusRequest::PrepareProposal(x) => Some(x.height),
+                ConsensusRequest::ProcessProposal(x) => Some(x.height),
+                ConsensusRequest::ExtendVote(x) => Some(x.height),
+                ConsensusRequest::VerifyVoteExtension(x) => Some(x.height),
+                ConsensusRequest::FinalizeBlock(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for MempoolRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                MempoolRequest::CheckTx(_) => "CheckTx",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            None
+        }
+    }
+
+    impl RequestExt for InfoRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                InfoRequest::Info(_) => "Info",
+                InfoRequest::Query(_) => "Query",
+                InfoRequest::Echo(_) => "Echo",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                InfoRequest::Query(x) => query_height(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl RequestExt for SnapshotRequest {
+        fn method(&self) -> &'static str {
+            match self {
+                SnapshotRequest::ListSnapshots => "ListSnapshots",
+                SnapshotRequest::OfferSnapshot(_) => "OfferSnapshot",
+                SnapshotRequest::LoadSnapshotChunk(_) => "LoadSnapshotChunk",
+                SnapshotRequest::ApplySnapshotChunk(_) => "ApplySnapshotChunk",
+            }
+        }
+
+        fn height(&self) -> Option<block::Height> {
+            match self {
+                SnapshotRequest::OfferSnapshot(x) => Some(x.snapshot.height),
+                SnapshotRequest::LoadSnapshotChunk(x) => Some(x.height),
+                _ => None,
+            }
+        }
+    }
+
+    impl_response_ext!(Response {
+            Exception,
+            Echo,
+            Info,
+            InitChain,
+            Query,
+            CheckTx,
+            Commit,
+            ListSnapshots,
+            OfferSnapshot,
+            LoadSnapshotChunk,
+            ApplySnapshotChunk,
+            PrepareProposal,
+            ProcessProposal,
+            ExtendVote,
+            VerifyVoteExtension,
+            FinalizeBlock,
+        }
+        unit { Flush }
+    );
+    impl_response_ext!(ConsensusResponse {
+        InitChain,
+        PrepareProposal,
+        ProcessProposal,
+        Commit,
+        ExtendVote,
+        VerifyVoteExtension,
+        FinalizeBlock,
+    });
+    impl_response_ext!(MempoolResponse { CheckTx });
+    impl_response_ext!(InfoResponse { Echo, Info, Query });
+    impl_response_ext!(SnapshotResponse {
+        ListSnapshots,
+        OfferSnapshot,
+        LoadSnapshotChunk,
+        ApplySnapshotChunk,
+    });
+}
@@ -0,0 +1,277 @@
+//! Metrics recorded by the ABCI servers.
+//!
+//! Metrics are recorded with the [`metrics`] facade, so they are exported by
+//! whichever recorder the application installs, e.g., a Prometheus exporter.
+//! Call [`describe`] after installing the recorder to register descriptions of
+//! all the metrics below.
+//!
+//! Metrics labeled by connection kind (`kind`) use the name of the connection's
+//! [`Category`], e.g. `consensus`, or `unknown` before the kind is detected.
+//!
+//! Without the `metrics` feature, the servers record nothing and the crate
+//! does not depend on the facade; the names below are kept for dashboards and
+//! alerts shared between builds.
+
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+
+use tendermint::abci::{request, response};
+
+use crate::{Category, ConnectionError};
+
+/// Counter of stalls detected while waiting on a component service, labeled by
+/// what the server was waiting for (`waiting_for`).
+pub const STALLS: &str = "abci_stalls_total";
+
+/// Gauge of the number of open connections.
+pub const CONNECTIONS_ACTIVE: &str = "abci_connections_active";
+
+/// Counter of connections accepted by the listener.
+pub const CONNECTIONS_ACCEPTED: &str = "abci_connections_accepted_total";
+
+/// Counter of errors accepting new connections.
+pub const ACCEPT_ERRORS: &str = "abci_accept_errors_total";
+
+/// Counter of closed connections, labeled by connection kind (`kind`) and why
+/// they closed (`reason`): `eof` if the node closed the connection, `io` on a
+/// socket error and `error` on any other error, such as a service failure.
+pub const CONNECTIONS_CLOSED: &str = "abci_connections_closed_total";
+
+/// Counter of frames read from the node, labeled by connection
+/// kind (`kind`).
+pub const FRAMES_RECEIVED: &str = "abci_frames_received_total";
+
+/// Counter of bytes of frames read from the node, excluding length prefixes,
+/// labeled by connection kind (`kind`).
+pub const BYTES_RECEIVED: &str = "abci_bytes_received_total";
+
+/// Counter of frames written to the node, labeled by connection
+/// kind (`kind`).
+pub const FRAMES_SENT: &str = "abci_frames_sent_total";
+
+/// Counter of bytes of frames written to the node, excluding length prefixes,
+/// labeled by connection kind (`kind`).
+pub const BYTES_SENT: &str = "abci_bytes_sent_total";
+
+/// Histogram of the time between reading a `Flush` request and writing its
+/// response, which includes waiting for all pending responses, labeled by
+/// connection kind (`kind`).
+pub const FLUSH_DURATION: &str = "abci_flush_duration_seconds";
+
+/// Histogram of the time between reading the request that starts executing a
+/// block (`BeginBlock` or `FinalizeBlock`) and writing the `Commit` response.
+pub const BLOCK_DURATION: &str = "abci_block_duration_seconds";
+
+/// Histogram of the time between reading a `Commit` request and writing its
+/// response.
+pub const COMMIT_DURATION: &str = "abci_commit_duration_seconds";
+
+/// Counter of `CheckTx` responses, labeled by the kind of check (`kind`, `new`
+/// or `recheck`), whether the transaction was accepted (`result`, `accepted`
+/// or `rejected`) and the response code (`code`).
+pub const CHECK_TX: &str = "abci_check_tx_total";
+
+/// Histogram of the size of transactions in `CheckTx` requests, labeled by the
+/// kind of check (`kind`).
+pub const CHECK_TX_SIZE: &str = "abci_check_tx_size_bytes";
+
+/// Histogram of the priorities given to accepted transactions by
+/// [`Priority`](crate::middleware::priority::Priority), labeled by the kind of
+/// check (`kind`).
+pub const TX_PRIORITY: &str = "abci_tx_priority";
+
+/// Counter of transactions rejected by
+/// [`TxFilter`](crate::middleware::filter::TxFilter), labeled by `method`
+/// (`CheckTx` or `PrepareProposal`) and the `rule` that rejected them
+/// (`pattern`, `blocked_sender`, `allowed_senders` or `predicate`).
+pub const TX_FILTERED: &str = "abci_tx_filtered_total";
+
+/// Counter of component services recreated by
+/// [`Supervised`](crate::middleware::supervise::Supervised) after failing.
+pub const SERVICE_RESTARTS: &str = "abci_service_restarts_total";
+
+/// Counter of requests whose response from a shadow service differed from the
+/// primary's, labeled by `method`; see
+/// [`mirror`](crate::middleware::mirror).
+pub const MIRROR_DIVERGENCES: &str = "abci_mirror_divergences_total";
+
+/// Registers descriptions of the metrics recorded by the servers.
+#[cfg(feature = "metrics")]
+pub fn describe() {
+    describe_counter!(
+        STALLS,
+        "Number of times a connection waited too long for a component service"
+    );
+    describe_gauge!(CONNECTIONS_ACTIVE, "Number of open connections");
+    describe_counter!(CONNECTIONS_ACCEPTED, "Number of connections accepted");
+    describe_counter!(ACCEPT_ERRORS, "Number of errors accepting connections");
+    describe_counter!(CONNECTIONS_CLOSED, "Number of connections closed");
+    describe_counter!(FRAMES_RECEIVED, "Number of frames read from the node");
+    describe_counter!(
+        BYTES_RECEIVED,
+        Unit::Bytes,
+        "Size of the frames read from the node"
+    );
+    describe_counter!(FRAMES_SENT, "Number of frames written to the node");
+    describe_counter!(
+        BYTES_SENT,
+        Unit::Bytes,
+        "Size of the frames written to the node"
+    );
+    describe_histogram!(
+        FLUSH_DURATION,
+        Unit::Seconds,
+        "Time taken to answer Flush requests"
+    );
+    describe_histogram!(
+        BLOCK_DURATION,
+        Unit::Seconds,
+        "Time taken to execute and commit blocks"
+    );
+    describe_histogram!(
+        COMMIT_DURATION,
+        Unit::Seconds,
+        "Time taken to answer Commit requests"
+    );
+    describe_counter!(CHECK_TX, "Number of transactions checked");
+    describe_counter!(TX_FILTERED, "Number of transactions filtered out");
+    describe_counter!(SERVICE_RESTARTS, "Number of failed services recreated");
+    describe_counter!(
+        MIRROR_DIVERGENCES,
+        "Number of shadow responses that differed from the primary's"
+    );
+    describe_histogram!(
+        CHECK_TX_SIZE,
+        Unit::Bytes,
+        "Size of the transactions checked"
+    );
+    describe_histogram!(
+        TX_PRIORITY,
+        "Priority given to the transactions accepted to the mempool"
+    );
+}
+
+/// Stands in for the handles of the `metrics` facade when the feature is off.
+#[cfg(not(feature = "metrics"))]
+struct Noop;
+
+#[cfg(not(feature = "metrics"))]
+impl Noop {
+    fn increment<T>(&self, _: T) {}
+
+    fn decrement<T>(&self, _: T) {}
+
+    fn record<T>(&self, _: T) {}
+}
+
+/// Evaluates the name and labels of a metric, so that recording it is a no-op
+/// without unused values, and returns a [`Noop`] handle.
+#[cfg(not(feature = "metrics"))]
+macro_rules! noop {
+    ($name:expr $(, $key:literal => $value:expr)* $(,)?) => {{
+        let _ = ($name, $(($key, $value)),*);
+        Noop
+    }};
+}
+
+#[cfg(not(feature = "metrics"))]
+use {noop as counter, noop as gauge, noop as histogram};
+
+pub(crate) fn stall(waiting_for: &'static str) {
+    counter!(STALLS, "waiting_for" => waiting_for).increment(1);
+}
+
+pub(crate) fn connection_accepted() {
+    counter!(CONNECTIONS_ACCEPTED).increment(1);
+    gauge!(CONNECTIONS_ACTIVE).increment(1.0);
+}
+
+#[cfg(feature = "net")]
+pub(crate) fn accept_error() {
+    counter!(ACCEPT_ERRORS).increment(1);
+}
+
+fn kind_label(kind: Option<Category>) -> &'static str {
+    kind.map_or("unknown", Category::name)
+}
+
+pub(crate) fn connection_closed(kind: Option<Category>, result: &Result<(), ConnectionError>) {
+    let reason = match result {
+        Ok(()) => "eof",
+        Err(e) if e.inner().is::<std::io::Error>() => "io",
+        Err(_) => "error",
+    };
+    gauge!(CONNECTIONS_ACTIVE).decrement(1.0);
+    counter!(CONNECTIONS_CLOSED, "kind" => kind_label(kind), "reason" => reason).increment(1);
+}
+
+pub(crate) fn frame_received(kind: Option<Category>, len: usize) {
+    let kind = kind_label(kind);
+    counter!(FRAMES_RECEIVED, "kind" => kind).increment(1);
+    counter!(BYTES_RECEIVED, "kind" => kind).increment(len as u64);
+}
+
+pub(crate) fn frame_sent(kind: Option<Category>, len: usize) {
+    let kind = kind_label(kind);
+    counter!(FRAMES_SENT, "kind" => kind).increment(1);
+    counter!(BYTES_SENT, "kind" => kind).increment(len as u64);
+}
+
+pub(crate) fn flush(kind: Option<Category>, elapsed: Duration) {
+    histogram!(FLUSH_DURATION, "kind" => kind_label(kind)).record(elapsed);
+}
+
+pub(crate) fn block(elapsed: Duration) {
+    histogram!(BLOCK_DURATION).record(elapsed);
+}
+
+pub(crate) fn commit(elapsed: Duration) {
+    histogram!(COMMIT_DURATION).record(elapsed);
+}
+
+fn check_tx_kind(kind: request::CheckTxKind) -> &'static str {
+    match kind {
+        request::CheckTxKind::New => "new",
+        request::CheckTxKind::Recheck => "recheck",
+    }
+}
+
+pub(crate) fn check_tx_request(check_tx: &request::CheckTx) {
+    let kind = check_tx_kind(check_tx.kind);
+    histogram!(CHECK_TX_SIZE, "kind" => kind).record(check_tx.tx.len() as f64);
+}
+
+pub(crate) fn check_tx_response(kind: request::CheckTxKind, check_tx: &response::CheckTx) {
+    let result = if check_tx.code.is_ok() {
+        "accepted"
+    } else {
+        "rejected"
+    };
+    counter!(
+        CHECK_TX,
+        "kind" => check_tx_kind(kind),
+        "result" => result,
+        "code" => check_tx.code.value().to_string(),
+    )
+    .increment(1);
+}
+
+pub(crate) fn tx_priority(kind: request::CheckTxKind, priority: i64) {
+    histogram!(TX_PRIORITY, "kind" => check_tx_kind(kind)).record(priority as f64);
+}
+
+pub(crate) fn tx_filtered(method: &'static str, rule: &'static str) {
+    counter!(TX_FILTERED, "method" => method, "rule" => rule).increment(1);
+}
+
+pub(crate) fn service_restarted() {
+    counter!(SERVICE_RESTARTS).increment(1);
+}
+
+pub(crate) fn mirror_diverged(method: &'static str) {
+    counter!(MIRROR_DIVERGENCES, "method" => method).increment(1);
+}
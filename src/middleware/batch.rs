@@ -0,0 +1,40 @@
+//! Whether the server delivers the transactions of a connection in batches.
+//!
+//! With `deliver_tx_batch`, the `DeliverTx` requests of the 0.34 and 0.37
+//! servers go to the batch service, and never reach the consensus service.
+//! The server marks the calls to the consensus service of such a connection,
+//! as it does with [`RequestId::current`](crate::RequestId::current), so that
+//! the layers that must see every request of a block, such as
+//! [`WalLayer`](super::wal::WalLayer) and [`HaltLayer`](super::halt::HaltLayer),
+//! fail rather than miss the transactions.
+
+use std::cell::Cell;
+
+use crate::BoxError;
+
+thread_local! {
+    static CURRENT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns `true` if called from within `call` on a consensus service invoked
+/// by a server that delivers its transactions in batches.
+pub(crate) fn deliver_tx_batched() -> bool {
+    CURRENT.with(|c| c.get())
+}
+
+/// Runs `f` with `batched` as whether the transactions of the current
+/// connection are delivered in batches.
+pub(crate) fn scope<T>(batched: bool, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT.with(|c| c.replace(batched));
+    let out = f();
+    CURRENT.with(|c| c.set(prev));
+    out
+}
+
+/// The error of `layer` for a request of a connection whose transactions are
+/// delivered in batches, if it is one.
+pub(crate) fn reject(layer: &str) -> Option<BoxError> {
+    deliver_tx_batched().then(|| {
+        format!("{layer} can't see the transactions delivered by `deliver_tx_batch`").into()
+    })
+}
@@ -0,0 +1,158 @@
+//! Deadlines by which the node needs the responses to its requests.
+//!
+//! With [`ConnectionOptions::request_timeout`](crate::ConnectionOptions::request_timeout)
+//! set, e.g. differently for consensus and mempool connections through its
+//! `overrides`, the server gives every request it reads a deadline that long
+//! after reading it. While the server is calling [`Service::call`], the
+//! deadline of the request is available with [`current`], as with
+//! [`RequestId::current`](crate::RequestId::current), so that services can
+//! abandon work whose answer would come too late to be useful:
+//!
+//! ```ignore
+//! fn call(&mut self, request: InfoRequest) -> Self::Future {
+//!     let deadline = deadline::current();
+//!     let query = self.query(request);
+//!     async move {
+//!         match deadline {
+//!             Some(deadline) => Ok(tokio::time::timeout_at(deadline, query).await??),
+//!             None => query.await,
+//!         }
+//!     }
+//!     .boxed()
+//! }
+//! ```
+//!
+//! [`DeadlineLayer`] does this for any service, failing the calls that miss
+//! their deadline with [`Expired`]. The deadline follows the request through
+//! [`split`](crate::v038::split).
+//!
+//! The server does not enforce deadlines itself: the node expects a response
+//! to every request, so a late response is still written when it resolves.
+
+use std::{
+    cell::Cell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use pin_project::pin_project;
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+
+use crate::BoxError;
+
+thread_local! {
+    static CURRENT: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Returns the deadline of the request being passed to [`Service::call`], if
+/// called from within `call` on a service invoked by the server, and the
+/// server has a request timeout.
+pub fn current() -> Option<Instant> {
+    CURRENT.with(|c| c.get())
+}
+
+/// Returns the time left until the deadline of the current request, or zero
+/// if it has passed.
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Runs `f` with `deadline` as the deadline of the current request.
+pub(crate) fn scope<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT.with(|c| c.replace(deadline));
+    let out = f();
+    CURRENT.with(|c| c.set(prev));
+    out
+}
+
+/// Applies [`Deadline`] to a service.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeadlineLayer;
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = Deadline<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Deadline { inner }
+    }
+}
+
+/// Fails the calls to the inner service that have not resolved by the
+/// deadline of their request, dropping their futures. Calls without a
+/// deadline are passed through. See the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct Deadline<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for Deadline<S>
+where
+    S: Service<R>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        ResponseFuture {
+            sleep: current().map(tokio::time::sleep_until),
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+/// Response future for [`Deadline`].
+#[pin_project]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    sleep: Option<Sleep>,
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Poll::Ready(output) = this.inner.poll(cx) {
+            return Poll::Ready(output.map_err(Into::into));
+        }
+        match this.sleep.as_pin_mut().map(|sleep| sleep.poll(cx)) {
+            Some(Poll::Ready(())) => Poll::Ready(Err(Expired { _p: () }.into())),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+/// The error returned by calls that [`Deadline`] abandoned.
+pub struct Expired {
+    _p: (),
+}
+
+impl fmt::Debug for Expired {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("Expired").finish()
+    }
+}
+
+impl fmt::Display for Expired {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("request deadline expired")
+    }
+}
+
+impl std::error::Error for Expired {}
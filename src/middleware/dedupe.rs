@@ -0,0 +1,352 @@
+//! Answers duplicate `CheckTx` requests from a cache.
+//!
+//! Popular transactions reach a node from many peers and clients, and each
+//! copy is checked again by the application. [`CheckTxCacheLayer`] remembers
+//! the verdict of the mempool service on each transaction, by hash, and
+//! answers new checks of a known transaction with it, without calling the
+//! service. Rechecks always reach the service, and update the cache.
+//!
+//! The cache must learn which transactions are committed, through the
+//! [`on_commit`](CheckTxCacheLayer::on_commit) layer of the consensus
+//! service:
+//!
+//! ```
+//! # use tower::ServiceBuilder;
+//! # use tower_abci::{apps::NoopApp, middleware::dedupe::CheckTxCacheLayer};
+//! # let (consensus, mempool) = (NoopApp, NoopApp);
+//! let cache = CheckTxCacheLayer::new(100_000);
+//! let consensus = ServiceBuilder::new().layer(cache.on_commit()).service(consensus);
+//! let mempool = ServiceBuilder::new().layer(cache).service(mempool);
+//! ```
+//!
+//! Once a block is committed, the verdicts on its transactions are dropped,
+//! since checking them again would find them already applied, and so are all
+//! rejections, since the new state may accept those transactions. Verdicts
+//! accepting the other transactions are kept, until the next recheck of each
+//! updates them.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use pin_project::pin_project;
+use sha2::{Digest, Sha256};
+use tendermint::abci::{request::CheckTxKind, response};
+use tower::{Layer, Service};
+
+type TxHash = [u8; 32];
+
+fn tx_hash(tx: &Bytes) -> TxHash {
+    Sha256::digest(tx).into()
+}
+
+/// The cache shared by the mempool and consensus layers.
+struct Cache {
+    verdicts: HashMap<TxHash, Verdict>,
+    /// The cached transactions in order of insertion, with the sequence
+    /// number of their verdict, for eviction.
+    order: VecDeque<(TxHash, u64)>,
+    capacity: usize,
+    sequence: u64,
+    /// The transactions of the block being executed.
+    block: Vec<TxHash>,
+}
+
+struct Verdict {
+    response: response::CheckTx,
+    sequence: u64,
+}
+
+impl Cache {
+    fn get(&self, hash: &TxHash) -> Option<response::CheckTx> {
+        self.verdicts
+            .get(hash)
+            .map(|verdict| verdict.response.clone())
+    }
+
+    fn insert(&mut self, hash: TxHash, response: response::CheckTx) {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        self.verdicts.insert(hash, Verdict { response, sequence });
+        self.order.push_back((hash, sequence));
+        while self.verdicts.len() > self.capacity {
+            let Some((hash, sequence)) = self.order.pop_front() else {
+                break;
+            };
+            // Skip the entries of verdicts since replaced or dropped.
+            if self.verdicts.get(&hash).map(|verdict| verdict.sequence) == Some(sequence) {
+                self.verdicts.remove(&hash);
+            }
+        }
+        // Compact the order once it is mostly stale entries.
+        if self.order.len() > 2 * self.capacity.max(self.verdicts.len()) {
+            let verdicts = &self.verdicts;
+            self.order.retain(|(hash, sequence)| {
+                verdicts.get(hash).map(|verdict| verdict.sequence) == Some(*sequence)
+            });
+        }
+    }
+
+    fn committed(&mut self) {
+        for hash in self.block.drain(..) {
+            self.verdicts.remove(&hash);
+        }
+        self.verdicts
+            .retain(|_, verdict| verdict.response.code.is_ok());
+    }
+}
+
+/// Applies [`CheckTxCache`] to a mempool service.
+#[derive(Clone)]
+pub struct CheckTxCacheLayer {
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl CheckTxCacheLayer {
+    /// Caches the verdicts on at most `capacity` transactions, evicting the
+    /// oldest first.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(Cache {
+                verdicts: HashMap::new(),
+                order: VecDeque::new(),
+                capacity: capacity.max(1),
+                sequence: 0,
+                block: Vec::new(),
+            })),
+        }
+    }
+
+    /// The layer of the consensus service that updates the cache as blocks
+    /// are committed. See the [module documentation](self) for details.
+    pub fn on_commit(&self) -> InvalidateOnCommitLayer {
+        InvalidateOnCommitLayer {
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<S> Layer<S> for CheckTxCacheLayer {
+    type Service = CheckTxCache<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CheckTxCache {
+            inner,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+/// Answers new checks of a transaction already checked from a cache.
+#[derive(Clone)]
+pub struct CheckTxCache<S> {
+    inner: S,
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// Applies [`InvalidateOnCommit`] to a consensus service.
+#[derive(Clone)]
+pub struct InvalidateOnCommitLayer {
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<S> Layer<S> for InvalidateOnCommitLayer {
+    type Service = InvalidateOnCommit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InvalidateOnCommit {
+            inner,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+/// Updates the cache of a [`CheckTxCache`] as blocks are committed by the
+/// inner consensus service.
+#[derive(Clone)]
+pub struct InvalidateOnCommit<S> {
+    inner: S,
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// Response future for [`CheckTxCache`].
+#[pin_project]
+pub struct CheckTxFuture<F, R>(#[pin] Checking<F, R>);
+
+#[pin_project(project = CheckingProj)]
+enum Checking<F, R> {
+    /// The cached verdict.
+    Cached(Option<R>),
+    /// The verdict of the inner service, cached once known.
+    Inner {
+        #[pin]
+        inner: F,
+        hash: TxHash,
+        cache: Arc<Mutex<Cache>>,
+        verdict: fn(&R) -> &response::CheckTx,
+    },
+}
+
+impl<F, R, E> Future for CheckTxFuture<F, R>
+where
+    F: Future<Output = Result<R, E>>,
+{
+    type Output = Result<R, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().0.project() {
+            CheckingProj::Cached(response) => {
+                Poll::Ready(Ok(response.take().expect("polled after completion")))
+            }
+            CheckingProj::Inner {
+                inner,
+                hash,
+                cache,
+                verdict,
+            } => {
+                let output = futures::ready!(inner.poll(cx));
+                if let Ok(response) = &output {
+                    cache
+                        .lock()
+                        .unwrap()
+                        .insert(*hash, verdict(response).clone());
+                }
+                Poll::Ready(output)
+            }
+        }
+    }
+}
+
+/// Response future for [`InvalidateOnCommit`].
+#[pin_project]
+pub struct CommitFuture<F> {
+    #[pin]
+    inner: F,
+    /// The cache, if this is the response to a `Commit`.
+    commit: Option<Arc<Mutex<Cache>>>,
+}
+
+impl<F, R, E> Future for CommitFuture<F>
+where
+    F: Future<Output = Result<R, E>>,
+{
+    type Output = Result<R, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = futures::ready!(this.inner.poll(cx));
+        if let (Ok(_), Some(cache)) = (&output, this.commit.take()) {
+            cache.lock().unwrap().committed();
+        }
+        Poll::Ready(output)
+    }
+}
+
+macro_rules! impl_mempool_service {
+    ($version:ident) => {
+        impl<S> Service<tendermint::$version::abci::MempoolRequest> for CheckTxCache<S>
+        where
+            S: Service<
+                tendermint::$version::abci::MempoolRequest,
+                Response = tendermint::$version::abci::MempoolResponse,
+            >,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = CheckTxFuture<S::Future, S::Response>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::MempoolRequest) -> Self::Future {
+                use tendermint::$version::abci::{MempoolRequest, MempoolResponse};
+
+                let MempoolRequest::CheckTx(check_tx) = &req;
+                let hash = tx_hash(&check_tx.tx);
+                if check_tx.kind == CheckTxKind::New {
+                    if let Some(response) = self.cache.lock().unwrap().get(&hash) {
+                        return CheckTxFuture(Checking::Cached(Some(MempoolResponse::CheckTx(
+                            response,
+                        ))));
+                    }
+                }
+                CheckTxFuture(Checking::Inner {
+                    inner: self.inner.call(req),
+                    hash,
+                    cache: self.cache.clone(),
+                    verdict: |response| match response {
+                        MempoolResponse::CheckTx(check_tx) => check_tx,
+                    },
+                })
+            }
+        }
+    };
+}
+
+impl_mempool_service!(v0_34);
+impl_mempool_service!(v0_37);
+impl_mempool_service!(v0_38);
+
+macro_rules! impl_consensus_service {
+    ($version:ident, |$cache:ident, $req:ident| $observe:block) => {
+        impl<S> Service<tendermint::$version::abci::ConsensusRequest> for InvalidateOnCommit<S>
+        where
+            S: Service<tendermint::$version::abci::ConsensusRequest>,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = CommitFuture<S::Future>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::ConsensusRequest) -> Self::Future {
+                use tendermint::$version::abci::ConsensusRequest;
+
+                let commit = matches!(req, ConsensusRequest::Commit).then(|| self.cache.clone());
+                {
+                    let $cache = &self.cache;
+                    let $req = &req;
+                    $observe
+                }
+                CommitFuture {
+                    inner: self.inner.call(req),
+                    commit,
+                }
+            }
+        }
+    };
+}
+
+impl_consensus_service!(v0_34, |cache, req| {
+    match req {
+        ConsensusRequest::BeginBlock(_) => cache.lock().unwrap().block.clear(),
+        ConsensusRequest::DeliverTx(deliver_tx) => {
+            let hash = tx_hash(&deliver_tx.tx);
+            cache.lock().unwrap().block.push(hash);
+        }
+        _ => {}
+    }
+});
+impl_consensus_service!(v0_37, |cache, req| {
+    match req {
+        ConsensusRequest::BeginBlock(_) => cache.lock().unwrap().block.clear(),
+        ConsensusRequest::DeliverTx(deliver_tx) => {
+            let hash = tx_hash(&deliver_tx.tx);
+            cache.lock().unwrap().block.push(hash);
+        }
+        _ => {}
+    }
+});
+impl_consensus_service!(v0_38, |cache, req| {
+    if let ConsensusRequest::FinalizeBlock(finalize_block) = req {
+        cache.lock().unwrap().block = finalize_block.txs.iter().map(tx_hash).collect();
+    }
+});
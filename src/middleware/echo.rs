@@ -0,0 +1,142 @@
+//! Customizes the answers to `Echo` requests.
+//!
+//! Operators check that an application is up, and which build it is, with
+//! `abci-cli echo`. [`EchoLayer`] answers `Echo` requests to the info service
+//! itself, with a payload computed from the echoed message, e.g. the name and
+//! version of the application:
+//!
+//! ```
+//! # use tower::ServiceBuilder;
+//! # use tower_abci::{apps::NoopApp, middleware::echo::EchoLayer};
+//! # let info = NoopApp;
+//! let info = ServiceBuilder::new()
+//!     .layer(EchoLayer::identity(
+//!         "kvstore",
+//!         env!("CARGO_PKG_VERSION"),
+//!         option_env!("GIT_HASH"),
+//!     ))
+//!     .service(info);
+//! ```
+//!
+//! after which `abci-cli echo hello` answers `hello (kvstore 1.2.0 3f2a9c1)`.
+//! Other requests are passed to the inner service.
+
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::future::{self, Either, Ready};
+use tower::{Layer, Service};
+
+/// Computes the answer to an `Echo` request from its message.
+type Reply = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Applies [`Echo`] to an info service.
+#[derive(Clone)]
+pub struct EchoLayer {
+    reply: Reply,
+}
+
+impl EchoLayer {
+    /// Answers `Echo` requests with `reply` applied to their message.
+    pub fn new<F>(reply: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        Self {
+            reply: Arc::new(reply),
+        }
+    }
+
+    /// Answers `Echo` requests with their message followed by the name and
+    /// version of the application, and the hash of the commit it was built
+    /// from if it is known.
+    pub fn identity(
+        name: impl Into<String>,
+        version: impl Into<String>,
+        git_hash: Option<&str>,
+    ) -> Self {
+        let identity = match git_hash {
+            Some(git_hash) => format!("{} {} {}", name.into(), version.into(), git_hash),
+            None => format!("{} {}", name.into(), version.into()),
+        };
+        Self::new(move |message| match message {
+            "" => identity.clone(),
+            message => format!("{message} ({identity})"),
+        })
+    }
+}
+
+impl fmt::Debug for EchoLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EchoLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for EchoLayer {
+    type Service = Echo<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Echo {
+            inner,
+            reply: self.reply.clone(),
+        }
+    }
+}
+
+/// Answers `Echo` requests without calling the inner service. See the
+/// [module documentation](self) for details.
+#[derive(Clone)]
+pub struct Echo<S> {
+    inner: S,
+    reply: Reply,
+}
+
+impl<S: fmt::Debug> fmt::Debug for Echo<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Echo")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+macro_rules! impl_info_service {
+    ($version:ident) => {
+        impl<S> Service<tendermint::$version::abci::InfoRequest> for Echo<S>
+        where
+            S: Service<
+                tendermint::$version::abci::InfoRequest,
+                Response = tendermint::$version::abci::InfoResponse,
+            >,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = Either<Ready<Result<S::Response, S::Error>>, S::Future>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::InfoRequest) -> Self::Future {
+                use tendermint::{
+                    abci::response,
+                    $version::abci::{InfoRequest, InfoResponse},
+                };
+
+                match req {
+                    InfoRequest::Echo(echo) => {
+                        let message = (self.reply)(&echo.message);
+                        Either::Left(future::ok(InfoResponse::Echo(response::Echo { message })))
+                    }
+                    req => Either::Right(self.inner.call(req)),
+                }
+            }
+        }
+    };
+}
+
+impl_info_service!(v0_34);
+impl_info_service!(v0_37);
+impl_info_service!(v0_38);
@@ -0,0 +1,308 @@
+//! Publishes the events of block execution on a broadcast channel.
+//!
+//! [`EventBusLayer`] wraps the consensus service and publishes a
+//! [`BlockEvent`] as each block starts, as each of its transactions is
+//! executed, and once it is committed, so that RPC servers, websocket feeds
+//! and metrics in the same process can follow the chain without touching the
+//! application:
+//!
+//! ```
+//! # use tower::ServiceBuilder;
+//! # use tower_abci::{apps::NoopApp, middleware::events::EventBusLayer};
+//! # let consensus = NoopApp;
+//! let bus = EventBusLayer::new(1024);
+//! let mut events = bus.subscribe();
+//! let consensus = ServiceBuilder::new().layer(bus).service(consensus);
+//! ```
+//!
+//! Events are published on a [`tokio::sync::broadcast`] channel, so a
+//! subscriber that falls more than `capacity` events behind misses the oldest
+//! ones, and learns how many from [`RecvError::Lagged`]; consensus is never
+//! held up. Events published while there are no subscribers are dropped.
+//!
+//! [`RecvError::Lagged`]: tokio::sync::broadcast::error::RecvError::Lagged
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use sha2::{Digest, Sha256};
+use tendermint::{abci::Event, block, Hash};
+use tokio::sync::broadcast;
+use tower::{Layer, Service};
+
+use super::index::TxRecord;
+
+/// An event of block execution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockEvent {
+    /// The block at `height` started executing. With CometBFT 0.34 and 0.37,
+    /// it is published once `BeginBlock` returns, with its events; with 0.38,
+    /// once `FinalizeBlock` returns, with the events of the block, before the
+    /// results of its transactions.
+    BeginBlock {
+        height: block::Height,
+        events: Vec<Event>,
+    },
+    /// A transaction of the block was executed. Pipelined `DeliverTx`
+    /// requests may complete out of order, so the results of a block are
+    /// published in the order they are known, each with its index.
+    TxResult(TxRecord),
+    /// The block at `height` was committed.
+    Commit { height: block::Height },
+}
+
+/// The block being executed.
+struct Block {
+    height: block::Height,
+    /// The index of the next `DeliverTx` of the block.
+    next: u32,
+}
+
+/// Applies [`EventBus`] to a consensus service.
+#[derive(Clone)]
+pub struct EventBusLayer {
+    events: broadcast::Sender<BlockEvent>,
+}
+
+impl EventBusLayer {
+    /// Publishes events on a channel holding up to `capacity` events not yet
+    /// received by every subscriber.
+    pub fn new(capacity: usize) -> Self {
+        let (events, _) = broadcast::channel(capacity.max(1));
+        Self { events }
+    }
+
+    /// Subscribes to the events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<BlockEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl<S> Layer<S> for EventBusLayer {
+    type Service = EventBus<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        EventBus {
+            inner,
+            events: self.events.clone(),
+            block: Arc::new(Mutex::new(Block {
+                height: 0u32.into(),
+                next: 0,
+            })),
+        }
+    }
+}
+
+/// Publishes the events of the blocks executed by the inner consensus
+/// service. See the [module documentation](self) for details.
+#[derive(Clone)]
+pub struct EventBus<S> {
+    inner: S,
+    events: broadcast::Sender<BlockEvent>,
+    block: Arc<Mutex<Block>>,
+}
+
+impl<S> EventBus<S> {
+    fn publish(events: &broadcast::Sender<BlockEvent>, event: BlockEvent) {
+        // An error only means there are no subscribers.
+        let _ = events.send(event);
+    }
+}
+
+fn tx_hash(tx: &[u8]) -> Hash {
+    Hash::Sha256(Sha256::digest(tx).into())
+}
+
+/// Publishes the events of a successful response.
+type Publisher<R> = Box<dyn FnOnce(&R) + Send>;
+
+/// Response future for [`EventBus`].
+#[pin_project]
+pub struct ResponseFuture<F, R> {
+    #[pin]
+    inner: F,
+    publish: Option<Publisher<R>>,
+}
+
+impl<F, R, E> Future for ResponseFuture<F, R>
+where
+    F: Future<Output = Result<R, E>>,
+{
+    type Output = Result<R, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = futures::ready!(this.inner.poll(cx));
+        if let (Ok(response), Some(publish)) = (&output, this.publish.take()) {
+            publish(response);
+        }
+        Poll::Ready(output)
+    }
+}
+
+macro_rules! impl_consensus_service {
+    ($version:ident, |$bus:ident, $block:ident, $req:ident| $publish:block) => {
+        impl<S> Service<tendermint::$version::abci::ConsensusRequest> for EventBus<S>
+        where
+            S: Service<
+                tendermint::$version::abci::ConsensusRequest,
+                Response = tendermint::$version::abci::ConsensusResponse,
+            >,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = ResponseFuture<S::Future, S::Response>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::ConsensusRequest) -> Self::Future {
+                use tendermint::$version::abci::{ConsensusRequest, ConsensusResponse};
+
+                let $bus = self.events.clone();
+                let publish: Option<Publisher<S::Response>> = match req {
+                    ConsensusRequest::Commit => {
+                        let height = self.block.lock().unwrap().height;
+                        Some(Box::new(move |_: &ConsensusResponse| {
+                            Self::publish(&$bus, BlockEvent::Commit { height })
+                        }))
+                    }
+                    _ => {
+                        let $block = &self.block;
+                        let $req = &req;
+                        $publish
+                    }
+                };
+                ResponseFuture {
+                    inner: self.inner.call(req),
+                    publish,
+                }
+            }
+        }
+    };
+}
+
+impl_consensus_service!(v0_34, |bus, block, req| {
+    match req {
+        ConsensusRequest::BeginBlock(begin_block) => {
+            let height = begin_block.header.height;
+            *block.lock().unwrap() = Block { height, next: 0 };
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::BeginBlock(begin_block) = response {
+                    Self::publish(
+                        &bus,
+                        BlockEvent::BeginBlock {
+                            height,
+                            events: begin_block.events.clone(),
+                        },
+                    );
+                }
+            }))
+        }
+        ConsensusRequest::DeliverTx(deliver_tx) => {
+            let hash = tx_hash(&deliver_tx.tx);
+            let (height, index) = {
+                let mut block = block.lock().unwrap();
+                block.next += 1;
+                (block.height, block.next - 1)
+            };
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::DeliverTx(deliver_tx) = response {
+                    Self::publish(
+                        &bus,
+                        BlockEvent::TxResult(TxRecord {
+                            height,
+                            index,
+                            hash,
+                            code: deliver_tx.code,
+                            events: deliver_tx.events.clone(),
+                        }),
+                    );
+                }
+            }))
+        }
+        _ => None,
+    }
+});
+impl_consensus_service!(v0_37, |bus, block, req| {
+    match req {
+        ConsensusRequest::BeginBlock(begin_block) => {
+            let height = begin_block.header.height;
+            *block.lock().unwrap() = Block { height, next: 0 };
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::BeginBlock(begin_block) = response {
+                    Self::publish(
+                        &bus,
+                        BlockEvent::BeginBlock {
+                            height,
+                            events: begin_block.events.clone(),
+                        },
+                    );
+                }
+            }))
+        }
+        ConsensusRequest::DeliverTx(deliver_tx) => {
+            let hash = tx_hash(&deliver_tx.tx);
+            let (height, index) = {
+                let mut block = block.lock().unwrap();
+                block.next += 1;
+                (block.height, block.next - 1)
+            };
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::DeliverTx(deliver_tx) = response {
+                    Self::publish(
+                        &bus,
+                        BlockEvent::TxResult(TxRecord {
+                            height,
+                            index,
+                            hash,
+                            code: deliver_tx.code,
+                            events: deliver_tx.events.clone(),
+                        }),
+                    );
+                }
+            }))
+        }
+        _ => None,
+    }
+});
+impl_consensus_service!(v0_38, |bus, block, req| {
+    match req {
+        ConsensusRequest::FinalizeBlock(finalize_block) => {
+            let height = finalize_block.height;
+            *block.lock().unwrap() = Block { height, next: 0 };
+            let hashes: Vec<_> = finalize_block.txs.iter().map(|tx| tx_hash(tx)).collect();
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::FinalizeBlock(finalize_block) = response {
+                    Self::publish(
+                        &bus,
+                        BlockEvent::BeginBlock {
+                            height,
+                            events: finalize_block.events.clone(),
+                        },
+                    );
+                    let results = hashes.into_iter().zip(&finalize_block.tx_results);
+                    for ((hash, result), index) in results.zip(0..) {
+                        Self::publish(
+                            &bus,
+                            BlockEvent::TxResult(TxRecord {
+                                height,
+                                index,
+                                hash,
+                                code: result.code,
+                                events: result.events.clone(),
+                            }),
+                        );
+                    }
+                }
+            }))
+        }
+        _ => None,
+    }
+});
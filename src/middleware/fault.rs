@@ -0,0 +1,210 @@
+//! Injects latency, errors, and unreadiness into a service, for chaos testing.
+//!
+//! Wrapping any of the four component services with [`FaultInjectionLayer`]
+//! makes it behave like a slow or flaky application, so that the whole node and
+//! application stack can be checked for tolerance of one. This is intended for
+//! test networks, not production, and requires the `fault` feature.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use pin_project::pin_project;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::time::Sleep;
+use tower::{Layer, Service};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::BoxError;
+
+/// Applies [`FaultInjection`] to a service.
+///
+/// By default no faults are injected; each kind of fault is enabled separately.
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjectionLayer {
+    latency: Duration,
+    jitter: Duration,
+    error_rate: f64,
+    unready_rate: f64,
+    unready_for: Duration,
+    seed: Option<u64>,
+}
+
+impl FaultInjectionLayer {
+    /// Creates a layer that injects no faults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays every response by `latency` plus a uniformly random extra delay
+    /// of up to `jitter`.
+    pub fn latency(mut self, latency: Duration, jitter: Duration) -> Self {
+        self.latency = latency;
+        self.jitter = jitter;
+        self
+    }
+
+    /// Fails each call with probability `rate`, without calling the inner
+    /// service, returning an [`InjectedFault`] error.
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Makes the service unready for `duration` with probability `rate` each
+    /// time it is polled for readiness while ready.
+    pub fn unready(mut self, rate: f64, duration: Duration) -> Self {
+        self.unready_rate = rate.clamp(0.0, 1.0);
+        self.unready_for = duration;
+        self
+    }
+
+    /// Seeds the random number generator, so that a run's faults are reproducible.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl<S> Layer<S> for FaultInjectionLayer {
+    type Service = FaultInjection<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        FaultInjection {
+            inner,
+            config: self.clone(),
+            rng,
+            unready: None,
+        }
+    }
+}
+
+/// Injects latency, errors, and unreadiness into the inner service.
+pub struct FaultInjection<S> {
+    inner: S,
+    config: FaultInjectionLayer,
+    rng: StdRng,
+    unready: Option<Pin<Box<Sleep>>>,
+}
+
+// Implemented manually because a pending `Sleep` can't be cloned; clones start ready.
+impl<S: Clone> Clone for FaultInjection<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            rng: self.rng.clone(),
+            unready: None,
+        }
+    }
+}
+
+impl<S, R> Service<R> for FaultInjection<S>
+where
+    S: Service<R>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.unready.is_none()
+            && self.config.unready_rate > 0.0
+            && self.rng.gen_bool(self.config.unready_rate)
+        {
+            tracing::debug!(duration = ?self.config.unready_for, "injecting unreadiness");
+            self.unready = Some(Box::pin(tokio::time::sleep(self.config.unready_for)));
+        }
+        if let Some(sleep) = self.unready.as_mut() {
+            futures::ready!(sleep.as_mut().poll(cx));
+            self.unready = None;
+        }
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let mut delay = self.config.latency;
+        if !self.config.jitter.is_zero() {
+            delay += self.rng.gen_range(Duration::ZERO..=self.config.jitter);
+        }
+        let delay = (!delay.is_zero()).then(|| Box::pin(tokio::time::sleep(delay)));
+
+        let inner = if self.config.error_rate > 0.0 && self.rng.gen_bool(self.config.error_rate) {
+            tracing::debug!("injecting error");
+            Inner::Fault
+        } else {
+            Inner::Call(self.inner.call(req))
+        };
+
+        ResponseFuture { delay, inner }
+    }
+}
+
+/// Response future for [`FaultInjection`].
+#[pin_project]
+pub struct ResponseFuture<F> {
+    delay: Option<Pin<Box<Sleep>>>,
+    #[pin]
+    inner: Inner<F>,
+}
+
+#[pin_project(project = InnerProj)]
+enum Inner<F> {
+    Call(#[pin] F),
+    Fault,
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(delay) = this.delay.as_mut() {
+            futures::ready!(delay.as_mut().poll(cx));
+            *this.delay = None;
+        }
+        match this.inner.project() {
+            InnerProj::Call(f) => f.poll(cx).map_err(Into::into),
+            InnerProj::Fault => Poll::Ready(Err(InjectedFault::new().into())),
+        }
+    }
+}
+
+/// The error returned by calls that [`FaultInjection`] chose to fail.
+pub struct InjectedFault {
+    _p: (),
+}
+
+impl InjectedFault {
+    pub(crate) fn new() -> Self {
+        InjectedFault { _p: () }
+    }
+}
+
+impl fmt::Debug for InjectedFault {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("InjectedFault").finish()
+    }
+}
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("injected fault")
+    }
+}
+
+impl std::error::Error for InjectedFault {}
@@ -0,0 +1,420 @@
+//! Rejecting transactions by byte patterns, senders or predicates.
+//!
+//! When a chain is flooded with spam, or a transaction triggers a bug, nodes
+//! need to keep some transactions out of their mempool and their proposals
+//! right away, without waiting for a new release of the application.
+//! [`TxFilterLayer`] rejects the transactions that match its rules in
+//! `CheckTx`, before they reach the mempool service, and drops them from the
+//! transactions offered by `PrepareProposal`, before they reach the consensus
+//! service:
+//!
+//! ```ignore
+//! let filter = TxFilterLayer::new(TxRules::default())
+//!     .sender(|tx| decode(tx).ok().map(|tx| tx.signer))
+//!     .reject_if("oversized memo", |tx| memo_len(tx) > 256);
+//! let mempool = ServiceBuilder::new().layer(filter.clone()).service(mempool);
+//! let consensus = ServiceBuilder::new().layer(filter.clone()).service(consensus);
+//! let server = Server::builder().consensus(consensus).mempool(mempool) /* ... */ .finish().unwrap();
+//! server.handle().set_tx_filter(filter);
+//! ```
+//!
+//! A transaction is rejected if it contains one of the
+//! [`blocked_patterns`](TxRules::blocked_patterns), if its sender is one of the
+//! [`blocked_senders`](TxRules::blocked_senders) or, when
+//! [`allowed_senders`](TxRules::allowed_senders) isn't empty, isn't one of
+//! them, or if one of the predicates given to
+//! [`reject_if`](TxFilterLayer::reject_if) says so. The sender rules only apply
+//! with a [`sender`](TxFilterLayer::sender) callback, since the sender of a
+//! transaction is only known to the application.
+//!
+//! The rules are shared by every clone of the layer, and can be replaced while
+//! the server runs: with [`set_rules`](TxFilterLayer::set_rules), through the
+//! `filter` commands of the [admin socket](crate::admin), or from the
+//! `tx_filter` section of the server's configuration, once the layer is
+//! attached to the server with
+//! [`ServerHandle::set_tx_filter`](crate::ServerHandle::set_tx_filter).
+//!
+//! The filter is a local policy: rechecks reject the transactions that became
+//! blocked, so that they leave the mempool, but `ProcessProposal` accepts
+//! blocks proposed by nodes with other rules, and the transactions that the
+//! application adds to its own proposals are kept. Each rejected transaction
+//! is logged at `DEBUG` and counted in [`metrics::TX_FILTERED`].
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    future::Future,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use tendermint::abci::{response, Code};
+use tower::{Layer, Service};
+
+use crate::metrics;
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+
+/// Extracts the sender of a transaction, if it has one.
+type Sender = Arc<dyn Fn(&[u8]) -> Option<String> + Send + Sync>;
+
+/// Decides whether to reject a transaction.
+type Predicate = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// The rules of a [`TxFilterLayer`] that can be changed while the server
+/// runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct TxRules {
+    /// Byte strings that no accepted transaction contains. Encoded in hex
+    /// with `serde`. Empty patterns are ignored.
+    #[cfg_attr(feature = "serde", serde(with = "hex_patterns"))]
+    pub blocked_patterns: Vec<Vec<u8>>,
+    /// Senders whose transactions are rejected.
+    pub blocked_senders: BTreeSet<String>,
+    /// If not empty, the only senders whose transactions are accepted.
+    pub allowed_senders: BTreeSet<String>,
+}
+
+impl TxRules {
+    /// Returns `true` if no rule rejects any transaction.
+    pub fn is_empty(&self) -> bool {
+        self.blocked_patterns.iter().all(Vec::is_empty)
+            && self.blocked_senders.is_empty()
+            && self.allowed_senders.is_empty()
+    }
+}
+
+/// Why a transaction was rejected.
+enum Rejection {
+    Pattern(Vec<u8>),
+    BlockedSender(String),
+    SenderNotAllowed(Option<String>),
+    Predicate(Arc<str>),
+}
+
+impl Rejection {
+    /// The label of the rule in [`metrics::TX_FILTERED`].
+    fn rule(&self) -> &'static str {
+        match self {
+            Rejection::Pattern(_) => "pattern",
+            Rejection::BlockedSender(_) => "blocked_sender",
+            Rejection::SenderNotAllowed(_) => "allowed_senders",
+            Rejection::Predicate(_) => "predicate",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Pattern(pattern) => {
+                write!(f, "contains blocked pattern 0x{}", hex::encode(pattern))
+            }
+            Rejection::BlockedSender(sender) => write!(f, "sender {sender} is blocked"),
+            Rejection::SenderNotAllowed(Some(sender)) => {
+                write!(f, "sender {sender} is not allowed")
+            }
+            Rejection::SenderNotAllowed(None) => f.write_str("sender unknown, not allowed"),
+            Rejection::Predicate(name) => write!(f, "rejected by {name}"),
+        }
+    }
+}
+
+/// Applies [`TxFilter`] to a mempool or consensus service. Clones share the
+/// same rules.
+#[derive(Clone)]
+pub struct TxFilterLayer {
+    rules: Arc<RwLock<TxRules>>,
+    sender: Option<Sender>,
+    predicates: Vec<(Arc<str>, Predicate)>,
+    code: Code,
+}
+
+impl TxFilterLayer {
+    /// Rejects the transactions matching `rules`, with code 1 in `CheckTx`.
+    pub fn new(rules: TxRules) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+            sender: None,
+            predicates: Vec::new(),
+            code: Code::Err(NonZeroU32::MIN),
+        }
+    }
+
+    /// Finds the sender of each transaction with `sender`, for the sender
+    /// rules. Transactions for which it returns `None` have no sender, and
+    /// are rejected while [`TxRules::allowed_senders`] isn't empty.
+    pub fn sender<F>(mut self, sender: F) -> Self
+    where
+        F: Fn(&[u8]) -> Option<String> + Send + Sync + 'static,
+    {
+        self.sender = Some(Arc::new(sender));
+        self
+    }
+
+    /// Also rejects the transactions for which `predicate` returns `true`,
+    /// logging `name` as the reason.
+    pub fn reject_if<F>(mut self, name: impl Into<String>, predicate: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.predicates
+            .push((name.into().into(), Arc::new(predicate)));
+        self
+    }
+
+    /// Rejects transactions in `CheckTx` with `code`, rather than 1.
+    pub fn code(mut self, code: NonZeroU32) -> Self {
+        self.code = Code::Err(code);
+        self
+    }
+
+    /// The current rules.
+    pub fn rules(&self) -> TxRules {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// The names of the predicates given to
+    /// [`reject_if`](Self::reject_if).
+    pub fn predicates(&self) -> impl Iterator<Item = &str> + '_ {
+        self.predicates.iter().map(|(name, _)| &**name)
+    }
+
+    /// Replaces the rules of every clone of the layer, applying to the
+    /// transactions checked or proposed from then on.
+    pub fn set_rules(&self, rules: TxRules) {
+        self.update_rules(|current| *current = rules);
+    }
+
+    /// Changes the rules of every clone of the layer with `update`.
+    pub fn update_rules(&self, update: impl FnOnce(&mut TxRules)) {
+        let mut rules = self.rules.write().unwrap();
+        update(&mut rules);
+        tracing::info!(rules = ?*rules, "transaction filter updated");
+    }
+
+    /// Checks `tx` against the rules, returning why it is rejected, if it
+    /// is.
+    fn check(&self, tx: &[u8]) -> Option<Rejection> {
+        {
+            let rules = self.rules.read().unwrap();
+            let blocked = rules
+                .blocked_patterns
+                .iter()
+                .filter(|pattern| !pattern.is_empty())
+                .find(|pattern| {
+                    tx.windows(pattern.len())
+                        .any(|window| window == &pattern[..])
+                });
+            if let Some(pattern) = blocked {
+                return Some(Rejection::Pattern(pattern.clone()));
+            }
+            if let Some(sender) = &self.sender {
+                if !rules.blocked_senders.is_empty() || !rules.allowed_senders.is_empty() {
+                    match sender(tx) {
+                        Some(sender) if rules.blocked_senders.contains(&sender) => {
+                            return Some(Rejection::BlockedSender(sender))
+                        }
+                        sender
+                            if !rules.allowed_senders.is_empty()
+                                && !sender
+                                    .as_ref()
+                                    .is_some_and(|s| rules.allowed_senders.contains(s)) =>
+                        {
+                            return Some(Rejection::SenderNotAllowed(sender))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        self.predicates
+            .iter()
+            .find(|(_, predicate)| predicate(tx))
+            .map(|(name, _)| Rejection::Predicate(name.clone()))
+    }
+
+    /// Checks `tx`, logging and counting it if it is rejected.
+    fn reject(&self, method: &'static str, tx: &[u8]) -> Option<Rejection> {
+        let rejection = self.check(tx)?;
+        tracing::debug!(method, reason = %rejection, "transaction filtered");
+        metrics::tx_filtered(method, rejection.rule());
+        Some(rejection)
+    }
+
+    fn rejected_check_tx(&self, rejection: Rejection) -> response::CheckTx {
+        response::CheckTx {
+            code: self.code,
+            log: format!("transaction filtered: {rejection}"),
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Debug for TxFilterLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TxFilterLayer")
+            .field("rules", &*self.rules.read().unwrap())
+            .field("sender", &self.sender.is_some())
+            .field("predicates", &self.predicates().collect::<Vec<_>>())
+            .field("code", &self.code)
+            .finish()
+    }
+}
+
+impl<S> Layer<S> for TxFilterLayer {
+    type Service = TxFilter<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TxFilter {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Rejects the transactions matching the rules of its layer in `CheckTx`, and
+/// drops them from `PrepareProposal`. See the [module documentation](self)
+/// for details.
+#[derive(Clone, Debug)]
+pub struct TxFilter<S> {
+    inner: S,
+    layer: TxFilterLayer,
+}
+
+/// Response future for the mempool service of [`TxFilter`].
+#[pin_project(project = CheckTxFutureProj)]
+pub enum CheckTxFuture<F, R> {
+    /// The response rejecting a filtered transaction.
+    Rejected(Option<R>),
+    /// The call to the inner service.
+    Inner(#[pin] F),
+}
+
+impl<F, R, E> Future for CheckTxFuture<F, R>
+where
+    F: Future<Output = Result<R, E>>,
+{
+    type Output = Result<R, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            CheckTxFutureProj::Rejected(response) => {
+                Poll::Ready(Ok(response.take().expect("polled after completion")))
+            }
+            CheckTxFutureProj::Inner(inner) => inner.poll(cx),
+        }
+    }
+}
+
+macro_rules! impl_mempool_service {
+    ($version:ident) => {
+        impl<S> Service<tendermint::$version::abci::MempoolRequest> for TxFilter<S>
+        where
+            S: Service<
+                tendermint::$version::abci::MempoolRequest,
+                Response = tendermint::$version::abci::MempoolResponse,
+            >,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = CheckTxFuture<S::Future, S::Response>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::MempoolRequest) -> Self::Future {
+                use tendermint::$version::abci::{MempoolRequest, MempoolResponse};
+
+                let MempoolRequest::CheckTx(check_tx) = &req;
+                match self.layer.reject("CheckTx", &check_tx.tx) {
+                    Some(rejection) => CheckTxFuture::Rejected(Some(MempoolResponse::CheckTx(
+                        self.layer.rejected_check_tx(rejection),
+                    ))),
+                    None => CheckTxFuture::Inner(self.inner.call(req)),
+                }
+            }
+        }
+    };
+}
+
+impl_mempool_service!(v0_34);
+impl_mempool_service!(v0_37);
+impl_mempool_service!(v0_38);
+
+// ABCI 0.34 has no `PrepareProposal`, so the layer only applies to the
+// consensus services of 0.37 and 0.38.
+macro_rules! impl_consensus_service {
+    ($version:ident) => {
+        impl<S> Service<tendermint::$version::abci::ConsensusRequest> for TxFilter<S>
+        where
+            S: Service<tendermint::$version::abci::ConsensusRequest>,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = S::Future;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(
+                &mut self,
+                mut req: tendermint::$version::abci::ConsensusRequest,
+            ) -> Self::Future {
+                use tendermint::$version::abci::ConsensusRequest;
+
+                if let ConsensusRequest::PrepareProposal(prepare_proposal) = &mut req {
+                    prepare_proposal
+                        .txs
+                        .retain(|tx| self.layer.reject("PrepareProposal", tx).is_none());
+                }
+                self.inner.call(req)
+            }
+        }
+    };
+}
+
+impl_consensus_service!(v0_37);
+impl_consensus_service!(v0_38);
+
+/// (De)serializes byte patterns as hex strings, with or without a `0x`
+/// prefix.
+#[cfg(feature = "serde")]
+mod hex_patterns {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        patterns: &[Vec<u8>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(patterns.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|pattern| {
+                super::parse_pattern(&pattern)
+                    .map_err(|_| D::Error::custom(format!("invalid hex pattern {pattern:?}")))
+            })
+            .collect()
+    }
+}
+
+/// Parses a byte pattern written in hex, with or without a `0x` prefix, as in
+/// the admin socket and the configuration.
+pub fn parse_pattern(pattern: &str) -> Result<Vec<u8>, hex::FromHexError> {
+    hex::decode(pattern.strip_prefix("0x").unwrap_or(pattern))
+}
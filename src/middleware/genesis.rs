@@ -0,0 +1,447 @@
+//! Keeps the genesis and current consensus parameters and validators.
+//!
+//! The node only sends the genesis validators and consensus parameters in
+//! `InitChain`, yet applications need them long after, e.g. to check the
+//! parameter updates they return against the current parameters.
+//! [`ChainStateLayer`] wraps the consensus service, captures them from
+//! `InitChain`, follows the validator and parameter updates of each block, and
+//! persists them to a [`ChainStateStore`] as blocks are committed:
+//!
+//! ```ignore
+//! let chain = ChainStateLayer::new(FileStore::new(home.join("chain_state.pb")))?;
+//! let chain_state = chain.handle();
+//! let consensus = ServiceBuilder::new().layer(chain).service(consensus);
+//! // Later, e.g. in the info service:
+//! let params = chain_state.get().map(|state| state.params);
+//! ```
+//!
+//! The parameters and validators returned by `InitChain` replace those of the
+//! request, as they do for the node. Updates returned while executing a block
+//! take effect once it is committed.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    task::{Context, Poll},
+};
+
+use futures::future::{BoxFuture, FutureExt};
+use prost::Message;
+use tendermint::{block, consensus, validator, Time};
+use tendermint_proto::{
+    google::protobuf::Timestamp,
+    v0_38::{abci::ValidatorUpdate, types::ConsensusParams},
+};
+use tower::{Layer, Service};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::BoxError;
+
+/// The genesis and current consensus parameters and validators of the chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainState {
+    pub chain_id: String,
+    pub genesis_time: Time,
+    pub initial_height: block::Height,
+    /// The validators of the genesis, as set by `InitChain`.
+    pub genesis_validators: Vec<validator::Update>,
+    /// The consensus parameters of the genesis, as set by `InitChain`.
+    pub genesis_params: consensus::Params,
+    /// The height of the last committed block, or zero before the first one.
+    pub height: block::Height,
+    /// The validators after the last committed block.
+    pub validators: Vec<validator::Update>,
+    /// The consensus parameters after the last committed block.
+    pub params: consensus::Params,
+}
+
+impl ChainState {
+    /// Applies `updates` to the validators: a validator with zero power is
+    /// removed, any other is added or has its power changed.
+    fn update_validators(&mut self, updates: &[validator::Update]) {
+        for update in updates {
+            self.validators
+                .retain(|validator| validator.pub_key != update.pub_key);
+            if update.power.value() > 0 {
+                self.validators.push(update.clone());
+            }
+        }
+    }
+}
+
+/// The protobuf encoding of a [`ChainState`].
+#[derive(Clone, PartialEq, Message)]
+struct RawChainState {
+    #[prost(string, tag = "1")]
+    chain_id: String,
+    #[prost(message, optional, tag = "2")]
+    genesis_time: Option<Timestamp>,
+    #[prost(int64, tag = "3")]
+    initial_height: i64,
+    #[prost(message, repeated, tag = "4")]
+    genesis_validators: Vec<ValidatorUpdate>,
+    #[prost(message, optional, tag = "5")]
+    genesis_params: Option<ConsensusParams>,
+    #[prost(int64, tag = "6")]
+    height: i64,
+    #[prost(message, repeated, tag = "7")]
+    validators: Vec<ValidatorUpdate>,
+    #[prost(message, optional, tag = "8")]
+    params: Option<ConsensusParams>,
+}
+
+impl ChainState {
+    /// Encodes the state as protobuf.
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let validators = |validators: &[validator::Update]| {
+            validators
+                .iter()
+                .cloned()
+                .map(ValidatorUpdate::from)
+                .collect()
+        };
+        RawChainState {
+            chain_id: self.chain_id.clone(),
+            genesis_time: Some(self.genesis_time.into()),
+            initial_height: self.initial_height.into(),
+            genesis_validators: validators(&self.genesis_validators),
+            genesis_params: Some(self.genesis_params.clone().into()),
+            height: self.height.into(),
+            validators: validators(&self.validators),
+            params: Some(self.params.clone().into()),
+        }
+        .encode_to_vec()
+    }
+
+    /// Decodes a state encoded with [`encode_to_vec`](Self::encode_to_vec).
+    pub fn decode(buf: &[u8]) -> Result<Self, BoxError> {
+        let raw = RawChainState::decode(buf)?;
+        let validators = |validators: Vec<ValidatorUpdate>| {
+            validators
+                .into_iter()
+                .map(validator::Update::try_from)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            chain_id: raw.chain_id,
+            genesis_time: raw.genesis_time.ok_or("missing genesis time")?.try_into()?,
+            initial_height: raw.initial_height.try_into()?,
+            genesis_validators: validators(raw.genesis_validators)?,
+            genesis_params: raw
+                .genesis_params
+                .ok_or("missing genesis consensus params")?
+                .try_into()?,
+            height: raw.height.try_into()?,
+            validators: validators(raw.validators)?,
+            params: raw.params.ok_or("missing consensus params")?.try_into()?,
+        })
+    }
+}
+
+/// Where a [`ChainState`] is persisted.
+pub trait ChainStateStore: Send + 'static {
+    /// The state last saved, if any.
+    fn load(&mut self) -> Result<Option<ChainState>, BoxError>;
+
+    /// Saves `state`, replacing the one saved before. It is called while the
+    /// response to `InitChain` or `Commit` is polled, so it should be quick.
+    fn save(&mut self, state: &ChainState) -> Result<(), BoxError>;
+}
+
+/// A [`ChainStateStore`] keeping the state in memory, for tests and for
+/// applications that replay the chain from genesis on each start.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    state: Option<ChainState>,
+}
+
+impl ChainStateStore for MemoryStore {
+    fn load(&mut self) -> Result<Option<ChainState>, BoxError> {
+        Ok(self.state.clone())
+    }
+
+    fn save(&mut self, state: &ChainState) -> Result<(), BoxError> {
+        self.state = Some(state.clone());
+        Ok(())
+    }
+}
+
+/// A [`ChainStateStore`] keeping the state, protobuf encoded, in a file,
+/// replaced atomically on each save.
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    /// Keeps the state in the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl ChainStateStore for FileStore {
+    fn load(&mut self) -> Result<Option<ChainState>, BoxError> {
+        match fs::read(&self.path) {
+            Ok(buf) => Ok(Some(ChainState::decode(&buf)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&mut self, state: &ChainState) -> Result<(), BoxError> {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, state.encode_to_vec())?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Checks a consensus parameter update against the current parameters.
+type ParamsValidator =
+    Arc<dyn Fn(&consensus::Params, &consensus::Params) -> Result<(), String> + Send + Sync>;
+
+/// The state shared by the layer, its services and handles.
+struct Shared {
+    store: Mutex<Box<dyn ChainStateStore>>,
+    /// The state as of the last commit.
+    state: RwLock<Option<ChainState>>,
+    /// The updates of the block being executed.
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    height: Option<block::Height>,
+    validators: Vec<validator::Update>,
+    params: Option<consensus::Params>,
+}
+
+impl Shared {
+    fn save(&self, state: ChainState) -> Result<(), BoxError> {
+        self.store.lock().unwrap().save(&state)?;
+        *self.state.write().unwrap() = Some(state);
+        Ok(())
+    }
+
+    fn init_chain(
+        &self,
+        request: &tendermint::abci::request::InitChain,
+        response: &tendermint::abci::response::InitChain,
+    ) -> Result<(), BoxError> {
+        let validators = if response.validators.is_empty() {
+            request.validators.clone()
+        } else {
+            response.validators.clone()
+        };
+        let params = response
+            .consensus_params
+            .clone()
+            .unwrap_or_else(|| request.consensus_params.clone());
+        self.save(ChainState {
+            chain_id: request.chain_id.clone(),
+            genesis_time: request.time,
+            initial_height: request.initial_height,
+            genesis_validators: validators.clone(),
+            genesis_params: params.clone(),
+            height: 0u32.into(),
+            validators,
+            params,
+        })
+    }
+
+    fn end_block(
+        &self,
+        validators: &[validator::Update],
+        params: Option<&consensus::Params>,
+        validate: Option<&ParamsValidator>,
+    ) -> Result<(), BoxError> {
+        if let (Some(update), Some(validate)) = (params, validate) {
+            let state = self.state.read().unwrap();
+            if let Some(state) = state.as_ref() {
+                validate(&state.params, update)
+                    .map_err(|e| format!("invalid consensus parameter update: {}", e))?;
+            }
+        }
+        let mut pending = self.pending.lock().unwrap();
+        pending.validators.extend_from_slice(validators);
+        if let Some(params) = params {
+            pending.params = Some(params.clone());
+        }
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<(), BoxError> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let Some(mut state) = self.state.read().unwrap().clone() else {
+            tracing::warn!("committed a block before InitChain, chain state not updated");
+            return Ok(());
+        };
+        if let Some(height) = pending.height {
+            state.height = height;
+        }
+        state.update_validators(&pending.validators);
+        if let Some(params) = pending.params {
+            state.params = params;
+        }
+        self.save(state)
+    }
+}
+
+/// A handle reading the [`ChainState`] kept by a [`ChainStateLayer`].
+#[derive(Clone)]
+pub struct ChainStateHandle {
+    shared: Arc<Shared>,
+}
+
+impl ChainStateHandle {
+    /// The state as of the last commit, or `None` before `InitChain`.
+    pub fn get(&self) -> Option<ChainState> {
+        self.shared.state.read().unwrap().clone()
+    }
+}
+
+/// Applies [`ChainStateKeeper`] to a consensus service.
+#[derive(Clone)]
+pub struct ChainStateLayer {
+    shared: Arc<Shared>,
+    validate: Option<ParamsValidator>,
+}
+
+impl ChainStateLayer {
+    /// Keeps the chain state in `store`, starting from the state it holds, if
+    /// any.
+    pub fn new(mut store: impl ChainStateStore) -> Result<Self, BoxError> {
+        let state = store.load()?;
+        Ok(Self {
+            shared: Arc::new(Shared {
+                store: Mutex::new(Box::new(store)),
+                state: RwLock::new(state),
+                pending: Mutex::new(Pending::default()),
+            }),
+            validate: None,
+        })
+    }
+
+    /// A handle reading the state kept by this layer.
+    pub fn handle(&self) -> ChainStateHandle {
+        ChainStateHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Checks each consensus parameter update returned by the consensus
+    /// service with `validate`, called with the current parameters and the
+    /// update. An update it rejects fails the request, which stops the node
+    /// instead of having it apply invalid parameters. Disabled by default.
+    pub fn validate_params<F>(mut self, validate: F) -> Self
+    where
+        F: Fn(&consensus::Params, &consensus::Params) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.validate = Some(Arc::new(validate));
+        self
+    }
+}
+
+impl<S> Layer<S> for ChainStateLayer {
+    type Service = ChainStateKeeper<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ChainStateKeeper {
+            inner,
+            shared: self.shared.clone(),
+            validate: self.validate.clone(),
+        }
+    }
+}
+
+/// Keeps the chain state of the blocks executed by the inner consensus
+/// service. See the [module documentation](self) for details.
+#[derive(Clone)]
+pub struct ChainStateKeeper<S> {
+    inner: S,
+    shared: Arc<Shared>,
+    validate: Option<ParamsValidator>,
+}
+
+macro_rules! impl_consensus_service {
+    ($version:ident, |$shared:ident, $validate:ident, $response:ident| $end_block:block) => {
+        impl<S> Service<tendermint::$version::abci::ConsensusRequest> for ChainStateKeeper<S>
+        where
+            S: Service<
+                tendermint::$version::abci::ConsensusRequest,
+                Response = tendermint::$version::abci::ConsensusResponse,
+                Error = BoxError,
+            >,
+            S::Future: Send + 'static,
+        {
+            type Response = S::Response;
+            type Error = BoxError;
+            type Future = BoxFuture<'static, Result<Self::Response, BoxError>>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::ConsensusRequest) -> Self::Future {
+                #[allow(unused_imports)]
+                use tendermint::$version::abci::{ConsensusRequest, ConsensusResponse};
+                use $crate::message::RequestExt;
+
+                let $shared = self.shared.clone();
+                let $validate = self.validate.clone();
+                let init_chain = match &req {
+                    ConsensusRequest::InitChain(init_chain) => Some(init_chain.clone()),
+                    _ => None,
+                };
+                if let Some(height) = req.height() {
+                    $shared.pending.lock().unwrap().height = Some(height);
+                }
+                let response = self.inner.call(req);
+                async move {
+                    let $response = response.await?;
+                    match (&$response, init_chain) {
+                        (ConsensusResponse::InitChain(response), Some(request)) => {
+                            $shared.init_chain(&request, response)?
+                        }
+                        (ConsensusResponse::Commit(_), _) => $shared.commit()?,
+                        _ => $end_block,
+                    }
+                    Ok($response)
+                }
+                .boxed()
+            }
+        }
+    };
+}
+
+impl_consensus_service!(v0_34, |shared, validate, response| {
+    if let ConsensusResponse::EndBlock(end_block) = &response {
+        shared.end_block(
+            &end_block.validator_updates,
+            end_block.consensus_param_updates.as_ref(),
+            validate.as_ref(),
+        )?;
+    }
+});
+impl_consensus_service!(v0_37, |shared, validate, response| {
+    if let ConsensusResponse::EndBlock(end_block) = &response {
+        shared.end_block(
+            &end_block.validator_updates,
+            end_block.consensus_param_updates.as_ref(),
+            validate.as_ref(),
+        )?;
+    }
+});
+impl_consensus_service!(v0_38, |shared, validate, response| {
+    if let ConsensusResponse::FinalizeBlock(finalize_block) = &response {
+        shared.end_block(
+            &finalize_block.validator_updates,
+            finalize_block.consensus_param_updates.as_ref(),
+            validate.as_ref(),
+        )?;
+    }
+});
@@ -0,0 +1,290 @@
+//! Halting the application at an upgrade height.
+//!
+//! When a chain upgrades its software at a given height, every node must stop
+//! before executing the block at that height, so that operators can swap the
+//! binary and restart, with the blocks before it all executed by the old
+//! version. [`HaltLayer`] stops the consensus service there, and signals the
+//! process to exit:
+//!
+//! ```no_run
+//! # use tower::ServiceBuilder;
+//! # use tower_abci::{apps::NoopApp, middleware::halt::HaltLayer, v038::Server, BoxError};
+//! # #[cfg(all(feature = "net", feature = "tracing"))]
+//! # async fn example() -> Result<(), BoxError> {
+//! # let (consensus, upgrade_height, addr) = (NoopApp, 1_200_000u32.into(), "127.0.0.1:26658");
+//! let halt = HaltLayer::new(upgrade_height);
+//! let consensus = ServiceBuilder::new().layer(halt.clone()).service(consensus);
+//! let server = Server::builder()
+//!     .consensus(consensus)
+//! #   .mempool(NoopApp)
+//! #   .info(NoopApp)
+//! #   .snapshot(NoopApp)
+//!     // ...
+//!     .finish()
+//!     .unwrap();
+//! halt.drain(server.handle());
+//! server.listen_tcp(addr).await?;
+//! if let Some(height) = halt.height() {
+//!     tracing::info!(%height, "halted for the upgrade, exiting");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The first consensus request for a block at or above the upgrade height, and
+//! every consensus request after it, is never passed to the inner service. By
+//! default it is left unanswered, so that the node waits, as it does for an
+//! application that halted; with [`fail`](HaltLayer::fail), it fails with
+//! [`Halted`] instead, which the server answers according to its error policy.
+//! The requests that carry no height, `DeliverTx` and `Commit`, belong to the
+//! block tracked by [`BlockHeight`]. A server that delivers them in batches,
+//! with `deliver_tx_batch`, bypasses the consensus service for them, so every
+//! consensus request of its connections fails instead.
+//!
+//! Once halted, [`halted`](HaltLayer::halted) resolves, and the server given to
+//! [`drain`](HaltLayer::drain), if any, is drained, so that its `listen_*`
+//! method returns.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use tendermint::block;
+use tokio::sync::watch;
+use tower::{Layer, Service};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{middleware::batch, BlockHeight, BoxError, RequestExt, ServerHandle};
+
+/// The state shared by the layer and its services.
+#[derive(Debug)]
+struct Shared {
+    /// The height the services halted at, once they did.
+    halted: watch::Sender<Option<block::Height>>,
+    /// The server to drain once halted.
+    drain: Mutex<Option<ServerHandle>>,
+}
+
+/// Applies [`Halt`] to a consensus service. Clones share the same halt.
+#[derive(Clone)]
+pub struct HaltLayer {
+    height: block::Height,
+    fail: bool,
+    shared: Arc<Shared>,
+}
+
+impl HaltLayer {
+    /// Halts before executing the block at `height`.
+    pub fn new(height: block::Height) -> Self {
+        Self {
+            height,
+            fail: false,
+            shared: Arc::new(Shared {
+                halted: watch::channel(None).0,
+                drain: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Fails the halted requests with [`Halted`], rather than leaving them
+    /// unanswered.
+    pub fn fail(mut self, fail: bool) -> Self {
+        self.fail = fail;
+        self
+    }
+
+    /// Drains the server of `handle` once halted, or right away if already
+    /// halted. Applies to every clone of the layer, so that it can be called
+    /// once the server is built with the layered service.
+    pub fn drain(&self, handle: ServerHandle) {
+        // Checked under the lock: `halt` records the height before taking it,
+        // so either it finds the handle, or the height is seen here.
+        let mut drain = self.shared.drain.lock().unwrap();
+        if self.is_halted() {
+            handle.drain();
+        }
+        *drain = Some(handle);
+    }
+
+    /// The height to halt at.
+    pub fn halt_height(&self) -> block::Height {
+        self.height
+    }
+
+    /// The height of the first block that wasn't executed, once halted.
+    pub fn height(&self) -> Option<block::Height> {
+        *self.shared.halted.borrow()
+    }
+
+    /// Returns `true` once halted.
+    pub fn is_halted(&self) -> bool {
+        self.height().is_some()
+    }
+
+    /// Resolves once halted, with the height of the first block that wasn't
+    /// executed.
+    pub async fn halted(&self) -> block::Height {
+        let mut halted = self.shared.halted.subscribe();
+        let height = halted
+            .wait_for(Option::is_some)
+            .await
+            .expect("the sender is owned by the layer");
+        height.expect("waited for a height")
+    }
+
+    fn halt(&self, height: block::Height) {
+        let halted = self.shared.halted.send_if_modified(|halted| match halted {
+            Some(_) => false,
+            None => {
+                *halted = Some(height);
+                true
+            }
+        });
+        if halted {
+            tracing::warn!(
+                %height,
+                halt_height = %self.height,
+                "halting the consensus service for an upgrade"
+            );
+            if let Some(handle) = &*self.shared.drain.lock().unwrap() {
+                handle.drain();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for HaltLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HaltLayer")
+            .field("height", &self.height)
+            .field("fail", &self.fail)
+            .field("halted", &self.height())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for HaltLayer {
+    type Service = Halt<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Halt {
+            inner,
+            layer: self.clone(),
+            height: BlockHeight::new(),
+        }
+    }
+}
+
+/// Stops passing the consensus requests to the inner service from the upgrade
+/// height on. See the [module documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct Halt<S> {
+    inner: S,
+    layer: HaltLayer,
+    height: BlockHeight,
+}
+
+impl<S, R> Service<R> for Halt<S>
+where
+    S: Service<R>,
+    S::Error: Into<BoxError>,
+    R: RequestExt,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.layer.is_halted() {
+            // The inner service isn't called anymore.
+            return Poll::Ready(Ok(()));
+        }
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        if let Some(error) = batch::reject("HaltLayer") {
+            return ResponseFuture {
+                inner: None,
+                halted: None,
+                error: Some(error),
+            };
+        }
+        let height = self.height.observe(&req).or(self.height.get());
+        if let Some(height) = height.filter(|height| *height >= self.layer.height) {
+            self.layer.halt(height);
+        }
+        match self.layer.height() {
+            Some(height) => ResponseFuture {
+                inner: None,
+                halted: self.layer.fail.then_some(height),
+                error: None,
+            },
+            None => ResponseFuture {
+                inner: Some(self.inner.call(req)),
+                halted: None,
+                error: None,
+            },
+        }
+    }
+}
+
+/// Response future for [`Halt`].
+#[pin_project]
+pub struct ResponseFuture<F> {
+    /// The call to the inner service, unless the request was halted.
+    #[pin]
+    inner: Option<F>,
+    /// The height to fail a halted request with, if it fails rather than
+    /// staying unanswered.
+    halted: Option<block::Height>,
+    /// The error to fail the request with, if it couldn't be halted safely.
+    error: Option<BoxError>,
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(error) = this.error.take() {
+            return Poll::Ready(Err(error));
+        }
+        match (this.inner.as_pin_mut(), this.halted) {
+            (Some(inner), _) => inner.poll(cx).map_err(Into::into),
+            (None, Some(height)) => Poll::Ready(Err(Halted { height: *height }.into())),
+            (None, None) => Poll::Pending,
+        }
+    }
+}
+
+/// The error returned by the requests that [`Halt`] failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Halted {
+    height: block::Height,
+}
+
+impl Halted {
+    /// The height of the first block that wasn't executed.
+    pub fn height(&self) -> block::Height {
+        self.height
+    }
+}
+
+impl fmt::Display for Halted {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "halted at height {} for an upgrade", self.height)
+    }
+}
+
+impl std::error::Error for Halted {}
@@ -0,0 +1,424 @@
+//! Emits a record of each committed transaction to an indexer.
+//!
+//! [`TxIndexLayer`] wraps the consensus service and observes the results of
+//! the transactions it executes, from its `DeliverTx` or `FinalizeBlock`
+//! responses. Once the block is committed, it hands a [`TxRecord`] of each of
+//! its transactions, in block order, to a [`TxSink`]:
+//!
+//! ```
+//! # use tower::ServiceBuilder;
+//! # use tower_abci::{apps::NoopApp, middleware::index::{TxIndexLayer, TxRecord}};
+//! # let consensus = NoopApp;
+//! let (records, mut receiver) = tokio::sync::mpsc::unbounded_channel::<TxRecord>();
+//! let consensus = ServiceBuilder::new()
+//!     .layer(TxIndexLayer::new(records))
+//!     .service(consensus);
+//! ```
+//!
+//! Sinks are provided for channels, files ([`FileSink`]) and callbacks
+//! ([`sink_fn`]). The records of a block are dropped if the connection closes
+//! before the block is committed, or if `Commit` fails, so an indexer only
+//! sees the transactions of committed blocks.
+
+use std::{
+    fmt,
+    fs::File,
+    future::Future,
+    io::{BufWriter, Write},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use sha2::{Digest, Sha256};
+use tendermint::{
+    abci::{Code, Event},
+    block, Hash,
+};
+use tokio::sync::mpsc;
+use tower::{Layer, Service};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::BoxError;
+
+/// The record of a committed transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxRecord {
+    /// The height of the block of the transaction.
+    pub height: block::Height,
+    /// The index of the transaction in its block.
+    pub index: u32,
+    /// The SHA-256 hash of the transaction, as CometBFT indexes it.
+    pub hash: Hash,
+    /// The result code of the transaction.
+    pub code: Code,
+    /// The events emitted by the transaction.
+    pub events: Vec<Event>,
+}
+
+/// A destination for the records of committed transactions.
+pub trait TxSink: Send + 'static {
+    /// Receives the records of the transactions of the block at `height`, in
+    /// block order, once the block is committed.
+    ///
+    /// It is called while the response to `Commit` is polled, so it should
+    /// not block for long. An error is logged, and the records are dropped.
+    fn committed(&mut self, height: block::Height, records: Vec<TxRecord>) -> Result<(), BoxError>;
+}
+
+/// Sends each record to the receiver of the channel. The channel is unbounded
+/// so that a slow indexer never holds up consensus; it is up to the receiver
+/// to keep up.
+impl TxSink for mpsc::UnboundedSender<TxRecord> {
+    fn committed(
+        &mut self,
+        _height: block::Height,
+        records: Vec<TxRecord>,
+    ) -> Result<(), BoxError> {
+        for record in records {
+            self.send(record)
+                .map_err(|_| "the transaction record receiver was dropped")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns a [`TxSink`] calling `f` with the records of each committed block.
+pub fn sink_fn<F>(f: F) -> SinkFn<F>
+where
+    F: FnMut(block::Height, Vec<TxRecord>) -> Result<(), BoxError> + Send + 'static,
+{
+    SinkFn(f)
+}
+
+/// A [`TxSink`] calling a closure. See [`sink_fn`].
+#[derive(Clone)]
+pub struct SinkFn<F>(F);
+
+impl<F> fmt::Debug for SinkFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SinkFn").finish_non_exhaustive()
+    }
+}
+
+impl<F> TxSink for SinkFn<F>
+where
+    F: FnMut(block::Height, Vec<TxRecord>) -> Result<(), BoxError> + Send + 'static,
+{
+    fn committed(&mut self, height: block::Height, records: Vec<TxRecord>) -> Result<(), BoxError> {
+        (self.0)(height, records)
+    }
+}
+
+/// A [`TxSink`] writing a line per record, flushed after each block:
+///
+/// ```text
+/// <height> <index> <hash> <code> <type>.<key>=<value> ...
+/// ```
+///
+/// The hash is in upper case hex, as CometBFT shows it, and an attribute
+/// whose key or value is not UTF-8 is written in lower case hex.
+pub struct FileSink<W: Write> {
+    writer: W,
+}
+
+impl FileSink<BufWriter<File>> {
+    /// Appends the records to the file at `path`, creating it if missing.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> FileSink<W> {
+    /// Writes the records to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send + 'static> TxSink for FileSink<W> {
+    fn committed(
+        &mut self,
+        _height: block::Height,
+        records: Vec<TxRecord>,
+    ) -> Result<(), BoxError> {
+        for record in &records {
+            write!(
+                self.writer,
+                "{} {} {} {}",
+                record.height,
+                record.index,
+                record.hash,
+                record.code.value()
+            )?;
+            for event in &record.events {
+                for attribute in &event.attributes {
+                    let key = attribute
+                        .key_str()
+                        .map_or_else(|_| hex::encode(attribute.key_bytes()), str::to_owned);
+                    let value = attribute
+                        .value_str()
+                        .map_or_else(|_| hex::encode(attribute.value_bytes()), str::to_owned);
+                    write!(self.writer, " {}.{}={}", event.kind, key, value)?;
+                }
+            }
+            writeln!(self.writer)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The records of the block being executed.
+struct Index<K> {
+    sink: K,
+    height: block::Height,
+    /// The index of the next `DeliverTx` of the block.
+    next: u32,
+    records: Vec<TxRecord>,
+}
+
+impl<K: TxSink> Index<K> {
+    fn begin_block(&mut self, height: block::Height) {
+        self.height = height;
+        self.next = 0;
+        self.records.clear();
+    }
+
+    fn committed(&mut self) {
+        let mut records = std::mem::take(&mut self.records);
+        // Pipelined DeliverTx responses may complete out of order.
+        records.sort_by_key(|record| record.index);
+        let height = self.height;
+        if let Err(e) = self.sink.committed(height, records) {
+            tracing::warn!(%height, error = %e, "failed to index committed transactions");
+        }
+    }
+}
+
+fn tx_hash(tx: &[u8]) -> Hash {
+    Hash::Sha256(Sha256::digest(tx).into())
+}
+
+/// Applies [`TxIndex`] to a consensus service.
+pub struct TxIndexLayer<K> {
+    index: Arc<Mutex<Index<K>>>,
+}
+
+// Implementing Clone manually avoids an (incorrect) derived K: Clone bound
+impl<K> Clone for TxIndexLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index.clone(),
+        }
+    }
+}
+
+impl<K: TxSink> TxIndexLayer<K> {
+    /// Hands the records of committed transactions to `sink`.
+    pub fn new(sink: K) -> Self {
+        Self {
+            index: Arc::new(Mutex::new(Index {
+                sink,
+                height: 0u32.into(),
+                next: 0,
+                records: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl<S, K> Layer<S> for TxIndexLayer<K> {
+    type Service = TxIndex<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TxIndex {
+            inner,
+            index: self.index.clone(),
+        }
+    }
+}
+
+/// Emits the records of the transactions committed by the inner consensus
+/// service. See the [module documentation](self) for details.
+pub struct TxIndex<S, K> {
+    inner: S,
+    index: Arc<Mutex<Index<K>>>,
+}
+
+impl<S: Clone, K> Clone for TxIndex<S, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            index: self.index.clone(),
+        }
+    }
+}
+
+/// Observes a successful response.
+type Observer<R> = Box<dyn FnOnce(&R) + Send>;
+
+/// Response future for [`TxIndex`].
+#[pin_project]
+pub struct ResponseFuture<F, R> {
+    #[pin]
+    inner: F,
+    observe: Option<Observer<R>>,
+}
+
+impl<F, R, E> Future for ResponseFuture<F, R>
+where
+    F: Future<Output = Result<R, E>>,
+{
+    type Output = Result<R, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = futures::ready!(this.inner.poll(cx));
+        if let (Ok(response), Some(observe)) = (&output, this.observe.take()) {
+            observe(response);
+        }
+        Poll::Ready(output)
+    }
+}
+
+macro_rules! impl_consensus_service {
+    ($version:ident, |$index:ident, $req:ident| $observe:block) => {
+        impl<S, K> Service<tendermint::$version::abci::ConsensusRequest> for TxIndex<S, K>
+        where
+            S: Service<
+                tendermint::$version::abci::ConsensusRequest,
+                Response = tendermint::$version::abci::ConsensusResponse,
+            >,
+            K: TxSink,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = ResponseFuture<S::Future, S::Response>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::ConsensusRequest) -> Self::Future {
+                #[allow(unused_imports)]
+                use tendermint::$version::abci::{ConsensusRequest, ConsensusResponse};
+
+                let observe: Option<Observer<S::Response>> = match req {
+                    ConsensusRequest::Commit => {
+                        let index = self.index.clone();
+                        Some(Box::new(move |_: &ConsensusResponse| {
+                            index.lock().unwrap().committed()
+                        }))
+                    }
+                    _ => {
+                        let $index = &self.index;
+                        let $req = &req;
+                        $observe
+                    }
+                };
+                ResponseFuture {
+                    inner: self.inner.call(req),
+                    observe,
+                }
+            }
+        }
+    };
+}
+
+impl_consensus_service!(v0_34, |index, req| {
+    match req {
+        ConsensusRequest::BeginBlock(begin_block) => {
+            index.lock().unwrap().begin_block(begin_block.header.height);
+            None
+        }
+        ConsensusRequest::DeliverTx(deliver_tx) => {
+            let hash = tx_hash(&deliver_tx.tx);
+            let position = {
+                let mut index = index.lock().unwrap();
+                index.next += 1;
+                index.next - 1
+            };
+            let index = index.clone();
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::DeliverTx(deliver_tx) = response {
+                    let mut index = index.lock().unwrap();
+                    let height = index.height;
+                    index.records.push(TxRecord {
+                        height,
+                        index: position,
+                        hash,
+                        code: deliver_tx.code,
+                        events: deliver_tx.events.clone(),
+                    });
+                }
+            }))
+        }
+        _ => None,
+    }
+});
+impl_consensus_service!(v0_37, |index, req| {
+    match req {
+        ConsensusRequest::BeginBlock(begin_block) => {
+            index.lock().unwrap().begin_block(begin_block.header.height);
+            None
+        }
+        ConsensusRequest::DeliverTx(deliver_tx) => {
+            let hash = tx_hash(&deliver_tx.tx);
+            let position = {
+                let mut index = index.lock().unwrap();
+                index.next += 1;
+                index.next - 1
+            };
+            let index = index.clone();
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::DeliverTx(deliver_tx) = response {
+                    let mut index = index.lock().unwrap();
+                    let height = index.height;
+                    index.records.push(TxRecord {
+                        height,
+                        index: position,
+                        hash,
+                        code: deliver_tx.code,
+                        events: deliver_tx.events.clone(),
+                    });
+                }
+            }))
+        }
+        _ => None,
+    }
+});
+impl_consensus_service!(v0_38, |index, req| {
+    match req {
+        ConsensusRequest::FinalizeBlock(finalize_block) => {
+            let height = finalize_block.height;
+            index.lock().unwrap().begin_block(height);
+            let hashes: Vec<_> = finalize_block.txs.iter().map(|tx| tx_hash(tx)).collect();
+            let index = index.clone();
+            Some(Box::new(move |response: &ConsensusResponse| {
+                if let ConsensusResponse::FinalizeBlock(finalize_block) = response {
+                    let records = hashes.into_iter().zip(&finalize_block.tx_results).zip(0..);
+                    index.lock().unwrap().records = records
+                        .map(|((hash, result), position)| TxRecord {
+                            height,
+                            index: position,
+                            hash,
+                            code: result.code,
+                            events: result.events.clone(),
+                        })
+                        .collect();
+                }
+            }))
+        }
+        _ => None,
+    }
+});
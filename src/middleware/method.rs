@@ -0,0 +1,118 @@
+//! Applying middleware to individual ABCI methods.
+//!
+//! The layers of a component service apply to every method of its category,
+//! while some only make sense for one of them, e.g. a timeout on `Query` but
+//! not on `Info`. [`ByMethod`] sends the requests of the methods it has a
+//! layer for through that layer, and the others straight to the service:
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use tendermint::v0_38::abci::{InfoRequest, InfoResponse};
+//! # use tower::{timeout::TimeoutLayer, ServiceBuilder};
+//! # use tower_abci::{apps::NoopApp, middleware::{echo::EchoLayer, method::ByMethod}};
+//! # let info = NoopApp;
+//! let info = ByMethod::<_, InfoRequest, InfoResponse>::new(info)
+//!     .layer("Query", TimeoutLayer::new(Duration::from_secs(2)))
+//!     .layer(
+//!         "Echo",
+//!         ServiceBuilder::new().layer(EchoLayer::identity("kvstore", "1.2.0", None)),
+//!     );
+//! ```
+//!
+//! Methods are named as by [`RequestExt::method`], e.g. `"CheckTx"`. Each
+//! layer is applied to a clone of the service when it is added, so the
+//! service should be cheap to clone and share its state between clones, as
+//! the services returned by [`split`](crate::v038::split) do. The requests of
+//! a method with a layer wait for the layered service to be ready once they
+//! are called, rather than in `poll_ready`.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    task::{Context, Poll},
+};
+
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use tower::{util::BoxCloneService, Layer, Service, ServiceExt};
+
+use crate::{BoxError, RequestExt};
+
+/// Applies layers to the requests of individual methods. See the [module
+/// documentation](self) for details.
+pub struct ByMethod<S, Request, Response> {
+    inner: S,
+    methods: HashMap<&'static str, BoxCloneService<Request, Response, BoxError>>,
+}
+
+impl<S, Request, Response> ByMethod<S, Request, Response>
+where
+    S: Service<Request, Response = Response> + Clone,
+{
+    /// Sends every request to `inner`, until layers are added.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Sends the requests for `method` through `layer` applied to a clone of
+    /// the service, replacing the layer the method had, if any.
+    pub fn layer<L>(mut self, method: &'static str, layer: L) -> Self
+    where
+        L: Layer<S>,
+        L::Service: Service<Request, Response = Response> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Error: Into<BoxError> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        let service = layer.layer(self.inner.clone()).map_err(Into::into);
+        self.methods.insert(method, BoxCloneService::new(service));
+        self
+    }
+}
+
+// Implementing Clone manually avoids derived bounds on the request and
+// response types.
+impl<S: Clone, Request, Response> Clone for ByMethod<S, Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            methods: self.methods.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, Request, Response> fmt::Debug for ByMethod<S, Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut methods: Vec<_> = self.methods.keys().collect();
+        methods.sort();
+        f.debug_struct("ByMethod")
+            .field("inner", &self.inner)
+            .field("methods", &methods)
+            .finish()
+    }
+}
+
+impl<S, Request, Response> Service<Request> for ByMethod<S, Request, Response>
+where
+    S: Service<Request, Response = Response>,
+    S::Error: Into<BoxError> + 'static,
+    S::Future: Send + 'static,
+    Request: RequestExt + Send + 'static,
+    Response: 'static,
+{
+    type Response = Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match self.methods.get(req.method()) {
+            Some(service) => service.clone().oneshot(req).boxed(),
+            None => self.inner.call(req).map_err(Into::into).boxed(),
+        }
+    }
+}
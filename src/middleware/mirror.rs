@@ -0,0 +1,310 @@
+//! Mirroring the consensus requests to a shadow application.
+//!
+//! A new version of an application is best tested on the traffic it will
+//! serve. [`MirrorLayer`] sends a copy of every request of the consensus
+//! service to a second, "shadow", service, e.g. the new version running
+//! against a copy of the state, and compares its responses with those of the
+//! primary service, which alone answer the node:
+//!
+//! ```ignore
+//! let mirror = MirrorLayer::new(NewApp::open(&shadow_home)?, 1000);
+//! let divergences = mirror.clone();
+//! let consensus = ServiceBuilder::new().layer(mirror).service(consensus);
+//! // Later:
+//! tracing::info!(divergences = divergences.divergences(), "canary report");
+//! ```
+//!
+//! The shadow service runs on a task of its own, fed through a queue, so it
+//! never slows down the primary service. If it falls behind by more than the
+//! capacity of the queue, the request that doesn't fit is dropped; since the
+//! shadow's state no longer follows the primary's from then on, mirroring
+//! stops for good, and [`is_stopped`](MirrorLayer::is_stopped) says so. The
+//! same happens if the shadow service fails to become ready.
+//!
+//! Each response of the shadow service that differs from the primary's, or
+//! that fails while the primary's succeeds, is logged at `WARN` with both
+//! responses, and counted in [`metrics::MIRROR_DIVERGENCES`]. Responses that
+//! legitimately differ, e.g. in the app version, can be compared with
+//! [`compare_with`](MirrorLayer::compare_with) instead of `==`.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use tokio::sync::{mpsc, oneshot};
+use tower::{Layer, Service, ServiceExt};
+use tracing::Instrument;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{metrics, task, BoxError, RequestExt};
+
+/// Decides whether the shadow's response matches the primary's.
+type Compare<Response> = Arc<dyn Fn(&Response, &Response) -> bool + Send + Sync>;
+
+/// A request for the shadow service, with the response of the primary
+/// service, once it has one.
+struct Mirrored<Request, Response> {
+    request: Request,
+    primary: oneshot::Receiver<Result<Response, String>>,
+}
+
+/// The state shared by the layer, its services and the shadow task.
+struct Shared<Response> {
+    compare: RwLock<Compare<Response>>,
+    stopped: AtomicBool,
+    mirrored: AtomicU64,
+    divergences: AtomicU64,
+}
+
+impl<Response> Shared<Response> {
+    fn stop(&self, reason: &str) {
+        if !self.stopped.swap(true, Ordering::Relaxed) {
+            tracing::error!(reason, "stopped mirroring requests to the shadow service");
+        }
+    }
+}
+
+/// Applies [`Mirror`] to a consensus service. Clones share the same shadow
+/// service.
+pub struct MirrorLayer<Request, Response> {
+    queue: mpsc::Sender<Mirrored<Request, Response>>,
+    shared: Arc<Shared<Response>>,
+}
+
+impl<Request, Response> MirrorLayer<Request, Response>
+where
+    Request: RequestExt + Send + 'static,
+    Response: fmt::Debug + PartialEq + Send + Sync + 'static,
+{
+    /// Mirrors the requests to `shadow`, on a new task, queueing at most
+    /// `capacity` requests for it. Must be called from within a tokio
+    /// runtime.
+    pub fn new<S>(shadow: S, capacity: usize) -> Self
+    where
+        S: Service<Request, Response = Response> + Send + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send,
+    {
+        let (queue, requests) = mpsc::channel(capacity.max(1));
+        let shared = Arc::new(Shared {
+            compare: RwLock::new(
+                Arc::new(|primary: &Response, shadow: &Response| primary == shadow)
+                    as Compare<Response>,
+            ),
+            stopped: AtomicBool::new(false),
+            mirrored: AtomicU64::new(0),
+            divergences: AtomicU64::new(0),
+        });
+        task::spawn(
+            "abci-mirror",
+            run(shadow, requests, shared.clone()).instrument(tracing::info_span!("abci_mirror")),
+        );
+        Self { queue, shared }
+    }
+}
+
+impl<Request, Response> MirrorLayer<Request, Response> {
+    /// Compares the responses with `compare`, called with the primary's and
+    /// the shadow's, instead of `==`.
+    pub fn compare_with<F>(self, compare: F) -> Self
+    where
+        F: Fn(&Response, &Response) -> bool + Send + Sync + 'static,
+    {
+        *self.shared.compare.write().unwrap() = Arc::new(compare);
+        self
+    }
+
+    /// The number of responses of the shadow service compared so far.
+    pub fn mirrored(&self) -> u64 {
+        self.shared.mirrored.load(Ordering::Relaxed)
+    }
+
+    /// The number of responses of the shadow service that differed from the
+    /// primary's so far.
+    pub fn divergences(&self) -> u64 {
+        self.shared.divergences.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` once mirroring stopped, because the shadow service fell
+    /// behind or failed.
+    pub fn is_stopped(&self) -> bool {
+        self.shared.stopped.load(Ordering::Relaxed)
+    }
+}
+
+/// Feeds the shadow service the mirrored requests, in order, and compares
+/// its responses with the primary's.
+async fn run<S, Request, Response>(
+    mut shadow: S,
+    mut requests: mpsc::Receiver<Mirrored<Request, Response>>,
+    shared: Arc<Shared<Response>>,
+) where
+    S: Service<Request, Response = Response>,
+    S::Error: Into<BoxError>,
+    Request: RequestExt,
+    Response: fmt::Debug,
+{
+    while let Some(Mirrored { request, primary }) = requests.recv().await {
+        let method = request.method();
+        if let Err(e) = shadow.ready().await.map_err(|e| e.into().to_string()) {
+            shared.stop(&format!("shadow service failed: {e}"));
+            return;
+        }
+        let shadow_response = shadow.call(request).await.map_err(|e| e.into().to_string());
+        let Ok(primary) = primary.await else {
+            // The primary's response was dropped, e.g. with its connection.
+            continue;
+        };
+        shared.mirrored.fetch_add(1, Ordering::Relaxed);
+        let compare = shared.compare.read().unwrap().clone();
+        let diverged = match (&primary, &shadow_response) {
+            (Ok(primary), Ok(shadow)) => !compare(primary, shadow),
+            (Ok(_), Err(_)) => true,
+            (Err(_), _) => false,
+        };
+        if diverged {
+            shared.divergences.fetch_add(1, Ordering::Relaxed);
+            metrics::mirror_diverged(method);
+            tracing::warn!(
+                method,
+                ?primary,
+                shadow = ?shadow_response,
+                "shadow response diverged from the primary's"
+            );
+        }
+    }
+}
+
+impl<Request, Response> Clone for MirrorLayer<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for MirrorLayer<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MirrorLayer")
+            .field("mirrored", &self.mirrored())
+            .field("divergences", &self.divergences())
+            .field("stopped", &self.is_stopped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Request, Response> Layer<S> for MirrorLayer<Request, Response> {
+    type Service = Mirror<S, Request, Response>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Mirror {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Sends a copy of each request to a shadow service, and compares its
+/// responses with those of the inner service. See the [module
+/// documentation](self) for details.
+pub struct Mirror<S, Request, Response> {
+    inner: S,
+    layer: MirrorLayer<Request, Response>,
+}
+
+impl<S: Clone, Request, Response> Clone for Mirror<S, Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, Request, Response> fmt::Debug for Mirror<S, Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mirror")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S, Request, Response> Service<Request> for Mirror<S, Request, Response>
+where
+    S: Service<Request, Response = Response>,
+    S::Error: fmt::Display,
+    Request: Clone,
+    Response: Clone,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, Response>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut primary = None;
+        if !self.layer.is_stopped() {
+            let (sender, receiver) = oneshot::channel();
+            let mirrored = Mirrored {
+                request: req.clone(),
+                primary: receiver,
+            };
+            match self.layer.queue.try_send(mirrored) {
+                Ok(()) => primary = Some(sender),
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    self.layer.shared.stop("the shadow service fell behind")
+                }
+                // The shadow task ended, and already said why.
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+        ResponseFuture {
+            inner: self.inner.call(req),
+            primary,
+        }
+    }
+}
+
+/// Response future for [`Mirror`].
+#[pin_project]
+pub struct ResponseFuture<F, Response> {
+    #[pin]
+    inner: F,
+    /// Hands the response to the shadow task, if the request was mirrored.
+    primary: Option<oneshot::Sender<Result<Response, String>>>,
+}
+
+impl<F, Response, E> Future for ResponseFuture<F, Response>
+where
+    F: Future<Output = Result<Response, E>>,
+    E: fmt::Display,
+    Response: Clone,
+{
+    type Output = Result<Response, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = futures::ready!(this.inner.poll(cx));
+        if let Some(primary) = this.primary.take() {
+            let _ = primary.send(match &output {
+                Ok(response) => Ok(response.clone()),
+                Err(e) => Err(e.to_string()),
+            });
+        }
+        Poll::Ready(output)
+    }
+}
@@ -0,0 +1,28 @@
+//! Tower middleware for ABCI component services.
+//!
+//! These layers are generic over the request types of every supported protocol
+//! version, and can be applied to the consensus, mempool, info, and snapshot
+//! services before they are handed to a `Server`.
+
+pub(crate) mod batch;
+pub mod deadline;
+pub mod dedupe;
+pub mod echo;
+pub mod events;
+#[cfg(feature = "fault")]
+pub mod fault;
+pub mod filter;
+pub mod genesis;
+pub mod halt;
+pub mod index;
+pub mod method;
+pub mod mirror;
+pub mod priority;
+pub mod raw;
+pub mod simulate;
+pub mod slow;
+pub mod supervise;
+pub mod swap;
+pub mod upgrade;
+#[cfg(feature = "net")]
+pub mod wal;
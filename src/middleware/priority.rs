@@ -0,0 +1,165 @@
+//! Sets the mempool priority of accepted transactions.
+//!
+//! The prioritized mempool of CometBFT 0.34 and 0.37 orders transactions by
+//! the `priority` of their `CheckTx` response, evicts the lowest priority ones
+//! when full, and allows one transaction per `sender` at a time.
+//! [`PriorityLayer`] fills in these fields with callbacks, so that the mempool
+//! service doesn't need to know about them:
+//!
+//! ```ignore
+//! let mempool = ServiceBuilder::new()
+//!     .layer(PriorityLayer::new(|_, response| response.gas_wanted).sender(|check_tx, _| signer(&check_tx.tx)))
+//!     .service(mempool);
+//! ```
+//!
+//! The priorities given are recorded in [`metrics::TX_PRIORITY`], to follow
+//! the distribution of fees, or whatever the priority stands for, over time.
+//!
+//! CometBFT 0.38 dropped the prioritized mempool, and ignores these fields, so
+//! the layer only applies to the mempool services of 0.34 and 0.37. None of
+//! the supported protocol versions has mempool lanes.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use tendermint::abci::{request, response};
+use tower::{Layer, Service};
+
+use crate::metrics;
+
+/// A callback computing a field of the `CheckTx` response of an accepted
+/// transaction.
+type Callback<T> = Arc<dyn Fn(&request::CheckTx, &response::CheckTx) -> T + Send + Sync>;
+
+/// Applies [`Priority`] to a mempool service.
+#[derive(Clone)]
+pub struct PriorityLayer {
+    priority: Callback<i64>,
+    sender: Option<Callback<String>>,
+}
+
+impl PriorityLayer {
+    /// Sets the priority of each accepted transaction to `priority` of the
+    /// request and the response of the mempool service.
+    pub fn new<F>(priority: F) -> Self
+    where
+        F: Fn(&request::CheckTx, &response::CheckTx) -> i64 + Send + Sync + 'static,
+    {
+        Self {
+            priority: Arc::new(priority),
+            sender: None,
+        }
+    }
+
+    /// Also sets the sender of each accepted transaction to `sender` of the
+    /// request and the response. By default, the sender set by the mempool
+    /// service is kept.
+    pub fn sender<F>(mut self, sender: F) -> Self
+    where
+        F: Fn(&request::CheckTx, &response::CheckTx) -> String + Send + Sync + 'static,
+    {
+        self.sender = Some(Arc::new(sender));
+        self
+    }
+}
+
+impl fmt::Debug for PriorityLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriorityLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for PriorityLayer {
+    type Service = Priority<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Priority {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Sets the priority, and optionally the sender, of the `CheckTx` responses
+/// accepting a transaction.
+#[derive(Clone)]
+pub struct Priority<S> {
+    inner: S,
+    layer: PriorityLayer,
+}
+
+/// Response future for [`Priority`].
+#[pin_project]
+pub struct ResponseFuture<F, R> {
+    #[pin]
+    inner: F,
+    request: request::CheckTx,
+    layer: PriorityLayer,
+    check_tx: fn(&mut R) -> &mut response::CheckTx,
+}
+
+impl<F, R, E> Future for ResponseFuture<F, R>
+where
+    F: Future<Output = Result<R, E>>,
+{
+    type Output = Result<R, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut output = futures::ready!(this.inner.poll(cx));
+        if let Ok(response) = &mut output {
+            let response = (this.check_tx)(response);
+            if response.code.is_ok() {
+                response.priority = (this.layer.priority)(this.request, response);
+                metrics::tx_priority(this.request.kind, response.priority);
+                if let Some(sender) = &this.layer.sender {
+                    response.sender = sender(this.request, response);
+                }
+            }
+        }
+        Poll::Ready(output)
+    }
+}
+
+macro_rules! impl_mempool_service {
+    ($version:ident) => {
+        impl<S> Service<tendermint::$version::abci::MempoolRequest> for Priority<S>
+        where
+            S: Service<
+                tendermint::$version::abci::MempoolRequest,
+                Response = tendermint::$version::abci::MempoolResponse,
+            >,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = ResponseFuture<S::Future, S::Response>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::MempoolRequest) -> Self::Future {
+                use tendermint::$version::abci::{MempoolRequest, MempoolResponse};
+
+                let MempoolRequest::CheckTx(request) = &req;
+                ResponseFuture {
+                    request: request.clone(),
+                    inner: self.inner.call(req),
+                    layer: self.layer.clone(),
+                    check_tx: |response| match response {
+                        MempoolResponse::CheckTx(check_tx) => check_tx,
+                    },
+                }
+            }
+        }
+    };
+}
+
+impl_mempool_service!(v0_34);
+impl_mempool_service!(v0_37);
@@ -0,0 +1,99 @@
+//! Handing services the encoded bytes of their requests.
+//!
+//! Some applications need the exact bytes a request was sent as, e.g. to hash
+//! or sign them, or to keep them for audit. The server keeps the encoded body
+//! of each request, without its length prefix, while it is calling
+//! [`Service::call`], as with [`RequestId::current`](crate::RequestId::current).
+//! [`RawLayer`] hands it to a service along with the decoded request:
+//!
+//! ```ignore
+//! impl Service<Raw<MempoolRequest>> for Mempool {
+//!     // ...
+//!     fn call(&mut self, raw: Raw<MempoolRequest>) -> Self::Future {
+//!         let digest = raw.bytes.as_deref().map(Sha256::digest);
+//!         // ...
+//!     }
+//! }
+//!
+//! let mempool = ServiceBuilder::new().layer(RawLayer).service(Mempool::new());
+//! ```
+//!
+//! The bytes follow the request through [`split`](crate::v038::split), but
+//! are not available to the batch services of rechecks, which are called with
+//! several requests at once. Keeping them costs no copy, since the byte
+//! strings of a decoded request share the buffer of its encoding.
+
+use std::{
+    cell::RefCell,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use tower::{Layer, Service};
+
+thread_local! {
+    static CURRENT: RefCell<Option<Bytes>> = const { RefCell::new(None) };
+}
+
+/// Returns the encoded body of the request being passed to [`Service::call`],
+/// if called from within `call` on a service invoked by the server.
+pub fn current() -> Option<Bytes> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Runs `f` with `bytes` as the encoding of the current request.
+pub(crate) fn scope<T>(bytes: Option<Bytes>, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT.with(|c| c.replace(bytes));
+    let out = f();
+    CURRENT.with(|c| *c.borrow_mut() = prev);
+    out
+}
+
+/// A request along with the bytes it was encoded as.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Raw<R> {
+    pub request: R,
+    /// The encoded body of the request, without its length prefix, or `None`
+    /// if the request was not read by the server, e.g. if the service was
+    /// called directly.
+    pub bytes: Option<Bytes>,
+}
+
+/// Applies [`WithRaw`] to a service of [`Raw`] requests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawLayer;
+
+impl<S> Layer<S> for RawLayer {
+    type Service = WithRaw<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WithRaw { inner }
+    }
+}
+
+/// Calls a service of [`Raw`] requests with the requests it is called with
+/// and their encoded bytes. See the [module documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct WithRaw<S> {
+    inner: S,
+}
+
+impl<S, Request> Service<Request> for WithRaw<S>
+where
+    S: Service<Raw<Request>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.inner.call(Raw {
+            request,
+            bytes: current(),
+        })
+    }
+}
@@ -0,0 +1,194 @@
+//! Estimates the gas of transactions by simulating them through `CheckTx`.
+//!
+//! Wallets estimate fees by executing a transaction without committing it.
+//! [`SimulateLayer`] recognizes the `CheckTx` requests marked for simulation,
+//! e.g. by a prefix of their bytes, and routes them to a simulation handler,
+//! typically executing the transaction against a cache of the state, instead
+//! of the mempool service:
+//!
+//! ```ignore
+//! let mempool = ServiceBuilder::new()
+//!     .layer(SimulateLayer::prefix(b"simulate:", simulator))
+//!     .service(mempool);
+//! ```
+//!
+//! The handler is called with the request without its marker, and its
+//! response, with the gas used and wanted, is returned to the client. A
+//! successful simulation is answered with [`DEFAULT_SIMULATED_CODE`], or the
+//! code set with [`SimulateLayer::code`], rather than zero, so that the node
+//! never adds a simulated transaction to its mempool; failed simulations keep
+//! the code of the handler.
+
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::future::{BoxFuture, FutureExt};
+use tendermint::abci::{
+    request::{self, CheckTxKind},
+    response,
+};
+use tower::{Layer, Service, ServiceExt};
+
+use crate::BoxError;
+
+/// The default code of the responses to successful simulations.
+pub const DEFAULT_SIMULATED_CODE: u32 = 1000;
+
+/// Recognizes a marked transaction, returning it without its marker.
+type Marker = Arc<dyn Fn(&Bytes) -> Option<Bytes> + Send + Sync>;
+
+/// Applies [`Simulate`] to a mempool service.
+#[derive(Clone)]
+pub struct SimulateLayer<H> {
+    marker: Marker,
+    handler: H,
+    code: u32,
+}
+
+impl<H> SimulateLayer<H> {
+    /// Simulates the transactions for which `marker` returns the transaction
+    /// to simulate, with `handler`.
+    pub fn new<F>(marker: F, handler: H) -> Self
+    where
+        F: Fn(&Bytes) -> Option<Bytes> + Send + Sync + 'static,
+    {
+        Self {
+            marker: Arc::new(marker),
+            handler,
+            code: DEFAULT_SIMULATED_CODE,
+        }
+    }
+
+    /// Simulates the transactions starting with `prefix`, without it, with
+    /// `handler`.
+    pub fn prefix(prefix: impl AsRef<[u8]>, handler: H) -> Self {
+        let prefix = Bytes::copy_from_slice(prefix.as_ref());
+        Self::new(
+            move |tx| tx.starts_with(&prefix).then(|| tx.slice(prefix.len()..)),
+            handler,
+        )
+    }
+
+    /// Answers successful simulations with `code`. Defaults to
+    /// [`DEFAULT_SIMULATED_CODE`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is zero, which would add simulated transactions to the
+    /// mempool.
+    pub fn code(mut self, code: u32) -> Self {
+        assert!(code != 0, "the code of simulations must be nonzero");
+        self.code = code;
+        self
+    }
+}
+
+impl<H> fmt::Debug for SimulateLayer<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimulateLayer")
+            .field("code", &self.code)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, H: Clone> Layer<S> for SimulateLayer<H> {
+    type Service = Simulate<S, H>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Simulate {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Routes the `CheckTx` requests marked for simulation to a simulation
+/// handler. See the [module documentation](self) for details.
+#[derive(Clone)]
+pub struct Simulate<S, H> {
+    inner: S,
+    layer: SimulateLayer<H>,
+}
+
+impl<S, H> Simulate<S, H>
+where
+    H: Service<request::CheckTx, Response = response::CheckTx, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    H::Future: Send + 'static,
+{
+    /// Simulates `check_tx` if it is a new check of a marked transaction.
+    fn simulate(
+        &self,
+        check_tx: &request::CheckTx,
+    ) -> Option<BoxFuture<'static, Result<response::CheckTx, BoxError>>> {
+        if check_tx.kind != CheckTxKind::New {
+            return None;
+        }
+        let tx = (self.layer.marker)(&check_tx.tx)?;
+        let code = self.layer.code;
+        let simulation = self.layer.handler.clone().oneshot(request::CheckTx {
+            tx,
+            kind: CheckTxKind::New,
+        });
+        Some(
+            async move {
+                let mut response = simulation.await?;
+                if response.code.is_ok() {
+                    response.code = code.into();
+                    if response.log.is_empty() {
+                        response.log = "simulated".to_string();
+                    }
+                }
+                Ok(response)
+            }
+            .boxed(),
+        )
+    }
+}
+
+macro_rules! impl_mempool_service {
+    ($version:ident) => {
+        impl<S, H> Service<tendermint::$version::abci::MempoolRequest> for Simulate<S, H>
+        where
+            S: Service<
+                tendermint::$version::abci::MempoolRequest,
+                Response = tendermint::$version::abci::MempoolResponse,
+                Error = BoxError,
+            >,
+            S::Future: Send + 'static,
+            H: Service<request::CheckTx, Response = response::CheckTx, Error = BoxError>
+                + Clone
+                + Send
+                + 'static,
+            H::Future: Send + 'static,
+        {
+            type Response = S::Response;
+            type Error = BoxError;
+            type Future = BoxFuture<'static, Result<Self::Response, BoxError>>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::MempoolRequest) -> Self::Future {
+                use tendermint::$version::abci::{MempoolRequest, MempoolResponse};
+
+                let MempoolRequest::CheckTx(check_tx) = &req;
+                match self.simulate(check_tx) {
+                    Some(simulation) => simulation.map(|r| r.map(MempoolResponse::CheckTx)).boxed(),
+                    None => self.inner.call(req).boxed(),
+                }
+            }
+        }
+    };
+}
+
+impl_mempool_service!(v0_34);
+impl_mempool_service!(v0_37);
+impl_mempool_service!(v0_38);
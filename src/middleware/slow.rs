@@ -0,0 +1,151 @@
+//! Warns about requests that take longer than expected.
+//!
+//! A slowly degrading `FinalizeBlock` or `Commit` is invisible until it starts
+//! to threaten the node's consensus timeouts. [`SlowRequestLayer`] makes it
+//! visible much earlier, by emitting a warning with the method, height, and
+//! elapsed time whenever a call to the inner service exceeds a threshold.
+//! The threshold can be adjusted while the application is running by building
+//! the layer with [`SlowRequestLayer::adjustable`].
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use pin_project::pin_project;
+use tendermint::block;
+use tokio::sync::watch;
+use tower::{Layer, Service};
+
+use crate::message::RequestExt;
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+
+/// The threshold above which calls are reported.
+#[derive(Clone, Debug)]
+enum Threshold {
+    Fixed(Duration),
+    Adjustable(watch::Receiver<Duration>),
+}
+
+impl Threshold {
+    fn get(&self) -> Duration {
+        match self {
+            Threshold::Fixed(threshold) => *threshold,
+            Threshold::Adjustable(threshold) => *threshold.borrow(),
+        }
+    }
+}
+
+/// Applies [`SlowRequest`] to a service.
+#[derive(Clone, Debug)]
+pub struct SlowRequestLayer {
+    threshold: Threshold,
+}
+
+impl SlowRequestLayer {
+    /// Warn about any call that takes at least `threshold` to complete.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold: Threshold::Fixed(threshold),
+        }
+    }
+
+    /// Warn about any call that takes at least `threshold` to complete, where
+    /// the threshold can later be changed with the returned sender. Calls use
+    /// the threshold in effect when they start.
+    pub fn adjustable(threshold: Duration) -> (Self, watch::Sender<Duration>) {
+        let (sender, receiver) = watch::channel(threshold);
+        let layer = Self {
+            threshold: Threshold::Adjustable(receiver),
+        };
+        (layer, sender)
+    }
+}
+
+impl<S> Layer<S> for SlowRequestLayer {
+    type Service = SlowRequest<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SlowRequest {
+            inner,
+            threshold: self.threshold.clone(),
+        }
+    }
+}
+
+/// Emits a warning whenever a call to the inner service exceeds a threshold.
+#[derive(Clone, Debug)]
+pub struct SlowRequest<S> {
+    inner: S,
+    threshold: Threshold,
+}
+
+impl<S> SlowRequest<S> {
+    /// Warn about any call to `inner` that takes at least `threshold` to complete.
+    pub fn new(inner: S, threshold: Duration) -> Self {
+        Self {
+            inner,
+            threshold: Threshold::Fixed(threshold),
+        }
+    }
+}
+
+impl<S, R> Service<R> for SlowRequest<S>
+where
+    S: Service<R>,
+    R: RequestExt,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let method = req.method();
+        let height = req.height();
+        ResponseFuture {
+            inner: self.inner.call(req),
+            method,
+            height,
+            threshold: self.threshold.get(),
+            start: Instant::now(),
+        }
+    }
+}
+
+/// Response future for [`SlowRequest`].
+#[pin_project]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    method: &'static str,
+    height: Option<block::Height>,
+    threshold: Duration,
+    start: Instant,
+}
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = futures::ready!(this.inner.poll(cx));
+        let elapsed = this.start.elapsed();
+        if elapsed >= *this.threshold {
+            tracing::warn!(
+                method = this.method,
+                height = this.height.map(|h| h.value()),
+                ?elapsed,
+                threshold = ?this.threshold,
+                "slow ABCI request"
+            );
+        }
+        Poll::Ready(output)
+    }
+}
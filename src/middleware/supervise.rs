@@ -0,0 +1,341 @@
+//! Recreating a component service when it fails.
+//!
+//! A service that fails `poll_ready` is broken for good, e.g. when the
+//! background task of a [`Buffer`](tower::buffer::Buffer) or of
+//! [`split`](crate::v038::split) panicked, and without supervision every
+//! connection to it fails until the process restarts. [`Supervised`] builds
+//! the service with a factory instead, and builds a new one when it fails:
+//!
+//! ```
+//! # use std::{fmt, time::Duration};
+//! # use tower_abci::{
+//! #     apps::NoopApp,
+//! #     middleware::supervise::{RestartPolicy, Supervised},
+//! #     v038::Server,
+//! #     BoxError,
+//! # };
+//! # #[derive(Debug)]
+//! # struct StorageError;
+//! # impl fmt::Display for StorageError {
+//! #     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+//! #         f.write_str("storage error")
+//! #     }
+//! # }
+//! # impl std::error::Error for StorageError {}
+//! # struct Mempool;
+//! # impl Mempool {
+//! #     fn open(_: &str) -> Result<NoopApp, StorageError> {
+//! #         Ok(NoopApp)
+//! #     }
+//! # }
+//! # let path = String::from("mempool.db");
+//! let mempool = Supervised::new(move || Ok(Mempool::open(&path)?))?
+//!     .policy(RestartPolicy::new(3, Duration::from_secs(60)))
+//!     .fatal_if(|e| e.is::<StorageError>());
+//! let server = Server::builder()
+//!     .mempool(mempool)
+//! #   .consensus(NoopApp)
+//! #   .info(NoopApp)
+//! #   .snapshot(NoopApp)
+//!     // ...
+//!     .finish()
+//!     .unwrap();
+//! # Ok::<(), BoxError>(())
+//! ```
+//!
+//! Errors from `poll_ready` are always fatal, while errors returned by calls
+//! are only fatal if [`fatal_if`](Supervised::fatal_if) says so; the request
+//! that failed is still answered according to the server's error policy. The
+//! service is shared by every connection, as with
+//! [`Swappable`](super::swap::Swappable): once it is recreated, each
+//! connection uses the new service from its next request. If the service
+//! fails more often than its [`RestartPolicy`] allows, or the factory fails,
+//! the error is returned to the server, and the connections using the service
+//! close.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use pin_project::pin_project;
+use tokio::time::Instant;
+use tower::Service;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{metrics, BoxError};
+
+/// Builds a new instance of a supervised service.
+type Factory<S> = Arc<dyn Fn() -> Result<S, BoxError> + Send + Sync>;
+
+/// Decides whether an error returned by a call is fatal to the service.
+type Fatal = Arc<dyn Fn(&BoxError) -> bool + Send + Sync>;
+
+/// How often a [`Supervised`] service may be recreated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// The number of times the service may be recreated within `within`.
+    pub max_restarts: usize,
+    /// The period over which restarts are counted.
+    pub within: Duration,
+}
+
+impl RestartPolicy {
+    /// Allows `max_restarts` restarts within any period of `within`.
+    pub fn new(max_restarts: usize, within: Duration) -> Self {
+        Self {
+            max_restarts,
+            within,
+        }
+    }
+}
+
+impl Default for RestartPolicy {
+    /// Allows 5 restarts per minute.
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(60))
+    }
+}
+
+/// A service recreated by its factory when it fails. See the [module
+/// documentation](self) for details.
+pub struct Supervised<S> {
+    shared: Arc<Shared<S>>,
+    /// The clone of the current service made ready for the next call, and its
+    /// generation.
+    ready: Option<(u64, S)>,
+}
+
+struct Shared<S> {
+    factory: Factory<S>,
+    fatal: Option<Fatal>,
+    policy: RestartPolicy,
+    state: Mutex<State<S>>,
+}
+
+struct State<S> {
+    service: S,
+    /// The number of times the service was recreated.
+    generation: u64,
+    /// When the service was recreated within the policy's period.
+    restarts: VecDeque<Instant>,
+    /// Why the service can't be recreated anymore, once it can't.
+    exhausted: Option<String>,
+}
+
+impl<S> Supervised<S> {
+    /// Builds the service with `factory`, which builds it again whenever it
+    /// fails. Fails if the factory does.
+    pub fn new<F>(factory: F) -> Result<Self, BoxError>
+    where
+        F: Fn() -> Result<S, BoxError> + Send + Sync + 'static,
+    {
+        let service = factory()?;
+        Ok(Self {
+            shared: Arc::new(Shared {
+                factory: Arc::new(factory),
+                fatal: None,
+                policy: RestartPolicy::default(),
+                state: Mutex::new(State {
+                    service,
+                    generation: 0,
+                    restarts: VecDeque::new(),
+                    exhausted: None,
+                }),
+            }),
+            ready: None,
+        })
+    }
+
+    /// Sets how often the service may be recreated. Defaults to
+    /// [`RestartPolicy::default`].
+    ///
+    /// Must be called before the service is cloned.
+    pub fn policy(mut self, policy: RestartPolicy) -> Self {
+        self.shared_mut().policy = policy;
+        self
+    }
+
+    /// Recreates the service when a call fails with an error for which
+    /// `fatal` returns `true`. By default, only readiness errors are fatal.
+    ///
+    /// Must be called before the service is cloned.
+    pub fn fatal_if<F>(mut self, fatal: F) -> Self
+    where
+        F: Fn(&BoxError) -> bool + Send + Sync + 'static,
+    {
+        self.shared_mut().fatal = Some(Arc::new(fatal));
+        self
+    }
+
+    /// The number of times the service was recreated.
+    pub fn restarts(&self) -> u64 {
+        self.shared.state.lock().unwrap().generation
+    }
+
+    fn shared_mut(&mut self) -> &mut Shared<S> {
+        Arc::get_mut(&mut self.shared).expect("Supervised configured after being cloned")
+    }
+}
+
+impl<S> Shared<S> {
+    /// Fails if the service couldn't be recreated, so that every connection
+    /// using it closes.
+    fn check(&self) -> Result<(), BoxError> {
+        match &self.state.lock().unwrap().exhausted {
+            Some(error) => Err(error.clone().into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the service of `generation`, which failed with `error`,
+    /// unless another connection already did. Fails if the service can't be
+    /// recreated, and so does every later [`check`](Self::check).
+    fn restart(&self, generation: u64, error: &BoxError) -> Result<(), BoxError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = &state.exhausted {
+            return Err(error.clone().into());
+        }
+        if state.generation != generation {
+            return Ok(());
+        }
+        let now = Instant::now();
+        while state
+            .restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) >= self.policy.within)
+        {
+            state.restarts.pop_front();
+        }
+        if state.restarts.len() >= self.policy.max_restarts {
+            tracing::error!(
+                %error,
+                max_restarts = self.policy.max_restarts,
+                within = ?self.policy.within,
+                "service failed too often, not recreating it"
+            );
+            let error = format!(
+                "service failed {} times within {:?}: {error}",
+                self.policy.max_restarts + 1,
+                self.policy.within
+            );
+            state.exhausted = Some(error.clone());
+            return Err(error.into());
+        }
+        tracing::warn!(%error, generation, "service failed, recreating it");
+        state.service = match (self.factory)() {
+            Ok(service) => service,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to recreate service");
+                state.exhausted = Some(format!("failed to recreate service: {e}"));
+                return Err(e);
+            }
+        };
+        state.generation += 1;
+        state.restarts.push_back(now);
+        metrics::service_restarted();
+        Ok(())
+    }
+}
+
+// Implementing Clone manually avoids an (incorrect) derived S: Clone bound,
+// and leaves the clone to make the current service ready on its own.
+impl<S> Clone for Supervised<S> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            ready: None,
+        }
+    }
+}
+
+impl<S> fmt::Debug for Supervised<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Supervised")
+            .field("policy", &self.shared.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Request> Service<Request> for Supervised<S>
+where
+    S: Service<Request> + Clone,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future, S>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.shared.check()?;
+        loop {
+            let shared = &self.shared;
+            let (generation, service) = self.ready.get_or_insert_with(|| {
+                let state = shared.state.lock().unwrap();
+                (state.generation, state.service.clone())
+            });
+            match service.poll_ready(cx) {
+                Poll::Ready(Err(error)) => {
+                    let generation = *generation;
+                    self.ready = None;
+                    self.shared.restart(generation, &error.into())?;
+                }
+                poll => return poll.map_err(Into::into),
+            }
+        }
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let (generation, mut service) = self
+            .ready
+            .take()
+            .expect("poll_ready must be called before call");
+        ResponseFuture {
+            inner: service.call(req),
+            shared: self.shared.fatal.is_some().then(|| self.shared.clone()),
+            generation,
+        }
+    }
+}
+
+/// Response future for [`Supervised`].
+#[pin_project]
+pub struct ResponseFuture<F, S> {
+    #[pin]
+    inner: F,
+    /// Set if call errors may be fatal.
+    shared: Option<Arc<Shared<S>>>,
+    generation: u64,
+}
+
+impl<F, S, T, E> Future for ResponseFuture<F, S>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let error = match futures::ready!(this.inner.poll(cx)) {
+            Ok(response) => return Poll::Ready(Ok(response)),
+            Err(error) => error.into(),
+        };
+        let Some(shared) = this.shared.as_ref() else {
+            return Poll::Ready(Err(error));
+        };
+        let fatal = shared.fatal.as_ref().expect("only kept with a predicate");
+        if fatal(&error) {
+            // The request fails either way, and a service that can't be
+            // recreated fails the next poll_ready, through `check`.
+            let _ = shared.restart(*this.generation, &error);
+        }
+        Poll::Ready(Err(error))
+    }
+}
@@ -0,0 +1,141 @@
+//! Replacing a component service while the server is running.
+//!
+//! [`Swappable`] wraps a service shared by every connection, and its
+//! [`SwapHandle`] replaces it, e.g. to point the info service at a new read
+//! replica without restarting the server:
+//!
+//! ```
+//! # use tendermint::abci::response;
+//! # use tower_abci::{apps::NoopApp, info::InfoService, middleware::swap::Swappable, v038::Server};
+//! # #[cfg(feature = "net")]
+//! # async fn example() {
+//! # let (replica, new_replica) = (response::Info::default(), response::Info::default());
+//! # let addr = "127.0.0.1:26658";
+//! let (info, swap) = Swappable::new(InfoService::new(replica));
+//! let server = Server::builder()
+//!     .info(info)
+//! #   .consensus(NoopApp)
+//! #   .mempool(NoopApp)
+//! #   .snapshot(NoopApp)
+//!     // ...
+//!     .finish()
+//!     .unwrap();
+//! tokio::spawn(server.listen_tcp(addr));
+//!
+//! // Later, once the new replica has caught up:
+//! swap.swap(InfoService::new(new_replica));
+//! # }
+//! ```
+//!
+//! Each request is sent to the service current when its connection polled for
+//! readiness. Requests already sent to the old service complete normally, so
+//! swapping doesn't disturb the connections, and connections that don't use
+//! the swapped service, such as the consensus connection for the info
+//! service, are unaffected.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use tower::Service;
+
+/// A service that can be replaced with its [`SwapHandle`]. See the
+/// [module documentation](self) for details.
+pub struct Swappable<S> {
+    current: Arc<Mutex<S>>,
+    /// The clone of the current service made ready for the next call.
+    ready: Option<S>,
+}
+
+/// Replaces the service of a [`Swappable`].
+pub struct SwapHandle<S> {
+    current: Arc<Mutex<S>>,
+}
+
+impl<S> Swappable<S> {
+    /// Wraps `service`, returning the wrapper and the handle replacing it.
+    pub fn new(service: S) -> (Self, SwapHandle<S>) {
+        let current = Arc::new(Mutex::new(service));
+        (
+            Self {
+                current: current.clone(),
+                ready: None,
+            },
+            SwapHandle { current },
+        )
+    }
+}
+
+// Implementing Clone manually avoids an (incorrect) derived S: Clone bound,
+// and leaves the clone to make the current service ready on its own.
+impl<S> Clone for Swappable<S> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+            ready: None,
+        }
+    }
+}
+
+impl<S> fmt::Debug for Swappable<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Swappable").finish_non_exhaustive()
+    }
+}
+
+impl<S> SwapHandle<S> {
+    /// Sends the requests of connections polling for readiness from now on to
+    /// `service`, returning the service it replaces.
+    pub fn swap(&self, service: S) -> S {
+        tracing::info!("swapping service");
+        std::mem::replace(&mut *self.current.lock().unwrap(), service)
+    }
+}
+
+impl<S: Clone> SwapHandle<S> {
+    /// A clone of the current service.
+    pub fn current(&self) -> S {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+impl<S> Clone for SwapHandle<S> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for SwapHandle<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SwapHandle").finish_non_exhaustive()
+    }
+}
+
+impl<S, Request> Service<Request> for Swappable<S>
+where
+    S: Service<Request> + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let current = &self.current;
+        self.ready
+            .get_or_insert_with(|| current.lock().unwrap().clone())
+            .poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        self.ready
+            .take()
+            .expect("poll_ready must be called before call")
+            .call(req)
+    }
+}
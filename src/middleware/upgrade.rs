@@ -0,0 +1,174 @@
+//! Routing consensus requests by block height, for coordinated upgrades.
+//!
+//! A change to consensus logic must take effect at the same height on every
+//! node, and the old logic must still be used to replay the blocks before it.
+//! Rather than checking the height all over the application, each version of
+//! the logic can be a service of its own, and [`ByHeight`] sends each request
+//! to the version active at the height of its block:
+//!
+//! ```
+//! # use tower_abci::{apps::{EchoApp, NoopApp}, middleware::upgrade::ByHeight, v038::Server};
+//! # let (v1, v2, v3) = (NoopApp, EchoApp, NoopApp);
+//! let consensus = ByHeight::new(v1)
+//!     .upgrade(1_200_000u32.into(), v2)
+//!     .upgrade(2_500_000u32.into(), v3);
+//! let server = Server::builder()
+//!     .consensus(consensus)
+//! #   .mempool(NoopApp)
+//! #   .info(NoopApp)
+//! #   .snapshot(NoopApp)
+//!     // ...
+//!     .finish()
+//!     .unwrap();
+//! ```
+//!
+//! A version is active from its activation height until the next one's. The
+//! requests that carry no height, `DeliverTx` and `Commit`, go to the version
+//! of the block they belong to, as tracked by [`BlockHeight`], and `InitChain`
+//! goes to the first version. The versions usually share the application's
+//! state, e.g. by wrapping the same store.
+//!
+//! Like [`Steer`](tower::steer::Steer), `ByHeight` is only ready once every
+//! version is, so that each request can be called on its version right away,
+//! keeping the order of the consensus requests.
+
+use std::{
+    fmt,
+    task::{Context, Poll},
+};
+
+use futures::future::{BoxFuture, FutureExt};
+use tendermint::block;
+use tower::{util::BoxCloneService, Service, ServiceExt};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{BlockHeight, BoxError, RequestExt};
+
+/// Routes the requests of a consensus connection to the version of the
+/// application active at their height. See the [module documentation](self)
+/// for details.
+pub struct ByHeight<Request, Response> {
+    /// The versions by activation height, in increasing order, starting with
+    /// the first version at height zero.
+    versions: Vec<(block::Height, BoxCloneService<Request, Response, BoxError>)>,
+    height: BlockHeight,
+    /// The index of the version the last request was sent to.
+    active: usize,
+}
+
+impl<Request, Response> ByHeight<Request, Response>
+where
+    Request: 'static,
+    Response: 'static,
+{
+    /// Sends every request to `first`, until upgrades are added.
+    pub fn new<S>(first: S) -> Self
+    where
+        S: Service<Request, Response = Response> + Clone + Send + 'static,
+        S::Error: Into<BoxError> + 'static,
+        S::Future: Send + 'static,
+    {
+        Self {
+            versions: vec![(block::Height::default(), boxed(first))],
+            height: BlockHeight::new(),
+            active: 0,
+        }
+    }
+
+    /// Sends the requests of the blocks from `height` on to `service`, until
+    /// the next upgrade, replacing the upgrade at that height, if any.
+    pub fn upgrade<S>(mut self, height: block::Height, service: S) -> Self
+    where
+        S: Service<Request, Response = Response> + Clone + Send + 'static,
+        S::Error: Into<BoxError> + 'static,
+        S::Future: Send + 'static,
+    {
+        match self.versions.binary_search_by_key(&height, |(at, _)| *at) {
+            Ok(index) => self.versions[index].1 = boxed(service),
+            Err(index) => self.versions.insert(index, (height, boxed(service))),
+        }
+        self
+    }
+
+    /// The activation heights of the versions after the first, in increasing
+    /// order.
+    pub fn upgrades(&self) -> impl Iterator<Item = block::Height> + '_ {
+        self.versions.iter().skip(1).map(|(height, _)| *height)
+    }
+}
+
+fn boxed<S, Request, Response>(service: S) -> BoxCloneService<Request, Response, BoxError>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError> + 'static,
+    S::Future: Send + 'static,
+    Request: 'static,
+{
+    BoxCloneService::new(service.map_err(Into::into))
+}
+
+// Implementing Clone manually avoids derived bounds on the request and
+// response types.
+impl<Request, Response> Clone for ByHeight<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            versions: self.versions.clone(),
+            height: self.height,
+            active: self.active,
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for ByHeight<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let upgrades: Vec<_> = self.versions.iter().skip(1).map(|(at, _)| at).collect();
+        f.debug_struct("ByHeight")
+            .field("upgrades", &upgrades)
+            .field("height", &self.height.get())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Request, Response> Service<Request> for ByHeight<Request, Response>
+where
+    Request: RequestExt + Send + 'static,
+    Response: 'static,
+{
+    type Response = Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut ready = true;
+        for (_, service) in &mut self.versions {
+            ready &= service.poll_ready(cx)?.is_ready();
+        }
+        if ready {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let active = match self.height.observe(&req).or(self.height.get()) {
+            Some(height) => self
+                .versions
+                .partition_point(|(at, _)| *at <= height)
+                .saturating_sub(1),
+            None => 0,
+        };
+        if active != self.active {
+            tracing::info!(
+                method = req.method(),
+                height = ?self.height.get(),
+                from = %self.versions[self.active].0,
+                to = %self.versions[active].0,
+                "switching to the version activated at another height"
+            );
+            self.active = active;
+        }
+        self.versions[active].1.call(req).boxed()
+    }
+}
@@ -0,0 +1,881 @@
+//! A write-ahead log of the requests of the consensus connection.
+//!
+//! The node keeps its own WAL, but it records consensus messages rather than
+//! what the application was asked to do, and it is gone along with the node's
+//! data. [`WalLayer`] appends every request of the consensus service to a file
+//! before calling the service, so that the exact sequence of requests that led
+//! an application to its state, or to a crash, can be examined and fed again
+//! to a fresh instance of the application with [`replay`]:
+//!
+//! ```no_run
+//! # use std::path::Path;
+//! # use tower::ServiceBuilder;
+//! # use tower_abci::{apps::NoopApp as App, middleware::wal::{self, WalLayer}, BoxError};
+//! # use tendermint::v0_38::abci::ConsensusRequest;
+//! # async fn example(home: &Path, consensus: App) -> Result<(), BoxError> {
+//! let wal = WalLayer::open(home.join("consensus.wal"))?;
+//! let consensus = ServiceBuilder::new().layer(wal).service(consensus);
+//!
+//! // Later, e.g. in a forensics tool:
+//! let replayed = wal::replay::<ConsensusRequest, _>(home.join("consensus.wal"), App).await?;
+//! println!("rebuilt state: {replayed:?}");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A request is written, and by default synced to disk, by a thread of the
+//! log's own, and the service is only called with it once it is, so a request
+//! that can't be persisted fails without reaching the service. The requests
+//! still reach the service in the order of their calls. A server that delivers
+//! transactions in batches, with `deliver_tx_batch`, bypasses the consensus
+//! service for them, so every consensus request of its connections fails
+//! instead of leaving a log without them.
+//!
+//! The file starts with the ABCI version of its requests, followed by each
+//! request as a record of its length, a checksum, and its protobuf encoding,
+//! as sent by the node. A record torn by a crash is dropped when the log is
+//! reopened, and ends a replay.
+//!
+//! The log grows with every block; rotating it, e.g. after each snapshot, is
+//! left to the application, by opening a new layer on another file.
+
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll},
+    thread,
+};
+
+use futures::future::{BoxFuture, FutureExt};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use tendermint::block;
+use tokio::sync::oneshot;
+use tower::{Layer, Service, ServiceExt};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{middleware::batch, BoxError, RequestExt};
+
+/// The first bytes of a log.
+const MAGIC: &[u8; 8] = b"ABCIWAL\0";
+
+/// A request that can be recorded in a write-ahead log: the consensus
+/// requests of every protocol version.
+pub trait Record: Sized {
+    /// The ABCI version of the request, e.g. `"0.38"`.
+    const PROTOCOL: &'static str;
+
+    /// Encodes the request as its protobuf `Request`.
+    fn encode_to_vec(&self) -> Vec<u8>;
+
+    /// Decodes a request encoded with [`encode_to_vec`](Self::encode_to_vec).
+    fn decode(buf: &[u8]) -> Result<Self, BoxError>;
+}
+
+macro_rules! impl_record {
+    ($version:ident, $protocol:literal) => {
+        impl Record for tendermint::$version::abci::ConsensusRequest {
+            const PROTOCOL: &'static str = $protocol;
+
+            fn encode_to_vec(&self) -> Vec<u8> {
+                let request = tendermint::$version::abci::Request::from(self.clone());
+                tendermint_proto::$version::abci::Request::from(request).encode_to_vec()
+            }
+
+            fn decode(buf: &[u8]) -> Result<Self, BoxError> {
+                let request = tendermint_proto::$version::abci::Request::decode(buf)?;
+                let request = tendermint::$version::abci::Request::try_from(request)?;
+                Ok(request.try_into()?)
+            }
+        }
+    };
+}
+
+impl_record!(v0_34, "0.34");
+impl_record!(v0_37, "0.37");
+impl_record!(v0_38, "0.38");
+
+fn checksum(body: &[u8]) -> [u8; 4] {
+    let digest = Sha256::digest(body);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+/// Reads into `buf` until it is full or the input ends, returning the number
+/// of bytes read.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Reads the header and records of a log, keeping track of the end of the
+/// last complete record.
+struct Records<R> {
+    reader: R,
+    /// The offset just past the header or the last complete record.
+    offset: u64,
+    /// Set once a record torn by a crash was found.
+    torn: bool,
+}
+
+impl<R: Read> Records<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            offset: 0,
+            torn: false,
+        }
+    }
+
+    /// Reads the protocol version in the header, or `None` if the log is
+    /// empty.
+    fn header(&mut self) -> Result<Option<String>, BoxError> {
+        let mut magic = [0; MAGIC.len() + 1];
+        let n = fill(&mut self.reader, &mut magic)?;
+        let len = n.min(MAGIC.len());
+        if magic[..len] != MAGIC[..len] {
+            return Err("not a write-ahead log".into());
+        }
+        if n < magic.len() {
+            // Empty, or torn while writing the first record.
+            if n > 0 {
+                return self.mark_torn();
+            }
+            return Ok(None);
+        }
+        let mut protocol = vec![0; magic[MAGIC.len()] as usize];
+        if fill(&mut self.reader, &mut protocol)? != protocol.len() {
+            return self.mark_torn();
+        }
+        self.offset = (magic.len() + protocol.len()) as u64;
+        Ok(Some(String::from_utf8(protocol)?))
+    }
+
+    /// Reads the body of the next record, or `None` at the end of the log or
+    /// at a torn record.
+    fn next(&mut self) -> Result<Option<Vec<u8>>, BoxError> {
+        if self.torn {
+            return Ok(None);
+        }
+        let mut prefix = [0; 8];
+        match fill(&mut self.reader, &mut prefix)? {
+            0 => return Ok(None),
+            8 => {}
+            _ => return self.mark_torn(),
+        }
+        let len = u32::from_be_bytes(prefix[..4].try_into().unwrap());
+        let mut body = vec![0; len as usize];
+        if fill(&mut self.reader, &mut body)? != body.len() {
+            return self.mark_torn();
+        }
+        if checksum(&body) != prefix[4..] {
+            // A crash can leave the last record with garbage rather than
+            // short, while a bad record followed by others is corruption.
+            if fill(&mut self.reader, &mut [0])? == 0 {
+                return self.mark_torn();
+            }
+            return Err(format!("corrupt record at offset {}", self.offset).into());
+        }
+        self.offset += (prefix.len() + body.len()) as u64;
+        Ok(Some(body))
+    }
+
+    fn mark_torn<T>(&mut self) -> Result<Option<T>, BoxError> {
+        tracing::warn!(
+            offset = self.offset,
+            "write-ahead log ends with a torn record"
+        );
+        self.torn = true;
+        Ok(None)
+    }
+}
+
+/// The log being appended to, owned by the thread writing it.
+struct Writer {
+    path: PathBuf,
+    file: Box<dyn LogFile>,
+    /// The protocol version in the header, once written.
+    protocol: Option<String>,
+    /// The offset just past the header or the last record written.
+    end: u64,
+    /// Set if a failed append couldn't be undone, after which the log only
+    /// fails.
+    broken: Option<String>,
+}
+
+/// The file a log is appended to, a trait so that tests can fail writes.
+trait LogFile: Write + Seek + Send {
+    fn set_len(&self, len: u64) -> io::Result<()>;
+
+    fn sync_data(&self) -> io::Result<()>;
+}
+
+impl LogFile for File {
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+impl Writer {
+    fn open(path: PathBuf) -> Result<Self, BoxError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let mut records = Records::new(BufReader::new(&file));
+        let protocol = records.header()?;
+        let mut count = 0;
+        while records.next()?.is_some() {
+            count += 1;
+        }
+        let end = records.offset;
+        if records.torn {
+            file.set_len(end)?;
+        }
+        file.seek(SeekFrom::Start(end))?;
+        tracing::info!(path = %path.display(), records = count, "opened write-ahead log");
+        Ok(Self {
+            path,
+            file: Box::new(file),
+            protocol,
+            end,
+            broken: None,
+        })
+    }
+
+    fn append(&mut self, append: &Append) -> Result<(), BoxError> {
+        if let Some(error) = &self.broken {
+            return Err(error.clone().into());
+        }
+        let mut buf = Vec::new();
+        match &self.protocol {
+            Some(protocol) if protocol == append.protocol => {}
+            Some(protocol) => {
+                return Err(format!(
+                    "write-ahead log {} records ABCI {} requests, not {}",
+                    self.path.display(),
+                    protocol,
+                    append.protocol
+                )
+                .into())
+            }
+            None => {
+                buf.extend_from_slice(MAGIC);
+                buf.push(append.protocol.len() as u8);
+                buf.extend_from_slice(append.protocol.as_bytes());
+            }
+        }
+        let body = &append.body;
+        buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&checksum(body));
+        buf.extend_from_slice(body);
+        let written = self.file.write_all(&buf).and_then(|()| {
+            if append.sync {
+                self.file.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(error) = written {
+            // A partial record followed by others would make the log corrupt,
+            // so it is dropped, and the next record written in its place.
+            let end = self.end;
+            let undone = self
+                .file
+                .set_len(end)
+                .and_then(|()| self.file.seek(SeekFrom::Start(end)));
+            if let Err(e) = undone {
+                tracing::error!(error = %e, "failed to drop a partial record from write-ahead log");
+                self.broken = Some(format!(
+                    "write-ahead log {} ends with a partial record: {e}",
+                    self.path.display()
+                ));
+            }
+            return Err(error.into());
+        }
+        self.end += buf.len() as u64;
+        self.protocol
+            .get_or_insert_with(|| append.protocol.to_owned());
+        Ok(())
+    }
+
+    /// Appends the records sent to `appends`, in order, until every sender
+    /// is dropped, recording the result of each in its request's entry of
+    /// `pending`.
+    fn run(mut self, appends: mpsc::Receiver<Append>, pending: Pending) {
+        for append in appends {
+            let result = self.append(&append);
+            let mut pending = pending.lock().unwrap();
+            if let Some(entry) = pending.iter_mut().find(|entry| entry.written.is_none()) {
+                entry.written = Some(result);
+            }
+            // The caller may be gone, e.g. if its connection closed.
+            let _ = append.done.send(());
+        }
+    }
+}
+
+/// A record for the writer thread to append.
+struct Append {
+    protocol: &'static str,
+    body: Vec<u8>,
+    sync: bool,
+    /// Signaled once the record is written, or failed to be.
+    done: oneshot::Sender<()>,
+}
+
+/// A request waiting for its record to be written before it is passed to the
+/// service.
+struct Entry {
+    /// The result of writing the record, once written.
+    written: Option<Result<(), BoxError>>,
+    /// Calls the service with the request if the record was written.
+    dispatch: Box<dyn FnOnce(Result<(), BoxError>) + Send>,
+}
+
+/// The requests waiting for their records, in the order of the calls.
+type Pending = Arc<Mutex<VecDeque<Entry>>>;
+
+/// Passes the requests whose records were written to the service, in order.
+///
+/// Each response future does this once its own record is written, so that
+/// no request waits for the future of another to be polled.
+fn dispatch(pending: &Pending) {
+    // The service is called under the lock, so that futures dispatching at
+    // the same time don't reorder the requests.
+    let mut pending = pending.lock().unwrap();
+    while pending.front().is_some_and(|entry| entry.written.is_some()) {
+        let entry = pending.pop_front().expect("checked the oldest entry");
+        (entry.dispatch)(entry.written.expect("checked the result"));
+    }
+}
+
+/// The state shared by the layer and its services.
+struct Shared {
+    /// Sends the records to the writer thread.
+    appends: mpsc::Sender<Append>,
+    pending: Pending,
+}
+
+/// Applies [`WriteAhead`] to a consensus service.
+#[derive(Clone)]
+pub struct WalLayer {
+    shared: Arc<Shared>,
+    sync: bool,
+}
+
+impl WalLayer {
+    /// Appends to the log at `path`, creating it if it doesn't exist. A torn
+    /// record at its end is dropped. Fails if the file can't be opened, or
+    /// isn't a log.
+    ///
+    /// The log is written by a thread of its own, which exits once the layer
+    /// and its services are dropped.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, BoxError> {
+        let writer = Writer::open(path.into())?;
+        let (appends, receiver) = mpsc::channel();
+        let pending = Pending::default();
+        let written = pending.clone();
+        thread::Builder::new()
+            .name("abci-wal".to_owned())
+            .spawn(move || writer.run(receiver, written))?;
+        Ok(Self {
+            shared: Arc::new(Shared { appends, pending }),
+            sync: true,
+        })
+    }
+
+    /// Whether each record is synced to disk before its request reaches the
+    /// service. Defaults to `true`; without it, the last requests before a
+    /// crash of the host may be missing from the log.
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+}
+
+impl<S> Layer<S> for WalLayer {
+    type Service = WriteAhead<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WriteAhead {
+            inner,
+            shared: self.shared.clone(),
+            sync: self.sync,
+        }
+    }
+}
+
+/// Appends each request to a write-ahead log before calling the inner
+/// consensus service. See the [module documentation](self) for details.
+///
+/// The service that was ready is called once its request is written, from
+/// the response future, and is replaced by a clone in the meantime.
+#[derive(Clone)]
+pub struct WriteAhead<S> {
+    inner: S,
+    shared: Arc<Shared>,
+    sync: bool,
+}
+
+impl<S, R> Service<R> for WriteAhead<S>
+where
+    R: Record + Send + 'static,
+    S: Service<R> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<S::Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        if let Some(error) = batch::reject("WalLayer") {
+            return async move { Err(error) }.boxed();
+        }
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let (done, written) = oneshot::channel();
+        let (dispatched, response) = oneshot::channel();
+        let append = Append {
+            protocol: R::PROTOCOL,
+            body: req.encode_to_vec(),
+            sync: self.sync,
+            done,
+        };
+        let entry = Entry {
+            written: None,
+            dispatch: Box::new(move |written| {
+                let _ = dispatched.send(written.map(|()| inner.call(req)));
+            }),
+        };
+        {
+            // Queued under the lock, so that the order of the entries is the
+            // order of the records.
+            let mut pending = self.shared.pending.lock().unwrap();
+            pending.push_back(entry);
+            // The writer only exits once every sender is dropped.
+            let _ = self.shared.appends.send(append);
+        }
+        let pending = self.shared.pending.clone();
+        async move {
+            if written.await.is_err() {
+                return Err("the write-ahead log writer stopped".into());
+            }
+            dispatch(&pending);
+            let response = response
+                .await
+                .expect("dispatched with the requests written before");
+            match response {
+                Ok(response) => response.await.map_err(Into::into),
+                Err(error) => {
+                    tracing::error!(%error, "failed to write request to write-ahead log");
+                    Err(error)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Reads the requests recorded in a write-ahead log.
+pub struct WalReader {
+    records: Records<BufReader<File>>,
+    protocol: Option<String>,
+}
+
+impl WalReader {
+    /// Opens the log at `path`. Fails if the file can't be opened, or isn't a
+    /// log.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let mut records = Records::new(BufReader::new(File::open(path)?));
+        let protocol = records.header()?;
+        Ok(Self { records, protocol })
+    }
+
+    /// The ABCI version of the recorded requests, or `None` if the log is
+    /// empty.
+    pub fn protocol_version(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Reads the next request, or `None` at the end of the log. Fails if the
+    /// log records requests of another version, or is corrupt.
+    pub fn next_request<R: Record>(&mut self) -> Result<Option<R>, BoxError> {
+        match self.protocol.as_deref() {
+            Some(protocol) if protocol != R::PROTOCOL => Err(format!(
+                "write-ahead log records ABCI {} requests, not {}",
+                protocol,
+                R::PROTOCOL
+            )
+            .into()),
+            _ => self
+                .records
+                .next()?
+                .map(|body| R::decode(&body))
+                .transpose(),
+        }
+    }
+
+    /// Returns `true` if the log ended with a record torn by a crash, once
+    /// it was read to its end.
+    pub fn is_truncated(&self) -> bool {
+        self.records.torn
+    }
+}
+
+/// What [`replay`] fed to the application.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Replayed {
+    /// The number of requests replayed.
+    pub requests: usize,
+    /// The height of the last replayed request that carries one.
+    pub height: Option<block::Height>,
+    /// Whether the log ended with a record torn by a crash.
+    pub truncated: bool,
+}
+
+/// Calls `service`, e.g. a fresh instance of the application, with every
+/// request recorded in the log at `path`, in order, waiting for each response
+/// before sending the next request as the node does. The responses are
+/// dropped; to examine them, read the requests with a [`WalReader`] and call
+/// the service with each.
+///
+/// Fails with the first error of the service, or if the log can't be read.
+pub async fn replay<R, S>(path: impl AsRef<Path>, mut service: S) -> Result<Replayed, BoxError>
+where
+    R: Record + RequestExt,
+    S: Service<R>,
+    S::Error: Into<BoxError>,
+{
+    let path = path.as_ref();
+    let mut reader = WalReader::open(path)?;
+    let mut replayed = Replayed::default();
+    while let Some(request) = reader.next_request::<R>()? {
+        let method = request.method();
+        if let Some(height) = request.height() {
+            replayed.height = Some(height);
+        }
+        service
+            .ready()
+            .await
+            .map_err(Into::into)?
+            .call(request)
+            .await
+            .map_err(|e| {
+                format!(
+                    "replaying request {} ({method}) failed: {}",
+                    replayed.requests,
+                    e.into()
+                )
+            })?;
+        replayed.requests += 1;
+    }
+    replayed.truncated = reader.is_truncated();
+    tracing::info!(path = %path.display(), ?replayed, "replayed write-ahead log");
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use tendermint::{
+        abci::request,
+        account,
+        v0_38::abci::{ConsensusRequest, ConsensusResponse},
+        Hash, Time,
+    };
+
+    use super::*;
+
+    /// A path for a log, unique to the test run.
+    fn temp_path(name: &str) -> PathBuf {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let n = NEXT.fetch_add(1, Ordering::SeqCst);
+        let path =
+            std::env::temp_dir().join(format!("tower-abci-wal-{}-{name}-{n}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn prepare_proposal(height: u32) -> ConsensusRequest {
+        ConsensusRequest::PrepareProposal(request::PrepareProposal {
+            max_tx_bytes: 1024,
+            txs: vec![format!("tx{height}").into()],
+            local_last_commit: None,
+            misbehavior: vec![],
+            height: height.into(),
+            time: Time::unix_epoch(),
+            next_validators_hash: Hash::None,
+            proposer_address: account::Id::new([0; 20]),
+        })
+    }
+
+    /// Records the requests it is called with.
+    fn recorder() -> (
+        Arc<Mutex<Vec<ConsensusRequest>>>,
+        impl Service<
+                ConsensusRequest,
+                Response = ConsensusResponse,
+                Error = BoxError,
+                Future = impl Send,
+            > + Clone
+            + Send
+            + 'static,
+    ) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let service = tower::service_fn(move |req: ConsensusRequest| {
+            recorded.lock().unwrap().push(req);
+            async { Ok::<_, BoxError>(ConsensusResponse::Commit(Default::default())) }
+        });
+        (requests, service)
+    }
+
+    /// Appends `requests` to the log at `path`.
+    async fn write(path: &Path, requests: &[ConsensusRequest]) {
+        let (_, service) = recorder();
+        let mut service = WalLayer::open(path).unwrap().layer(service);
+        for req in requests {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(req.clone())
+                .await
+                .unwrap();
+        }
+    }
+
+    fn requests() -> Vec<ConsensusRequest> {
+        vec![
+            prepare_proposal(1),
+            ConsensusRequest::Commit,
+            prepare_proposal(2),
+            ConsensusRequest::Commit,
+        ]
+    }
+
+    #[tokio::test]
+    async fn replays_the_written_requests() {
+        let path = temp_path("replay");
+        write(&path, &requests()).await;
+
+        let (replayed, service) = recorder();
+        let report = replay(&path, service).await.unwrap();
+        assert_eq!(
+            report,
+            Replayed {
+                requests: 4,
+                height: Some(2u32.into()),
+                truncated: false,
+            }
+        );
+        assert_eq!(*replayed.lock().unwrap(), requests());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writes_each_request_before_calling_the_service() {
+        let path = temp_path("order");
+        let logged = path.clone();
+        let calls = Arc::new(AtomicU64::new(0));
+        let service = tower::service_fn(move |req: ConsensusRequest| {
+            // The request is already in the log, after the earlier ones.
+            let n = calls.fetch_add(1, Ordering::SeqCst);
+            let mut reader = WalReader::open(&logged).unwrap();
+            let mut logged = Vec::new();
+            while let Some(request) = reader.next_request::<ConsensusRequest>().unwrap() {
+                logged.push(request);
+            }
+            assert_eq!(logged.get(n as usize), Some(&req));
+            async { Ok::<_, BoxError>(ConsensusResponse::Commit(Default::default())) }
+        });
+        let mut service = WalLayer::open(&path).unwrap().layer(service);
+        // Pipelined, as on a connection, and completed out of order.
+        let mut responses = Vec::new();
+        for req in requests() {
+            responses.push(service.ready().await.unwrap().call(req));
+        }
+        for response in responses.into_iter().rev() {
+            response.await.unwrap();
+        }
+        let (replayed, recorder) = recorder();
+        replay(&path, recorder).await.unwrap();
+        assert_eq!(*replayed.lock().unwrap(), requests());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn drops_a_torn_tail() {
+        let path = temp_path("torn");
+        write(&path, &requests()).await;
+        let len = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let (replayed, service) = recorder();
+        let report = replay(&path, service).await.unwrap();
+        assert_eq!(report.requests, 3);
+        assert!(report.truncated);
+        assert_eq!(*replayed.lock().unwrap(), requests()[..3]);
+
+        // Reopening drops the torn record, and appends after the others.
+        write(&path, &[ConsensusRequest::Commit]).await;
+        let (replayed, service) = recorder();
+        let report = replay(&path, service).await.unwrap();
+        assert_eq!(report.requests, 4);
+        assert!(!report.truncated);
+        assert_eq!(*replayed.lock().unwrap(), requests());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn treats_a_garbled_last_record_as_torn() {
+        let path = temp_path("garbled");
+        write(&path, &requests()).await;
+        let mut bytes = fs::read(&path).unwrap();
+        // Garble the end of the last record, as a crash mid-write can.
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        let (replayed, service) = recorder();
+        let report = replay(&path, service).await.unwrap();
+        assert_eq!(report.requests, 3);
+        assert!(report.truncated);
+        assert_eq!(*replayed.lock().unwrap(), requests()[..3]);
+        fs::remove_file(path).unwrap();
+    }
+
+    /// Fails writes once a budget of bytes is spent, after writing as much of
+    /// the buffer as it allows.
+    struct FailingFile {
+        inner: Box<dyn LogFile>,
+        budget: Arc<Mutex<Option<usize>>>,
+    }
+
+    impl Write for FailingFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut budget = self.budget.lock().unwrap();
+            match budget.as_mut() {
+                Some(0) => Err(io::Error::other("disk full")),
+                Some(left) => {
+                    let n = self.inner.write(&buf[..buf.len().min(*left)])?;
+                    *left -= n;
+                    Ok(n)
+                }
+                None => self.inner.write(buf),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl Seek for FailingFile {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl LogFile for FailingFile {
+        fn set_len(&self, len: u64) -> io::Result<()> {
+            self.inner.set_len(len)
+        }
+
+        fn sync_data(&self) -> io::Result<()> {
+            self.inner.sync_data()
+        }
+    }
+
+    fn append(writer: &mut Writer, request: &ConsensusRequest) -> Result<(), BoxError> {
+        writer.append(&Append {
+            protocol: ConsensusRequest::PROTOCOL,
+            body: Record::encode_to_vec(request),
+            sync: true,
+            done: oneshot::channel().0,
+        })
+    }
+
+    #[tokio::test]
+    async fn drops_a_partial_record_after_a_failed_write() {
+        let path = temp_path("failed");
+        let budget = Arc::new(Mutex::new(None));
+        let mut writer = Writer::open(path.clone()).unwrap();
+        writer.file = Box::new(FailingFile {
+            inner: writer.file,
+            budget: budget.clone(),
+        });
+        let requests = requests();
+        append(&mut writer, &requests[0]).unwrap();
+        // The disk fills up partway through the second record.
+        *budget.lock().unwrap() = Some(5);
+        append(&mut writer, &requests[1]).unwrap_err();
+        *budget.lock().unwrap() = None;
+        append(&mut writer, &requests[2]).unwrap();
+        drop(writer);
+
+        // The log is intact, and reopening it keeps both records.
+        let (replayed, service) = recorder();
+        let report = replay(&path, service).await.unwrap();
+        assert_eq!(report.requests, 2);
+        assert!(!report.truncated);
+        assert_eq!(
+            *replayed.lock().unwrap(),
+            [requests[0].clone(), requests[2].clone()]
+        );
+        write(&path, &requests[3..]).await;
+        let (replayed, service) = recorder();
+        assert_eq!(replay(&path, service).await.unwrap().requests, 3);
+        assert_eq!(replayed.lock().unwrap()[2], requests[3]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_corrupt_record() {
+        let path = temp_path("corrupt");
+        write(&path, &requests()).await;
+        let mut bytes = fs::read(&path).unwrap();
+        // A byte of the body of the first record, followed by the others.
+        let header = MAGIC.len() + 1 + "0.38".len();
+        bytes[header + 8] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        let (replayed, service) = recorder();
+        let error = replay(&path, service).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("corrupt record at offset {header}")
+        );
+        assert!(replayed.lock().unwrap().is_empty());
+        assert!(WalLayer::open(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+//! Stand-ins for the parts of the `tracing` API used by the crate, when built
+//! without the `tracing` feature.
+//!
+//! Each module using `tracing` imports this one in its place, so that the
+//! events and spans compile to nothing without changing their call sites.
+//! [`Level`] is kept, as the per-method log levels of the servers are part of
+//! their configuration either way.
+
+// Which stand-ins are used depends on the other features.
+#![allow(dead_code)]
+
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+/// Records nothing, but type-checks the fields and message of an event
+/// without evaluating them, as `tracing` does for a disabled event.
+macro_rules! event {
+    (target: $target:expr, $($rest:tt)*) => {
+        if false {
+            let _ = $target;
+            $crate::no_tracing::event!(@ $($rest)*);
+        }
+    };
+    (@) => {};
+    (@ { $($fields:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::no_tracing::event!(@ $($fields)*);
+        $crate::no_tracing::event!(@ $($($rest)*)?);
+    };
+    (@ $($key:ident).+ = ?$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $crate::no_tracing::event!(@ $($($rest)*)?);
+    };
+    (@ $($key:ident).+ = %$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $crate::no_tracing::event!(@ $($($rest)*)?);
+    };
+    (@ $($key:ident).+ = $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $crate::no_tracing::event!(@ $($($rest)*)?);
+    };
+    (@ ?$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $crate::no_tracing::event!(@ $($($rest)*)?);
+    };
+    (@ %$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $crate::no_tracing::event!(@ $($($rest)*)?);
+    };
+    (@ $($key:ident).+ $(, $($rest:tt)*)?) => {
+        let _ = &$($key).+;
+        $crate::no_tracing::event!(@ $($($rest)*)?);
+    };
+    (@ $message:literal $(, $args:expr)* $(,)?) => {
+        let _ = format_args!($message $(, $args)*);
+    };
+    (@ $($rest:tt)*) => {
+        compile_error!(concat!("unsupported event syntax: ", stringify!($($rest)*)));
+    };
+    ($($rest:tt)*) => {
+        if false {
+            $crate::no_tracing::event!(@ $($rest)*);
+        }
+    };
+}
+
+/// Returns a [`Span`] that records nothing, type-checking its fields as
+/// [`event!`] does.
+macro_rules! span {
+    ($name:literal $(, $($fields:tt)*)?) => {{
+        if false {
+            $crate::no_tracing::event!(@ $($($fields)*)?);
+        }
+        $crate::no_tracing::Span::none()
+    }};
+}
+
+pub(crate) use {
+    event, event as trace, event as debug, event as info, event as warn, event as error,
+    span as info_span, span as debug_span,
+};
+
+/// A span that records nothing.
+#[derive(Clone, Debug)]
+pub(crate) struct Span;
+
+impl Span {
+    pub(crate) fn none() -> Self {
+        Span
+    }
+
+    pub(crate) fn current() -> Self {
+        Span
+    }
+
+    pub(crate) fn record<V>(&self, _field: &str, _value: V) -> &Self {
+        self
+    }
+
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    pub(crate) fn enter(&self) -> Entered<'_> {
+        Entered(PhantomData)
+    }
+}
+
+/// The guard of an entered [`Span`].
+pub(crate) struct Entered<'a>(PhantomData<&'a Span>);
+
+/// Attaching a [`Span`] to a future, which leaves it as is.
+pub(crate) trait Instrument: Sized {
+    fn instrument(self, _span: Span) -> instrument::Instrumented<Self> {
+        self
+    }
+
+    fn in_current_span(self) -> instrument::Instrumented<Self> {
+        self
+    }
+}
+
+impl<T> Instrument for T {}
+
+pub(crate) mod instrument {
+    /// A future with a [`Span`](super::Span) attached, i.e. the future itself.
+    pub(crate) type Instrumented<T> = T;
+}
+
+pub(crate) mod field {
+    /// The value of a field recorded later.
+    pub(crate) struct Empty;
+
+    pub(crate) fn display<T>(value: T) -> T {
+        value
+    }
+
+    pub(crate) fn debug<T>(value: T) -> T {
+        value
+    }
+}
+
+/// The verbosity of a log event, as `tracing::Level`, which it replaces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Level(Inner);
+
+// Ordered as `tracing` does, from the least to the most verbose.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Inner {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// The "error" level.
+    pub const ERROR: Level = Level(Inner::Error);
+    /// The "warn" level.
+    pub const WARN: Level = Level(Inner::Warn);
+    /// The "info" level.
+    pub const INFO: Level = Level(Inner::Info);
+    /// The "debug" level.
+    pub const DEBUG: Level = Level(Inner::Debug);
+    /// The "trace" level.
+    pub const TRACE: Level = Level(Inner::Trace);
+
+    /// The name of the level, e.g. `"INFO"`.
+    pub fn as_str(&self) -> &'static str {
+        match self.0 {
+            Inner::Error => "ERROR",
+            Inner::Warn => "WARN",
+            Inner::Info => "INFO",
+            Inner::Debug => "DEBUG",
+            Inner::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    /// Parses the name of a level, in any case, or its number, from `1` for
+    /// "error" to `5` for "trace".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Level::ERROR,
+            Level::WARN,
+            Level::INFO,
+            Level::DEBUG,
+            Level::TRACE,
+        ]
+        .into_iter()
+        .enumerate()
+        .find(|(i, level)| s.eq_ignore_ascii_case(level.as_str()) || s == (i + 1).to_string())
+        .map(|(_, level)| level)
+        .ok_or(ParseLevelError)
+    }
+}
+
+/// The error returned when parsing a [`Level`] fails.
+#[derive(Clone, Debug)]
+pub struct ParseLevelError;
+
+impl fmt::Display for ParseLevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid log level")
+    }
+}
+
+impl std::error::Error for ParseLevelError {}
@@ -0,0 +1,308 @@
+//! Settings controlling how the server handles each connection.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use crate::Level;
+
+#[cfg(all(feature = "net", not(feature = "tracing")))]
+use crate::no_tracing as tracing;
+use crate::{pipeline::Category, CheckTxError, ErrorPolicy};
+
+/// Settings controlling how the server handles each connection.
+///
+/// These can be set all at once with `ServerBuilder::connection_options`, or
+/// individually with the corresponding `ServerBuilder` methods. While the
+/// server is running, they can be changed with
+/// [`ServerHandle::update_options`](crate::ServerHandle::update_options), and
+/// open connections apply the new settings to the requests they read next.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionOptions {
+    /// What to do when the mempool, info or snapshot service returns an error.
+    pub error_policy: ErrorPolicy,
+    /// If set, errors from the mempool service are answered with a `CheckTx`
+    /// response instead of applying the error policy.
+    pub check_tx_error: Option<CheckTxError>,
+    /// Wait for each consensus response to resolve before calling the
+    /// consensus service with the next request.
+    pub serial_consensus: bool,
+    /// Stop reading requests after a `Commit` until its response is flushed.
+    pub commit_barrier: bool,
+    /// If set, warn when the server has been waiting on a service for too long.
+    pub stall_detection: Option<StallDetection>,
+    /// If set, stop reading requests while this many responses are pending.
+    /// A limit of zero is treated as one.
+    pub max_in_flight: Option<usize>,
+    /// If set, responses are written at most this long after they resolve,
+    /// even if the node has not sent a `Flush`. Otherwise, responses are
+    /// buffered until the node sends a `Flush`, or until the write buffer of
+    /// `buffer_sizes` is full.
+    pub flush_interval: Option<Duration>,
+    /// If set, close the connection when a request hasn't been read in full
+    /// this long after its first bytes arrived.
+    pub read_timeout: Option<Duration>,
+    /// If set, close the connection when writing responses has made no
+    /// progress for this long, as when the node stops reading them, rather
+    /// than waiting on the write forever.
+    pub write_timeout: Option<Duration>,
+    /// If set, close the connection when no request has been read and no
+    /// response written for this long, as when the node went away without
+    /// closing it. Nodes only send requests on a connection when they need
+    /// to, e.g. none on the mempool connection while no transactions arrive,
+    /// so this is usually set on the consensus connection through
+    /// `overrides`, to a few block times.
+    pub idle_timeout: Option<Duration>,
+    /// If set, TCP connections accepted from now on send keepalive probes
+    /// after being idle for this long, so that the operating system closes
+    /// them once the node stops answering.
+    pub tcp_keepalive: Option<Duration>,
+    /// The sizes of the connection's read and write buffers.
+    pub buffer_sizes: BufferSizes,
+    /// If set, requests and responses of at least this many bytes are
+    /// decoded and encoded on the blocking thread pool rather than on the
+    /// connection's task, so that a huge snapshot chunk or block doesn't
+    /// stall the other tasks of its worker thread.
+    pub blocking_codec_len: Option<usize>,
+    /// Per-kind limits on the number of requests dispatched to each component
+    /// service whose responses are still pending.
+    pub pipeline_depth: PipelineDepth,
+    /// If set, the responses to `CheckTx` requests are computed on tasks of
+    /// their own, so that up to this many are processed in parallel, while
+    /// still being delivered in request order. Only the work a mempool
+    /// service does in the future it returns, rather than in `call`, runs in
+    /// parallel. A limit of zero is treated as one.
+    pub check_tx_concurrency: Option<usize>,
+    /// If set, the responses to `Query` requests are computed on tasks of
+    /// their own, so that up to this many info requests are processed in
+    /// parallel, while still being delivered in request order. As with
+    /// `check_tx_concurrency`, only the work done in the future returned by
+    /// the info service runs in parallel. A limit of zero is treated as one.
+    pub query_concurrency: Option<usize>,
+    /// If set, the server stops accepting connections while too many
+    /// requests are pending across every connection. Only the setting for
+    /// every connection applies to accepting; its `throttle_info` applies to
+    /// info connections by their own settings.
+    pub overload: Option<Overload>,
+    /// If set, each request is given a deadline this long after it is read,
+    /// which services can read with
+    /// [`deadline::current`](crate::middleware::deadline::current) to abandon
+    /// work the node can no longer use.
+    pub request_timeout: Option<Duration>,
+    /// Record a one-line summary of each request served; see
+    /// [`summary`](crate::summary).
+    pub summary_log: bool,
+    /// The level at which requests and responses for each method are logged,
+    /// keyed by method name, e.g. `"CheckTx"`. Methods without an entry are
+    /// logged at `DEBUG`.
+    pub log_levels: BTreeMap<String, Level>,
+    /// Settings replacing these ones on connections of a given kind, once the
+    /// kind has been detected. Overrides nested in an override are ignored.
+    pub overrides: HashMap<Category, ConnectionOptions>,
+}
+
+impl ConnectionOptions {
+    /// The settings for a connection of the given `kind`: its override if it
+    /// has one, and otherwise these settings.
+    pub fn for_kind(&self, kind: Option<Category>) -> &ConnectionOptions {
+        kind.and_then(|kind| self.overrides.get(&kind))
+            .unwrap_or(self)
+    }
+
+    /// The level at which requests and responses for `method` are logged.
+    pub(crate) fn log_level(&self, method: &str) -> Level {
+        self.log_levels.get(method).copied().unwrap_or(Level::DEBUG)
+    }
+
+    /// Enables keepalive probes on an accepted `socket`, if `tcp_keepalive`
+    /// is set. Failing to is logged, and leaves the socket as it was.
+    #[cfg(feature = "net")]
+    pub(crate) fn apply_tcp_keepalive(&self, socket: &tokio::net::TcpStream) {
+        let Some(time) = self.tcp_keepalive else {
+            return;
+        };
+        let keepalive = socket2::TcpKeepalive::new().with_time(time);
+        // Probe as often as the idle time, rather than the operating
+        // system's default, e.g. 75 seconds on Linux.
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+        let keepalive = keepalive.with_interval(time);
+        if let Err(e) = socket2::SockRef::from(socket).set_tcp_keepalive(&keepalive) {
+            tracing::warn!(error = %e, "failed to enable TCP keepalive");
+        }
+    }
+}
+
+/// Detects services that stop making progress.
+///
+/// The server is stalled when a service's `poll_ready` or the oldest pending
+/// response future has not resolved within `interval`. While it is stalled,
+/// the server logs a warning with the state of its request queue once per
+/// `interval`, and increments the `abci_stalls_total` counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StallDetection {
+    /// How long to wait for progress before reporting a stall.
+    pub interval: Duration,
+    /// Close the connection when a stall is detected, so that the node
+    /// reconnects, instead of continuing to wait.
+    pub close: bool,
+}
+
+impl StallDetection {
+    /// Warns about stalls lasting `interval`, without closing the connection.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            close: false,
+        }
+    }
+
+    /// Sets whether to close the connection when a stall is detected.
+    pub fn close(mut self, close: bool) -> Self {
+        self.close = close;
+        self
+    }
+}
+
+/// Protects the consensus connection from spikes of mempool and query
+/// traffic.
+///
+/// Once `max_in_flight` requests are pending across every connection, the
+/// server stops accepting connections, and, if `throttle_info` is set, info
+/// connections stop reading requests, until fewer than `resume_below` are
+/// pending. Consensus and mempool connections keep reading, so that the node
+/// is never held up waiting for the application to accept a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overload {
+    /// The number of pending requests at which the server is overloaded.
+    pub max_in_flight: usize,
+    /// The number of pending requests below which the server resumes.
+    pub resume_below: usize,
+    /// Delay reading requests on info connections while overloaded.
+    pub throttle_info: bool,
+}
+
+impl Overload {
+    /// Pauses accepting connections while at least `max_in_flight` requests
+    /// are pending.
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight,
+            resume_below: max_in_flight,
+            throttle_info: false,
+        }
+    }
+
+    /// Sets the number of pending requests below which the server resumes,
+    /// so that it doesn't flap around `max_in_flight`. Values above
+    /// `max_in_flight` are treated as `max_in_flight`.
+    pub fn resume_below(mut self, resume_below: usize) -> Self {
+        self.resume_below = resume_below;
+        self
+    }
+
+    /// Sets whether info connections stop reading requests while overloaded.
+    pub fn throttle_info(mut self, throttle_info: bool) -> Self {
+        self.throttle_info = throttle_info;
+        self
+    }
+}
+
+/// The sizes of a connection's buffers, trading memory for throughput.
+///
+/// The read buffer grows to hold the largest request read, so its initial
+/// capacity only saves reallocations, and `max_request_len` bounds it. The
+/// initial capacity applies to connections opened after it is set, since
+/// the buffer is allocated before the kind of the connection is known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferSizes {
+    /// The initial capacity of the read buffer, in bytes. Defaults to 8 KiB.
+    pub read_capacity: usize,
+    /// If set, a request longer than this many bytes fails the connection
+    /// instead of being buffered.
+    pub max_request_len: Option<usize>,
+    /// Once this many bytes of responses are buffered, they are written
+    /// before the next response is buffered, whether or not the node has sent
+    /// a `Flush`. Defaults to 8 KiB.
+    pub write_capacity: usize,
+}
+
+impl Default for BufferSizes {
+    fn default() -> Self {
+        Self {
+            read_capacity: 8 * 1024,
+            max_request_len: None,
+            write_capacity: 8 * 1024,
+        }
+    }
+}
+
+impl BufferSizes {
+    /// Sets the initial capacity of the read buffer.
+    pub fn read_capacity(mut self, capacity: usize) -> Self {
+        self.read_capacity = capacity;
+        self
+    }
+
+    /// Limits the length of requests.
+    pub fn max_request_len(mut self, len: usize) -> Self {
+        self.max_request_len = Some(len);
+        self
+    }
+
+    /// Sets how many bytes of responses are buffered before they are written.
+    pub fn write_capacity(mut self, capacity: usize) -> Self {
+        self.write_capacity = capacity;
+        self
+    }
+}
+
+/// Limits on how many requests of each kind may be in flight at once.
+///
+/// When a request arrives for a component service that already has its limit
+/// of pending responses, the server waits for the oldest pending responses to
+/// resolve before calling the service. A limit of zero is treated as one, and
+/// `None` leaves that kind unlimited. `ConnectionOptions::serial_consensus`
+/// takes precedence over the consensus limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineDepth {
+    pub consensus: Option<usize>,
+    pub mempool: Option<usize>,
+    pub snapshot: Option<usize>,
+    pub info: Option<usize>,
+}
+
+impl PipelineDepth {
+    /// Limits the number of pending consensus requests.
+    pub fn consensus(mut self, depth: usize) -> Self {
+        self.consensus = Some(depth);
+        self
+    }
+
+    /// Limits the number of pending mempool requests.
+    pub fn mempool(mut self, depth: usize) -> Self {
+        self.mempool = Some(depth);
+        self
+    }
+
+    /// Limits the number of pending snapshot requests.
+    pub fn snapshot(mut self, depth: usize) -> Self {
+        self.snapshot = Some(depth);
+        self
+    }
+
+    /// Limits the number of pending info requests.
+    pub fn info(mut self, depth: usize) -> Self {
+        self.info = Some(depth);
+        self
+    }
+
+    pub(crate) fn get(&self, category: Category) -> Option<usize> {
+        match category {
+            Category::Consensus => self.consensus,
+            Category::Mempool => self.mempool,
+            Category::Snapshot => self.snapshot,
+            Category::Info => self.info,
+        }
+    }
+}
@@ -0,0 +1,664 @@
+//! Version-independent bookkeeping for the servers' request pipelines.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+use futures::{stream::Stream, task::AtomicWaker};
+
+use tendermint::{abci::MethodKind, block};
+use tokio::{
+    sync::watch,
+    time::{Instant, Sleep},
+};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{BoxError, RequestId, StallDetection};
+
+/// The category of a pipelined request, i.e., which component service it was
+/// dispatched to.
+///
+/// The node opens a separate connection for each category, so this is also
+/// the kind of a connection, which the server detects from the first request
+/// it reads other than `Flush`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Category {
+    /// Requests handled by the consensus service.
+    Consensus,
+    /// Requests handled by the mempool service.
+    Mempool,
+    /// Requests handled by the snapshot service.
+    Snapshot,
+    /// Requests handled by the info service.
+    Info,
+}
+
+impl Category {
+    /// The category of requests of the given kind, or `None` for `Flush`,
+    /// which is handled by the server itself.
+    pub(crate) fn of(kind: &MethodKind) -> Option<Self> {
+        match kind {
+            MethodKind::Consensus => Some(Category::Consensus),
+            MethodKind::Mempool => Some(Category::Mempool),
+            MethodKind::Snapshot => Some(Category::Snapshot),
+            MethodKind::Info => Some(Category::Info),
+            MethodKind::Flush => None,
+        }
+    }
+
+    /// The lowercase name of the category, e.g. `"consensus"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Consensus => "consensus",
+            Category::Mempool => "mempool",
+            Category::Snapshot => "snapshot",
+            Category::Info => "info",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A request whose response is still pending.
+#[derive(Clone, Debug)]
+pub(crate) struct Pending {
+    pub(crate) id: RequestId,
+    pub(crate) category: Category,
+    pub(crate) method: &'static str,
+    pub(crate) height: Option<block::Height>,
+    /// The encoded size of the request, excluding its length prefix.
+    pub(crate) size: usize,
+    /// When the request was read from the connection.
+    pub(crate) received: std::time::Instant,
+}
+
+/// Tracks the requests whose responses are still pending.
+///
+/// Responses are delivered in request order, so the requests are kept in a
+/// queue parallel to the queue of response futures. The queue is shared with
+/// the [`ServerHandle`](crate::ServerHandle), which reads it to report the
+/// state of the connection.
+///
+/// The total number of pending requests of every connection of a server is
+/// kept in its `load`, for the overload protection.
+#[derive(Clone, Debug)]
+pub(crate) struct InFlight {
+    queue: Arc<Mutex<Queue>>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    order: VecDeque<Pending>,
+    counts: [usize; 4],
+    load: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        // The requests of a failed connection are no longer pending.
+        let pending = self.order.len();
+        self.load.send_modify(|load| *load -= pending);
+    }
+}
+
+impl InFlight {
+    /// A queue counting its requests in `load`.
+    pub(crate) fn new(load: Arc<watch::Sender<usize>>) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(Queue {
+                order: VecDeque::new(),
+                counts: [0; 4],
+                load,
+            })),
+        }
+    }
+
+    /// Records that a request was dispatched.
+    pub(crate) fn push(&mut self, pending: Pending) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.counts[pending.category.index()] += 1;
+        queue.order.push_back(pending);
+        queue.load.send_modify(|load| *load += 1);
+    }
+
+    /// Records that the oldest pending response was delivered, returning its request.
+    pub(crate) fn pop(&mut self) -> Pending {
+        let mut queue = self.queue.lock().unwrap();
+        let pending = queue
+            .order
+            .pop_front()
+            .expect("popped more responses than were pushed");
+        queue.counts[pending.category.index()] -= 1;
+        queue.load.send_modify(|load| *load -= 1);
+        pending
+    }
+
+    pub(crate) fn get(&self, category: Category) -> usize {
+        self.queue.lock().unwrap().counts[category.index()]
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queue.lock().unwrap().order.len()
+    }
+
+    /// The pending requests, oldest first.
+    pub(crate) fn requests(&self) -> Vec<Pending> {
+        self.queue.lock().unwrap().order.iter().cloned().collect()
+    }
+}
+
+/// The response futures of a connection, which resolve in any order but are
+/// delivered in the order they were queued.
+///
+/// Each future has a waker of its own, which records that its slot was woken,
+/// so that polling the queue only polls the futures that were woken since,
+/// and a wakeup of a future costs a lock and a push rather than a pass over
+/// the queue. The outputs of futures that complete ahead of the oldest one
+/// wait in their slot, and [`pop_completed`](Self::pop_completed) hands out
+/// the ones that are next in order without polling anything.
+pub(crate) struct ResponseQueue<F: Future> {
+    slots: VecDeque<Slot<F>>,
+    /// The sequence number of the oldest slot.
+    head: u64,
+    woken: Arc<Woken>,
+}
+
+enum Slot<F: Future> {
+    Pending { future: Pin<Box<F>>, waker: Waker },
+    Done(F::Output),
+}
+
+/// The slots woken since the queue was last polled, and the waker of the
+/// task polling the queue.
+#[derive(Default)]
+struct Woken {
+    slots: Mutex<Vec<u64>>,
+    waker: AtomicWaker,
+}
+
+/// Wakes the slot with sequence number `seq`.
+struct SlotWaker {
+    seq: u64,
+    woken: Arc<Woken>,
+}
+
+impl Wake for SlotWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.slots.lock().unwrap().push(self.seq);
+        self.woken.waker.wake();
+    }
+}
+
+impl<F: Future> ResponseQueue<F> {
+    pub(crate) fn new() -> Self {
+        Self {
+            slots: VecDeque::new(),
+            head: 0,
+            woken: Arc::default(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Queues `future`, which is first polled the next time the queue is.
+    pub(crate) fn push_back(&mut self, future: F) {
+        let seq = self.head + self.slots.len() as u64;
+        let waker = Waker::from(Arc::new(SlotWaker {
+            seq,
+            woken: self.woken.clone(),
+        }));
+        self.slots.push_back(Slot::Pending {
+            future: Box::pin(future),
+            waker,
+        });
+        self.woken.slots.lock().unwrap().push(seq);
+    }
+
+    /// Removes the output of the oldest future, if it has already completed.
+    pub(crate) fn pop_completed(&mut self) -> Option<F::Output> {
+        if !matches!(self.slots.front(), Some(Slot::Done(_))) {
+            return None;
+        }
+        self.head += 1;
+        match self.slots.pop_front() {
+            Some(Slot::Done(output)) => Some(output),
+            _ => unreachable!("checked the oldest slot"),
+        }
+    }
+}
+
+// The futures are pinned in boxes of their own, and the outputs are never
+// pinned.
+impl<F: Future> Unpin for ResponseQueue<F> {}
+
+impl<F: Future> Stream for ResponseQueue<F> {
+    type Item = F::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(output) = this.pop_completed() {
+            return Poll::Ready(Some(output));
+        }
+        if this.slots.is_empty() {
+            return Poll::Ready(None);
+        }
+        this.woken.waker.register(cx.waker());
+        let woken = std::mem::take(&mut *this.woken.slots.lock().unwrap());
+        for seq in woken {
+            // Slots woken after completing may have been removed already.
+            let Some(slot) = seq
+                .checked_sub(this.head)
+                .and_then(|index| this.slots.get_mut(index as usize))
+            else {
+                continue;
+            };
+            if let Slot::Pending { future, waker } = slot {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(waker)) {
+                    *slot = Slot::Done(output);
+                }
+            }
+        }
+        match this.pop_completed() {
+            Some(output) => Poll::Ready(Some(output)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Reports waits on component services that exceed the configured interval.
+pub(crate) struct StallDetector {
+    options: Option<StallDetection>,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl StallDetector {
+    pub(crate) fn new(options: Option<StallDetection>) -> Self {
+        let deadline = Instant::now() + options.map(|o| o.interval).unwrap_or_default();
+        Self {
+            options,
+            deadline: Box::pin(tokio::time::sleep_until(deadline)),
+        }
+    }
+
+    /// Replaces the stall detection settings, restarting the interval.
+    pub(crate) fn reconfigure(&mut self, options: Option<StallDetection>) {
+        self.options = options;
+        self.progress();
+    }
+
+    /// Restarts the interval after the pipeline made progress.
+    pub(crate) fn progress(&mut self) {
+        if let Some(options) = self.options {
+            self.deadline
+                .as_mut()
+                .reset(Instant::now() + options.interval);
+        }
+    }
+
+    /// Resolves when an interval passes without progress. Never resolves if
+    /// stall detection is disabled.
+    pub(crate) async fn expired(&mut self) {
+        match self.options {
+            Some(_) => self.deadline.as_mut().await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// Reports a stall while `waiting_for` something, failing if the
+    /// connection should be closed, and otherwise restarting the interval.
+    pub(crate) fn stalled(
+        &mut self,
+        waiting_for: &'static str,
+        in_flight: &InFlight,
+    ) -> Result<(), BoxError> {
+        let Some(options) = self.options else {
+            return Ok(());
+        };
+        tracing::warn!(
+            waiting_for,
+            interval = ?options.interval,
+            in_flight = in_flight.len(),
+            consensus = in_flight.get(Category::Consensus),
+            mempool = in_flight.get(Category::Mempool),
+            snapshot = in_flight.get(Category::Snapshot),
+            info = in_flight.get(Category::Info),
+            "connection stalled"
+        );
+        crate::metrics::stall(waiting_for);
+        if options.close {
+            return Err(format!("stalled waiting for {}", waiting_for).into());
+        }
+        self.progress();
+        Ok(())
+    }
+
+    /// Waits for `future`, reporting a stall each interval it remains pending.
+    pub(crate) async fn watch<F: Future>(
+        &mut self,
+        waiting_for: &'static str,
+        in_flight: &InFlight,
+        future: F,
+    ) -> Result<F::Output, BoxError> {
+        tokio::pin!(future);
+        self.progress();
+        loop {
+            tokio::select! {
+                output = &mut future => {
+                    self.progress();
+                    return Ok(output);
+                }
+                () = self.expired() => self.stalled(waiting_for, in_flight)?,
+            }
+        }
+    }
+}
+
+/// Bounds how long responses stay buffered while waiting for a `Flush`.
+pub(crate) struct FlushTimer {
+    interval: Option<Duration>,
+    /// Whether responses have been buffered since the last flush.
+    buffered: bool,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl FlushTimer {
+    pub(crate) fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            buffered: false,
+            deadline: Box::pin(tokio::time::sleep_until(Instant::now())),
+        }
+    }
+
+    /// Replaces the interval, which applies from the next buffered response.
+    pub(crate) fn reconfigure(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+    }
+
+    /// Notes that a response was buffered, starting the interval if it is the
+    /// first since the last flush.
+    pub(crate) fn buffered(&mut self) {
+        if self.buffered {
+            return;
+        }
+        self.buffered = true;
+        if let Some(interval) = self.interval {
+            self.deadline.as_mut().reset(Instant::now() + interval);
+        }
+    }
+
+    /// Notes that the buffered responses were written.
+    pub(crate) fn flushed(&mut self) {
+        self.buffered = false;
+    }
+
+    /// Resolves when responses have been buffered for the interval. Never
+    /// resolves if there is no interval.
+    pub(crate) async fn expired(&mut self) {
+        match (self.interval, self.buffered) {
+            (Some(_), true) => self.deadline.as_mut().await,
+            _ => futures::future::pending().await,
+        }
+    }
+}
+
+/// Detects connections whose node has gone away without closing them.
+pub(crate) struct IdleTimer {
+    timeout: Option<Duration>,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl IdleTimer {
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        let mut timer = Self {
+            timeout,
+            deadline: Box::pin(tokio::time::sleep_until(Instant::now())),
+        };
+        timer.activity();
+        timer
+    }
+
+    /// Replaces the timeout, counting from now.
+    pub(crate) fn reconfigure(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.activity();
+    }
+
+    /// Notes that a request was read or a response written.
+    pub(crate) fn activity(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.deadline.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    /// Resolves when the connection has been idle for the timeout. Never
+    /// resolves if there is no timeout.
+    pub(crate) async fn expired(&mut self) {
+        match self.timeout {
+            Some(_) => self.deadline.as_mut().await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// The error closing an idle connection.
+    pub(crate) fn error(&self) -> BoxError {
+        let timeout = self.timeout.expect("only expires with a timeout");
+        tracing::warn!(?timeout, "closing idle connection");
+        format!("no requests for {timeout:?}, the node may be gone").into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{channel::oneshot, future};
+
+    use super::*;
+
+    /// Counts the wakeups of the task polling the queue.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl CountingWaker {
+        fn count(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    fn poll<F: Future>(
+        queue: &mut ResponseQueue<F>,
+        waker: &Arc<CountingWaker>,
+    ) -> Poll<Option<F::Output>> {
+        let waker = Waker::from(waker.clone());
+        Pin::new(queue).poll_next(&mut Context::from_waker(&waker))
+    }
+
+    /// A queue of `n` futures, and the senders completing them.
+    fn channels(
+        n: usize,
+    ) -> (
+        ResponseQueue<oneshot::Receiver<usize>>,
+        Vec<oneshot::Sender<usize>>,
+    ) {
+        let mut queue = ResponseQueue::new();
+        let senders = (0..n)
+            .map(|_| {
+                let (tx, rx) = oneshot::channel();
+                queue.push_back(rx);
+                tx
+            })
+            .collect();
+        (queue, senders)
+    }
+
+    #[test]
+    fn delivers_in_queue_order() {
+        let mut queue = ResponseQueue::new();
+        for i in 0..3 {
+            queue.push_back(future::ready(i));
+        }
+        let waker = Arc::new(CountingWaker::default());
+        for i in 0..3 {
+            assert_eq!(poll(&mut queue, &waker), Poll::Ready(Some(i)));
+        }
+        assert_eq!(poll(&mut queue, &waker), Poll::Ready(None));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn holds_outputs_completed_out_of_order() {
+        let (mut queue, mut senders) = channels(3);
+        let waker = Arc::new(CountingWaker::default());
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+
+        // The last future completes first, and waits for the others.
+        senders.pop().unwrap().send(2).unwrap();
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+        assert_eq!(queue.pop_completed(), None);
+        assert_eq!(queue.len(), 3);
+
+        senders.remove(0).send(0).unwrap();
+        assert_eq!(poll(&mut queue, &waker), Poll::Ready(Some(Ok(0))));
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+
+        senders.remove(0).send(1).unwrap();
+        assert_eq!(poll(&mut queue, &waker), Poll::Ready(Some(Ok(1))));
+        // The completed output is handed out without polling.
+        assert_eq!(queue.pop_completed(), Some(Ok(2)));
+        assert_eq!(poll(&mut queue, &waker), Poll::Ready(None));
+    }
+
+    #[test]
+    fn wakes_the_polling_task_on_completion() {
+        let (mut queue, mut senders) = channels(2);
+        let waker = Arc::new(CountingWaker::default());
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+        assert_eq!(waker.count(), 0);
+
+        // Completing any future wakes the task, even ahead of the oldest.
+        senders.pop().unwrap().send(1).unwrap();
+        assert_eq!(waker.count(), 1);
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+        senders.pop().unwrap().send(0).unwrap();
+        assert_eq!(waker.count(), 2);
+        assert_eq!(poll(&mut queue, &waker), Poll::Ready(Some(Ok(0))));
+        assert_eq!(poll(&mut queue, &waker), Poll::Ready(Some(Ok(1))));
+    }
+
+    #[test]
+    fn wakes_the_latest_polling_task() {
+        let (mut queue, mut senders) = channels(1);
+        let first = Arc::new(CountingWaker::default());
+        let second = Arc::new(CountingWaker::default());
+        assert_eq!(poll(&mut queue, &first), Poll::Pending);
+        assert_eq!(poll(&mut queue, &second), Poll::Pending);
+
+        senders.pop().unwrap().send(0).unwrap();
+        assert_eq!((first.count(), second.count()), (0, 1));
+        assert_eq!(poll(&mut queue, &second), Poll::Ready(Some(Ok(0))));
+    }
+
+    #[test]
+    fn polls_only_woken_futures() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let mut queue = ResponseQueue::new();
+        let mut senders = Vec::new();
+        for _ in 0..3 {
+            let (tx, rx) = oneshot::channel::<()>();
+            let polls = polls.clone();
+            let mut rx = rx;
+            queue.push_back(future::poll_fn(move |cx| {
+                polls.fetch_add(1, Ordering::SeqCst);
+                Pin::new(&mut rx).poll(cx)
+            }));
+            senders.push(tx);
+        }
+        let waker = Arc::new(CountingWaker::default());
+        // Each queued future is polled once.
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+        assert_eq!(polls.load(Ordering::SeqCst), 3);
+        // Polling again without wakeups polls nothing.
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+        assert_eq!(polls.load(Ordering::SeqCst), 3);
+        // Only the woken future is polled.
+        senders.remove(1).send(()).unwrap();
+        assert_eq!(poll(&mut queue, &waker), Poll::Pending);
+        assert_eq!(polls.load(Ordering::SeqCst), 4);
+    }
+
+    fn in_flight() -> InFlight {
+        InFlight::new(Arc::new(watch::channel(0).0))
+    }
+
+    #[tokio::test]
+    async fn stall_detection_keeps_waiting_through_stalls() {
+        let mut stall = StallDetector::new(Some(StallDetection::new(Duration::from_millis(5))));
+        let slow = tokio::time::sleep(Duration::from_millis(30));
+        assert!(stall.watch("slow", &in_flight(), slow).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn stall_detection_fails_a_stall_when_closing() {
+        let options = StallDetection::new(Duration::from_millis(5)).close(true);
+        let mut stall = StallDetector::new(Some(options));
+        let error = stall
+            .watch("response", &in_flight(), future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "stalled waiting for response");
+        // Disabled, it never reports one.
+        let mut stall = StallDetector::new(None);
+        let slow = tokio::time::sleep(Duration::from_millis(10));
+        assert!(stall.watch("response", &in_flight(), slow).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn idle_timer_expires_without_activity() {
+        let timeout = Duration::from_millis(20);
+        let mut idle = IdleTimer::new(Some(timeout));
+        let started = Instant::now();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        idle.activity();
+        idle.expired().await;
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert!(idle.error().to_string().starts_with("no requests for"));
+
+        let mut idle = IdleTimer::new(None);
+        let expired = tokio::time::timeout(timeout * 2, idle.expired()).await;
+        assert!(expired.is_err());
+    }
+}
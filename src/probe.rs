@@ -0,0 +1,244 @@
+//! Liveness and readiness probes for orchestrators.
+//!
+//! [`listen_tcp`] serves a minimal HTTP endpoint for, e.g., Kubernetes
+//! `httpGet` probes:
+//!
+//! - `GET /livez` answers `200 OK` as long as the process is serving the
+//!   endpoint;
+//! - `GET /readyz` answers `200 OK` only once the ABCI listener is bound and
+//!   the consensus, mempool, info and snapshot services are all ready, and
+//!   `503 Service Unavailable`, with the reason, otherwise;
+//! - `GET /health` runs the [health checks](crate::health), answering the
+//!   report with `200 OK` if they all succeed, and `503 Service Unavailable`
+//!   otherwise.
+//!
+//! ```no_run
+//! # use tower_abci::{apps::NoopApp, probe, v038::Server, BoxError};
+//! # #[cfg(feature = "net")]
+//! # async fn example() -> Result<(), BoxError> {
+//! # let server = Server::builder()
+//! #     .consensus(NoopApp)
+//! #     .mempool(NoopApp)
+//! #     .info(NoopApp)
+//! #     .snapshot(NoopApp)
+//! #     .finish()
+//! #     .unwrap();
+//! let readiness = server.readiness();
+//! tokio::spawn(probe::listen_tcp(readiness, "0.0.0.0:8080"));
+//! server.listen_tcp("127.0.0.1:26658").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A draining server is reported as not ready, so that orchestrators stop
+//! routing to it while its connections close.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+#[cfg(feature = "net")]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, ToSocketAddrs},
+};
+use tower::{Service, ServiceExt};
+#[cfg(feature = "net")]
+use tracing::Instrument;
+
+#[cfg(all(feature = "net", not(feature = "tracing")))]
+use crate::no_tracing as tracing;
+#[cfg(feature = "net")]
+use crate::task;
+use crate::{BoxError, ServerHandle};
+
+/// How long each service may take to become ready, by default.
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Why a server is not ready.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotReady {
+    /// The server is not listening for connections yet, or any longer.
+    NotListening,
+    /// The server is draining its connections.
+    Draining,
+    /// A service failed to become ready.
+    Failed {
+        service: &'static str,
+        error: String,
+    },
+    /// A service did not become ready within the timeout.
+    TimedOut { service: &'static str },
+}
+
+impl fmt::Display for NotReady {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotReady::NotListening => write!(f, "not listening"),
+            NotReady::Draining => write!(f, "draining"),
+            NotReady::Failed { service, error } => {
+                write!(f, "{service} service failed: {error}")
+            }
+            NotReady::TimedOut { service } => write!(f, "{service} service not ready"),
+        }
+    }
+}
+
+impl std::error::Error for NotReady {}
+
+/// Checks that the services of a server are ready.
+type ServicesCheck =
+    Arc<dyn Fn(Duration) -> BoxFuture<'static, Result<(), NotReady>> + Send + Sync>;
+
+/// Checks whether a server can serve requests, obtained with
+/// `Server::readiness`.
+#[derive(Clone)]
+pub struct Readiness {
+    handle: ServerHandle,
+    services: ServicesCheck,
+    timeout: Duration,
+}
+
+impl Readiness {
+    pub(crate) fn new<F>(handle: ServerHandle, services: F) -> Self
+    where
+        F: Fn(Duration) -> BoxFuture<'static, Result<(), NotReady>> + Send + Sync + 'static,
+    {
+        Self {
+            handle,
+            services: Arc::new(services),
+            timeout: DEFAULT_READY_TIMEOUT,
+        }
+    }
+
+    /// Sets how long each service may take to become ready. Defaults to
+    /// [`DEFAULT_READY_TIMEOUT`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Checks that the server is listening and not draining, and that each of
+    /// its services becomes ready within the timeout.
+    pub async fn check(&self) -> Result<(), NotReady> {
+        if self.handle.is_draining() {
+            return Err(NotReady::Draining);
+        }
+        if !self.handle.is_listening() {
+            return Err(NotReady::NotListening);
+        }
+        (self.services)(self.timeout).await
+    }
+}
+
+impl fmt::Debug for Readiness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Readiness")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Waits for `service` to become ready, releasing its readiness afterwards.
+pub(crate) async fn ready<S, R>(
+    name: &'static str,
+    mut service: S,
+    timeout: Duration,
+) -> Result<(), NotReady>
+where
+    S: Service<R, Error = BoxError>,
+{
+    match tokio::time::timeout(timeout, service.ready()).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(NotReady::Failed {
+            service: name,
+            error: e.to_string(),
+        }),
+        Err(_) => Err(NotReady::TimedOut { service: name }),
+    }
+}
+
+/// The longest probe request read, in bytes.
+#[cfg(feature = "net")]
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// Serves the probe endpoints for `readiness` on a TCP socket at `addr`.
+#[cfg(feature = "net")]
+pub async fn listen_tcp<A: ToSocketAddrs + fmt::Debug>(
+    readiness: Readiness,
+    addr: A,
+) -> Result<(), BoxError> {
+    let listener = TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    tracing::info!(?addr, "ABCI probe endpoint starting on tcp socket");
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, _addr)) => {
+                let readiness = readiness.clone();
+                let span = tracing::debug_span!("abci_probe_connection");
+                task::spawn(
+                    "abci-probe-connection",
+                    async move {
+                        if let Err(e) = serve(readiness, socket).await {
+                            tracing::debug!(error = %e, "probe connection failed");
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                tracing::error!({ %e }, "error accepting new probe connection");
+            }
+        }
+    }
+}
+
+/// Answers a single HTTP request read from `socket`.
+#[cfg(feature = "net")]
+async fn serve(readiness: Readiness, mut socket: tokio::net::TcpStream) -> Result<(), BoxError> {
+    let mut request = Vec::new();
+    let mut buf = [0; 512];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = tokio::time::timeout(Duration::from_secs(5), socket.read(&mut buf)).await??;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+        if request.len() > MAX_REQUEST_LEN {
+            return Err("probe request too long".into());
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/livez" | "/healthz")) => ("200 OK", "ok\n".to_string()),
+        (Some("GET"), Some("/readyz")) => match readiness.check().await {
+            Ok(()) => ("200 OK", "ok\n".to_string()),
+            Err(e) => ("503 Service Unavailable", format!("{e}\n")),
+        },
+        (Some("GET"), Some("/health")) => {
+            let report = readiness
+                .handle
+                .health_check_within(readiness.timeout)
+                .await;
+            match report.is_healthy() {
+                true => ("200 OK", report.to_string()),
+                false => ("503 Service Unavailable", report.to_string()),
+            }
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,240 @@
+//! Answering queries with ICS-23 proofs.
+//!
+//! Light clients, and IBC relayers in particular, query the state with
+//! `prove = true`, and verify the value in the response against the app hash
+//! with the `ProofOps` of the response. Those carry [ICS-23] commitment
+//! proofs, protobuf encoded in `ProofOp`s whose type names the kind of tree
+//! they prove against, e.g. [`PROOF_OP_IAVL`] for a store and
+//! [`PROOF_OP_SIMPLE`] for the multistore root in the Cosmos SDK layout.
+//!
+//! This module provides the ICS-23 proof messages, a [`ProveKey`] trait for
+//! stores to produce proofs of presence or absence of a key, and [`answer`]
+//! to build the `Query` response from them:
+//!
+//! ```ignore
+//! let store = Arc::new(store);
+//! let info = QueryRouter::new(info)
+//!     .route_fn("/store", move |query| proof::answer(&*store, &query, Vec::new()));
+//! ```
+//!
+//! The trees themselves, and how proofs are computed from them, are up to the
+//! application.
+//!
+//! [ICS-23]: https://github.com/cosmos/ics23
+
+use bytes::Bytes;
+use prost::Message;
+use tendermint::{
+    abci::{request, response},
+    block,
+    merkle::proof::{ProofOp, ProofOps},
+};
+
+use crate::BoxError;
+
+/// The `ProofOp` type of proofs against an IAVL tree.
+pub const PROOF_OP_IAVL: &str = "ics23:iavl";
+
+/// The `ProofOp` type of proofs against a simple Merkle tree, such as the
+/// root of a multistore.
+pub const PROOF_OP_SIMPLE: &str = "ics23:simple";
+
+/// The `ProofOp` type of proofs against a sparse Merkle tree.
+pub const PROOF_OP_SMT: &str = "ics23:smt";
+
+/// The hash function of a proof step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum HashOp {
+    NoHash = 0,
+    Sha256 = 1,
+    Sha512 = 2,
+    Keccak256 = 3,
+    Ripemd160 = 4,
+    Bitcoin = 5,
+    Sha512256 = 6,
+    Blake2b512 = 7,
+    Blake2s256 = 8,
+    Blake3 = 9,
+}
+
+/// How the length of the key and value of a leaf are prefixed to them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum LengthOp {
+    NoPrefix = 0,
+    VarProto = 1,
+    VarRlp = 2,
+    Fixed32Big = 3,
+    Fixed32Little = 4,
+    Fixed64Big = 5,
+    Fixed64Little = 6,
+    Require32Bytes = 7,
+    Require64Bytes = 8,
+}
+
+/// How a leaf of the tree is hashed from its key and value.
+#[derive(Clone, PartialEq, Message)]
+pub struct LeafOp {
+    #[prost(enumeration = "HashOp", tag = "1")]
+    pub hash: i32,
+    #[prost(enumeration = "HashOp", tag = "2")]
+    pub prehash_key: i32,
+    #[prost(enumeration = "HashOp", tag = "3")]
+    pub prehash_value: i32,
+    #[prost(enumeration = "LengthOp", tag = "4")]
+    pub length: i32,
+    #[prost(bytes = "vec", tag = "5")]
+    pub prefix: Vec<u8>,
+}
+
+impl LeafOp {
+    /// The leaves of the simple Merkle trees of Tendermint, as in the
+    /// multistore root of the Cosmos SDK.
+    pub fn tendermint() -> Self {
+        Self {
+            hash: HashOp::Sha256.into(),
+            prehash_key: HashOp::NoHash.into(),
+            prehash_value: HashOp::Sha256.into(),
+            length: LengthOp::VarProto.into(),
+            prefix: vec![0],
+        }
+    }
+}
+
+/// A step from a child node to its parent: the parent is the hash of
+/// `prefix`, the child's hash and `suffix`.
+#[derive(Clone, PartialEq, Message)]
+pub struct InnerOp {
+    #[prost(enumeration = "HashOp", tag = "1")]
+    pub hash: i32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub prefix: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub suffix: Vec<u8>,
+}
+
+/// A proof that `key` has `value` in the tree: the leaf, and the path from it
+/// to the root.
+#[derive(Clone, PartialEq, Message)]
+pub struct ExistenceProof {
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    pub leaf: Option<LeafOp>,
+    #[prost(message, repeated, tag = "4")]
+    pub path: Vec<InnerOp>,
+}
+
+/// A proof that `key` is not in the tree: the proofs of its neighbours, at
+/// most one of which is missing, if `key` is before the first or after the
+/// last key of the tree.
+#[derive(Clone, PartialEq, Message)]
+pub struct NonExistenceProof {
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+    #[prost(message, optional, tag = "2")]
+    pub left: Option<ExistenceProof>,
+    #[prost(message, optional, tag = "3")]
+    pub right: Option<ExistenceProof>,
+}
+
+/// The ICS-23 proof carried in a `ProofOp`.
+#[derive(Clone, PartialEq, Message)]
+pub struct CommitmentProof {
+    #[prost(oneof = "commitment_proof::Proof", tags = "1, 2")]
+    pub proof: Option<commitment_proof::Proof>,
+}
+
+/// The kinds of [`CommitmentProof`]s.
+pub mod commitment_proof {
+    /// The proof of a [`CommitmentProof`](super::CommitmentProof). Batch and
+    /// compressed proofs are not supported.
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Proof {
+        #[prost(message, tag = "1")]
+        Exist(super::ExistenceProof),
+        #[prost(message, tag = "2")]
+        Nonexist(super::NonExistenceProof),
+    }
+}
+
+impl From<ExistenceProof> for CommitmentProof {
+    fn from(proof: ExistenceProof) -> Self {
+        Self {
+            proof: Some(commitment_proof::Proof::Exist(proof)),
+        }
+    }
+}
+
+impl From<NonExistenceProof> for CommitmentProof {
+    fn from(proof: NonExistenceProof) -> Self {
+        Self {
+            proof: Some(commitment_proof::Proof::Nonexist(proof)),
+        }
+    }
+}
+
+impl CommitmentProof {
+    /// Packages the proof as a `ProofOp` of type `op_type`, proving `key`:
+    /// the key in the tree for a store proof, or the name of the store for
+    /// a multistore root proof.
+    pub fn to_proof_op(&self, op_type: impl Into<String>, key: impl Into<Vec<u8>>) -> ProofOp {
+        ProofOp {
+            field_type: op_type.into(),
+            key: key.into(),
+            data: self.encode_to_vec(),
+        }
+    }
+}
+
+/// A store whose values can be proven against its root hash.
+pub trait ProveKey {
+    /// The `ProofOp` type of the proofs of the store, e.g. [`PROOF_OP_IAVL`].
+    fn proof_op_type(&self) -> &str;
+
+    /// The height of the last committed state, which queries at height zero
+    /// are answered at.
+    fn latest_height(&self) -> block::Height;
+
+    /// The value of `key` as of `height`, if any.
+    fn get(&self, key: &[u8], height: block::Height) -> Result<Option<Bytes>, BoxError>;
+
+    /// Proves the value of `key` as of `height` with an [`ExistenceProof`],
+    /// or its absence with a [`NonExistenceProof`].
+    fn prove(&self, key: &[u8], height: block::Height) -> Result<CommitmentProof, BoxError>;
+}
+
+/// Answers `query` with the value of the key in its data in `store`, and if
+/// the query asks for it, with the proof of the store followed by `outer`,
+/// the proofs from the root of the store up to the app hash, if any.
+pub fn answer<P: ProveKey + ?Sized>(
+    store: &P,
+    query: &request::Query,
+    outer: Vec<ProofOp>,
+) -> Result<response::Query, BoxError> {
+    let height = if query.height.value() == 0 {
+        store.latest_height()
+    } else {
+        query.height
+    };
+    let value = store.get(&query.data, height)?.unwrap_or_default();
+    let proof = if query.prove {
+        let op = store
+            .prove(&query.data, height)?
+            .to_proof_op(store.proof_op_type(), query.data.to_vec());
+        let ops = std::iter::once(op).chain(outer).collect();
+        Some(ProofOps { ops })
+    } else {
+        None
+    };
+    Ok(response::Query {
+        key: query.data.clone(),
+        value,
+        proof,
+        height,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,195 @@
+//! Routing of `Query` requests by path.
+//!
+//! Applications usually serve several kinds of queries, told apart by the
+//! path of the request, e.g. `/store` for raw state lookups, `/p2p/filter/...`
+//! for peer filtering and `/custom/<module>/...` for application-specific
+//! queries. [`QueryRouter`] dispatches each query to the service registered
+//! for its path, and answers queries with an unknown path with an error,
+//! instead of every info service matching on path strings:
+//!
+//! ```ignore
+//! let info = QueryRouter::new(info)
+//!     .route("/store", store.clone())
+//!     .route_fn("/p2p/filter", |query| Ok(filter_peer(&query.path)))
+//!     .route("/custom/bank", bank);
+//! let server = v038::Server::builder().info(info)
+//! ```
+//!
+//! A route matches the paths equal to its prefix and those continuing it with
+//! a `/`, so `/store` matches `/store` and `/store/key`, but not `/stores`.
+//! The longest matching prefix wins. Routed services see the full path of the
+//! query.
+
+use std::{
+    cmp::Reverse,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::future::{BoxFuture, FutureExt};
+use tendermint::abci::{request, response, Code};
+use tower::{util::BoxCloneService, Service, ServiceExt};
+
+use crate::BoxError;
+
+/// The code of the `Query` responses to queries whose path has no route.
+pub const CODE_UNKNOWN_PATH: u32 = 1;
+
+type QueryService = BoxCloneService<request::Query, response::Query, BoxError>;
+
+/// An info service dispatching `Query` requests to the service registered
+/// for their path, and the other info requests to an inner info service. See
+/// the [module documentation](self) for details.
+///
+/// It also serves `request::Query` on its own, for info services that need
+/// to handle queries themselves before routing them.
+#[derive(Clone)]
+pub struct QueryRouter<I> {
+    info: I,
+    /// The routes, longest prefix first.
+    routes: Vec<(String, QueryService)>,
+    fallback: Option<QueryService>,
+}
+
+impl<I> QueryRouter<I> {
+    /// Routes no paths, and serves the `Info` and `Echo` requests, and the
+    /// `SetOption` requests of 0.34, with `info`.
+    pub fn new(info: I) -> Self {
+        Self {
+            info,
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Routes the queries whose path is `prefix`, or starts with `prefix`
+    /// followed by a `/`, to `service`. A route for the same prefix replaces
+    /// the previous one.
+    pub fn route<S>(mut self, prefix: impl Into<String>, service: S) -> Self
+    where
+        S: Service<request::Query, Response = response::Query, Error = BoxError>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let prefix = prefix.into();
+        self.routes.retain(|(route, _)| *route != prefix);
+        self.routes.push((prefix, BoxCloneService::new(service)));
+        self.routes.sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+        self
+    }
+
+    /// Routes the queries of `prefix`, as [`route`](Self::route) does, to a
+    /// function answering them synchronously.
+    pub fn route_fn<F>(self, prefix: impl Into<String>, f: F) -> Self
+    where
+        F: Fn(request::Query) -> Result<response::Query, BoxError> + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        self.route(
+            prefix,
+            tower::service_fn(move |query| futures::future::ready(f(query))),
+        )
+    }
+
+    /// Sends the queries matching no route to `service`, instead of
+    /// answering them with [`CODE_UNKNOWN_PATH`].
+    pub fn fallback<S>(mut self, service: S) -> Self
+    where
+        S: Service<request::Query, Response = response::Query, Error = BoxError>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.fallback = Some(BoxCloneService::new(service));
+        self
+    }
+
+    /// Dispatches `query` to the service of its route.
+    fn dispatch(
+        &self,
+        query: request::Query,
+    ) -> BoxFuture<'static, Result<response::Query, BoxError>> {
+        let service = self
+            .routes
+            .iter()
+            .find(|(prefix, _)| matches(&query.path, prefix))
+            .map(|(_, service)| service)
+            .or(self.fallback.as_ref());
+        match service {
+            Some(service) => service.clone().oneshot(query).boxed(),
+            None => futures::future::ready(Ok(unknown_path(query))).boxed(),
+        }
+    }
+}
+
+/// Whether `path` is `prefix`, or starts with `prefix` followed by a `/`.
+fn matches(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+pub(crate) fn unknown_path(query: request::Query) -> response::Query {
+    response::Query {
+        code: Code::from(CODE_UNKNOWN_PATH),
+        log: format!("unknown query path: {:?}", query.path),
+        height: query.height,
+        ..Default::default()
+    }
+}
+
+impl<I> Service<request::Query> for QueryRouter<I> {
+    type Response = response::Query;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<response::Query, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, query: request::Query) -> Self::Future {
+        self.dispatch(query)
+    }
+}
+
+macro_rules! impl_info_service {
+    ($version:ident) => {
+        impl<I> Service<tendermint::$version::abci::InfoRequest> for QueryRouter<I>
+        where
+            I: Service<
+                tendermint::$version::abci::InfoRequest,
+                Response = tendermint::$version::abci::InfoResponse,
+                Error = BoxError,
+            >,
+            I::Future: Send + 'static,
+        {
+            type Response = tendermint::$version::abci::InfoResponse;
+            type Error = BoxError;
+            type Future = BoxFuture<'static, Result<Self::Response, BoxError>>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.info.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::InfoRequest) -> Self::Future {
+                use tendermint::$version::abci::{InfoRequest, InfoResponse};
+
+                match req {
+                    InfoRequest::Query(query) => self
+                        .dispatch(query)
+                        .map(|r| r.map(InfoResponse::Query))
+                        .boxed(),
+                    req => self.info.call(req).boxed(),
+                }
+            }
+        }
+    };
+}
+
+impl_info_service!(v0_34);
+impl_info_service!(v0_37);
+impl_info_service!(v0_38);
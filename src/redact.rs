@@ -0,0 +1,550 @@
+//! Size-bounded [`Debug`] formatting of ABCI requests and responses.
+//!
+//! Transactions, snapshot chunks, genesis state and query results can be
+//! arbitrarily large, so formatting a request with `?request` can write
+//! megabytes into a log line. The [`Redacted`] wrapper formats the same
+//! structures, but replaces each potentially large byte field with its length,
+//! a short hex prefix and a SHA-256 fingerprint, which is enough to correlate
+//! payloads across log lines without reproducing them.
+//!
+//! Formatting is also bounded in time: the output of a [`Redacted`] value is
+//! cut off after [`MAX_LEN`] bytes, which stops formatting the rest of the
+//! value, and fingerprints hash at most the first [`FINGERPRINT_LEN`] bytes of
+//! a field. Events only format their fields when they are recorded, so
+//! logging a request costs nothing more than a level check when the level
+//! is disabled, and a bounded amount of work otherwise.
+
+use std::fmt::{self, Write as _};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use sha2::{Digest, Sha256};
+use tendermint::abci::{request, response, types::ExecTxResult};
+
+/// Byte fields at most this long are printed in full.
+const INLINE_LEN: usize = 32;
+
+/// The number of leading bytes printed for longer byte fields.
+const PREFIX_LEN: usize = 8;
+
+/// The most bytes of output of a [`Redacted`] value, after which it is cut
+/// off with `..`.
+pub const MAX_LEN: usize = 4 * 1024;
+
+/// The most leading bytes of a byte field hashed into its fingerprint.
+pub const FINGERPRINT_LEN: usize = 1024 * 1024;
+
+/// Wraps a request or response so that its [`Debug`] output is bounded in size.
+///
+/// This is what the ABCI servers use when logging requests and responses, and
+/// it can be used in the same way by applications and middleware:
+/// `tracing::debug!(request = ?Redacted(&request))`.
+#[derive(Clone, Copy)]
+pub struct Redacted<T>(pub T);
+
+/// Types that have a size-bounded [`Debug`] representation, used by [`Redacted`].
+pub trait Redact {
+    /// Formats `self` like [`fmt::Debug::fmt`], but with large byte fields summarized.
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl<T: Redact> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bounded = Bounded {
+            inner: f,
+            remaining: MAX_LEN,
+            truncated: false,
+        };
+        let value = Unbounded(&self.0);
+        let result = if bounded.inner.alternate() {
+            write!(bounded, "{:#?}", value)
+        } else {
+            write!(bounded, "{:?}", value)
+        };
+        if bounded.truncated {
+            // The error only stopped the formatting of the rest of the value.
+            return bounded.inner.write_str("..");
+        }
+        result
+    }
+}
+
+/// Formats a value with [`Redact::fmt_redacted`], without a bound.
+struct Unbounded<'a, T>(&'a T);
+
+impl<T: Redact> fmt::Debug for Unbounded<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_redacted(f)
+    }
+}
+
+/// A writer passing at most `remaining` bytes on to `inner`, which fails once
+/// they are used up so that the formatting stops.
+struct Bounded<'a, 'b> {
+    inner: &'a mut fmt::Formatter<'b>,
+    remaining: usize,
+    truncated: bool,
+}
+
+impl fmt::Write for Bounded<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() <= self.remaining {
+            self.remaining -= s.len();
+            return self.inner.write_str(s);
+        }
+        let mut end = self.remaining;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.remaining = 0;
+        self.truncated = true;
+        self.inner.write_str(&s[..end])?;
+        Err(fmt::Error)
+    }
+}
+
+impl<T: Redact + ?Sized> Redact for &T {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt_redacted(f)
+    }
+}
+
+impl<T: Redact> Redact for Result<T, crate::BoxError> {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ok(t) => f.debug_tuple("Ok").field(&Redacted(t)).finish(),
+            Err(e) => f.debug_tuple("Err").field(e).finish(),
+        }
+    }
+}
+
+/// Records an event at a level chosen at runtime, since `tracing` requires
+/// the level of each call site to be a constant.
+macro_rules! event_at {
+    ($level:expr, $($args:tt)+) => {
+        let level = $level;
+        if level == tracing::Level::ERROR {
+            tracing::error!($($args)+)
+        } else if level == tracing::Level::WARN {
+            tracing::warn!($($args)+)
+        } else if level == tracing::Level::INFO {
+            tracing::info!($($args)+)
+        } else if level == tracing::Level::DEBUG {
+            tracing::debug!($($args)+)
+        } else {
+            tracing::trace!($($args)+)
+        }
+    };
+}
+
+/// Logs a request read by the server at the given `level`.
+pub(crate) fn log_request(level: tracing::Level, request: &impl Redact) {
+    event_at!(level, request = ?Redacted(request), "new request");
+}
+
+/// Logs a response about to be sent by the server at the given `level`.
+pub(crate) fn log_response(level: tracing::Level, response: &impl Redact) {
+    event_at!(level, response = ?Redacted(response), "sending response");
+}
+
+/// A byte field, formatted as its length, prefix and SHA-256 fingerprint.
+struct Bytes<'a>(&'a [u8]);
+
+impl fmt::Debug for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.len() <= INLINE_LEN {
+            return write!(f, "0x{}", hex::encode(self.0));
+        }
+        write!(
+            f,
+            "<{} bytes 0x{}.. ",
+            self.0.len(),
+            hex::encode(&self.0[..PREFIX_LEN])
+        )?;
+        // Hashing is the costly part, so it's skipped if the output was cut
+        // off above.
+        let hashed = &self.0[..self.0.len().min(FINGERPRINT_LEN)];
+        let digest = Sha256::digest(hashed);
+        if hashed.len() < self.0.len() {
+            write!(f, "sha256[..{}]:", hashed.len())?;
+        } else {
+            f.write_str("sha256:")?;
+        }
+        write!(f, "{}>", hex::encode(&digest[..PREFIX_LEN]))
+    }
+}
+
+/// A list of transactions, formatted as a count and total size.
+struct Txs<'a, T>(&'a [T]);
+
+impl<T: AsRef<[u8]>> fmt::Debug for Txs<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: usize = self.0.iter().map(|tx| tx.as_ref().len()).sum();
+        write!(f, "<{} txs, {} bytes>", self.0.len(), total)
+    }
+}
+
+/// A list of transaction results, with each result's data redacted.
+struct TxResults<'a>(&'a [ExecTxResult]);
+
+impl fmt::Debug for TxResults<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(Redacted)).finish()
+    }
+}
+
+// ===== impl request types =====
+
+impl Redact for request::InitChain {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InitChain")
+            .field("time", &self.time)
+            .field("chain_id", &self.chain_id)
+            .field("consensus_params", &self.consensus_params)
+            .field("validators", &self.validators)
+            .field("app_state_bytes", &Bytes(&self.app_state_bytes))
+            .field("initial_height", &self.initial_height)
+            .finish()
+    }
+}
+
+impl Redact for request::Query {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Query")
+            .field("data", &Bytes(&self.data))
+            .field("path", &self.path)
+            .field("height", &self.height)
+            .field("prove", &self.prove)
+            .finish()
+    }
+}
+
+impl Redact for request::CheckTx {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CheckTx")
+            .field("tx", &Bytes(&self.tx))
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl Redact for request::DeliverTx {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeliverTx")
+            .field("tx", &Bytes(&self.tx))
+            .finish()
+    }
+}
+
+impl Redact for request::OfferSnapshot {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OfferSnapshot")
+            .field("height", &self.snapshot.height)
+            .field("format", &self.snapshot.format)
+            .field("chunks", &self.snapshot.chunks)
+            .field("hash", &Bytes(&self.snapshot.hash))
+            .field("metadata", &Bytes(&self.snapshot.metadata))
+            .field("app_hash", &self.app_hash)
+            .finish()
+    }
+}
+
+impl Redact for request::ApplySnapshotChunk {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApplySnapshotChunk")
+            .field("index", &self.index)
+            .field("chunk", &Bytes(&self.chunk))
+            .field("sender", &self.sender)
+            .finish()
+    }
+}
+
+impl Redact for request::PrepareProposal {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrepareProposal")
+            .field("max_tx_bytes", &self.max_tx_bytes)
+            .field("txs", &Txs(&self.txs))
+            .field("height", &self.height)
+            .field("time", &self.time)
+            .field("proposer_address", &self.proposer_address)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for request::ProcessProposal {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessProposal")
+            .field("txs", &Txs(&self.txs))
+            .field("hash", &self.hash)
+            .field("height", &self.height)
+            .field("time", &self.time)
+            .field("proposer_address", &self.proposer_address)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for request::ExtendVote {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtendVote")
+            .field("hash", &self.hash)
+            .field("height", &self.height)
+            .field("time", &self.time)
+            .field("txs", &Txs(&self.txs))
+            .field("proposer_address", &self.proposer_address)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for request::VerifyVoteExtension {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifyVoteExtension")
+            .field("hash", &self.hash)
+            .field("validator_address", &self.validator_address)
+            .field("height", &self.height)
+            .field("vote_extension", &Bytes(&self.vote_extension))
+            .finish()
+    }
+}
+
+impl Redact for request::FinalizeBlock {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FinalizeBlock")
+            .field("txs", &Txs(&self.txs))
+            .field("hash", &self.hash)
+            .field("height", &self.height)
+            .field("time", &self.time)
+            .field("proposer_address", &self.proposer_address)
+            .finish_non_exhaustive()
+    }
+}
+
+// ===== impl response types =====
+
+impl Redact for response::Query {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Query")
+            .field("code", &self.code)
+            .field("log", &self.log)
+            .field("info", &self.info)
+            .field("index", &self.index)
+            .field("key", &Bytes(&self.key))
+            .field("value", &Bytes(&self.value))
+            .field("proof", &self.proof.as_ref().map(|p| p.ops.len()))
+            .field("height", &self.height)
+            .field("codespace", &self.codespace)
+            .finish()
+    }
+}
+
+impl Redact for response::CheckTx {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CheckTx")
+            .field("code", &self.code)
+            .field("data", &Bytes(&self.data))
+            .field("log", &self.log)
+            .field("gas_wanted", &self.gas_wanted)
+            .field("gas_used", &self.gas_used)
+            .field("codespace", &self.codespace)
+            .field("priority", &self.priority)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for response::DeliverTx {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeliverTx")
+            .field("code", &self.code)
+            .field("data", &Bytes(&self.data))
+            .field("log", &self.log)
+            .field("gas_wanted", &self.gas_wanted)
+            .field("gas_used", &self.gas_used)
+            .field("codespace", &self.codespace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for ExecTxResult {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExecTxResult")
+            .field("code", &self.code)
+            .field("data", &Bytes(&self.data))
+            .field("log", &self.log)
+            .field("gas_wanted", &self.gas_wanted)
+            .field("gas_used", &self.gas_used)
+            .field("codespace", &self.codespace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for response::LoadSnapshotChunk {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadSnapshotChunk")
+            .field("chunk", &Bytes(&self.chunk))
+            .finish()
+    }
+}
+
+impl Redact for response::PrepareProposal {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrepareProposal")
+            .field("txs", &Txs(&self.txs))
+            .finish()
+    }
+}
+
+impl Redact for response::ExtendVote {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtendVote")
+            .field("vote_extension", &Bytes(&self.vote_extension))
+            .finish()
+    }
+}
+
+impl Redact for response::FinalizeBlock {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FinalizeBlock")
+            .field("tx_results", &TxResults(&self.tx_results))
+            .field("validator_updates", &self.validator_updates)
+            .field("consensus_param_updates", &self.consensus_param_updates)
+            .field("app_hash", &self.app_hash)
+            .finish_non_exhaustive()
+    }
+}
+
+// ===== impl v0_34 =====
+
+impl Redact for tendermint::v0_34::abci::Request {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use tendermint::v0_34::abci::Request::*;
+        match self {
+            InitChain(x) => f.debug_tuple("InitChain").field(&Redacted(x)).finish(),
+            Query(x) => f.debug_tuple("Query").field(&Redacted(x)).finish(),
+            CheckTx(x) => f.debug_tuple("CheckTx").field(&Redacted(x)).finish(),
+            DeliverTx(x) => f.debug_tuple("DeliverTx").field(&Redacted(x)).finish(),
+            OfferSnapshot(x) => f.debug_tuple("OfferSnapshot").field(&Redacted(x)).finish(),
+            ApplySnapshotChunk(x) => f
+                .debug_tuple("ApplySnapshotChunk")
+                .field(&Redacted(x))
+                .finish(),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+impl Redact for tendermint::v0_34::abci::Response {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use tendermint::v0_34::abci::Response::*;
+        match self {
+            Query(x) => f.debug_tuple("Query").field(&Redacted(x)).finish(),
+            CheckTx(x) => f.debug_tuple("CheckTx").field(&Redacted(x)).finish(),
+            DeliverTx(x) => f.debug_tuple("DeliverTx").field(&Redacted(x)).finish(),
+            LoadSnapshotChunk(x) => f
+                .debug_tuple("LoadSnapshotChunk")
+                .field(&Redacted(x))
+                .finish(),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+// ===== impl v0_37 =====
+
+impl Redact for tendermint::v0_37::abci::Request {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use tendermint::v0_37::abci::Request::*;
+        match self {
+            InitChain(x) => f.debug_tuple("InitChain").field(&Redacted(x)).finish(),
+            Query(x) => f.debug_tuple("Query").field(&Redacted(x)).finish(),
+            CheckTx(x) => f.debug_tuple("CheckTx").field(&Redacted(x)).finish(),
+            DeliverTx(x) => f.debug_tuple("DeliverTx").field(&Redacted(x)).finish(),
+            OfferSnapshot(x) => f.debug_tuple("OfferSnapshot").field(&Redacted(x)).finish(),
+            ApplySnapshotChunk(x) => f
+                .debug_tuple("ApplySnapshotChunk")
+                .field(&Redacted(x))
+                .finish(),
+            PrepareProposal(x) => f
+                .debug_tuple("PrepareProposal")
+                .field(&Redacted(x))
+                .finish(),
+            ProcessProposal(x) => f
+                .debug_tuple("ProcessProposal")
+                .field(&Redacted(x))
+                .finish(),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+impl Redact for tendermint::v0_37::abci::Response {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use tendermint::v0_37::abci::Response::*;
+        match self {
+            Query(x) => f.debug_tuple("Query").field(&Redacted(x)).finish(),
+            CheckTx(x) => f.debug_tuple("CheckTx").field(&Redacted(x)).finish(),
+            DeliverTx(x) => f.debug_tuple("DeliverTx").field(&Redacted(x)).finish(),
+            LoadSnapshotChunk(x) => f
+                .debug_tuple("LoadSnapshotChunk")
+                .field(&Redacted(x))
+                .finish(),
+            PrepareProposal(x) => f
+                .debug_tuple("PrepareProposal")
+                .field(&Redacted(x))
+                .finish(),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+// ===== impl v0_38 =====
+
+impl Redact for tendermint::v0_38::abci::Request {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use tendermint::v0_38::abci::Request::*;
+        match self {
+            InitChain(x) => f.debug_tuple("InitChain").field(&Redacted(x)).finish(),
+            Query(x) => f.debug_tuple("Query").field(&Redacted(x)).finish(),
+            CheckTx(x) => f.debug_tuple("CheckTx").field(&Redacted(x)).finish(),
+            OfferSnapshot(x) => f.debug_tuple("OfferSnapshot").field(&Redacted(x)).finish(),
+            ApplySnapshotChunk(x) => f
+                .debug_tuple("ApplySnapshotChunk")
+                .field(&Redacted(x))
+                .finish(),
+            PrepareProposal(x) => f
+                .debug_tuple("PrepareProposal")
+                .field(&Redacted(x))
+                .finish(),
+            ProcessProposal(x) => f
+                .debug_tuple("ProcessProposal")
+                .field(&Redacted(x))
+                .finish(),
+            ExtendVote(x) => f.debug_tuple("ExtendVote").field(&Redacted(x)).finish(),
+            VerifyVoteExtension(x) => f
+                .debug_tuple("VerifyVoteExtension")
+                .field(&Redacted(x))
+                .finish(),
+            FinalizeBlock(x) => f.debug_tuple("FinalizeBlock").field(&Redacted(x)).finish(),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+impl Redact for tendermint::v0_38::abci::Response {
+    fn fmt_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use tendermint::v0_38::abci::Response::*;
+        match self {
+            Query(x) => f.debug_tuple("Query").field(&Redacted(x)).finish(),
+            CheckTx(x) => f.debug_tuple("CheckTx").field(&Redacted(x)).finish(),
+            LoadSnapshotChunk(x) => f
+                .debug_tuple("LoadSnapshotChunk")
+                .field(&Redacted(x))
+                .finish(),
+            PrepareProposal(x) => f
+                .debug_tuple("PrepareProposal")
+                .field(&Redacted(x))
+                .finish(),
+            ExtendVote(x) => f.debug_tuple("ExtendVote").field(&Redacted(x)).finish(),
+            FinalizeBlock(x) => f.debug_tuple("FinalizeBlock").field(&Redacted(x)).finish(),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
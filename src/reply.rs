@@ -0,0 +1,83 @@
+//! Constructors for common ABCI responses.
+//!
+//! The response structs of `tendermint` are built with struct literals, which
+//! for the usual accept and reject responses means repeating the same
+//! `..Default::default()` update everywhere. The traits of this module add
+//! constructors for them:
+//!
+//! ```
+//! use tendermint::abci::response;
+//! use tower_abci::reply::{CodeResponse, ExceptionExt};
+//! # const CODE_BAD_NONCE: u32 = 3;
+//! # let error = std::io::Error::other("disk full");
+//!
+//! let accepted = response::CheckTx::ok();
+//! let rejected = response::CheckTx::err(CODE_BAD_NONCE, "nonce too low");
+//! let exception = response::Exception::from_error(&error);
+//! ```
+
+use tendermint::abci::{response, types::ExecTxResult};
+
+/// Constructors for the responses carrying a result code and log.
+pub trait CodeResponse: Sized {
+    /// A successful response, with code zero and otherwise default fields.
+    fn ok() -> Self;
+
+    /// A failed response with `code` and `log`, and otherwise default fields.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is zero, which would mark the response as successful.
+    fn err(code: u32, log: impl Into<String>) -> Self;
+
+    /// A failed response with `code`, and the message of `error` as the log.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is zero, as [`err`](Self::err) does.
+    fn from_error(code: u32, error: &(dyn std::error::Error + 'static)) -> Self {
+        Self::err(code, error.to_string())
+    }
+}
+
+macro_rules! impl_code_response {
+    ($($ty:ty),*) => {
+        $(
+            impl CodeResponse for $ty {
+                fn ok() -> Self {
+                    Self::default()
+                }
+
+                fn err(code: u32, log: impl Into<String>) -> Self {
+                    assert!(code != 0, "error response code must be nonzero");
+                    Self {
+                        code: code.into(),
+                        log: log.into(),
+                        ..Default::default()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_code_response!(
+    response::CheckTx,
+    response::DeliverTx,
+    response::Query,
+    ExecTxResult
+);
+
+/// Constructors for `Exception` responses.
+pub trait ExceptionExt {
+    /// An exception reporting `error`.
+    fn from_error(error: &(dyn std::error::Error + 'static)) -> Self;
+}
+
+impl ExceptionExt for response::Exception {
+    fn from_error(error: &(dyn std::error::Error + 'static)) -> Self {
+        Self {
+            error: error.to_string(),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Request identifiers for correlating application logs with server logs.
+//!
+//! The server numbers every request it reads from a connection, starting from
+//! zero, and records the resulting [`RequestId`] on the `request` tracing span
+//! that it creates for that request. Component services are called inside that
+//! span, and [`split`](crate::v038::split) forwards it to the application's
+//! task, so events recorded by the application carry the id automatically.
+//!
+//! The id is also available directly, via [`RequestId::current`], while the
+//! server or the `split` worker is calling [`Service::call`](tower::Service::call).
+
+use std::{
+    cell::Cell,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+thread_local! {
+    static CURRENT: Cell<Option<RequestId>> = const { Cell::new(None) };
+}
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a new process-unique connection id.
+pub(crate) fn next_connection_id() -> u64 {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Identifies a request by the connection it arrived on and its position in
+/// that connection's request stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequestId {
+    connection: u64,
+    sequence: u64,
+}
+
+impl RequestId {
+    pub(crate) fn new(connection: u64, sequence: u64) -> Self {
+        Self {
+            connection,
+            sequence,
+        }
+    }
+
+    /// The id of the connection the request arrived on.
+    pub fn connection(&self) -> u64 {
+        self.connection
+    }
+
+    /// The position of the request in its connection's request stream.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the id of the request being passed to [`Service::call`], if
+    /// called from within `call` on a service invoked by the server.
+    ///
+    /// [`Service::call`]: tower::Service::call
+    pub fn current() -> Option<RequestId> {
+        CURRENT.with(|c| c.get())
+    }
+
+    /// Runs `f` with `id` as the current request id.
+    pub(crate) fn scope<T>(id: Option<RequestId>, f: impl FnOnce() -> T) -> T {
+        let prev = CURRENT.with(|c| c.replace(id));
+        let out = f();
+        CURRENT.with(|c| c.set(prev));
+        out
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.connection, self.sequence)
+    }
+}
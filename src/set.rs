@@ -0,0 +1,210 @@
+//! Running several servers in one process.
+//!
+//! A [`ServerSet`] runs servers of any protocol version together, e.g. one per
+//! chain of a multi-chain host, and stops them together: when one of them
+//! stops, whether it failed or was drained, or when the shutdown signal
+//! resolves, every other server is drained too.
+//!
+//! ```ignore
+//! let mut set = ServerSet::new();
+//! set.add("chain-a", chain_a.handle(), chain_a.listen_tcp("127.0.0.1:26658"));
+//! set.add("chain-b", chain_b.handle(), chain_b.listen_unix("/run/chain-b.sock"));
+//!
+//! let handle = set.handle();
+//! tokio::spawn(monitor(handle));
+//! set.run_until(async { tokio::signal::ctrl_c().await.unwrap() }).await?;
+//! ```
+//!
+//! The servers are added with their handle and the future returned by their
+//! `listen_*` method, which resolves once they stop accepting connections.
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use futures::{
+    future::{self, BoxFuture},
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt,
+};
+use tokio::select;
+use tracing::Instrument;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{health::HealthReport, task, BoxError, ServerHandle};
+
+/// Servers run and stopped together. See the [module documentation](self).
+#[derive(Default)]
+pub struct ServerSet {
+    listeners: Vec<(String, BoxFuture<'static, Result<(), BoxError>>)>,
+    handle: ServerSetHandle,
+}
+
+impl ServerSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the server with the given `handle`, listening with `listen`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a server named `name` was already added.
+    pub fn add<F>(&mut self, name: impl Into<String>, handle: ServerHandle, listen: F) -> &mut Self
+    where
+        F: Future<Output = Result<(), BoxError>> + Send + 'static,
+    {
+        let name = name.into();
+        let mut servers = self.handle.servers.lock().unwrap();
+        assert!(
+            servers.iter().all(|(added, _)| *added != name),
+            "a server named {name:?} was already added"
+        );
+        servers.push((name.clone(), handle));
+        drop(servers);
+        self.listeners.push((name, listen.boxed()));
+        self
+    }
+
+    /// A handle for inspecting and shutting down the servers of the set.
+    pub fn handle(&self) -> ServerSetHandle {
+        self.handle.clone()
+    }
+
+    /// Runs the servers until one of them stops, then drains the others and
+    /// waits for them to stop.
+    pub async fn run(self) -> Result<(), ServerSetError> {
+        self.run_until(future::pending()).await
+    }
+
+    /// Runs the servers until one of them stops or `shutdown` resolves, then
+    /// drains the others and waits for them to stop.
+    ///
+    /// Fails with the errors of every server that failed.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<(), ServerSetError> {
+        let handle = self.handle;
+        let mut running: FuturesUnordered<_> = self
+            .listeners
+            .into_iter()
+            .map(|(name, listen)| {
+                let span = tracing::info_span!("abci_server", name = %name);
+                task::spawn(&format!("abci-server-{name}"), listen.instrument(span))
+                    .map(move |result| (name, result))
+            })
+            .collect();
+
+        let mut failures = Vec::new();
+        let shutdown = shutdown.fuse();
+        tokio::pin!(shutdown);
+        loop {
+            select! {
+                () = &mut shutdown => {
+                    tracing::info!("shutting down servers");
+                    handle.shutdown();
+                }
+                stopped = running.next() => {
+                    let Some((name, result)) = stopped else {
+                        break;
+                    };
+                    match result.map_err(BoxError::from).and_then(|result| result) {
+                        Ok(()) => tracing::info!(%name, "server stopped"),
+                        Err(error) => {
+                            tracing::error!(%name, %error, "server failed");
+                            failures.push(ServerFailure { name, error });
+                        }
+                    }
+                    if !handle.is_shutting_down() {
+                        handle.shutdown();
+                    }
+                }
+            }
+        }
+
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(ServerSetError { failures }),
+        }
+    }
+}
+
+impl fmt::Debug for ServerSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerSet")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A handle for inspecting and shutting down the servers of a [`ServerSet`].
+#[derive(Clone, Debug, Default)]
+pub struct ServerSetHandle {
+    servers: Arc<Mutex<Vec<(String, ServerHandle)>>>,
+}
+
+impl ServerSetHandle {
+    /// The names of the servers, in the order they were added.
+    pub fn names(&self) -> Vec<String> {
+        let servers = self.servers.lock().unwrap();
+        servers.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// The handle of the server named `name`.
+    pub fn get(&self, name: &str) -> Option<ServerHandle> {
+        let servers = self.servers.lock().unwrap();
+        servers
+            .iter()
+            .find(|(added, _)| added == name)
+            .map(|(_, handle)| handle.clone())
+    }
+
+    /// Drains every server, as with [`ServerHandle::drain`].
+    pub fn shutdown(&self) {
+        for (_, handle) in self.servers.lock().unwrap().iter() {
+            handle.drain();
+        }
+    }
+
+    /// Returns `true` if every server is draining.
+    pub fn is_shutting_down(&self) -> bool {
+        let servers = self.servers.lock().unwrap();
+        servers.iter().all(|(_, handle)| handle.is_draining())
+    }
+
+    /// Runs the [health checks](crate::health) of every server concurrently.
+    pub async fn health_check(&self) -> Vec<(String, HealthReport)> {
+        let servers = self.servers.lock().unwrap().clone();
+        future::join_all(servers.into_iter().map(|(name, handle)| async move {
+            let report = handle.health_check().await;
+            (name, report)
+        }))
+        .await
+    }
+}
+
+/// A server of a [`ServerSet`] that failed.
+#[derive(Debug)]
+pub struct ServerFailure {
+    pub name: String,
+    pub error: BoxError,
+}
+
+/// The failures of the servers of a [`ServerSet`].
+#[derive(Debug)]
+pub struct ServerSetError {
+    pub failures: Vec<ServerFailure>,
+}
+
+impl fmt::Display for ServerSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} server(s) failed", self.failures.len())?;
+        for failure in &self.failures {
+            write!(f, "; {}: {}", failure.name, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ServerSetError {}
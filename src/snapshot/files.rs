@@ -0,0 +1,159 @@
+//! Serving snapshot chunks from files.
+//!
+//! [`FileChunks`] answers `LoadSnapshotChunk` requests by reading the chunk
+//! from its file with `tokio::fs`, instead of calling the inner snapshot
+//! service, which handles the other snapshot requests:
+//!
+//! ```ignore
+//! let snapshot = FileChunks::new("/var/lib/app/snapshots", SnapshotManager::new(store))
+//!     .max_concurrent_loads(4);
+//! let server = v038::Server::builder().snapshot(snapshot)
+//! ```
+//!
+//! The chunk `index` of the snapshot of `format` at `height` is the file
+//! [`chunk_path`]`(root, height, format, index)`, i.e.
+//! `<root>/<height>/<format>/<index>`; a missing file is served as an empty
+//! chunk. Chunks are read without holding up the connection's other
+//! requests, and at most a given number at once, so that peers state syncing
+//! from the node at the same time don't have all their chunks in memory at
+//! once. Each chunk is still read whole, since the response carries it as a
+//! single message.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::future::{BoxFuture, FutureExt};
+use tendermint::{
+    abci::{request, response},
+    block,
+};
+use tokio::{io::AsyncReadExt, sync::Semaphore};
+use tower::Service;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::BoxError;
+
+/// The largest chunk served, the limit of CometBFT on snapshot chunk messages.
+pub const MAX_CHUNK_LEN: u64 = 16 * 1024 * 1024;
+
+/// The default number of chunks read at once.
+pub const DEFAULT_MAX_CONCURRENT_LOADS: usize = 4;
+
+/// The path of the file of the chunk `index` of the snapshot of `format` at
+/// `height`, under `root`.
+pub fn chunk_path(root: &Path, height: block::Height, format: u32, index: u32) -> PathBuf {
+    root.join(height.to_string())
+        .join(format.to_string())
+        .join(index.to_string())
+}
+
+/// A snapshot service serving chunks from files, and the other requests with
+/// an inner snapshot service. See the [module documentation](self) for
+/// details.
+#[derive(Clone)]
+pub struct FileChunks<S> {
+    inner: S,
+    root: Arc<PathBuf>,
+    loads: Arc<Semaphore>,
+}
+
+impl<S> FileChunks<S> {
+    /// Serves the chunks under `root`, and the other requests with `inner`.
+    pub fn new(root: impl Into<PathBuf>, inner: S) -> Self {
+        Self {
+            inner,
+            root: Arc::new(root.into()),
+            loads: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_LOADS)),
+        }
+    }
+
+    /// Reads at most `max` chunks at once; the others wait. Defaults to
+    /// [`DEFAULT_MAX_CONCURRENT_LOADS`].
+    pub fn max_concurrent_loads(mut self, max: usize) -> Self {
+        self.loads = Arc::new(Semaphore::new(max.max(1)));
+        self
+    }
+
+    /// Reads the chunk of `request`.
+    fn load(
+        &self,
+        request: request::LoadSnapshotChunk,
+    ) -> BoxFuture<'static, Result<response::LoadSnapshotChunk, BoxError>> {
+        let path = chunk_path(&self.root, request.height, request.format, request.chunk);
+        let loads = self.loads.clone();
+        async move {
+            let _permit = loads.acquire_owned().await?;
+            let chunk = read_chunk(&path).await.map_err(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "failed to read snapshot chunk");
+                e
+            })?;
+            Ok(response::LoadSnapshotChunk { chunk })
+        }
+        .boxed()
+    }
+}
+
+/// Reads the chunk at `path`, or an empty chunk if there is no such file.
+async fn read_chunk(path: &Path) -> Result<Bytes, BoxError> {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Bytes::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let len = file.metadata().await?.len();
+    if len > MAX_CHUNK_LEN {
+        return Err(format!("snapshot chunk of {} bytes is too large", len).into());
+    }
+    let mut chunk = BytesMut::with_capacity(len as usize);
+    while file.read_buf(&mut chunk).await? != 0 {
+        if chunk.len() as u64 > MAX_CHUNK_LEN {
+            return Err("snapshot chunk grew too large while read".into());
+        }
+    }
+    Ok(chunk.freeze())
+}
+
+macro_rules! impl_snapshot_service {
+    ($version:ident) => {
+        impl<S> Service<tendermint::$version::abci::SnapshotRequest> for FileChunks<S>
+        where
+            S: Service<
+                tendermint::$version::abci::SnapshotRequest,
+                Response = tendermint::$version::abci::SnapshotResponse,
+                Error = BoxError,
+            >,
+            S::Future: Send + 'static,
+        {
+            type Response = tendermint::$version::abci::SnapshotResponse;
+            type Error = BoxError;
+            type Future = BoxFuture<'static, Result<Self::Response, BoxError>>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::SnapshotRequest) -> Self::Future {
+                use tendermint::$version::abci::{
+                    SnapshotRequest as Request, SnapshotResponse as Response,
+                };
+
+                match req {
+                    Request::LoadSnapshotChunk(req) => self
+                        .load(req)
+                        .map(|r| r.map(Response::LoadSnapshotChunk))
+                        .boxed(),
+                    req => self.inner.call(req).boxed(),
+                }
+            }
+        }
+    };
+}
+
+impl_snapshot_service!(v0_34);
+impl_snapshot_service!(v0_37);
+impl_snapshot_service!(v0_38);
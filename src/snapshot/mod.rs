@@ -0,0 +1,466 @@
+//! A snapshot service for state sync.
+//!
+//! State sync has nodes serve snapshots of their state in chunks, and a new
+//! node restore its state from a snapshot offered by its peers instead of
+//! replaying every block. The protocol is the same for every application:
+//! list the snapshots, serve their chunks, and accept an offered snapshot,
+//! persist its chunks as they arrive, and restore the state once all of them
+//! did. [`SnapshotManager`] implements it on top of a [`SnapshotStore`], which
+//! only stores snapshots and restores the state from them:
+//!
+//! ```ignore
+//! let snapshot = SnapshotManager::new(store);
+//! let server = v038::Server::builder().snapshot(snapshot)
+//! ```
+//!
+//! With [`verify_chunks`](SnapshotManager::verify_chunks), the manager checks
+//! each chunk against the hashes in the metadata of the snapshot before
+//! persisting it, and has the node fetch a corrupt chunk again from another
+//! peer.
+//!
+//! Once the chunks are restored, a callback set with
+//! [`verify_restored`](SnapshotManager::verify_restored) can check the
+//! restored state against the app hash the node trusts, and
+//! [`max_failed_snapshots`](SnapshotManager::max_failed_snapshots) aborts
+//! state sync after too many failed snapshots. The
+//! [`progress`](SnapshotManager::progress) of the restoration can be watched,
+//! e.g. to report it to operators.
+//!
+//! The manager can also create snapshots periodically, with the
+//! [`scheduler`](SnapshotManager::scheduler) layer of the consensus service.
+//!
+//! The manager implements the snapshot service of every supported protocol
+//! version. Store errors fail the request, and so the connection, except
+//! those restoring the state, which reject the snapshot so that the node
+//! tries another one.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::future::{ready, Ready};
+use sha2::{Digest, Sha256};
+use tendermint::{
+    abci::{request, response, response::ApplySnapshotChunkResult, types::Snapshot},
+    block, AppHash,
+};
+use tokio::sync::watch;
+use tower::Service;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::BoxError;
+
+#[cfg(feature = "net")]
+pub mod files;
+pub mod scheduler;
+
+/// The most times a chunk is fetched again after failing verification,
+/// before the snapshot is rejected.
+pub const MAX_CHUNK_RETRIES: u32 = 3;
+
+/// The metadata of a snapshot of `chunks` for
+/// [`verify_chunks`](SnapshotManager::verify_chunks): the concatenation of
+/// the SHA-256 hash of each chunk, in order.
+pub fn chunk_hashes<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Bytes {
+    chunks.into_iter().flat_map(Sha256::digest).collect()
+}
+
+/// A callback verifying the state restored from a snapshot against the app
+/// hash trusted by the node.
+type VerifyCallback = Arc<dyn Fn(&Snapshot, &AppHash) -> Result<(), BoxError> + Send + Sync>;
+
+/// The progress of the restoration of a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RestoreProgress {
+    pub height: block::Height,
+    pub format: u32,
+    pub chunks: u32,
+    /// The number of chunks persisted so far.
+    pub persisted: u32,
+    pub status: RestoreStatus,
+}
+
+/// The status of the restoration of a snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreStatus {
+    /// Chunks are being applied.
+    Restoring,
+    /// The state was restored, and verified if a callback is set.
+    Restored,
+    /// The snapshot was rejected, and the node may try another one.
+    Rejected,
+    /// The snapshot was rejected, and state sync aborted.
+    Aborted,
+}
+
+/// The storage of the snapshots of an application.
+///
+/// Its methods are called on the connection task of the snapshot connection,
+/// and should not block for long.
+pub trait SnapshotStore {
+    /// The snapshots available to other nodes.
+    fn list(&self) -> Result<Vec<Snapshot>, BoxError>;
+
+    /// The chunk `index` of the snapshot of `format` at `height`, or `None`
+    /// if there is no such snapshot or chunk.
+    fn load_chunk(
+        &self,
+        height: block::Height,
+        format: u32,
+        index: u32,
+    ) -> Result<Option<Bytes>, BoxError>;
+
+    /// Decides whether to restore the state from `snapshot`, offered by a
+    /// peer, whose state must have `app_hash`. By default, all snapshots are
+    /// accepted.
+    fn offer(
+        &mut self,
+        snapshot: &Snapshot,
+        app_hash: &AppHash,
+    ) -> Result<response::OfferSnapshot, BoxError> {
+        let _ = (snapshot, app_hash);
+        Ok(response::OfferSnapshot::Accept)
+    }
+
+    /// Persists the chunk `index` of `snapshot`, an accepted snapshot being
+    /// restored. Chunks are persisted in order of their index, those applied
+    /// ahead of their turn being held until then. A chunk may be persisted
+    /// again, replacing the previous one, if the node applies it again.
+    fn persist_chunk(
+        &mut self,
+        snapshot: &Snapshot,
+        index: u32,
+        chunk: Bytes,
+    ) -> Result<(), BoxError>;
+
+    /// Restores the state from `snapshot`, once all of its chunks are
+    /// persisted. An error rejects the snapshot, e.g. if the restored state
+    /// doesn't have `app_hash`.
+    fn restore(&mut self, snapshot: &Snapshot, app_hash: &AppHash) -> Result<(), BoxError>;
+}
+
+/// A snapshot service serving and restoring the snapshots of a
+/// [`SnapshotStore`]. See the [module documentation](self) for details.
+pub struct SnapshotManager<S> {
+    state: Arc<Mutex<State<S>>>,
+}
+
+// Implementing Clone manually avoids an (incorrect) derived S: Clone bound
+impl<S> Clone for SnapshotManager<S> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+struct State<S> {
+    store: S,
+    /// The snapshot being restored, if any.
+    restoring: Option<Restoring>,
+    verify_chunks: bool,
+    verify_restored: Option<VerifyCallback>,
+    max_failed_snapshots: Option<u32>,
+    /// The number of snapshots rejected after they were accepted.
+    failed_snapshots: u32,
+    progress: watch::Sender<Option<RestoreProgress>>,
+}
+
+/// An accepted snapshot being restored.
+struct Restoring {
+    snapshot: Snapshot,
+    app_hash: AppHash,
+    /// The index of the next chunk to persist.
+    next: u32,
+    /// The chunks applied ahead of their turn.
+    pending: BTreeMap<u32, Bytes>,
+    /// The times each chunk failed verification.
+    failures: Vec<u32>,
+}
+
+impl<S: SnapshotStore> SnapshotManager<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                store,
+                restoring: None,
+                verify_chunks: false,
+                verify_restored: None,
+                max_failed_snapshots: None,
+                failed_snapshots: 0,
+                progress: watch::channel(None).0,
+            })),
+        }
+    }
+
+    /// Verifies each chunk of a snapshot being restored against the SHA-256
+    /// hashes in the metadata of the snapshot, as built by [`chunk_hashes`],
+    /// before persisting it. Snapshots whose metadata doesn't hold a hash per
+    /// chunk are rejected.
+    ///
+    /// A chunk failing verification is fetched again from another peer, and
+    /// its sender rejected, up to [`MAX_CHUNK_RETRIES`] times, after which
+    /// the snapshot is rejected. Disabled by default.
+    pub fn verify_chunks(self) -> Self {
+        self.state.lock().unwrap().verify_chunks = true;
+        self
+    }
+
+    /// Calls `verify` once the state is restored from a snapshot, with the
+    /// snapshot and the app hash trusted by the node, which the restored state
+    /// must have. An error rejects the snapshot. Disabled by default.
+    pub fn verify_restored<F>(self, verify: F) -> Self
+    where
+        F: Fn(&Snapshot, &AppHash) -> Result<(), BoxError> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().verify_restored = Some(Arc::new(verify));
+        self
+    }
+
+    /// Aborts state sync once `max` accepted snapshots failed to restore,
+    /// instead of having the node try other snapshots. Disabled by default.
+    pub fn max_failed_snapshots(self, max: u32) -> Self {
+        self.state.lock().unwrap().max_failed_snapshots = Some(max.max(1));
+        self
+    }
+
+    /// The progress of the snapshot being restored, or restored last, if any.
+    pub fn progress(&self) -> watch::Receiver<Option<RestoreProgress>> {
+        self.state.lock().unwrap().progress.subscribe()
+    }
+}
+
+impl<S: SnapshotStore> State<S> {
+    fn list_snapshots(&self) -> Result<response::ListSnapshots, BoxError> {
+        Ok(response::ListSnapshots {
+            snapshots: self.store.list()?,
+        })
+    }
+
+    fn offer_snapshot(
+        &mut self,
+        request: request::OfferSnapshot,
+    ) -> Result<response::OfferSnapshot, BoxError> {
+        // An offer abandons the snapshot being restored, if any.
+        self.restoring = None;
+        if self.aborted() {
+            return Ok(response::OfferSnapshot::Abort);
+        }
+        let snapshot = request.snapshot;
+        if snapshot.chunks == 0 {
+            return Ok(response::OfferSnapshot::Reject);
+        }
+        let hashes_len = snapshot.chunks as usize * Sha256::output_size();
+        if self.verify_chunks && snapshot.metadata.len() != hashes_len {
+            tracing::warn!(
+                height = %snapshot.height,
+                metadata.len = snapshot.metadata.len(),
+                "snapshot metadata doesn't hold a hash per chunk"
+            );
+            return Ok(response::OfferSnapshot::Reject);
+        }
+        let response = self.store.offer(&snapshot, &request.app_hash)?;
+        if response == response::OfferSnapshot::Accept {
+            tracing::info!(
+                height = %snapshot.height,
+                format = snapshot.format,
+                chunks = snapshot.chunks,
+                "restoring snapshot"
+            );
+            self.progress.send_replace(Some(RestoreProgress {
+                height: snapshot.height,
+                format: snapshot.format,
+                chunks: snapshot.chunks,
+                persisted: 0,
+                status: RestoreStatus::Restoring,
+            }));
+            self.restoring = Some(Restoring {
+                next: 0,
+                pending: BTreeMap::new(),
+                failures: vec![0; snapshot.chunks as usize],
+                snapshot,
+                app_hash: request.app_hash,
+            });
+        }
+        Ok(response)
+    }
+
+    fn load_snapshot_chunk(
+        &self,
+        request: request::LoadSnapshotChunk,
+    ) -> Result<response::LoadSnapshotChunk, BoxError> {
+        let chunk = self
+            .store
+            .load_chunk(request.height, request.format, request.chunk)?;
+        Ok(response::LoadSnapshotChunk {
+            chunk: chunk.unwrap_or_default(),
+        })
+    }
+
+    fn apply_snapshot_chunk(
+        &mut self,
+        request: request::ApplySnapshotChunk,
+    ) -> Result<response::ApplySnapshotChunk, BoxError> {
+        let restoring = self
+            .restoring
+            .as_mut()
+            .ok_or("ApplySnapshotChunk without an accepted snapshot")?;
+        let index = request.index;
+        if index >= restoring.snapshot.chunks {
+            tracing::warn!(index, sender = %request.sender, "chunk index out of range");
+            return Ok(apply_result(self.reject()));
+        }
+        if self.verify_chunks && !chunk_matches(&restoring.snapshot, index, &request.chunk) {
+            restoring.failures[index as usize] += 1;
+            let failures = restoring.failures[index as usize];
+            tracing::warn!(index, sender = %request.sender, failures, "chunk hash mismatch");
+            if failures > MAX_CHUNK_RETRIES {
+                return Ok(apply_result(self.reject()));
+            }
+            return Ok(response::ApplySnapshotChunk {
+                result: ApplySnapshotChunkResult::Retry,
+                refetch_chunks: vec![index],
+                reject_senders: vec![request.sender],
+            });
+        }
+        if index > restoring.next {
+            restoring.pending.insert(index, request.chunk);
+            return Ok(apply_result(ApplySnapshotChunkResult::Accept));
+        }
+        self.store
+            .persist_chunk(&restoring.snapshot, index, request.chunk)?;
+        if index == restoring.next {
+            restoring.next += 1;
+            while let Some(chunk) = restoring.pending.remove(&restoring.next) {
+                self.store
+                    .persist_chunk(&restoring.snapshot, restoring.next, chunk)?;
+                restoring.next += 1;
+            }
+        }
+        let persisted = restoring.next;
+        self.progress.send_modify(|progress| {
+            if let Some(progress) = progress {
+                progress.persisted = persisted;
+            }
+        });
+        if restoring.next < restoring.snapshot.chunks {
+            return Ok(apply_result(ApplySnapshotChunkResult::Accept));
+        }
+
+        let restored = self.restore();
+        let restoring = self.restoring.as_ref().expect("checked above");
+        let height = restoring.snapshot.height;
+        match restored {
+            Ok(()) => {
+                tracing::info!(%height, "restored snapshot");
+                self.restoring = None;
+                self.set_status(RestoreStatus::Restored);
+                Ok(apply_result(ApplySnapshotChunkResult::Accept))
+            }
+            Err(e) => {
+                tracing::warn!(%height, error = %e, "failed to restore snapshot");
+                Ok(apply_result(self.reject()))
+            }
+        }
+    }
+
+    /// Restores the state from the snapshot being restored, and verifies it.
+    fn restore(&mut self) -> Result<(), BoxError> {
+        let restoring = self.restoring.as_ref().expect("restoring a snapshot");
+        self.store
+            .restore(&restoring.snapshot, &restoring.app_hash)?;
+        if let Some(verify) = &self.verify_restored {
+            verify(&restoring.snapshot, &restoring.app_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Whether state sync was aborted after too many failed snapshots.
+    fn aborted(&self) -> bool {
+        self.max_failed_snapshots
+            .is_some_and(|max| self.failed_snapshots >= max)
+    }
+
+    /// Rejects the snapshot being restored, aborting state sync if too many
+    /// snapshots failed.
+    fn reject(&mut self) -> ApplySnapshotChunkResult {
+        self.restoring = None;
+        self.failed_snapshots += 1;
+        if self.aborted() {
+            tracing::warn!(failed = self.failed_snapshots, "aborting state sync");
+            self.set_status(RestoreStatus::Aborted);
+            ApplySnapshotChunkResult::Abort
+        } else {
+            self.set_status(RestoreStatus::Rejected);
+            ApplySnapshotChunkResult::RejectSnapshot
+        }
+    }
+
+    fn set_status(&self, status: RestoreStatus) {
+        self.progress.send_modify(|progress| {
+            if let Some(progress) = progress {
+                progress.status = status;
+            }
+        });
+    }
+}
+
+/// Whether `chunk` has the hash of the chunk `index` in the metadata of
+/// `snapshot`, whose length was checked when it was offered.
+fn chunk_matches(snapshot: &Snapshot, index: u32, chunk: &[u8]) -> bool {
+    let len = Sha256::output_size();
+    let start = index as usize * len;
+    snapshot.metadata[start..start + len] == Sha256::digest(chunk)[..]
+}
+
+fn apply_result(result: ApplySnapshotChunkResult) -> response::ApplySnapshotChunk {
+    response::ApplySnapshotChunk {
+        result,
+        refetch_chunks: Vec::new(),
+        reject_senders: Vec::new(),
+    }
+}
+
+macro_rules! impl_snapshot_service {
+    ($version:ident) => {
+        impl<S: SnapshotStore> Service<tendermint::$version::abci::SnapshotRequest>
+            for SnapshotManager<S>
+        {
+            type Response = tendermint::$version::abci::SnapshotResponse;
+            type Error = BoxError;
+            type Future = Ready<Result<Self::Response, BoxError>>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, req: tendermint::$version::abci::SnapshotRequest) -> Self::Future {
+                use tendermint::$version::abci::{
+                    SnapshotRequest as Request, SnapshotResponse as Response,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                ready(match req {
+                    Request::ListSnapshots => state.list_snapshots().map(Response::ListSnapshots),
+                    Request::OfferSnapshot(req) => {
+                        state.offer_snapshot(req).map(Response::OfferSnapshot)
+                    }
+                    Request::LoadSnapshotChunk(req) => state
+                        .load_snapshot_chunk(req)
+                        .map(Response::LoadSnapshotChunk),
+                    Request::ApplySnapshotChunk(req) => state
+                        .apply_snapshot_chunk(req)
+                        .map(Response::ApplySnapshotChunk),
+                })
+            }
+        }
+    };
+}
+
+impl_snapshot_service!(v0_34);
+impl_snapshot_service!(v0_37);
+impl_snapshot_service!(v0_38);
@@ -0,0 +1,233 @@
+//! Periodic snapshot creation.
+//!
+//! [`SnapshotSchedulerLayer`] wraps the consensus service, follows the height
+//! of the blocks it executes, and once every `interval` blocks, after the
+//! block is committed, has the store of a [`SnapshotManager`] create a
+//! snapshot of the committed state, and delete all but the `keep` most recent
+//! snapshots:
+//!
+//! ```ignore
+//! let snapshot = SnapshotManager::new(store);
+//! let consensus = ServiceBuilder::new()
+//!     .layer(snapshot.scheduler(1000, 2))
+//!     .service(consensus);
+//! ```
+//!
+//! Snapshots are created and deleted on the blocking thread pool while holding
+//! the manager's lock, so that peers never list a snapshot being created or
+//! load the chunks of one being deleted; snapshot requests wait meanwhile. A
+//! snapshot that would start while the previous one is still being created is
+//! skipped.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use tendermint::{abci::types::Snapshot, block};
+use tower::{Layer, Service};
+
+use super::{SnapshotManager, SnapshotStore, State};
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{message::RequestExt, BoxError};
+
+/// A [`SnapshotStore`] that can also create and delete snapshots.
+pub trait CreateSnapshot: SnapshotStore {
+    /// Creates a snapshot of the state as of the commit of the block at
+    /// `height`. It is called after the block is committed, so blocks after it
+    /// may be executed meanwhile.
+    fn create(&mut self, height: block::Height) -> Result<(), BoxError>;
+
+    /// Deletes `snapshot`, one of those [listed](SnapshotStore::list).
+    fn delete(&mut self, snapshot: &Snapshot) -> Result<(), BoxError>;
+}
+
+impl<S: CreateSnapshot + Send + 'static> SnapshotManager<S> {
+    /// A layer creating a snapshot with the store of this manager every
+    /// `interval` blocks, keeping the `keep` most recent ones. See the
+    /// [module documentation](self) for details.
+    pub fn scheduler(&self, interval: u64, keep: usize) -> SnapshotSchedulerLayer<S> {
+        SnapshotSchedulerLayer {
+            state: self.state.clone(),
+            interval: interval.max(1),
+            keep: keep.max(1),
+        }
+    }
+}
+
+/// Applies [`SnapshotScheduler`] to a consensus service.
+pub struct SnapshotSchedulerLayer<S> {
+    state: Arc<Mutex<State<S>>>,
+    interval: u64,
+    keep: usize,
+}
+
+// Implementing Clone manually avoids an (incorrect) derived S: Clone bound
+impl<S> Clone for SnapshotSchedulerLayer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            interval: self.interval,
+            keep: self.keep,
+        }
+    }
+}
+
+impl<C, S> Layer<C> for SnapshotSchedulerLayer<S> {
+    type Service = SnapshotScheduler<C, S>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        SnapshotScheduler {
+            inner,
+            schedule: Arc::new(Schedule {
+                state: self.state.clone(),
+                interval: self.interval,
+                keep: self.keep,
+                height: AtomicU64::new(0),
+                creating: AtomicBool::new(false),
+            }),
+        }
+    }
+}
+
+/// Creates snapshots of the state periodically, as blocks are committed by
+/// the inner consensus service.
+pub struct SnapshotScheduler<C, S> {
+    inner: C,
+    schedule: Arc<Schedule<S>>,
+}
+
+impl<C: Clone, S> Clone for SnapshotScheduler<C, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            schedule: self.schedule.clone(),
+        }
+    }
+}
+
+/// The schedule shared by the clones of a [`SnapshotScheduler`].
+struct Schedule<S> {
+    state: Arc<Mutex<State<S>>>,
+    interval: u64,
+    keep: usize,
+    /// The height of the block being executed, or 0 before the first one.
+    height: AtomicU64,
+    /// Whether a snapshot is being created.
+    creating: AtomicBool,
+}
+
+impl<S: CreateSnapshot + Send + 'static> Schedule<S> {
+    /// Creates a snapshot at `height`, if it is due and none is being created.
+    fn committed(self: &Arc<Self>, height: u64) {
+        if height == 0 || !height.is_multiple_of(self.interval) {
+            return;
+        }
+        if self.creating.swap(true, Ordering::AcqRel) {
+            tracing::warn!(
+                height,
+                "skipping snapshot, the previous one is still being created"
+            );
+            return;
+        }
+        let schedule = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut state = schedule.state.lock().unwrap();
+            if let Err(e) = create_and_prune(&mut state.store, height, schedule.keep) {
+                tracing::error!(height, error = %e, "failed to create snapshot");
+            }
+            drop(state);
+            schedule.creating.store(false, Ordering::Release);
+        });
+    }
+}
+
+/// Creates a snapshot at `height`, and deletes all but the snapshots of the
+/// `keep` highest heights.
+fn create_and_prune<S: CreateSnapshot>(
+    store: &mut S,
+    height: u64,
+    keep: usize,
+) -> Result<(), BoxError> {
+    store.create(height.try_into()?)?;
+    tracing::info!(height, "created snapshot");
+    let snapshots = store.list()?;
+    let mut heights: Vec<_> = snapshots.iter().map(|snapshot| snapshot.height).collect();
+    heights.sort_unstable_by(|a, b| b.cmp(a));
+    heights.dedup();
+    let Some(&oldest) = heights.get(keep - 1) else {
+        return Ok(());
+    };
+    for snapshot in snapshots.iter().filter(|snapshot| snapshot.height < oldest) {
+        store.delete(snapshot)?;
+        tracing::info!(height = %snapshot.height, format = snapshot.format, "deleted snapshot");
+    }
+    Ok(())
+}
+
+impl<C, S, R> Service<R> for SnapshotScheduler<C, S>
+where
+    C: Service<R>,
+    R: RequestExt,
+    S: CreateSnapshot + Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = ResponseFuture<C::Future, S>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let commit = match req.method() {
+            "BeginBlock" | "FinalizeBlock" => {
+                if let Some(height) = req.height() {
+                    self.schedule
+                        .height
+                        .store(height.value(), Ordering::Release);
+                }
+                None
+            }
+            "Commit" => Some(self.schedule.clone()),
+            _ => None,
+        };
+        ResponseFuture {
+            inner: self.inner.call(req),
+            commit,
+        }
+    }
+}
+
+/// Response future for [`SnapshotScheduler`].
+#[pin_project]
+pub struct ResponseFuture<F, S> {
+    #[pin]
+    inner: F,
+    /// The schedule, if this is the response to a `Commit`.
+    commit: Option<Arc<Schedule<S>>>,
+}
+
+impl<F, T, E, S> Future for ResponseFuture<F, S>
+where
+    F: Future<Output = Result<T, E>>,
+    S: CreateSnapshot + Send + 'static,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = futures::ready!(this.inner.poll(cx));
+        if let (Ok(_), Some(schedule)) = (&output, this.commit.take()) {
+            schedule.committed(schedule.height.load(Ordering::Acquire));
+        }
+        Poll::Ready(output)
+    }
+}
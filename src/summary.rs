@@ -0,0 +1,57 @@
+//! One-line summaries of each request served.
+//!
+//! When enabled with `ServerBuilder::summary_log`, the server records one
+//! `INFO` event per request, with the target [`TARGET`], when it writes the
+//! response. Unlike the `DEBUG` dumps of full requests and responses, the
+//! summary only carries scalar fields, so it can be emitted in production and
+//! consumed by log pipelines, e.g., with `tracing_subscriber`'s JSON formatter:
+//!
+//! - `connection`: the id of the connection the request arrived on;
+//! - `request`: the [`RequestId`](crate::RequestId) of the request;
+//! - `method`: the ABCI method, e.g. `"FinalizeBlock"`;
+//! - `height`: the block height carried by the request, if any;
+//! - `code`: the response code, for responses that carry one;
+//! - `outcome`: `success`, `failure` or `exception`, as classified by
+//!   [`ResponseExt::outcome`](crate::ResponseExt::outcome);
+//! - `request_size` and `response_size`: the encoded sizes of the request and
+//!   response in bytes, excluding length prefixes;
+//! - `latency_us`: the time from reading the request to writing the response,
+//!   in microseconds.
+//!
+//! Summaries can be routed separately from other events by filtering on the
+//! target, e.g. `RUST_LOG=tower_abci::summary=info`.
+
+use tendermint::abci::Code;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::{message::Outcome, pipeline::Pending};
+
+/// The target of summary events.
+pub const TARGET: &str = "tower_abci::summary";
+
+fn outcome_label(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Success => "success",
+        Outcome::Failure => "failure",
+        Outcome::Exception => "exception",
+    }
+}
+
+/// Records the summary of a `pending` request, answered by a response with the
+/// given `code` and `outcome` that encodes to `size` bytes.
+pub(crate) fn record(pending: &Pending, code: Option<Code>, outcome: Outcome, size: usize) {
+    tracing::info!(
+        target: TARGET,
+        connection = pending.id.connection(),
+        request = %pending.id,
+        method = pending.method,
+        height = pending.height.map(|height| height.value()),
+        code = code.map(|code| code.value()),
+        outcome = outcome_label(outcome),
+        request_size = pending.size,
+        response_size = size,
+        latency_us = pending.received.elapsed().as_micros() as u64,
+        "request served"
+    );
+}
@@ -0,0 +1,94 @@
+//! Spawning of the tasks driving the servers.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    runtime::Handle,
+    task::{JoinError, JoinHandle},
+};
+
+/// Spawns a task with the given name.
+///
+/// When built with `--cfg tokio_unstable`, the name is attached to the task
+/// itself, so that it shows up in tokio-console and runtime dumps. Otherwise,
+/// callers should instrument the future with a span carrying the same
+/// identity.
+pub(crate) fn spawn<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    {
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn(future)
+            .expect("spawning a task on the current runtime")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Spawns a task with the given name on `runtime`, as [`spawn`] does on the
+/// current runtime.
+pub(crate) fn spawn_on<F>(name: &str, runtime: &Handle, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    {
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn_on(future, runtime)
+            .expect("spawning a task on the given runtime")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    {
+        let _ = name;
+        runtime.spawn(future)
+    }
+}
+
+/// A task that is aborted when its handle is dropped, e.g., when the
+/// connection waiting for its output closes.
+pub(crate) struct Scoped<T>(JoinHandle<T>);
+
+impl<T: Send + 'static> Scoped<T> {
+    /// Spawns a task with the given name, as [`spawn`] does.
+    pub(crate) fn spawn<F>(name: &str, future: F) -> Self
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        Self(spawn(name, future))
+    }
+
+    /// Spawns a task with the given name on `runtime`, as [`spawn_on`] does.
+    pub(crate) fn spawn_on<F>(name: &str, runtime: &Handle, future: F) -> Self
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        Self(spawn_on(name, runtime, future))
+    }
+}
+
+impl<T> Future for Scoped<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for Scoped<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,116 @@
+//! The scenario and results of the ABCI conformance suite, which is run with
+//! the `conformance::run` function of a protocol version, e.g.
+//! [`v038::conformance::run`](crate::v038::conformance::run).
+
+use std::fmt;
+
+use bytes::Bytes;
+use tendermint::abci::request;
+
+use crate::BoxError;
+
+/// The chain an application is driven through by the conformance suite.
+#[derive(Clone, Debug)]
+pub struct Conformance {
+    pub(crate) genesis: request::InitChain,
+    pub(crate) blocks: Vec<Vec<Bytes>>,
+}
+
+impl Conformance {
+    /// Starts a scenario for a chain with the given genesis, e.g.
+    /// [`testing::genesis`](super::genesis).
+    pub fn new(genesis: request::InitChain) -> Self {
+        Self {
+            genesis,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Adds a block with the given transactions, which should be valid for the
+    /// application in that order.
+    ///
+    /// Blocks without transactions are allowed; the suite also checks a chain
+    /// of empty blocks on its own.
+    pub fn block(mut self, txs: Vec<Bytes>) -> Self {
+        self.blocks.push(txs);
+        self
+    }
+}
+
+/// A behavior of the application that contradicts the ABCI specification.
+#[derive(Debug)]
+pub struct Violation {
+    /// The name of the check that failed, e.g. `"handshake"`.
+    pub check: &'static str,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.check, self.message)
+    }
+}
+
+/// The outcome of the conformance suite.
+#[derive(Debug, Default)]
+pub struct Report {
+    passed: Vec<&'static str>,
+    violations: Vec<Violation>,
+}
+
+impl Report {
+    /// The names of the checks that passed.
+    pub fn passed(&self) -> &[&'static str] {
+        &self.passed
+    }
+
+    /// The checks that failed.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Returns `true` if every check passed.
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Panics with every violation if a check failed, for use in tests.
+    #[track_caller]
+    pub fn assert_ok(&self) {
+        if !self.is_ok() {
+            panic!("the application violates the ABCI specification:\n{}", self);
+        }
+    }
+
+    pub(crate) fn record(&mut self, check: &'static str, result: Result<(), BoxError>) {
+        match result {
+            Ok(()) => self.passed.push(check),
+            Err(e) => self.violations.push(Violation {
+                check,
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.passed {
+            writeln!(f, "ok {}", check)?;
+        }
+        for violation in &self.violations {
+            writeln!(f, "FAILED {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns an error with `message` unless `condition` holds.
+pub(crate) fn ensure(condition: bool, message: impl FnOnce() -> String) -> Result<(), BoxError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(message().into())
+    }
+}
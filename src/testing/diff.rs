@@ -0,0 +1,96 @@
+//! Readable differences between expected and actual responses.
+
+use std::fmt;
+
+/// The number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+/// A response that differs from the one expected, e.g. when replaying a
+/// recorded session against a new build of an application.
+#[derive(Clone, Debug)]
+pub struct Difference<Response> {
+    /// The position of the exchange in the session, counting from zero.
+    pub index: usize,
+    /// The ABCI method of the request, e.g. `"FinalizeBlock"`.
+    pub method: &'static str,
+    pub expected: Response,
+    pub actual: Response,
+}
+
+impl<Response: fmt::Debug> fmt::Display for Difference<Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expected = format!("{:#?}", self.expected);
+        let actual = format!("{:#?}", self.actual);
+        writeln!(
+            f,
+            "response {} to {} differs (-expected +actual):",
+            self.index, self.method
+        )?;
+        f.write_str(&lines(&expected, &actual).unwrap_or_default())
+    }
+}
+
+/// A line-by-line diff of `expected` and `actual`, or `None` if they are
+/// equal.
+///
+/// Removed lines are prefixed with `-`, added lines with `+`, and unchanged
+/// lines around them with two spaces; longer runs of unchanged lines are
+/// elided.
+pub fn lines(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // The length of the longest common subsequence of the suffixes starting
+    // at each pair of lines.
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            ops.push((' ', expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', expected[i]));
+            i += 1;
+        } else {
+            ops.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    let near_change = |k: usize| {
+        changed
+            .iter()
+            .any(|&c| k + CONTEXT >= c && k <= c + CONTEXT)
+    };
+    let mut out = String::new();
+    let mut elided = false;
+    for (k, (op, line)) in ops.iter().enumerate() {
+        if near_change(k) {
+            out.push(*op);
+            out.push(' ');
+            out.push_str(line);
+            out.push('\n');
+            elided = false;
+        } else if !elided {
+            out.push_str("  ...\n");
+            elided = true;
+        }
+    }
+    Some(out)
+}
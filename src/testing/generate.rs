@@ -0,0 +1,434 @@
+//! Random but valid block sequences, for finding nondeterminism and ordering
+//! assumptions in applications.
+//!
+//! A [`Generator`] builds a `proptest` [`Strategy`] of [`Sequence`]s: a
+//! genesis with a validator set, and blocks with transactions, last commits in
+//! which some validators are absent, evidence of misbehavior at past heights,
+//! and changes to the validator set. Every sequence is valid as far as the
+//! node is concerned, shrunk ones included: heights are consecutive, more than
+//! two thirds of the voting power signs each last commit, and votes and
+//! evidence refer to the validator set at their height, with updates taking
+//! effect two blocks after the block they were returned for, as in CometBFT.
+//!
+//! The same seed always produces the same sequence, so a failing case can be
+//! reproduced from the seed alone. The `check_sequences` function of each
+//! protocol version, e.g.
+//! [`v038::testing::check_sequences`](crate::v038::testing::check_sequences),
+//! feeds generated sequences through the harness, and shrinks those that fail.
+
+use std::{future::Future, ops::RangeInclusive};
+
+use bytes::Bytes;
+use proptest::{
+    collection, option,
+    prelude::*,
+    sample::Index,
+    strategy::{SBoxedStrategy, ValueTree},
+    test_runner::{Config, RngAlgorithm, TestRng, TestRunner},
+};
+use tendermint::{
+    abci::{
+        request,
+        types::{
+            BlockSignatureInfo, CommitInfo, Misbehavior, MisbehaviorKind, Validator, VoteInfo,
+        },
+    },
+    account,
+    block::{self, BlockIdFlag},
+    validator, vote, PublicKey, Time,
+};
+
+use super::{genesis, BLOCK_INTERVAL};
+use crate::BoxError;
+
+/// Generates random block sequences.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    chain_id: String,
+    blocks: RangeInclusive<usize>,
+    txs_per_block: RangeInclusive<usize>,
+    tx: SBoxedStrategy<Bytes>,
+    validators: RangeInclusive<usize>,
+    absent_rate: f64,
+    evidence_rate: f64,
+    validator_update_rate: f64,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Generator {
+    /// Creates a generator of sequences of 1 to 10 blocks, with up to 8
+    /// transactions of 1 to 64 random bytes each, and 1 to 4 validators.
+    pub fn new() -> Self {
+        Self {
+            chain_id: "test-chain".to_string(),
+            blocks: 1..=10,
+            txs_per_block: 0..=8,
+            tx: collection::vec(any::<u8>(), 1..=64)
+                .prop_map(Bytes::from)
+                .sboxed(),
+            validators: 1..=4,
+            absent_rate: 0.1,
+            evidence_rate: 0.05,
+            validator_update_rate: 0.1,
+        }
+    }
+
+    /// Sets the chain id of the genesis. Defaults to `test-chain`.
+    pub fn chain_id(mut self, chain_id: impl Into<String>) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+
+    /// Sets the range of the number of blocks in a sequence.
+    pub fn blocks(mut self, blocks: RangeInclusive<usize>) -> Self {
+        self.blocks = blocks;
+        self
+    }
+
+    /// Sets the range of the number of transactions in a block.
+    pub fn txs_per_block(mut self, txs: RangeInclusive<usize>) -> Self {
+        self.txs_per_block = txs;
+        self
+    }
+
+    /// Generates transactions with `tx` instead of as random bytes, e.g. to
+    /// produce transactions the application accepts.
+    pub fn tx(mut self, tx: impl Strategy<Value = Bytes> + Send + Sync + 'static) -> Self {
+        self.tx = tx.sboxed();
+        self
+    }
+
+    /// Sets the range of the size of the genesis validator set.
+    pub fn validators(mut self, validators: RangeInclusive<usize>) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    /// Sets the probability that a validator is absent from a last commit, as
+    /// long as more than two thirds of the voting power remains. Defaults to
+    /// 0.1.
+    pub fn absent_rate(mut self, rate: f64) -> Self {
+        self.absent_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the probability that a block carries evidence of misbehavior.
+    /// Defaults to 0.05.
+    pub fn evidence_rate(mut self, rate: f64) -> Self {
+        self.evidence_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the probability that the validator set changes after a block.
+    /// Defaults to 0.1.
+    pub fn validator_update_rate(mut self, rate: f64) -> Self {
+        self.validator_update_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The strategy of the sequences, e.g. for a `proptest!` test of the
+    /// application's own.
+    pub fn strategy(&self) -> SBoxedStrategy<Sequence> {
+        let validators = *self.validators.start().max(&1)..=*self.validators.end().max(&1);
+        // A validator set grows by at most one validator a block.
+        let max_validators = validators.end() + self.blocks.end();
+        let block = (
+            collection::vec(self.tx.clone(), self.txs_per_block.clone()),
+            collection::vec(prop::bool::weighted(self.absent_rate), max_validators),
+            option::weighted(self.evidence_rate, (any::<Index>(), any::<Index>())),
+            option::weighted(
+                self.validator_update_rate,
+                (0..3u8, any::<Index>(), any::<[u8; 32]>(), 1u32..=100),
+            ),
+        )
+            .prop_map(|(txs, absent, evidence, update)| Choices {
+                txs,
+                absent,
+                evidence,
+                update,
+            });
+        let chain_id = self.chain_id.clone();
+        (
+            collection::vec((any::<[u8; 32]>(), 1u32..=100), validators),
+            collection::vec(block, self.blocks.clone()),
+        )
+            .prop_map(move |(validators, blocks)| build(&chain_id, validators, blocks))
+            .sboxed()
+    }
+
+    /// Generates the sequence for `seed`, the one `check_sequences` starts
+    /// from for that seed, before shrinking it.
+    pub fn generate(&self, seed: u64) -> Sequence {
+        self.strategy()
+            .new_tree(&mut runner(seed))
+            .expect("sequence strategies never reject")
+            .current()
+    }
+}
+
+/// The test runner generating the sequences of `seed`.
+fn runner(seed: u64) -> TestRunner {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    TestRunner::new_with_rng(
+        Config::default(),
+        TestRng::from_seed(RngAlgorithm::ChaCha, &bytes),
+    )
+}
+
+/// Runs `check` on the sequence generated for each seed, and on failure
+/// shrinks it to the simplest sequence that still fails, returning its error.
+///
+/// `check` returns the error of a sequence without the seed, e.g. `failed:
+/// the application panicked`.
+pub(crate) async fn check_seeds<F, Fut>(
+    generator: &Generator,
+    seeds: impl IntoIterator<Item = u64>,
+    mut check: F,
+) -> Result<(), BoxError>
+where
+    F: FnMut(Sequence) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let strategy = generator.strategy();
+    for seed in seeds {
+        let mut runner = runner(seed);
+        let mut tree = strategy
+            .new_tree(&mut runner)
+            .expect("sequence strategies never reject");
+        let Err(mut error) = check(tree.current()).await else {
+            continue;
+        };
+        let mut minimal = tree.current();
+        let mut failing = true;
+        for _ in 0..runner.config().max_shrink_iters {
+            let shrunk = if failing {
+                tree.simplify()
+            } else {
+                tree.complicate()
+            };
+            if !shrunk {
+                break;
+            }
+            match check(tree.current()).await {
+                Ok(()) => failing = false,
+                Err(e) => {
+                    failing = true;
+                    error = e;
+                    minimal = tree.current();
+                }
+            }
+        }
+        return Err(format!(
+            "sequence with seed {} {}\nshrunk to {:#?}",
+            seed, error, minimal
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The random choices a block of a sequence is built from.
+#[derive(Clone, Debug)]
+struct Choices {
+    txs: Vec<Bytes>,
+    /// Whether each validator of the last commit is absent, if it can be.
+    absent: Vec<bool>,
+    /// The height and the culprit of the block's evidence.
+    evidence: Option<(Index, Index)>,
+    /// The kind of the update, the validator it changes, and the key and
+    /// power of a new one.
+    update: Option<(u8, Index, [u8; 32], u32)>,
+}
+
+/// A generated chain.
+#[derive(Clone, Debug)]
+pub struct Sequence {
+    /// The `InitChain` request starting the chain, with the genesis
+    /// validators.
+    pub genesis: request::InitChain,
+    /// The blocks following the genesis, at consecutive heights.
+    pub blocks: Vec<SequenceBlock>,
+}
+
+/// A generated block.
+#[derive(Clone, Debug)]
+pub struct SequenceBlock {
+    pub txs: Vec<Bytes>,
+    /// The votes of the validators of the previous height.
+    pub last_commit: CommitInfo,
+    pub misbehavior: Vec<Misbehavior>,
+    /// The changes to the validator set that the sequence assumes the block
+    /// results in. An application under test may be scripted to return them,
+    /// but nothing checks that it does.
+    pub validator_updates: Vec<validator::Update>,
+}
+
+/// Builds a valid sequence from the genesis validators' keys and powers and
+/// the choices of each block, whatever they are.
+fn build(chain_id: &str, validators: Vec<([u8; 32], u32)>, blocks: Vec<Choices>) -> Sequence {
+    let mut genesis = genesis(chain_id.to_string());
+    let mut initial: Vec<validator::Update> = Vec::new();
+    for (key, power) in validators {
+        let pub_key = public_key(key);
+        // Shrinking draws keys towards the same bytes.
+        if initial.iter().all(|v| v.pub_key != pub_key) {
+            initial.push(validator::Update {
+                pub_key,
+                power: power.into(),
+            });
+        }
+    }
+    genesis.validators = initial.clone();
+
+    // The validator set of each height of the sequence, indexed from the
+    // genesis height. Updates returned for a block at height h apply from
+    // height h + 2.
+    let mut sets = vec![initial.clone(), initial];
+    let blocks = blocks
+        .into_iter()
+        .enumerate()
+        .map(|(index, choices)| {
+            let last_commit = match index.checked_sub(1) {
+                Some(last) => last_commit(&sets[last], &choices.absent),
+                None => CommitInfo {
+                    round: block::Round::default(),
+                    votes: vec![],
+                },
+            };
+            let misbehavior = match (index.checked_sub(1), choices.evidence) {
+                (Some(last), Some((at, culprit))) => {
+                    let at = at.index(last + 1);
+                    vec![misbehavior(&genesis, at, &sets[at], culprit)]
+                }
+                _ => vec![],
+            };
+            let current = sets[index + 1].clone();
+            let validator_updates = match choices.update {
+                Some(update) => vec![self::update(&current, update)],
+                None => vec![],
+            };
+            sets.push(apply(current, &validator_updates));
+            SequenceBlock {
+                txs: choices.txs,
+                last_commit,
+                misbehavior,
+                validator_updates,
+            }
+        })
+        .collect();
+    Sequence { genesis, blocks }
+}
+
+fn last_commit(set: &[validator::Update], absent: &[bool]) -> CommitInfo {
+    let total: u64 = set.iter().map(|v| v.power.value()).sum();
+    let mut signed = total;
+    let votes = set
+        .iter()
+        .zip(absent)
+        .map(|(v, &absent)| {
+            let power = v.power.value();
+            let absent = absent && (signed - power) * 3 > total * 2;
+            if absent {
+                signed -= power;
+            }
+            VoteInfo {
+                validator: abci_validator(v),
+                sig_info: BlockSignatureInfo::Flag(if absent {
+                    BlockIdFlag::Absent
+                } else {
+                    BlockIdFlag::Commit
+                }),
+            }
+        })
+        .collect();
+    CommitInfo {
+        round: block::Round::default(),
+        votes,
+    }
+}
+
+fn misbehavior(
+    genesis: &request::InitChain,
+    at: usize,
+    set: &[validator::Update],
+    culprit: Index,
+) -> Misbehavior {
+    let culprit = culprit.get(set);
+    let total: u64 = set.iter().map(|v| v.power.value()).sum();
+    Misbehavior {
+        kind: MisbehaviorKind::DuplicateVote,
+        validator: abci_validator(culprit),
+        height: (genesis.initial_height.value() + at as u64)
+            .try_into()
+            .expect("heights fit"),
+        time: block_time(genesis.time, at),
+        total_voting_power: total.try_into().expect("power fits"),
+    }
+}
+
+/// The time of the block `index` blocks after the genesis.
+fn block_time(genesis: Time, index: usize) -> Time {
+    (genesis + BLOCK_INTERVAL * index as u32).expect("block times fit")
+}
+
+/// The ed25519 key nearest `bytes` that is a valid point.
+fn public_key(mut bytes: [u8; 32]) -> PublicKey {
+    loop {
+        if let Some(key) = PublicKey::from_raw_ed25519(&bytes) {
+            return key;
+        }
+        bytes[0] = bytes[0].wrapping_add(1);
+    }
+}
+
+fn abci_validator(update: &validator::Update) -> Validator {
+    Validator {
+        address: account::Id::from(update.pub_key)
+            .as_bytes()
+            .try_into()
+            .expect("account ids are 20 bytes"),
+        power: update.power,
+    }
+}
+
+/// A change to `set`: adding a validator, removing one if others remain, or
+/// changing the power of one.
+fn update(
+    set: &[validator::Update],
+    (kind, existing, key, power): (u8, Index, [u8; 32], u32),
+) -> validator::Update {
+    let existing = existing.get(set);
+    let pub_key = public_key(key);
+    match kind {
+        // A new key already in the set changes that validator's power.
+        0 => validator::Update {
+            pub_key,
+            power: power.into(),
+        },
+        1 if set.len() > 1 => validator::Update {
+            pub_key: existing.pub_key,
+            power: vote::Power::default(),
+        },
+        _ => validator::Update {
+            pub_key: existing.pub_key,
+            power: power.into(),
+        },
+    }
+}
+
+/// The validator set after applying `updates` to `set`.
+fn apply(mut set: Vec<validator::Update>, updates: &[validator::Update]) -> Vec<validator::Update> {
+    for update in updates {
+        set.retain(|v| v.pub_key != update.pub_key);
+        if update.power.value() > 0 {
+            set.push(update.clone());
+        }
+    }
+    set
+}
@@ -0,0 +1,138 @@
+//! Golden-file snapshots of responses.
+//!
+//! [`assert_golden`] renders a value, usually a response or a [`Block`] of
+//! responses, to text and compares it with a file checked in next to the
+//! tests, so that any change to a consensus-critical response shows up in
+//! review as a change to that file:
+//!
+//! ```ignore
+//! let block = chain.produce_block(txs).await?;
+//! golden::assert_golden("tests/golden/transfer.txt", &block.finalize_block);
+//! ```
+//!
+//! The rendering is the value's pretty `Debug` output, whose fields are in
+//! declaration order, with byte strings replaced by their hex encoding, e.g.
+//! `hex"0a0b"`. When a change is intended, the files are rewritten by running
+//! the tests with the environment variable [`UPDATE_ENV`] set to `1`.
+//!
+//! [`Block`]: crate::v038::testing::Block
+
+use std::{fmt, path::Path};
+
+use super::diff;
+
+/// The environment variable that makes [`assert_golden`] rewrite the golden
+/// files instead of comparing against them.
+pub const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+/// Renders `value` deterministically for a golden file.
+pub fn render(value: &impl fmt::Debug) -> String {
+    let mut out = hex_byte_strings(&format!("{:#?}", value));
+    out.push('\n');
+    out
+}
+
+/// Compares the rendering of `value` with the golden file at `path`, and
+/// panics with a diff if they differ or the file does not exist.
+///
+/// If [`UPDATE_ENV`] is set to `1`, writes the rendering to the file instead,
+/// creating its directory if needed.
+#[track_caller]
+pub fn assert_golden(path: impl AsRef<Path>, value: &impl fmt::Debug) {
+    let path = path.as_ref();
+    let actual = render(value);
+    if std::env::var(UPDATE_ENV).is_ok_and(|update| update == "1") {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::write(path, actual).unwrap();
+        return;
+    }
+    let expected = match std::fs::read_to_string(path) {
+        Ok(expected) => expected,
+        Err(e) => panic!(
+            "cannot read golden file {}: {}; run with {}=1 to create it",
+            path.display(),
+            e,
+            UPDATE_ENV
+        ),
+    };
+    if let Some(diff) = diff::lines(&expected, &actual) {
+        panic!(
+            "response differs from golden file {} (-expected +actual); run with {}=1 to \
+             update it if the change is intended:\n{}",
+            path.display(),
+            UPDATE_ENV,
+            diff
+        );
+    }
+}
+
+/// Replaces the byte string literals of `Debug` output, e.g. `b"\x01a"`, with
+/// their hex encoding, e.g. `hex"0161"`, leaving string literals as they are.
+fn hex_byte_strings(debug: &str) -> String {
+    let mut out = String::with_capacity(debug.len());
+    let mut rest = debug;
+    let mut after_word = false;
+    while let Some(c) = rest.chars().next() {
+        if c == '"' || (c == 'b' && !after_word && rest[1..].starts_with('"')) {
+            let byte_string = c == 'b';
+            let start = if byte_string { 2 } else { 1 };
+            let Some((bytes, len)) = unescape(&rest[start..]) else {
+                // Not a literal after all; copy the rest verbatim.
+                break;
+            };
+            if byte_string {
+                out.push_str("hex\"");
+                out.push_str(&hex::encode(bytes));
+                out.push('"');
+            } else {
+                out.push_str(&rest[..start + len]);
+            }
+            rest = &rest[start + len..];
+            after_word = false;
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+            after_word = c.is_alphanumeric() || c == '_';
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decodes the escaped contents of a string or byte string literal up to its
+/// closing quote, returning the bytes and the length of the literal's
+/// remainder, including the closing quote.
+fn unescape(literal: &str) -> Option<(Vec<u8>, usize)> {
+    let mut bytes = Vec::new();
+    let mut chars = literal.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((bytes, i + 1)),
+            '\\' => match chars.next()?.1 {
+                'n' => bytes.push(b'\n'),
+                'r' => bytes.push(b'\r'),
+                't' => bytes.push(b'\t'),
+                '0' => bytes.push(0),
+                '\\' => bytes.push(b'\\'),
+                '"' => bytes.push(b'"'),
+                '\'' => bytes.push(b'\''),
+                'x' => {
+                    let hi = chars.next()?.1.to_digit(16)?;
+                    let lo = chars.next()?.1.to_digit(16)?;
+                    bytes.push((hi * 16 + lo) as u8);
+                }
+                // Unicode escapes only occur in string literals, whose
+                // contents are kept as they are.
+                'u' => {}
+                _ => return None,
+            },
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,163 @@
+//! Scripted component services, for testing middleware, proxies and the server
+//! itself without a real application.
+//!
+//! A [`Mock`] records every request it receives and answers it with the next
+//! scripted response. Clones share the same script and recorded requests, so
+//! a test can keep a clone to inspect the service after handing it to a
+//! `Server`:
+//!
+//! ```
+//! # use tendermint::v0_38::abci::{ConsensusRequest, ConsensusResponse, Request};
+//! # use tower_abci::{
+//! #     apps::NoopApp,
+//! #     testing::genesis,
+//! #     v038::{testing::{self, MockConsensus}, Server},
+//! #     BoxError,
+//! # };
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), BoxError> {
+//! let consensus = MockConsensus::new();
+//! consensus.respond(ConsensusResponse::InitChain(Default::default()));
+//! let server = Server::builder()
+//!     .consensus(consensus.clone())
+//! #   .mempool(NoopApp)
+//! #   .info(NoopApp)
+//! #   .snapshot(NoopApp)
+//!     // ...
+//!     .finish()
+//!     .unwrap();
+//! // drive the server...
+//! # testing::connect(&server).call(Request::InitChain(genesis("test-chain"))).await?;
+//! assert!(matches!(consensus.requests()[..], [ConsensusRequest::InitChain(_)]));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each protocol version's `testing` module has aliases for the mocks of its
+//! four categories, e.g. [`v038::testing::MockConsensus`](crate::v038::testing::MockConsensus).
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::future::{ready, Ready};
+use tower::Service;
+
+use crate::BoxError;
+
+/// A service that records its requests and returns scripted responses.
+pub struct Mock<Request, Response> {
+    inner: Arc<Mutex<Script<Request, Response>>>,
+}
+
+type Fallback<Request, Response> = Box<dyn FnMut(&Request) -> Response + Send>;
+
+struct Script<Request, Response> {
+    received: Vec<Request>,
+    responses: VecDeque<Result<Response, String>>,
+    fallback: Option<Fallback<Request, Response>>,
+}
+
+impl<Request, Response> Mock<Request, Response> {
+    /// Creates a mock with an empty script, which fails every request.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Script {
+                received: Vec::new(),
+                responses: VecDeque::new(),
+                fallback: None,
+            })),
+        }
+    }
+
+    /// Appends a response to the script.
+    pub fn respond(&self, response: Response) -> &Self {
+        let mut script = self.inner.lock().unwrap();
+        script.responses.push_back(Ok(response));
+        self
+    }
+
+    /// Appends an error to the script, which fails the request it answers
+    /// with the given message.
+    pub fn fail(&self, message: impl Into<String>) -> &Self {
+        let mut script = self.inner.lock().unwrap();
+        script.responses.push_back(Err(message.into()));
+        self
+    }
+
+    /// Answers requests with `respond` once the script is exhausted, instead
+    /// of failing them.
+    pub fn otherwise(&self, respond: impl FnMut(&Request) -> Response + Send + 'static) -> &Self {
+        let mut script = self.inner.lock().unwrap();
+        script.fallback = Some(Box::new(respond));
+        self
+    }
+
+    /// The number of scripted responses that have not been returned yet.
+    pub fn remaining(&self) -> usize {
+        self.inner.lock().unwrap().responses.len()
+    }
+
+    /// Removes and returns the requests received so far, in order.
+    pub fn take_requests(&self) -> Vec<Request> {
+        std::mem::take(&mut self.inner.lock().unwrap().received)
+    }
+}
+
+impl<Request: Clone, Response> Mock<Request, Response> {
+    /// The requests received so far, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.inner.lock().unwrap().received.clone()
+    }
+}
+
+impl<Request, Response> Default for Mock<Request, Response> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Request, Response> Clone for Mock<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for Mock<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let script = self.inner.lock().unwrap();
+        f.debug_struct("Mock")
+            .field("received", &script.received.len())
+            .field("remaining", &script.responses.len())
+            .field("fallback", &script.fallback.is_some())
+            .finish()
+    }
+}
+
+impl<Request: fmt::Debug, Response> Service<Request> for Mock<Request, Response> {
+    type Response = Response;
+    type Error = BoxError;
+    type Future = Ready<Result<Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let mut script = self.inner.lock().unwrap();
+        let result = match script.responses.pop_front() {
+            Some(result) => result.map_err(BoxError::from),
+            None => match script.fallback.as_mut() {
+                Some(respond) => Ok(respond(&request)),
+                None => Err(format!("no response scripted for {:?}", request).into()),
+            },
+        };
+        script.received.push(request);
+        ready(result)
+    }
+}
@@ -0,0 +1,198 @@
+//! Helpers shared by the in-memory testing harnesses of every protocol
+//! version, e.g. [`v038::testing`](crate::v038::testing).
+
+use std::time::Duration;
+
+use tendermint::{
+    abci::{
+        request,
+        types::{CommitInfo, ExtendedCommitInfo, ExtendedVoteInfo},
+    },
+    account,
+    block::{self, header::Version, parts},
+    chain,
+    consensus::{
+        self,
+        params::{AbciParams, ValidatorParams, VersionParams},
+    },
+    evidence, public_key, AppHash, Hash, Time,
+};
+
+use crate::BoxError;
+
+pub mod conformance;
+pub mod diff;
+pub mod generate;
+pub mod golden;
+pub mod mock;
+#[cfg(feature = "docker")]
+pub mod node;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+pub use conformance::Conformance;
+pub use diff::Difference;
+pub use mock::Mock;
+
+/// The time between consecutive blocks produced by a block driver.
+pub const BLOCK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An `InitChain` request for a new chain with the given id, starting at
+/// height 1 at the Unix epoch, with CometBFT's default consensus parameters
+/// and no validators.
+pub fn genesis(chain_id: impl Into<String>) -> request::InitChain {
+    request::InitChain {
+        time: Time::unix_epoch(),
+        chain_id: chain_id.into(),
+        consensus_params: consensus::Params {
+            block: block::Size {
+                max_bytes: 22020096,
+                max_gas: -1,
+                time_iota_ms: block::Size::default_time_iota_ms(),
+            },
+            evidence: evidence::Params {
+                max_age_num_blocks: 100000,
+                max_age_duration: evidence::Duration(Duration::from_secs(48 * 60 * 60)),
+                max_bytes: 1048576,
+            },
+            validator: ValidatorParams {
+                pub_key_types: vec![public_key::Algorithm::Ed25519],
+            },
+            version: Some(VersionParams { app: 0 }),
+            abci: AbciParams::default(),
+        },
+        validators: vec![],
+        app_state_bytes: Default::default(),
+        initial_height: block::Height::from(1u32),
+    }
+}
+
+/// The last commit of a block, with empty vote extensions, as sent in
+/// `PrepareProposal`.
+pub(crate) fn extended(commit: &CommitInfo) -> ExtendedCommitInfo {
+    ExtendedCommitInfo {
+        round: commit.round,
+        votes: commit
+            .votes
+            .iter()
+            .map(|vote| ExtendedVoteInfo {
+                validator: vote.validator.clone(),
+                sig_info: vote.sig_info,
+                vote_extension: Default::default(),
+                extension_signature: None,
+            })
+            .collect(),
+    }
+}
+
+/// Unwraps a response of the given variant of the `Response` enum in scope,
+/// or returns an error naming the method of the unexpected response.
+macro_rules! expect_response {
+    ($response:expr, $variant:ident) => {
+        match $response {
+            Response::$variant(response) => response,
+            response => {
+                return Err(format!(
+                    concat!("expected a ", stringify!($variant), " response, got {}"),
+                    crate::ResponseExt::method(&response)
+                )
+                .into())
+            }
+        }
+    };
+}
+pub(crate) use expect_response;
+
+/// The state of the chain simulated by a block driver.
+#[derive(Debug)]
+pub(crate) struct Chain {
+    chain_id: chain::Id,
+    /// The height of the next block.
+    height: block::Height,
+    /// The time of the next block.
+    time: Time,
+    app_hash: AppHash,
+    max_block_bytes: i64,
+    last_block_id: Option<block::Id>,
+    /// The header of the block executed but not yet committed, if any.
+    uncommitted: Option<block::Header>,
+}
+
+impl Chain {
+    pub(crate) fn new(genesis: &request::InitChain, app_hash: AppHash) -> Result<Self, BoxError> {
+        Ok(Self {
+            chain_id: genesis.chain_id.parse()?,
+            height: genesis.initial_height,
+            time: genesis.time,
+            app_hash,
+            max_block_bytes: genesis.consensus_params.block.max_bytes as i64,
+            last_block_id: None,
+            uncommitted: None,
+        })
+    }
+
+    /// The header of the next block.
+    pub(crate) fn next_header(&self) -> Result<block::Header, BoxError> {
+        if self.uncommitted.is_some() {
+            return Err("the last block was not committed".into());
+        }
+        let header = block::Header {
+            version: Version { block: 11, app: 0 },
+            chain_id: self.chain_id.clone(),
+            height: self.height,
+            time: self.time,
+            last_block_id: self.last_block_id,
+            last_commit_hash: None,
+            data_hash: None,
+            validators_hash: Hash::None,
+            next_validators_hash: Hash::None,
+            consensus_hash: Hash::None,
+            app_hash: self.app_hash.clone(),
+            last_results_hash: None,
+            evidence_hash: None,
+            proposer_address: account::Id::new([0; 20]),
+        };
+        Ok(header)
+    }
+
+    /// Records that the block with the given header was executed, so that it
+    /// is the next block to commit.
+    pub(crate) fn executed(&mut self, header: block::Header) {
+        self.uncommitted = Some(header);
+    }
+
+    /// The height of the uncommitted block.
+    pub(crate) fn uncommitted(&self) -> Result<block::Height, BoxError> {
+        self.uncommitted
+            .as_ref()
+            .map(|header| header.height)
+            .ok_or_else(|| "no block to commit".into())
+    }
+
+    /// Commits the uncommitted block, after which the app hash is `app_hash`.
+    pub(crate) fn commit(&mut self, app_hash: AppHash) -> Result<(), BoxError> {
+        let header = self.uncommitted.take().ok_or("no block to commit")?;
+        self.last_block_id = Some(block::Id {
+            hash: header.hash(),
+            part_set_header: parts::Header::default(),
+        });
+        self.height = self.height.increment();
+        self.time = (self.time + BLOCK_INTERVAL)?;
+        self.app_hash = app_hash;
+        Ok(())
+    }
+
+    /// The height of the next block.
+    pub(crate) fn height(&self) -> block::Height {
+        self.height
+    }
+
+    /// The maximum size of a block, from the genesis consensus parameters.
+    pub(crate) fn max_block_bytes(&self) -> i64 {
+        self.max_block_bytes
+    }
+
+    /// The app hash after the last committed block.
+    pub(crate) fn app_hash(&self) -> &AppHash {
+        &self.app_hash
+    }
+}
@@ -0,0 +1,283 @@
+//! End-to-end tests against a real CometBFT node running in Docker.
+//!
+//! [`TestNode`] starts a CometBFT container configured to dial the application
+//! under test, waits until the node produces blocks, and exposes its RPC
+//! endpoint:
+//!
+//! ```ignore
+//! tokio::spawn(server.listen_tcp("0.0.0.0:26658"));
+//! let node = TestNode::builder().app_port(26658).start().await?;
+//! node.broadcast_tx(b"name=satoshi").await?;
+//! node.wait_for_height(3).await?;
+//! let status = node.rpc("status", &[]).await?;
+//! ```
+//!
+//! The node reaches the application on the host through
+//! `host.docker.internal`, so the application must listen on an address
+//! reachable from the container, e.g. `0.0.0.0`, rather than `127.0.0.1`.
+//! The container is removed when the [`TestNode`] is dropped.
+//!
+//! This requires the `docker` CLI and a running Docker daemon.
+
+use std::{net::SocketAddr, process::Stdio, time::Duration};
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    process::Command,
+};
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::BoxError;
+
+/// The CometBFT image used by default.
+pub const DEFAULT_IMAGE: &str = "cometbft/cometbft:v0.38.x";
+
+/// The RPC port inside the container.
+const RPC_PORT: u16 = 26657;
+
+/// Configures a [`TestNode`].
+#[derive(Clone, Debug)]
+pub struct TestNodeBuilder {
+    image: String,
+    app_port: u16,
+    startup_timeout: Duration,
+    poll_interval: Duration,
+}
+
+impl Default for TestNodeBuilder {
+    fn default() -> Self {
+        Self {
+            image: DEFAULT_IMAGE.to_string(),
+            app_port: 26658,
+            startup_timeout: Duration::from_secs(60),
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+impl TestNodeBuilder {
+    /// Sets the CometBFT image, whose version must match the protocol version
+    /// of the server, e.g. `cometbft/cometbft:v0.34.x` for a `v034::Server`.
+    /// Defaults to [`DEFAULT_IMAGE`].
+    pub fn image(mut self, image: impl Into<String>) -> Self {
+        self.image = image.into();
+        self
+    }
+
+    /// Sets the port on the host the application listens on. Defaults to
+    /// 26658.
+    pub fn app_port(mut self, port: u16) -> Self {
+        self.app_port = port;
+        self
+    }
+
+    /// Sets how long to wait for the node to produce its first block,
+    /// including pulling the image if needed. Defaults to 60 seconds.
+    pub fn startup_timeout(mut self, timeout: Duration) -> Self {
+        self.startup_timeout = timeout;
+        self
+    }
+
+    /// Sets how often the node's status is polled while waiting. Defaults to
+    /// 250 milliseconds.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Starts the container, and waits until the node has produced a block.
+    pub async fn start(self) -> Result<TestNode, BoxError> {
+        let script = format!(
+            "cometbft init --home /cometbft && exec cometbft node --home /cometbft \
+             --proxy_app=tcp://host.docker.internal:{} --rpc.laddr=tcp://0.0.0.0:{}",
+            self.app_port, RPC_PORT
+        );
+        let id = docker(&[
+            "run",
+            "--detach",
+            "--add-host=host.docker.internal:host-gateway",
+            &format!("--publish=127.0.0.1::{}", RPC_PORT),
+            "--entrypoint=sh",
+            &self.image,
+            "-c",
+            &script,
+        ])
+        .await?;
+        // From here on, dropping the node removes the container.
+        let mut node = TestNode {
+            container: id.trim().to_string(),
+            rpc_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            poll_interval: self.poll_interval,
+            timeout: self.startup_timeout,
+        };
+        let port = docker(&["port", &node.container, &format!("{}/tcp", RPC_PORT)]).await?;
+        node.rpc_addr = port
+            .lines()
+            .find_map(|line| line.trim().parse().ok())
+            .ok_or_else(|| format!("unexpected output of docker port: {:?}", port))?;
+        tracing::info!(container = %node.container, rpc = %node.rpc_addr, "started CometBFT node");
+
+        node.wait_for_height(1).await?;
+        Ok(node)
+    }
+}
+
+/// A CometBFT node running in a Docker container.
+#[derive(Debug)]
+pub struct TestNode {
+    container: String,
+    rpc_addr: SocketAddr,
+    poll_interval: Duration,
+    /// How long to wait for heights.
+    timeout: Duration,
+}
+
+impl TestNode {
+    /// Configures a new node.
+    pub fn builder() -> TestNodeBuilder {
+        TestNodeBuilder::default()
+    }
+
+    /// The id of the node's container.
+    pub fn container(&self) -> &str {
+        &self.container
+    }
+
+    /// The address of the node's RPC endpoint on the host.
+    pub fn rpc_addr(&self) -> SocketAddr {
+        self.rpc_addr
+    }
+
+    /// Calls an RPC method with URI parameters, and returns its result.
+    ///
+    /// Parameter values follow CometBFT's URI conventions: strings are quoted,
+    /// e.g. `("path", "\"/store\"")`, and bytes are hex with a `0x` prefix.
+    pub async fn rpc(&self, method: &str, params: &[(&str, &str)]) -> Result<Value, BoxError> {
+        let mut path = format!("/{}", method);
+        for (i, (key, value)) in params.iter().enumerate() {
+            path.push(if i == 0 { '?' } else { '&' });
+            path.push_str(&percent_encode(key));
+            path.push('=');
+            path.push_str(&percent_encode(value));
+        }
+        let body = http_get(self.rpc_addr, &path).await?;
+        let mut response: Value = serde_json::from_slice(&body)?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("RPC {} failed: {}", method, error).into());
+        }
+        Ok(response["result"].take())
+    }
+
+    /// The height of the latest block of the node.
+    pub async fn latest_height(&self) -> Result<u64, BoxError> {
+        let status = self.rpc("status", &[]).await?;
+        let height = status["sync_info"]["latest_block_height"]
+            .as_str()
+            .ok_or("status without a latest block height")?;
+        Ok(height.parse()?)
+    }
+
+    /// Waits until the node has produced the block at `height`.
+    ///
+    /// Fails if it does not within the startup timeout, e.g. because the node
+    /// cannot reach the application, in which case the error includes the
+    /// end of the node's logs.
+    pub async fn wait_for_height(&self, height: u64) -> Result<(), BoxError> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            // The RPC endpoint is unavailable until the node has started.
+            if let Ok(latest) = self.latest_height().await {
+                if latest >= height {
+                    return Ok(());
+                }
+            }
+            if tokio::time::Instant::now() >= deadline {
+                let logs = self.logs().await.unwrap_or_default();
+                return Err(format!(
+                    "node did not reach height {} within {:?}; last logs:\n{}",
+                    height, self.timeout, logs
+                )
+                .into());
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Submits a transaction with `broadcast_tx_sync`, returning the result of
+    /// its `CheckTx`.
+    pub async fn broadcast_tx(&self, tx: &[u8]) -> Result<Value, BoxError> {
+        let tx = format!("0x{}", hex::encode(tx));
+        self.rpc("broadcast_tx_sync", &[("tx", &tx)]).await
+    }
+
+    /// The last lines of the node's logs.
+    pub async fn logs(&self) -> Result<String, BoxError> {
+        docker(&["logs", "--tail=50", &self.container]).await
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        // Drop can't be async, and the container must be gone before the
+        // test process exits.
+        let removed = std::process::Command::new("docker")
+            .args(["rm", "--force", &self.container])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if !removed.is_ok_and(|status| status.success()) {
+            tracing::warn!(container = %self.container, "failed to remove CometBFT container");
+        }
+    }
+}
+
+/// Runs a docker command, returning its standard output.
+async fn docker(args: &[&str]) -> Result<String, BoxError> {
+    let output = Command::new("docker").args(args).output().await?;
+    if !output.status.success() {
+        return Err(format!(
+            "docker {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Sends an HTTP/1.0 GET request, which the server answers without chunked
+/// encoding before closing the connection, and returns the response body.
+async fn http_get(addr: SocketAddr, path: &str) -> Result<Vec<u8>, BoxError> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("malformed HTTP response")?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status = head.lines().next().unwrap_or_default();
+    // JSON-RPC errors are reported with a 500 status, but with a JSON body.
+    if !matches!(status.split(' ').nth(1), Some("200" | "500")) {
+        return Err(format!("RPC request failed: {}", status).into());
+    }
+    Ok(response.split_off(end + 4))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
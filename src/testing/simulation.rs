@@ -0,0 +1,95 @@
+//! Deterministic simulation of the testing harness.
+//!
+//! A [`Simulation`] runs a test on a single-threaded runtime whose clock is
+//! paused, so that a failing case, e.g. from
+//! [`Generator`](super::generate::Generator), replays identically every time:
+//!
+//! - every task of the server, the driver and the application runs on the
+//!   same thread, and is polled in the order it was woken;
+//! - time only advances when every task is idle, straight to the next timer,
+//!   so timeouts, slow-request warnings and stall detection fire at the same
+//!   point of every run, however loaded the machine is;
+//! - when built with `--cfg tokio_unstable`, the runtime's random number
+//!   generator, which picks the branch `tokio::select!` polls first, is seeded
+//!   from the simulation's seed. Otherwise that choice remains random.
+//!
+//! ```ignore
+//! #[test]
+//! fn sequences() {
+//!     let simulation = Simulation::from_env();
+//!     simulation.run(async {
+//!         let sequence = Generator::new().generate(simulation.seed());
+//!         run_sequence(testing::connect(&new_server()), &sequence).await.unwrap();
+//!     });
+//! }
+//! ```
+//!
+//! If the test panics, the seed is printed, and the run can be replayed by
+//! setting the environment variable [`SEED_ENV`] to it.
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use std::future::Future;
+
+/// The environment variable read by [`Simulation::from_env`].
+pub const SEED_ENV: &str = "SIMULATION_SEED";
+
+/// A seeded, single-threaded runtime with paused time.
+#[derive(Clone, Copy, Debug)]
+pub struct Simulation {
+    seed: u64,
+}
+
+impl Simulation {
+    /// Creates a simulation with the given seed.
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Creates a simulation with the seed in [`SEED_ENV`], or a random one if
+    /// it is not set.
+    ///
+    /// # Panics
+    ///
+    /// If the variable is set but is not a number.
+    pub fn from_env() -> Self {
+        match std::env::var(SEED_ENV) {
+            Ok(seed) => Self::new(
+                seed.parse()
+                    .unwrap_or_else(|_| panic!("{} is not a seed: {:?}", SEED_ENV, seed)),
+            ),
+            Err(_) => Self::new(rand::random()),
+        }
+    }
+
+    /// The seed of the simulation, e.g. to generate the test's inputs.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Runs `future` to completion on a new runtime, returning its output.
+    pub fn run<F: Future>(&self, future: F) -> F::Output {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        builder.enable_all().start_paused(true);
+        #[cfg(tokio_unstable)]
+        builder.rng_seed(tokio::runtime::RngSeed::from_bytes(
+            &self.seed.to_le_bytes(),
+        ));
+        let runtime = builder.build().expect("building a current-thread runtime");
+
+        let _report = ReportSeed(self.seed);
+        tracing::debug!(seed = self.seed, "starting simulation");
+        runtime.block_on(future)
+    }
+}
+
+/// Prints the seed of a simulation that panics.
+struct ReportSeed(u64);
+
+impl Drop for ReportSeed {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            eprintln!("simulation failed; replay it with {}={}", SEED_ENV, self.0);
+        }
+    }
+}
@@ -1,8 +1,23 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, IoSlice};
 use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
 
-use tokio_util::codec::{Decoder, Encoder};
+use futures::{sink::Sink, stream::Stream};
+use pin_project::pin_project;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinHandle,
+    time::Sleep,
+};
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
 
-use bytes::{Buf, BufMut, BytesMut};
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 // encode_varint and decode_varint will be removed once
 // https://github.com/tendermint/tendermint/issues/5783 lands in Tendermint.
@@ -17,6 +32,7 @@ pub fn decode_varint<B: Buf>(mut buf: &mut B) -> Result<u64, prost::DecodeError>
 
 pub struct Decode<M> {
     state: DecodeState,
+    max_len: Option<usize>,
     _marker: PhantomData<M>,
 }
 
@@ -24,25 +40,39 @@ impl<M> Default for Decode<M> {
     fn default() -> Self {
         Self {
             state: DecodeState::Head,
+            max_len: None,
             _marker: PhantomData,
         }
     }
 }
 
+impl<M> Decode<M> {
+    /// Fails on messages longer than `max_len` bytes, if set, instead of
+    /// buffering them.
+    pub fn set_max_len(&mut self, max_len: Option<usize>) {
+        self.max_len = max_len;
+    }
+}
+
+impl<M> Decode<M> {
+    /// Whether the head of a message was read, but not its whole body.
+    fn in_body(&self) -> bool {
+        matches!(self.state, DecodeState::Body { .. })
+    }
+}
+
 #[derive(Debug)]
 enum DecodeState {
     Head,
     Body { len: usize },
 }
 
-impl<M: prost::Message + Default> Decoder for Decode<M> {
-    type Item = M;
-    type Error = crate::BoxError;
-
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+impl<M> Decode<M> {
+    /// Splits the body of the next message off `src`, once it is complete.
+    fn split_body(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, crate::BoxError> {
         match self.state {
             DecodeState::Head => {
-                tracing::trace!(?src, "decoding head");
+                tracing::trace!(src.len = src.len(), "decoding head");
 
                 // we don't use decode_varint directly, because it advances the
                 // buffer regardless of success, but Decoder assumes that when
@@ -60,11 +90,18 @@ impl<M: prost::Message + Default> Decoder for Decode<M> {
                         return Ok(None);
                     }
                 };
+                if let Some(max_len) = self.max_len.filter(|&max_len| len > max_len) {
+                    return Err(format!(
+                        "message of {} bytes exceeds the limit of {} bytes",
+                        len, max_len
+                    )
+                    .into());
+                }
                 self.state = DecodeState::Body { len };
                 tracing::trace!(?self.state, "ready for body");
 
                 // Recurse to attempt body decoding.
-                self.decode(src)
+                self.split_body(src)
             }
             DecodeState::Body { len } => {
                 if src.len() < len {
@@ -73,14 +110,154 @@ impl<M: prost::Message + Default> Decoder for Decode<M> {
                 }
 
                 let body = src.split_to(len);
-                tracing::trace!(?body, "decoding body");
-                let message = M::decode(body)?;
 
                 // Now reset the decoder state for the next message.
                 self.state = DecodeState::Head;
 
-                Ok(Some(message))
+                Ok(Some(body))
+            }
+        }
+    }
+}
+
+impl<M: prost::Message + Default> Decoder for Decode<M> {
+    type Item = M;
+    type Error = crate::BoxError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.split_body(src)? {
+            Some(body) => {
+                tracing::trace!(body.len = body.len(), "decoding body");
+                Ok(Some(M::decode(body)?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// A decoder of the bodies of length-delimited messages, left undecoded.
+struct Bodies(Decode<()>);
+
+impl Decoder for Bodies {
+    type Item = BytesMut;
+    type Error = crate::BoxError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.0.split_body(src)
+    }
+}
+
+/// A stream of length-delimited messages read from `R`.
+///
+/// It reads messages as a `FramedRead` with [`Decode`] does, except that
+/// messages of at least the blocking length, if one is set, are decoded on
+/// the blocking thread pool, so that decoding a huge message doesn't hold up
+/// the other tasks of the worker thread.
+#[pin_project]
+pub struct DecodeRead<R, M> {
+    #[pin]
+    inner: FramedRead<R, Bodies>,
+    blocking_len: Option<usize>,
+    decoding: Option<JoinHandle<Result<M, prost::DecodeError>>>,
+    /// The body of the message read last.
+    frame: Option<Bytes>,
+    read_timeout: Option<Duration>,
+    /// When the message being read times out, once part of it has arrived.
+    partial: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R: AsyncRead, M> DecodeRead<R, M> {
+    /// Reads from `inner` with a read buffer of the given initial capacity.
+    pub fn with_capacity(inner: R, capacity: usize) -> Self {
+        Self {
+            inner: FramedRead::with_capacity(inner, Bodies(Decode::default()), capacity),
+            blocking_len: None,
+            decoding: None,
+            frame: None,
+            read_timeout: None,
+            partial: None,
+        }
+    }
+}
+
+impl<R, M> DecodeRead<R, M> {
+    /// Fails on messages longer than `max_len` bytes, if set, instead of
+    /// buffering them.
+    pub fn set_max_len(&mut self, max_len: Option<usize>) {
+        self.inner.decoder_mut().0.set_max_len(max_len);
+    }
+
+    /// Decodes messages of at least `blocking_len` bytes, if set, on the
+    /// blocking thread pool.
+    pub fn set_blocking_len(&mut self, blocking_len: Option<usize>) {
+        self.blocking_len = blocking_len;
+    }
+
+    /// Fails if a message isn't read in full within `read_timeout` of its
+    /// first bytes, if set. The time between messages isn't limited.
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.read_timeout = read_timeout;
+    }
+
+    /// Takes the encoded body of the message read last, without its length
+    /// prefix.
+    pub fn take_frame(&mut self) -> Option<Bytes> {
+        self.frame.take()
+    }
+}
+
+impl<R, M> Stream for DecodeRead<R, M>
+where
+    R: AsyncRead,
+    M: prost::Message + Default + Send + 'static,
+{
+    type Item = Result<M, crate::BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(decoding) = this.decoding {
+                let decoded = ready!(Pin::new(decoding).poll(cx));
+                *this.decoding = None;
+                return Poll::Ready(Some(match decoded {
+                    Ok(decoded) => decoded.map_err(Into::into),
+                    Err(e) => Err(e.into()),
+                }));
             }
+            let body = match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(body))) => body,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => {
+                    let reading =
+                        !this.inner.read_buffer().is_empty() || this.inner.decoder().0.in_body();
+                    let Some(timeout) = this.read_timeout.filter(|_| reading) else {
+                        *this.partial = None;
+                        return Poll::Pending;
+                    };
+                    let partial = this
+                        .partial
+                        .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+                    ready!(partial.as_mut().poll(cx));
+                    let message = format!("message not received in full within {timeout:?}");
+                    return Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        message,
+                    )
+                    .into())));
+                }
+            };
+            *this.partial = None;
+            // Decoding slices the byte strings of the message out of its
+            // body, so keeping the body costs no copy.
+            let body = body.freeze();
+            *this.frame = Some(body.clone());
+            if this.blocking_len.is_some_and(|len| body.len() >= len) {
+                *this.decoding = Some(tokio::task::spawn_blocking(move || M::decode(body)));
+                continue;
+            }
+            tracing::trace!(body.len = body.len(), "decoding body");
+            return Poll::Ready(Some(M::decode(body).map_err(Into::into)));
         }
     }
 }
@@ -111,3 +288,179 @@ impl<M: prost::Message + Sized + std::fmt::Debug> Encoder<M> for Encode<M> {
         Ok(())
     }
 }
+
+/// The default number of buffered bytes at which [`EncodeWrite`] writes them
+/// out before accepting another message.
+const BACKPRESSURE_BOUNDARY: usize = 8 * 1024;
+
+/// The most frames written by a single vectored write.
+const MAX_SLICES: usize = 64;
+
+/// A sink writing length-delimited messages to `W` with vectored writes.
+///
+/// Each message is encoded with its length prefix into a buffer of its own,
+/// and the queued buffers are written with `poll_write_vectored` when the sink
+/// is flushed, so that many small messages go out in a single system call
+/// without being copied into one contiguous buffer.
+#[pin_project]
+pub struct EncodeWrite<W, M> {
+    #[pin]
+    inner: W,
+    queue: VecDeque<Bytes>,
+    queued_len: usize,
+    capacity: usize,
+    blocking_len: Option<usize>,
+    encoding: Option<JoinHandle<Result<Bytes, crate::BoxError>>>,
+    write_timeout: Option<Duration>,
+    /// When the write in progress times out, once it is blocked.
+    blocked: Option<Pin<Box<Sleep>>>,
+    _marker: PhantomData<M>,
+}
+
+impl<W, M> EncodeWrite<W, M> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            queue: VecDeque::new(),
+            queued_len: 0,
+            capacity: BACKPRESSURE_BOUNDARY,
+            blocking_len: None,
+            encoding: None,
+            write_timeout: None,
+            blocked: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the number of buffered bytes at which they are written out.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// Encodes messages of at least `blocking_len` bytes, if set, on the
+    /// blocking thread pool. Messages are still written in the order they
+    /// are sent.
+    pub fn set_blocking_len(&mut self, blocking_len: Option<usize>) {
+        self.blocking_len = blocking_len;
+    }
+
+    /// Fails if writing makes no progress for `write_timeout`, if set, as when
+    /// the node stops reading responses.
+    pub fn set_write_timeout(&mut self, write_timeout: Option<Duration>) {
+        self.write_timeout = write_timeout;
+    }
+
+    /// Queues the message being encoded on the blocking thread pool, if any,
+    /// once it is encoded.
+    fn poll_encoding(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), crate::BoxError>> {
+        let this = self.project();
+        if let Some(encoding) = this.encoding {
+            let frame = ready!(Pin::new(encoding).poll(cx));
+            *this.encoding = None;
+            let frame = frame??;
+            *this.queued_len += frame.len();
+            this.queue.push_back(frame);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Waits on a blocked write, failing once it has been blocked for `timeout`,
+/// if set.
+fn poll_blocked(
+    blocked: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<(), crate::BoxError>> {
+    let Some(timeout) = timeout else {
+        return Poll::Pending;
+    };
+    let blocked = blocked.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+    ready!(blocked.as_mut().poll(cx));
+    let message = format!("the node read no responses for {timeout:?}");
+    Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, message).into()))
+}
+
+/// Encodes `item` with its length prefix.
+fn frame<M: prost::Message>(item: &M) -> Result<Bytes, crate::BoxError> {
+    let len = item.encoded_len();
+    let mut buf = BytesMut::with_capacity(len + prost::length_delimiter_len(len));
+    encode_varint(len as u64, &mut buf);
+    item.encode(&mut buf)?;
+    Ok(buf.freeze())
+}
+
+impl<W: AsyncWrite, M: prost::Message + Send + 'static> Sink<M> for EncodeWrite<W, M> {
+    type Error = crate::BoxError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_encoding(cx))?;
+        if self.queued_len >= self.capacity {
+            self.poll_flush(cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: M) -> Result<(), Self::Error> {
+        let this = self.project();
+        if this
+            .blocking_len
+            .is_some_and(|len| item.encoded_len() >= len)
+        {
+            // poll_ready waits for the previous message to be encoded.
+            debug_assert!(this.encoding.is_none());
+            *this.encoding = Some(tokio::task::spawn_blocking(move || frame(&item)));
+            return Ok(());
+        }
+        let frame = frame(&item)?;
+        *this.queued_len += frame.len();
+        this.queue.push_back(frame);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_encoding(cx))?;
+        let mut this = self.project();
+        while !this.queue.is_empty() {
+            let slices: Vec<IoSlice<'_>> = this
+                .queue
+                .iter()
+                .take(MAX_SLICES)
+                .map(|buf| IoSlice::new(buf))
+                .collect();
+            let mut written = match this.inner.as_mut().poll_write_vectored(cx, &slices) {
+                Poll::Ready(written) => written?,
+                Poll::Pending => return poll_blocked(this.blocked, *this.write_timeout, cx),
+            };
+            *this.blocked = None;
+            if written == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+            }
+            *this.queued_len -= written;
+            while written > 0 {
+                let front = this.queue.front_mut().expect("wrote queued bytes");
+                if written < front.len() {
+                    front.advance(written);
+                    break;
+                }
+                written -= front.len();
+                this.queue.pop_front();
+            }
+        }
+        if this.inner.poll_flush(cx)?.is_pending() {
+            return poll_blocked(this.blocked, *this.write_timeout, cx);
+        }
+        *this.blocked = None;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        ready!(self.project().inner.poll_shutdown(cx))?;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,568 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::{FutureExt, TryFutureExt};
+use futures::sink::SinkExt;
+use futures::stream::{FuturesOrdered, StreamExt};
+use pin_project::pin_project;
+use prost::Message;
+use tendermint_proto::v0_34::abci as pb;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::{
+    net::{TcpListener, ToSocketAddrs},
+    select,
+};
+use tower::{Service, ServiceExt};
+use tracing::Instrument;
+
+use super::server::apply_codec_options;
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::v034::codec::{DecodeRead, EncodeWrite};
+use crate::{
+    context::PeerAddr,
+    handle::{LocalAddr, Registration},
+    health::{self, HealthCheck, HealthReport},
+    metrics,
+    middleware::raw,
+    pipeline::Category,
+    request_id, task, BoxError, BufferSizes, ConnectionError, ConnectionOptions, RequestId,
+    ServerHandle,
+};
+
+/// An ABCI server whose component services take the protobuf
+/// [`Request`](pb::Request) and return the protobuf [`Response`](pb::Response)
+/// types directly.
+///
+/// It skips the conversions to and from the `tendermint` domain types that
+/// [`Server`](super::Server) performs, for applications that need the last
+/// bit of throughput, or fields that the domain types don't model yet. Each
+/// service is called with the requests of its category, and must answer each
+/// with a response of the same method.
+///
+/// The server answers `Flush` requests itself and delivers the responses in
+/// request order, like `Server`, and its [`ServerHandle`] lists, closes and
+/// drains its connections. It is otherwise deliberately minimal: an error
+/// from any service closes the connection, and of the connection options only
+/// the buffer sizes and the blocking codec length apply. The health check
+/// only waits for each service to become ready.
+pub struct ProtoServer<C, M, I, S> {
+    consensus: C,
+    mempool: M,
+    info: I,
+    snapshot: S,
+    handle: ServerHandle,
+}
+
+pub struct ProtoServerBuilder<C, M, I, S> {
+    consensus: Option<C>,
+    mempool: Option<M>,
+    info: Option<I>,
+    snapshot: Option<S>,
+    options: ConnectionOptions,
+}
+
+impl<C, M, I, S> Default for ProtoServerBuilder<C, M, I, S> {
+    fn default() -> Self {
+        Self {
+            consensus: None,
+            mempool: None,
+            info: None,
+            snapshot: None,
+            options: ConnectionOptions::default(),
+        }
+    }
+}
+
+impl<C, M, I, S> ProtoServerBuilder<C, M, I, S>
+where
+    C: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    C::Future: Send + 'static,
+    M: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    M::Future: Send + 'static,
+    I: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    I::Future: Send + 'static,
+    S: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    S::Future: Send + 'static,
+{
+    pub fn consensus(mut self, consensus: C) -> Self {
+        self.consensus = Some(consensus);
+        self
+    }
+
+    pub fn mempool(mut self, mempool: M) -> Self {
+        self.mempool = Some(mempool);
+        self
+    }
+
+    pub fn info(mut self, info: I) -> Self {
+        self.info = Some(info);
+        self
+    }
+
+    pub fn snapshot(mut self, snapshot: S) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Sets the sizes of each connection's read and write buffers. See
+    /// [`BufferSizes`] for the defaults.
+    pub fn buffer_sizes(mut self, buffer_sizes: BufferSizes) -> Self {
+        self.options.buffer_sizes = buffer_sizes;
+        self
+    }
+
+    /// Decodes requests and encodes responses of at least `len` bytes on the
+    /// blocking thread pool. Disabled by default.
+    pub fn blocking_codec_len(mut self, len: usize) -> Self {
+        self.options.blocking_codec_len = Some(len);
+        self
+    }
+
+    pub fn finish(self) -> Option<ProtoServer<C, M, I, S>> {
+        let consensus = self.consensus?;
+        let mempool = self.mempool?;
+        let info = self.info?;
+        let snapshot = self.snapshot?;
+        let health = health_check(
+            consensus.clone(),
+            mempool.clone(),
+            info.clone(),
+            snapshot.clone(),
+        );
+
+        Some(ProtoServer {
+            consensus,
+            mempool,
+            info,
+            snapshot,
+            handle: ServerHandle::new("0.34", self.options, health),
+        })
+    }
+}
+
+/// Checks that the component services become ready.
+fn health_check<C, M, I, S>(consensus: C, mempool: M, info: I, snapshot: S) -> HealthCheck
+where
+    C: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    C::Future: Send + 'static,
+    M: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    M::Future: Send + 'static,
+    I: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    I::Future: Send + 'static,
+    S: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    S::Future: Send + 'static,
+{
+    // The services need not be `Sync`, while the check is shared.
+    let services = std::sync::Mutex::new((consensus, mempool, info, snapshot));
+    HealthCheck::new(move |timeout| {
+        let (consensus, mempool, info, snapshot) = services.lock().unwrap().clone();
+        let consensus = health::timed(
+            Category::Consensus,
+            "ready",
+            timeout,
+            ServiceExt::<pb::Request>::ready_oneshot(consensus).map_ok(drop),
+        );
+        let mempool = health::timed(
+            Category::Mempool,
+            "ready",
+            timeout,
+            ServiceExt::<pb::Request>::ready_oneshot(mempool).map_ok(drop),
+        );
+        let info = health::timed(
+            Category::Info,
+            "ready",
+            timeout,
+            ServiceExt::<pb::Request>::ready_oneshot(info).map_ok(drop),
+        );
+        let snapshot = health::timed(
+            Category::Snapshot,
+            "ready",
+            timeout,
+            ServiceExt::<pb::Request>::ready_oneshot(snapshot).map_ok(drop),
+        );
+        async move {
+            let (consensus, mempool, info, snapshot) =
+                futures::join!(consensus, mempool, info, snapshot);
+            HealthReport {
+                components: vec![consensus, mempool, info, snapshot],
+            }
+        }
+        .boxed()
+    })
+}
+
+impl<C, M, I, S> ProtoServer<C, M, I, S>
+where
+    C: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    C::Future: Send + 'static,
+    M: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    M::Future: Send + 'static,
+    I: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    I::Future: Send + 'static,
+    S: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + Clone + 'static,
+    S::Future: Send + 'static,
+{
+    pub fn builder() -> ProtoServerBuilder<C, M, I, S> {
+        ProtoServerBuilder::default()
+    }
+
+    /// Returns a handle for inspecting the server's connections and closing
+    /// them while it is listening.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Spawns a task serving a connection over the given halves of a socket.
+    fn spawn_connection(
+        &self,
+        peer: PeerAddr,
+        read: impl AsyncReadExt + std::marker::Unpin + Send + 'static,
+        write: impl AsyncWriteExt + std::marker::Unpin + Send + 'static,
+    ) {
+        let conn = Connection {
+            id: request_id::next_connection_id(),
+            peer,
+            consensus: self.consensus.clone(),
+            mempool: self.mempool.clone(),
+            info: self.info.clone(),
+            snapshot: self.snapshot.clone(),
+            handle: self.handle.clone(),
+        };
+        let span = tracing::info_span!(
+            "abci_connection",
+            id = conn.id,
+            kind = tracing::field::Empty
+        );
+        metrics::connection_accepted();
+        task::spawn(
+            &format!("abci-connection-{}", conn.id),
+            async move {
+                if let Err(e) = conn.run(read, write).await {
+                    tracing::error!(error = %e, "connection failed");
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Listens on `address`, e.g. the listen address of a
+    /// [`ServerConfig`](crate::config::ServerConfig).
+    #[cfg(feature = "config")]
+    pub async fn listen(self, address: &crate::config::ListenAddress) -> Result<(), BoxError> {
+        use crate::config::ListenAddress;
+
+        match address {
+            ListenAddress::Tcp { address } => self.listen_tcp(address.as_str()).await,
+            #[cfg(target_family = "unix")]
+            ListenAddress::Unix { path } => self.listen_unix(path).await,
+            #[cfg(not(target_family = "unix"))]
+            ListenAddress::Unix { .. } => {
+                Err("unix domain sockets are not supported on this platform".into())
+            }
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "abci_accept_loop",
+            skip_all,
+            fields(transport = "uds", addr = tracing::field::Empty)
+        )
+    )]
+    pub async fn listen_unix(self, path: impl AsRef<std::path::Path>) -> Result<(), BoxError> {
+        let listener = self
+            .handle
+            .fail_on_error(tokio::net::UnixListener::bind(path))?;
+        let addr = self.handle.fail_on_error(listener.local_addr())?;
+        tracing::Span::current().record("addr", tracing::field::debug(&addr));
+        tracing::info!(?addr, "ABCI protobuf server starting on uds");
+        self.handle
+            .set_listening(LocalAddr::Unix(addr.as_pathname().map(Into::into)));
+
+        loop {
+            let accepted = select! {
+                accepted = listener.accept() => accepted,
+                () = self.handle.drained() => {
+                    self.handle.stop_listening();
+                    tracing::info!("no longer accepting connections");
+                    return Ok(());
+                }
+            };
+            match accepted {
+                Ok((socket, addr)) => {
+                    tracing::debug!(?addr, "accepted new connection");
+                    let peer = PeerAddr::Unix(addr.as_pathname().map(Into::into));
+                    let (read, write) = socket.into_split();
+                    self.spawn_connection(peer, read, write);
+                }
+                Err(e) => {
+                    tracing::error!({ %e }, "error accepting new connection");
+                    metrics::accept_error();
+                }
+            }
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "abci_accept_loop",
+            skip_all,
+            fields(transport = "tcp", addr = tracing::field::Empty)
+        )
+    )]
+    pub async fn listen_tcp<A: ToSocketAddrs + std::fmt::Debug>(
+        self,
+        addr: A,
+    ) -> Result<(), BoxError> {
+        let listener = self.handle.fail_on_error(TcpListener::bind(addr).await)?;
+        let addr = self.handle.fail_on_error(listener.local_addr())?;
+        tracing::Span::current().record("addr", tracing::field::display(&addr));
+        tracing::info!(?addr, "ABCI protobuf server starting on tcp socket");
+        self.handle.set_listening(LocalAddr::Tcp(addr));
+
+        loop {
+            let accepted = select! {
+                accepted = listener.accept() => accepted,
+                () = self.handle.drained() => {
+                    self.handle.stop_listening();
+                    tracing::info!("no longer accepting connections");
+                    return Ok(());
+                }
+            };
+            match accepted {
+                Ok((socket, addr)) => {
+                    tracing::debug!(?addr, "accepted new connection");
+                    let (read, write) = socket.into_split();
+                    self.spawn_connection(PeerAddr::Tcp(addr), read, write);
+                }
+                Err(e) => {
+                    tracing::error!({ %e }, "error accepting new connection");
+                    metrics::accept_error();
+                }
+            }
+        }
+    }
+}
+
+struct Connection<C, M, I, S> {
+    id: u64,
+    peer: PeerAddr,
+    consensus: C,
+    mempool: M,
+    info: I,
+    snapshot: S,
+    handle: ServerHandle,
+}
+
+impl<C, M, I, S> Connection<C, M, I, S>
+where
+    C: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + 'static,
+    C::Future: Send + 'static,
+    M: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + 'static,
+    M::Future: Send + 'static,
+    I: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + 'static,
+    I::Future: Send + 'static,
+    S: Service<pb::Request, Response = pb::Response, Error = BoxError> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    async fn run(
+        self,
+        read: impl AsyncReadExt + std::marker::Unpin + Send + 'static,
+        write: impl AsyncWriteExt + std::marker::Unpin + Send + 'static,
+    ) -> Result<(), ConnectionError> {
+        let id = self.id;
+        let registration = self.handle.register(id, Some(self.peer.clone()), None);
+        let read_capacity = registration.options().borrow().buffer_sizes.read_capacity;
+        let request_stream = DecodeRead::with_capacity(read, read_capacity);
+        let response_sink = EncodeWrite::<_, pb::Response>::new(write);
+        let mut kind = None;
+        let result = self
+            .serve(&mut kind, &registration, request_stream, response_sink)
+            .await
+            .map_err(|e| ConnectionError::new(id, kind, None, None, e));
+        metrics::connection_closed(kind, &result);
+        result
+    }
+
+    async fn serve<R, W>(
+        mut self,
+        kind: &mut Option<Category>,
+        registration: &Registration,
+        mut request_stream: DecodeRead<R, pb::Request>,
+        mut response_sink: EncodeWrite<W, pb::Response>,
+    ) -> Result<(), BoxError>
+    where
+        R: AsyncReadExt + std::marker::Unpin,
+        W: AsyncWriteExt + std::marker::Unpin,
+    {
+        tracing::info!("listening for requests");
+
+        let mut responses = FuturesOrdered::new();
+        let mut close = registration.close_signal();
+        let mut options = registration.options().borrow().clone();
+        apply_codec_options(&mut request_stream, &mut response_sink, &options);
+        let mut sequence = 0;
+        let mut closing = false;
+
+        loop {
+            if closing && responses.is_empty() {
+                response_sink.flush().await?;
+                tracing::info!("closing connection on request");
+                return Ok(());
+            }
+            select! {
+                req = request_stream.next(), if !closing => {
+                    let proto = match req.transpose()? {
+                        Some(proto) => proto,
+                        None => return Ok(()),
+                    };
+                    let frame = request_stream.take_frame();
+                    metrics::frame_received(*kind, proto.encoded_len());
+                    let Some(category) = category(&proto)? else {
+                        // Answer the Flush once every pending response is
+                        // written.
+                        while let Some(response) = responses.next().await {
+                            send(&mut response_sink, *kind, response).await?;
+                        }
+                        send(&mut response_sink, *kind, Ok(flush())).await?;
+                        response_sink.flush().await?;
+                        continue;
+                    };
+                    if kind.is_none() {
+                        tracing::Span::current().record("kind", category.name());
+                        tracing::info!(kind = category.name(), "detected connection kind");
+                        *kind = Some(category);
+                        registration.set_kind(category);
+                        options = options.for_kind(Some(category)).clone();
+                        apply_codec_options(&mut request_stream, &mut response_sink, &options);
+                    }
+                    let id = RequestId::new(self.id, sequence);
+                    sequence += 1;
+                    let span = tracing::debug_span!("request", %id);
+                    let response = match category {
+                        Category::Consensus => {
+                            let service = self.consensus.ready().await?;
+                            ResponseFuture::Consensus(span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || service.call(proto))
+                                })
+                            }))
+                        }
+                        Category::Mempool => {
+                            let service = self.mempool.ready().await?;
+                            ResponseFuture::Mempool(span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || service.call(proto))
+                                })
+                            }))
+                        }
+                        Category::Info => {
+                            let service = self.info.ready().await?;
+                            ResponseFuture::Info(span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || service.call(proto))
+                                })
+                            }))
+                        }
+                        Category::Snapshot => {
+                            let service = self.snapshot.ready().await?;
+                            ResponseFuture::Snapshot(span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || service.call(proto))
+                                })
+                            }))
+                        }
+                    };
+                    responses.push_back(response.instrument(span));
+                }
+                rsp = responses.next(), if !responses.is_empty() => {
+                    let response = rsp.expect("didn't poll when responses was empty");
+                    send(&mut response_sink, *kind, response).await?;
+                }
+                () = close.requested(), if !closing => {
+                    // Stop reading requests, but deliver the pending responses.
+                    tracing::debug!(responses.len = responses.len(), "asked to close");
+                    closing = true;
+                }
+            }
+        }
+    }
+}
+
+/// Buffers a response, or returns the error if it failed.
+async fn send<W>(
+    sink: &mut EncodeWrite<W, pb::Response>,
+    kind: Option<Category>,
+    response: Result<pb::Response, BoxError>,
+) -> Result<(), BoxError>
+where
+    W: AsyncWriteExt + std::marker::Unpin,
+{
+    let response = response?;
+    metrics::frame_sent(kind, response.encoded_len());
+    // Written when the connection is next flushed.
+    sink.feed(response).await
+}
+
+/// The category of a request, or `None` for a `Flush`.
+fn category(request: &pb::Request) -> Result<Option<Category>, BoxError> {
+    use pb::request::Value;
+
+    Ok(
+        match request.value.as_ref().ok_or("request without a value")? {
+            Value::Flush(_) => None,
+            Value::InitChain(_)
+            | Value::BeginBlock(_)
+            | Value::DeliverTx(_)
+            | Value::EndBlock(_)
+            | Value::Commit(_) => Some(Category::Consensus),
+            Value::CheckTx(_) => Some(Category::Mempool),
+            Value::ListSnapshots(_)
+            | Value::OfferSnapshot(_)
+            | Value::LoadSnapshotChunk(_)
+            | Value::ApplySnapshotChunk(_) => Some(Category::Snapshot),
+            Value::Echo(_) | Value::Info(_) | Value::SetOption(_) | Value::Query(_) => {
+                Some(Category::Info)
+            }
+        },
+    )
+}
+
+fn flush() -> pb::Response {
+    pb::Response {
+        value: Some(pb::response::Value::Flush(pb::ResponseFlush {})),
+    }
+}
+
+/// The response to a request, from the service of its category.
+#[pin_project(project = ResponseFutureProj)]
+enum ResponseFuture<C, M, I, S> {
+    Consensus(#[pin] C),
+    Mempool(#[pin] M),
+    Info(#[pin] I),
+    Snapshot(#[pin] S),
+}
+
+impl<C, M, I, S> Future for ResponseFuture<C, M, I, S>
+where
+    C: Future<Output = Result<pb::Response, BoxError>>,
+    M: Future<Output = Result<pb::Response, BoxError>>,
+    I: Future<Output = Result<pb::Response, BoxError>>,
+    S: Future<Output = Result<pb::Response, BoxError>>,
+{
+    type Output = Result<pb::Response, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Consensus(future) => future.poll(cx),
+            ResponseFutureProj::Mempool(future) => future.poll(cx),
+            ResponseFutureProj::Info(future) => future.poll(cx),
+            ResponseFutureProj::Snapshot(future) => future.poll(cx),
+        }
+    }
+}
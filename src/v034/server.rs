@@ -1,27 +1,56 @@
 use std::convert::{TryFrom, TryInto};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
 
-use futures::future::{FutureExt, TryFutureExt};
-use futures::sink::SinkExt;
-use futures::stream::{FuturesOrdered, StreamExt};
+#[cfg(feature = "net")]
+use crate::handle::LocalAddr;
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use futures::sink::{Sink, SinkExt};
+use futures::stream::StreamExt;
+use pin_project::pin_project;
+use prost::Message;
+use tendermint_proto::v0_34::abci as pb;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::{
-    net::{TcpListener, ToSocketAddrs},
-    select,
+#[cfg(feature = "net")]
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::{runtime::Handle, select, sync::oneshot};
+use tower::{util::BoxCloneService, Service, ServiceExt};
+use tracing::Instrument;
+
+use crate::v034::codec::{DecodeRead, EncodeWrite};
+use crate::{
+    context::{ConnectionCallback, ConnectionContext, PeerAddr},
+    error::ERROR_RESPONSE_CODE,
+    handle::Registration,
+    health::{self, HealthCheck, HealthReport},
+    metrics,
+    middleware::{batch, deadline, raw},
+    pipeline::{Category, FlushTimer, IdleTimer, InFlight, Pending, ResponseQueue, StallDetector},
+    probe::{self, Readiness},
+    redact,
+    reply::{CodeResponse, ExceptionExt},
+    request_id, summary, task, BoxError, BufferSizes, CheckTxError, ConnectionError,
+    ConnectionOptions, ErrorPolicy, InterruptedBlock, Overload, PipelineDepth, RequestExt,
+    RequestId, ResponseExt, ServerHandle, StallDetection,
+};
+use tendermint::abci::{
+    request::{self, CheckTxKind},
+    response,
 };
+use tendermint::block;
 
-use crate::BoxError;
-use tendermint::abci::MethodKind;
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
 use tendermint::v0_34::abci::{
     ConsensusRequest, ConsensusResponse, InfoRequest, InfoResponse, MempoolRequest,
     MempoolResponse, Request, Response, SnapshotRequest, SnapshotResponse,
 };
-use tokio_util::codec::{FramedRead, FramedWrite};
-use tower::{Service, ServiceExt};
 
-#[cfg(target_family = "unix")]
-use std::path::Path;
-#[cfg(target_family = "unix")]
-use tokio::net::UnixListener;
+/// The ABCI version sent in `Info` requests.
+pub(crate) const ABCI_VERSION: &str = "0.17.0";
 
 /// An ABCI server which listens for connections and forwards requests to four
 /// component ABCI [`Service`]s.
@@ -30,6 +59,60 @@ pub struct Server<C, M, I, S> {
     mempool: M,
     info: I,
     snapshot: S,
+    on_connection_error: Option<ErrorCallback>,
+    on_interrupted_block: Option<InterruptedBlockCallback>,
+    on_connection: Option<ConnectionCallback>,
+    consensus_runtime: Option<Handle>,
+    deliver_tx_batch: Option<Batching<request::DeliverTx, response::DeliverTx>>,
+    recheck_batch: Option<Batching<request::CheckTx, response::CheckTx>>,
+    handle: ServerHandle,
+}
+
+/// A callback invoked when a connection fails.
+type ErrorCallback = Arc<dyn Fn(&ConnectionError) + Send + Sync + 'static>;
+
+/// A callback invoked when a connection closes during block execution.
+type InterruptedBlockCallback = Arc<dyn Fn(&InterruptedBlock) + Send + Sync + 'static>;
+
+/// How consecutive requests of a method are batched into a single call.
+#[derive(Clone)]
+struct Batching<Req, Rsp> {
+    service: BoxCloneService<Vec<Req>, Vec<Rsp>, BoxError>,
+    /// The most requests of a batch.
+    max_len: usize,
+    /// What a stall while waiting for the service to be ready is reported as.
+    waiting_for: &'static str,
+}
+
+impl<Req: 'static, Rsp: 'static> Batching<Req, Rsp> {
+    fn new<B>(max_len: usize, batch: B, waiting_for: &'static str) -> Self
+    where
+        B: Service<Vec<Req>, Response = Vec<Rsp>, Error = BoxError> + Send + Clone + 'static,
+        B::Future: Send + 'static,
+    {
+        Self {
+            service: BoxCloneService::new(batch),
+            max_len: max_len.max(1),
+            waiting_for,
+        }
+    }
+
+    /// Calls the batch service with `requests` once it is ready, in the scope
+    /// of `lead`, the first request of the batch.
+    async fn call(
+        &mut self,
+        stall: &mut StallDetector,
+        in_flight: &InFlight,
+        lead: &Pending,
+        span: &tracing::Span,
+        requests: Vec<Req>,
+    ) -> Result<BoxFuture<'static, Result<Vec<Rsp>, BoxError>>, BoxError> {
+        let waiting_for = self.waiting_for;
+        let service = stall
+            .watch(waiting_for, in_flight, self.service.ready())
+            .await??;
+        Ok(span.in_scope(|| RequestId::scope(Some(lead.id), || service.call(requests))))
+    }
 }
 
 pub struct ServerBuilder<C, M, I, S> {
@@ -37,6 +120,13 @@ pub struct ServerBuilder<C, M, I, S> {
     mempool: Option<M>,
     info: Option<I>,
     snapshot: Option<S>,
+    options: ConnectionOptions,
+    on_connection_error: Option<ErrorCallback>,
+    on_interrupted_block: Option<InterruptedBlockCallback>,
+    on_connection: Option<ConnectionCallback>,
+    consensus_runtime: Option<Handle>,
+    deliver_tx_batch: Option<Batching<request::DeliverTx, response::DeliverTx>>,
+    recheck_batch: Option<Batching<request::CheckTx, response::CheckTx>>,
 }
 
 impl<C, M, I, S> Default for ServerBuilder<C, M, I, S> {
@@ -46,6 +136,13 @@ impl<C, M, I, S> Default for ServerBuilder<C, M, I, S> {
             mempool: None,
             info: None,
             snapshot: None,
+            options: ConnectionOptions::default(),
+            on_connection_error: None,
+            on_interrupted_block: None,
+            on_connection: None,
+            consensus_runtime: None,
+            deliver_tx_batch: None,
+            recheck_batch: None,
         }
     }
 }
@@ -90,21 +187,435 @@ where
         self
     }
 
+    /// Sets what the server does when the mempool, info or snapshot service
+    /// returns an error. Defaults to [`ErrorPolicy::Disconnect`].
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.options.error_policy = error_policy;
+        self
+    }
+
+    /// Answers errors from the mempool service with a `CheckTx` response
+    /// described by `check_tx_error`, instead of applying the error policy.
+    pub fn check_tx_error(mut self, check_tx_error: CheckTxError) -> Self {
+        self.options.check_tx_error = Some(check_tx_error);
+        self
+    }
+
+    /// If `true`, the server waits for each consensus response to resolve
+    /// before calling the consensus service with the next request, so that
+    /// consensus requests are never executed concurrently, even if the node
+    /// pipelines them. Defaults to `false`.
+    pub fn serial_consensus(mut self, serial_consensus: bool) -> Self {
+        self.options.serial_consensus = serial_consensus;
+        self
+    }
+
+    /// If `true`, the server stops reading requests after a `Commit` until the
+    /// `Commit` response has been written and flushed to the node, so that the
+    /// application has fully persisted its state before the node proceeds.
+    /// Defaults to `false`.
+    pub fn commit_barrier(mut self, commit_barrier: bool) -> Self {
+        self.options.commit_barrier = commit_barrier;
+        self
+    }
+
+    /// Warns about, and optionally closes connections on, services that stop
+    /// making progress. Disabled by default.
+    pub fn stall_detection(mut self, stall_detection: StallDetection) -> Self {
+        self.options.stall_detection = Some(stall_detection);
+        self
+    }
+
+    /// Stops accepting connections, and optionally reading info requests,
+    /// while too many requests are pending across every connection. See
+    /// [`Overload`]. Disabled by default.
+    pub fn overload(mut self, overload: Overload) -> Self {
+        self.options.overload = Some(overload);
+        self
+    }
+
+    /// Limits how many requests of each kind may be pipelined to the
+    /// component services at once. Unlimited by default.
+    pub fn pipeline_depth(mut self, pipeline_depth: PipelineDepth) -> Self {
+        self.options.pipeline_depth = pipeline_depth;
+        self
+    }
+
+    /// Limits the number of responses pending on each connection. Once the
+    /// limit is reached, the server stops reading requests until a response
+    /// has been delivered, applying backpressure to the node. Unlimited by
+    /// default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_in_flight` is zero.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        assert!(max_in_flight > 0, "max_in_flight must be nonzero");
+        self.options.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Writes responses at most `interval` after they resolve, even if the
+    /// node has not sent a `Flush`. By default, responses are buffered until
+    /// the node sends a `Flush`, or until the write buffer is full.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.options.flush_interval = Some(interval);
+        self
+    }
+
+    /// Processes up to `concurrency` `CheckTx` requests in parallel, each on a
+    /// task of its own, while still delivering their responses in order. The
+    /// mempool service must do its work in the future it returns, rather than
+    /// in `call`, for that work to run in parallel. Disabled by default.
+    pub fn check_tx_concurrency(mut self, concurrency: usize) -> Self {
+        self.options.check_tx_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Processes up to `concurrency` info requests in parallel, running each
+    /// `Query` on a task of its own, while still delivering their responses in
+    /// order. Queries are read-only, so this is safe as long as the info
+    /// service does its reads in the future it returns. Disabled by default.
+    pub fn query_concurrency(mut self, concurrency: usize) -> Self {
+        self.options.query_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Gives each request a deadline `timeout` after it is read, which
+    /// services can read with
+    /// [`deadline::current`](crate::middleware::deadline::current). Requests
+    /// have no deadline by default.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.options.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections that take longer than `timeout` to send a request
+    /// once its first bytes arrive. Disabled by default.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.options.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections on which responses can't be written for `timeout`,
+    /// as when the node stops reading them. Disabled by default.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.options.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections on which no request has been read and no response
+    /// written for `timeout`. See [`ConnectionOptions::idle_timeout`] for
+    /// which connections to set it on. Disabled by default.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.options.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends TCP keepalive probes on accepted connections idle for `time`,
+    /// so that connections to a node that went away are closed. Disabled by
+    /// default.
+    pub fn tcp_keepalive(mut self, time: Duration) -> Self {
+        self.options.tcp_keepalive = Some(time);
+        self
+    }
+
+    /// Sets the sizes of each connection's read and write buffers. See
+    /// [`BufferSizes`] for the defaults.
+    pub fn buffer_sizes(mut self, buffer_sizes: BufferSizes) -> Self {
+        self.options.buffer_sizes = buffer_sizes;
+        self
+    }
+
+    /// Decodes requests and encodes responses of at least `len` bytes on the
+    /// blocking thread pool, so that huge snapshot chunks and blocks don't
+    /// hold up the other connections. Disabled by default.
+    pub fn blocking_codec_len(mut self, len: usize) -> Self {
+        self.options.blocking_codec_len = Some(len);
+        self
+    }
+
+    /// If `true`, the server records an `INFO` event with the target
+    /// `tower_abci::summary` for each request it serves, carrying the method,
+    /// height, response code, sizes and latency as structured fields. See the
+    /// [`summary`](crate::summary) module for the fields. Defaults to `false`.
+    pub fn summary_log(mut self, summary_log: bool) -> Self {
+        self.options.summary_log = summary_log;
+        self
+    }
+
+    /// Logs requests and responses for `method`, e.g. `"CheckTx"`, at `level`
+    /// instead of `DEBUG`.
+    pub fn log_level(mut self, method: impl Into<String>, level: tracing::Level) -> Self {
+        self.options.log_levels.insert(method.into(), level);
+        self
+    }
+
+    /// Uses `options` instead of the other settings on connections of the
+    /// given `kind`, once the server has detected their kind from their first
+    /// request, e.g., to detect stalls sooner on the consensus connection.
+    pub fn kind_options(mut self, kind: Category, options: ConnectionOptions) -> Self {
+        self.options.overrides.insert(kind, options);
+        self
+    }
+
+    /// Replaces all per-connection settings at once.
+    pub fn connection_options(mut self, options: ConnectionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// A builder with the connection settings of `config`. Its listen address
+    /// is used by [`Server::listen`].
+    #[cfg(feature = "config")]
+    pub fn from_config(config: &crate::config::ServerConfig) -> Self {
+        Self::default().connection_options(config.connection_options())
+    }
+
+    /// Registers a callback invoked with the error whenever a connection fails,
+    /// in addition to the error being logged.
+    pub fn on_connection_error(
+        mut self,
+        callback: impl Fn(&ConnectionError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_connection_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Registers a callback invoked when a connection closes after a block
+    /// started executing but before its `Commit` response was sent, so that
+    /// the application can discard the partially executed block.
+    pub fn on_interrupted_block(
+        mut self,
+        callback: impl Fn(&InterruptedBlock) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_interrupted_block = Some(Arc::new(callback));
+        self
+    }
+
+    /// Registers a callback invoked with the context of each new connection
+    /// before it is served, e.g. to attach extensions for the services to
+    /// read; see the [`context`](crate::context) module.
+    pub fn on_connection(
+        mut self,
+        callback: impl Fn(&mut ConnectionContext) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_connection = Some(Arc::new(callback));
+        self
+    }
+
+    /// Serves the consensus connection on `runtime`, instead of the runtime
+    /// the server listens on, so that block execution does not compete for
+    /// worker threads with floods of `CheckTx` and `Query` requests.
+    ///
+    /// Connections read their requests up to the first one that isn't a
+    /// `Flush` on the listening runtime, and the consensus connection then
+    /// moves to `runtime` with its consensus service calls. The runtime can
+    /// run on a dedicated thread with a higher scheduling priority, set in
+    /// `on_thread_start` of its builder:
+    ///
+    /// ```no_run
+    /// # use tower_abci::{apps::NoopApp, v034::Server, BoxError};
+    /// let runtime = tokio::runtime::Builder::new_current_thread()
+    ///     .enable_all()
+    ///     .build()?;
+    /// let handle = runtime.handle().clone();
+    /// std::thread::spawn(move || runtime.block_on(std::future::pending::<()>()));
+    /// let server = Server::builder()
+    /// #   .consensus(NoopApp)
+    /// #   .mempool(NoopApp)
+    /// #   .info(NoopApp)
+    /// #   .snapshot(NoopApp)
+    ///     // ...
+    ///     .consensus_runtime(handle)
+    ///     .finish();
+    /// # Ok::<(), BoxError>(())
+    /// ```
+    ///
+    /// The socket stays registered with the listening runtime, which must
+    /// keep running. Disabled by default.
+    pub fn consensus_runtime(mut self, runtime: Handle) -> Self {
+        self.consensus_runtime = Some(runtime);
+        self
+    }
+
+    /// Delivers the transactions of consecutive `DeliverTx` requests to
+    /// `batch` in a single call, instead of calling the consensus service
+    /// with each request, to save the per-request overhead of the service.
+    ///
+    /// A batch ends at the first request of another method, usually the
+    /// `EndBlock` of the block, or once it holds `max_len` transactions.
+    /// `batch` must return one response per transaction, in order, and each
+    /// `DeliverTx` request is answered with its own response. Calls to
+    /// `batch` are made in order with the calls to the consensus service, so
+    /// if the two don't share a queue, e.g. the consensus service of
+    /// [`split::service`](crate::v034::split::service), set
+    /// [`serial_consensus`](Self::serial_consensus) to keep their effects in
+    /// order.
+    ///
+    /// The batched requests bypass the consensus service, and the layers on
+    /// it. The `WalLayer` of [`middleware`](crate::middleware) and its
+    /// [`HaltLayer`](crate::middleware::halt::HaltLayer) would miss the
+    /// transactions of each block, so they fail every consensus request of
+    /// the server instead, and its
+    /// [`MirrorLayer`](crate::middleware::mirror::MirrorLayer) mirrors the
+    /// blocks without their transactions. Disabled by default.
+    pub fn deliver_tx_batch<B>(mut self, max_len: usize, batch: B) -> Self
+    where
+        B: Service<Vec<request::DeliverTx>, Response = Vec<response::DeliverTx>, Error = BoxError>
+            + Send
+            + Clone
+            + 'static,
+        B::Future: Send + 'static,
+    {
+        self.deliver_tx_batch = Some(Batching::new(
+            max_len,
+            batch,
+            "DeliverTx batch service readiness",
+        ));
+        self
+    }
+
+    /// Checks the transactions of consecutive `CheckTx` requests of the
+    /// `Recheck` kind with `batch` in a single call, instead of calling the
+    /// mempool service with each request, to save the per-request overhead
+    /// of the service when the node rechecks its mempool after each block.
+    ///
+    /// A batch ends at the first other request, usually the `Flush` the node
+    /// sends after its rechecks, or once it holds `max_len` transactions.
+    /// `batch` must return one response per transaction, in order, and each
+    /// request is answered with its own response. Batched rechecks don't
+    /// count towards [`check_tx_concurrency`](Self::check_tx_concurrency),
+    /// and an error of `batch` fails the connection, whatever the
+    /// [`error_policy`](Self::error_policy). Disabled by default.
+    pub fn recheck_batch<B>(mut self, max_len: usize, batch: B) -> Self
+    where
+        B: Service<Vec<request::CheckTx>, Response = Vec<response::CheckTx>, Error = BoxError>
+            + Send
+            + Clone
+            + 'static,
+        B::Future: Send + 'static,
+    {
+        self.recheck_batch = Some(Batching::new(
+            max_len,
+            batch,
+            "recheck batch service readiness",
+        ));
+        self
+    }
+
     pub fn finish(self) -> Option<Server<C, M, I, S>> {
         let consensus = self.consensus?;
         let mempool = self.mempool?;
         let info = self.info?;
         let snapshot = self.snapshot?;
+        let health = health_check(
+            consensus.clone(),
+            mempool.clone(),
+            info.clone(),
+            snapshot.clone(),
+        );
 
         Some(Server {
             consensus,
             mempool,
             info,
             snapshot,
+            on_connection_error: self.on_connection_error,
+            on_interrupted_block: self.on_interrupted_block,
+            on_connection: self.on_connection,
+            consensus_runtime: self.consensus_runtime,
+            deliver_tx_batch: self.deliver_tx_batch,
+            recheck_batch: self.recheck_batch,
+            handle: ServerHandle::new("0.34", self.options, health),
         })
     }
 }
 
+/// Checks the health of the component services; see the
+/// [`health`](crate::health) module.
+fn health_check<C, M, I, S>(consensus: C, mempool: M, info: I, snapshot: S) -> HealthCheck
+where
+    C: Service<ConsensusRequest, Response = ConsensusResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    C::Future: Send + 'static,
+    M: Service<MempoolRequest, Response = MempoolResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    M::Future: Send + 'static,
+    I: Service<InfoRequest, Response = InfoResponse, Error = BoxError> + Send + Clone + 'static,
+    I::Future: Send + 'static,
+    S: Service<SnapshotRequest, Response = SnapshotResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    // The services need not be `Sync`, while the check is shared.
+    let services = std::sync::Mutex::new((consensus, mempool, info, snapshot));
+    HealthCheck::new(move |timeout| {
+        let (consensus, mempool, info, snapshot) = services.lock().unwrap().clone();
+        let consensus = health::timed(
+            Category::Consensus,
+            "ready",
+            timeout,
+            ServiceExt::<ConsensusRequest>::ready_oneshot(consensus).map_ok(drop),
+        );
+        let mempool = health::timed(
+            Category::Mempool,
+            "ready",
+            timeout,
+            ServiceExt::<MempoolRequest>::ready_oneshot(mempool).map_ok(drop),
+        );
+        let echo = health::timed(Category::Info, "echo", timeout, {
+            let echo = info.clone().oneshot(InfoRequest::Echo(request::Echo {
+                message: health::ECHO_MESSAGE.to_string(),
+            }));
+            async move {
+                match echo.await? {
+                    // The answer may be customized, e.g. with `EchoLayer`.
+                    InfoResponse::Echo(_) => Ok(()),
+                    _ => Err("unexpected response to Echo".into()),
+                }
+            }
+        });
+        let info = health::timed(Category::Info, "info", timeout, {
+            let info = info.oneshot(InfoRequest::Info(request::Info {
+                version: String::new(),
+                block_version: 11,
+                p2p_version: 8,
+                abci_version: ABCI_VERSION.to_string(),
+            }));
+            async move {
+                match info.await? {
+                    InfoResponse::Info(_) => Ok(()),
+                    _ => Err("unexpected response to Info".into()),
+                }
+            }
+        });
+        let snapshot = health::timed(Category::Snapshot, "list_snapshots", timeout, {
+            let list = snapshot.oneshot(SnapshotRequest::ListSnapshots);
+            async move {
+                match list.await? {
+                    SnapshotResponse::ListSnapshots(_) => Ok(()),
+                    _ => Err("unexpected response to ListSnapshots".into()),
+                }
+            }
+        });
+        async move {
+            let (consensus, mempool, echo, info, snapshot) =
+                futures::join!(consensus, mempool, echo, info, snapshot);
+            HealthReport {
+                components: vec![consensus, mempool, echo, info, snapshot],
+            }
+        }
+        .boxed()
+    })
+}
+
 impl<C, M, I, S> Server<C, M, I, S>
 where
     C: Service<ConsensusRequest, Response = ConsensusResponse, Error = BoxError>
@@ -129,55 +640,206 @@ where
         ServerBuilder::default()
     }
 
-    #[cfg(target_family = "unix")]
-    pub async fn listen_unix(self, path: impl AsRef<Path>) -> Result<(), BoxError> {
-        let listener = UnixListener::bind(path)?;
-        let addr = listener.local_addr()?;
+    /// Returns a handle for inspecting the server's connections, closing
+    /// them, and changing their settings while it is listening.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Sends synthetic requests through each component service, reporting
+    /// their status and latency, as with
+    /// [`ServerHandle::health_check`].
+    pub async fn health_check(&self) -> HealthReport {
+        self.handle.health_check().await
+    }
+
+    /// Returns a check that the server is listening and that its services
+    /// are ready, e.g. for [`probe::listen_tcp`](crate::probe::listen_tcp).
+    pub fn readiness(&self) -> Readiness {
+        // The services need not be `Sync`, while the check is shared.
+        let services = std::sync::Mutex::new((
+            self.consensus.clone(),
+            self.mempool.clone(),
+            self.info.clone(),
+            self.snapshot.clone(),
+        ));
+        Readiness::new(self.handle.clone(), move |timeout| {
+            let (consensus, mempool, info, snapshot) = services.lock().unwrap().clone();
+            async move {
+                probe::ready::<_, ConsensusRequest>("consensus", consensus, timeout).await?;
+                probe::ready::<_, MempoolRequest>("mempool", mempool, timeout).await?;
+                probe::ready::<_, InfoRequest>("info", info, timeout).await?;
+                probe::ready::<_, SnapshotRequest>("snapshot", snapshot, timeout).await
+            }
+            .boxed()
+        })
+    }
+
+    /// Serves a connection opened by the caller over the given halves of a
+    /// stream, on a new task, as if it had been accepted by a listener.
+    ///
+    /// This is how applications built without the `net` feature serve the
+    /// node, over a transport of their own.
+    pub fn accept(
+        &self,
+        read: impl AsyncReadExt + std::marker::Unpin + Send + 'static,
+        write: impl AsyncWriteExt + std::marker::Unpin + Send + 'static,
+    ) {
+        self.spawn_connection(None, read, write);
+    }
+
+    /// Spawns a task serving a connection over the given halves of a socket.
+    pub(crate) fn spawn_connection(
+        &self,
+        peer: Option<PeerAddr>,
+        read: impl AsyncReadExt + std::marker::Unpin + Send + 'static,
+        write: impl AsyncWriteExt + std::marker::Unpin + Send + 'static,
+    ) {
+        let conn = Connection {
+            id: request_id::next_connection_id(),
+            consensus: self.consensus.clone(),
+            mempool: self.mempool.clone(),
+            info: self.info.clone(),
+            snapshot: self.snapshot.clone(),
+            on_interrupted_block: self.on_interrupted_block.clone(),
+            on_connection: self.on_connection.clone(),
+            peer,
+            consensus_runtime: self.consensus_runtime.clone(),
+            deliver_tx_batch: self.deliver_tx_batch.clone(),
+            recheck_batch: self.recheck_batch.clone(),
+            handle: self.handle.clone(),
+        };
+        let on_error = self.on_connection_error.clone();
+        let span = tracing::info_span!(
+            "abci_connection",
+            id = conn.id,
+            kind = tracing::field::Empty
+        );
+        metrics::connection_accepted();
+        task::spawn(
+            &format!("abci-connection-{}", conn.id),
+            async move {
+                let result = conn.run(read, write).await;
+                if let Err(e) = result {
+                    tracing::error!(error = %e, "connection failed");
+                    if let Some(on_error) = on_error {
+                        on_error(&e);
+                    }
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Listens on `address`, e.g. the listen address of a
+    /// [`ServerConfig`](crate::config::ServerConfig).
+    #[cfg(all(feature = "config", feature = "net"))]
+    pub async fn listen(self, address: &crate::config::ListenAddress) -> Result<(), BoxError> {
+        use crate::config::ListenAddress;
+
+        match address {
+            ListenAddress::Tcp { address } => self.listen_tcp(address.as_str()).await,
+            #[cfg(target_family = "unix")]
+            ListenAddress::Unix { path } => self.listen_unix(path).await,
+            #[cfg(not(target_family = "unix"))]
+            ListenAddress::Unix { .. } => {
+                Err("unix domain sockets are not supported on this platform".into())
+            }
+        }
+    }
+
+    #[cfg(all(target_family = "unix", feature = "net"))]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "abci_accept_loop",
+            skip_all,
+            fields(transport = "uds", addr = tracing::field::Empty)
+        )
+    )]
+    pub async fn listen_unix(self, path: impl AsRef<std::path::Path>) -> Result<(), BoxError> {
+        let listener = self
+            .handle
+            .fail_on_error(tokio::net::UnixListener::bind(path))?;
+        let addr = self.handle.fail_on_error(listener.local_addr())?;
+        tracing::Span::current().record("addr", tracing::field::debug(&addr));
         tracing::info!(?addr, "ABCI server starting on uds");
+        self.handle
+            .set_listening(LocalAddr::Unix(addr.as_pathname().map(Into::into)));
 
         loop {
-            match listener.accept().await {
-                Ok((socket, _addr)) => {
-                    tracing::debug!(?_addr, "accepted new connection");
-                    let conn = Connection {
-                        consensus: self.consensus.clone(),
-                        mempool: self.mempool.clone(),
-                        info: self.info.clone(),
-                        snapshot: self.snapshot.clone(),
-                    };
+            let overload = self.handle.options().overload;
+            let accepted = select! {
+                accepted = listener.accept() => accepted,
+                () = self.handle.overloaded(overload) => {
+                    self.handle.unloaded(overload, "accepting").await;
+                    continue;
+                }
+                () = self.handle.drained() => {
+                    self.handle.stop_listening();
+                    tracing::info!("no longer accepting connections");
+                    return Ok(());
+                }
+            };
+            match accepted {
+                Ok((socket, addr)) => {
+                    tracing::debug!(?addr, "accepted new connection");
+                    let peer = PeerAddr::Unix(addr.as_pathname().map(Into::into));
                     let (read, write) = socket.into_split();
-                    tokio::spawn(async move { conn.run(read, write).await.unwrap() });
+                    self.spawn_connection(Some(peer), read, write);
                 }
                 Err(e) => {
                     tracing::error!({ %e }, "error accepting new connection");
+                    metrics::accept_error();
                 }
             }
         }
     }
 
+    #[cfg(feature = "net")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "abci_accept_loop",
+            skip_all,
+            fields(transport = "tcp", addr = tracing::field::Empty)
+        )
+    )]
     pub async fn listen_tcp<A: ToSocketAddrs + std::fmt::Debug>(
         self,
         addr: A,
     ) -> Result<(), BoxError> {
-        let listener = TcpListener::bind(addr).await?;
-        let addr = listener.local_addr()?;
+        let listener = self.handle.fail_on_error(TcpListener::bind(addr).await)?;
+        let addr = self.handle.fail_on_error(listener.local_addr())?;
+        tracing::Span::current().record("addr", tracing::field::display(&addr));
         tracing::info!(?addr, "ABCI server starting on tcp socket");
+        self.handle.set_listening(LocalAddr::Tcp(addr));
 
         loop {
-            match listener.accept().await {
-                Ok((socket, _addr)) => {
-                    tracing::debug!(?_addr, "accepted new connection");
-                    let conn = Connection {
-                        consensus: self.consensus.clone(),
-                        mempool: self.mempool.clone(),
-                        info: self.info.clone(),
-                        snapshot: self.snapshot.clone(),
-                    };
+            let overload = self.handle.options().overload;
+            let accepted = select! {
+                accepted = listener.accept() => accepted,
+                () = self.handle.overloaded(overload) => {
+                    self.handle.unloaded(overload, "accepting").await;
+                    continue;
+                }
+                () = self.handle.drained() => {
+                    self.handle.stop_listening();
+                    tracing::info!("no longer accepting connections");
+                    return Ok(());
+                }
+            };
+            match accepted {
+                Ok((socket, addr)) => {
+                    tracing::debug!(?addr, "accepted new connection");
+                    self.handle.options().apply_tcp_keepalive(&socket);
+                    let peer = PeerAddr::Tcp(addr);
                     let (read, write) = socket.into_split();
-                    tokio::spawn(async move { conn.run(read, write).await.unwrap() });
+                    self.spawn_connection(Some(peer), read, write);
                 }
                 Err(e) => {
                     tracing::error!({ %e }, "error accepting new connection");
+                    metrics::accept_error();
                 }
             }
         }
@@ -185,10 +847,18 @@ where
 }
 
 struct Connection<C, M, I, S> {
+    id: u64,
+    peer: Option<PeerAddr>,
     consensus: C,
     mempool: M,
     info: I,
     snapshot: S,
+    on_interrupted_block: Option<InterruptedBlockCallback>,
+    on_connection: Option<ConnectionCallback>,
+    consensus_runtime: Option<Handle>,
+    deliver_tx_batch: Option<Batching<request::DeliverTx, response::DeliverTx>>,
+    recheck_batch: Option<Batching<request::CheckTx, response::CheckTx>>,
+    handle: ServerHandle,
 }
 
 impl<C, M, I, S> Connection<C, M, I, S>
@@ -202,81 +872,879 @@ where
     S: Service<SnapshotRequest, Response = SnapshotResponse, Error = BoxError> + Send + 'static,
     S::Future: Send + 'static,
 {
-    // XXX handle errors gracefully
-    // figure out how / if to return errors to tendermint
+    // Errors from the non-consensus services are handled according to the
+    // connection's `ErrorPolicy`; any error that reaches this loop is fatal.
     async fn run(
+        self,
+        read: impl AsyncReadExt + std::marker::Unpin + Send + 'static,
+        write: impl AsyncWriteExt + std::marker::Unpin + Send + 'static,
+    ) -> Result<(), ConnectionError> {
+        let id = self.id;
+        let on_interrupted_block = self.on_interrupted_block.clone();
+        let registration = self
+            .handle
+            .register(id, self.peer.clone(), self.on_connection.as_ref());
+        let read_capacity = registration.options().borrow().buffer_sizes.read_capacity;
+        let request_stream = DecodeRead::with_capacity(read, read_capacity);
+        let response_sink = EncodeWrite::<_, pb::Response>::new(write);
+        let (result, progress) = match self.consensus_runtime.clone() {
+            Some(runtime) => {
+                self.serve_detected(runtime, registration, request_stream, response_sink)
+                    .await
+            }
+            None => {
+                let mut progress = Progress::default();
+                let result = self
+                    .serve(
+                        &mut progress,
+                        &registration,
+                        request_stream,
+                        response_sink,
+                        None,
+                    )
+                    .await;
+                (result, progress)
+            }
+        };
+        if let (Some(_), Some(last_method)) = (progress.block_started, progress.method) {
+            let interrupted = InterruptedBlock {
+                connection: id,
+                height: progress.height,
+                last_method,
+            };
+            tracing::warn!(?interrupted, "connection closed during block execution");
+            if let Some(on_interrupted_block) = on_interrupted_block {
+                on_interrupted_block(&interrupted);
+            }
+        }
+        let result = result.map_err(|e| {
+            ConnectionError::new(id, progress.kind, progress.method, progress.height, e)
+        });
+        metrics::connection_closed(progress.kind, &result);
+        result
+    }
+
+    /// Reads requests up to the first one that isn't a `Flush`, and serves
+    /// the connection on `runtime` if that request reveals it is the
+    /// consensus connection, or here otherwise.
+    async fn serve_detected<R, W>(
+        self,
+        runtime: Handle,
+        registration: Registration,
+        mut request_stream: DecodeRead<R, pb::Request>,
+        mut response_sink: EncodeWrite<W, pb::Response>,
+    ) -> (Result<(), BoxError>, Progress)
+    where
+        R: AsyncReadExt + std::marker::Unpin + Send + 'static,
+        W: AsyncWriteExt + std::marker::Unpin + Send + 'static,
+    {
+        let mut progress = Progress::default();
+        let first = match read_first_request(&mut request_stream, &mut response_sink).await {
+            Ok(first) => first,
+            Err(e) => return (Err(e), progress),
+        };
+        let kind = first
+            .clone()
+            .and_then(|proto| Request::try_from(proto).ok())
+            .and_then(|request| Category::of(&request.kind()));
+        if kind != Some(Category::Consensus) {
+            let result = self
+                .serve(
+                    &mut progress,
+                    &registration,
+                    request_stream,
+                    response_sink,
+                    first,
+                )
+                .await;
+            return (result, progress);
+        }
+
+        tracing::info!("serving consensus connection on its own runtime");
+        let name = format!("abci-consensus-{}", self.id);
+        let served = task::Scoped::spawn_on(
+            &name,
+            &runtime,
+            async move {
+                let result = self
+                    .serve(
+                        &mut progress,
+                        &registration,
+                        request_stream,
+                        response_sink,
+                        first,
+                    )
+                    .await;
+                (result, progress)
+            }
+            .in_current_span(),
+        )
+        .await;
+        match served {
+            Ok(served) => served,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => {
+                let progress = Progress {
+                    kind: Some(Category::Consensus),
+                    ..Progress::default()
+                };
+                (Err("the consensus runtime shut down".into()), progress)
+            }
+        }
+    }
+
+    async fn serve<R, W>(
         mut self,
-        read: impl AsyncReadExt + std::marker::Unpin,
-        write: impl AsyncWriteExt + std::marker::Unpin,
-    ) -> Result<(), BoxError> {
+        progress: &mut Progress,
+        registration: &Registration,
+        mut request_stream: DecodeRead<R, pb::Request>,
+        mut response_sink: EncodeWrite<W, pb::Response>,
+        mut first: Option<pb::Request>,
+    ) -> Result<(), BoxError>
+    where
+        R: AsyncReadExt + std::marker::Unpin,
+        W: AsyncWriteExt + std::marker::Unpin,
+    {
         tracing::info!("listening for requests");
 
-        use tendermint_proto::v0_34::abci as pb;
-
-        let (mut request_stream, mut response_sink) = {
-            use crate::v034::codec::{Decode, Encode};
-            (
-                FramedRead::new(read, Decode::<pb::Request>::default()),
-                FramedWrite::new(write, Encode::<pb::Response>::default()),
-            )
-        };
+        let mut responses = ResponseQueue::new();
+        let mut in_flight = registration.in_flight();
+        let mut close = registration.close_signal();
+        let mut options_watch = registration.options();
+        // The settings for every connection, and the ones that apply to this
+        // connection once its kind is known.
+        let mut shared_options = options_watch.borrow_and_update().clone();
+        let mut options = shared_options.clone();
 
-        let mut responses = FuturesOrdered::new();
+        apply_codec_options(&mut request_stream, &mut response_sink, &options);
+        let mut stall = StallDetector::new(options.stall_detection);
+        let mut flush_timer = FlushTimer::new(options.flush_interval);
+        let mut idle = IdleTimer::new(options.idle_timeout);
+        let mut sequence = 0;
+        let mut closing = false;
+        // The requests held for the next batch.
+        let mut batch = Vec::new();
 
         loop {
+            if closing {
+                // The requests held for a batch were read from the node, and
+                // are answered like the others before closing.
+                self.dispatch_batch(&mut batch, &mut stall, &mut in_flight, &mut responses)
+                    .await?;
+            }
+            if closing && responses.is_empty() {
+                response_sink.flush().await?;
+                tracing::info!("closing connection on request");
+                return Ok(());
+            }
+            // Stop reading requests while the in-flight limit is reached; the
+            // response branch below keeps draining until there is room again.
+            let accepting = !closing
+                && options
+                    .max_in_flight
+                    .is_none_or(|max| responses.len() < max.max(1));
+            // While the server is overloaded, info connections may wait
+            // before reading, to leave room for consensus.
+            let throttled = progress.kind == Some(Category::Info)
+                && options
+                    .overload
+                    .is_some_and(|overload| overload.throttle_info);
             select! {
-                req = request_stream.next() => {
+                req = async {
+                    if throttled {
+                        self.handle.unloaded(options.overload, "reading info requests").await;
+                    }
+                    next_request(&mut first, &mut request_stream).await
+                }, if accepting => {
                     let proto = match req.transpose()? {
                         Some(proto) => proto,
                         None => return Ok(()),
                     };
+                    let frame = request_stream.take_frame();
+                    idle.activity();
+                    let received = Instant::now();
+                    let size = proto.encoded_len();
                     let request = Request::try_from(proto)?;
-                    tracing::debug!(?request, "new request");
-                    match request.kind() {
-                        MethodKind::Consensus => {
-                            let request = request.try_into().expect("checked kind");
-                            let response = self.consensus.ready().await?.call(request);
-                            // Need to box here for type erasure
-                            responses.push_back(response.map_ok(Response::from).boxed());
+                    if progress.kind.is_none() {
+                        // The node opens one connection per category, so the
+                        // first request that isn't a Flush reveals its kind.
+                        if let Some(kind) = Category::of(&request.kind()) {
+                            tracing::Span::current().record("kind", kind.name());
+                            tracing::info!(kind = kind.name(), "detected connection kind");
+                            progress.kind = Some(kind);
+                            registration.set_kind(kind);
+                            options = shared_options.for_kind(Some(kind)).clone();
+                            stall.reconfigure(options.stall_detection);
+                            flush_timer.reconfigure(options.flush_interval);
+                            idle.reconfigure(options.idle_timeout);
+                            apply_codec_options(
+                                &mut request_stream,
+                                &mut response_sink,
+                                &options,
+                            );
                         }
-                        MethodKind::Mempool => {
-                            let request = request.try_into().expect("checked kind");
-                            let response = self.mempool.ready().await?.call(request);
-                            responses.push_back(response.map_ok(Response::from).boxed());
+                    }
+                    let deadline = options
+                        .request_timeout
+                        .map(|timeout| tokio::time::Instant::now() + timeout);
+                    metrics::frame_received(progress.kind, size);
+                    let id = RequestId::new(self.id, sequence);
+                    sequence += 1;
+                    let method = request.method();
+                    let height = request.height();
+                    progress.method = Some(method);
+                    progress.height = height.or(progress.height);
+                    match method {
+                        "BeginBlock" | "FinalizeBlock" => {
+                            progress.block_started.get_or_insert_with(Instant::now);
                         }
-                        MethodKind::Snapshot => {
-                            let request = request.try_into().expect("checked kind");
-                            let response = self.snapshot.ready().await?.call(request);
-                            responses.push_back(response.map_ok(Response::from).boxed());
+                        "Commit" => progress.commit_started = Some(Instant::now()),
+                        _ => {}
+                    }
+                    let span = tracing::debug_span!("request", %id, method);
+                    span.in_scope(|| redact::log_request(options.log_level(method), &request));
+                    let batch_len = match &request {
+                        Request::DeliverTx(_) => self.deliver_tx_batch.as_ref().map(|b| b.max_len),
+                        Request::CheckTx(check_tx) if check_tx.kind == CheckTxKind::Recheck => {
+                            self.recheck_batch.as_ref().map(|b| b.max_len)
                         }
-                        MethodKind::Info => {
-                            let request = request.try_into().expect("checked kind");
-                            let response = self.info.ready().await?.call(request);
-                            responses.push_back(response.map_ok(Response::from).boxed());
+                        _ => None,
+                    };
+                    // A batch ends at the first request of another method, or
+                    // once it is full.
+                    let same_method = batch
+                        .first()
+                        .is_none_or(|(_, pending, _): &(_, Pending, _)| pending.method == method);
+                    let request = match batch_len.filter(|_| same_method) {
+                        Some(max_len) => {
+                            let pending = Pending {
+                                id,
+                                category: Category::of(&request.kind())
+                                    .expect("batched requests have a category"),
+                                method,
+                                height,
+                                size,
+                                received,
+                            };
+                            batch.push((request, pending, span.clone()));
+                            if batch.len() < max_len {
+                                continue;
+                            }
+                            None
                         }
-                        MethodKind::Flush => {
+                        None => Some(request),
+                    };
+                    self.dispatch_batch(&mut batch, &mut stall, &mut in_flight, &mut responses)
+                        .await?;
+                    let Some(request) = request else {
+                        continue;
+                    };
+                    let category = match Category::of(&request.kind()) {
+                        Some(category) => category,
+                        None => {
                             // Instead of propagating Flush requests to the application,
                             // handle them here by awaiting all pending responses.
+                            let flush_started = Instant::now();
                             tracing::debug!(responses.len = responses.len(), "flushing responses");
-                            while let Some(response) = responses.next().await {
-                                // XXX: sometimes we might want to send errors to tendermint
-                                // https://docs.tendermint.com/v0.32/spec/abci/abci.html#errors
-                                tracing::debug!(?response, "flushing response");
-                                response_sink.send(response?.into()).await?;
+                            while let Some(response) =
+                                stall.watch("response", &in_flight, responses.next()).await?
+                            {
+                                let pending = in_flight.pop();
+                                send_response(
+                                    &mut response_sink,
+                                    progress,
+                                    &options,
+                                    pending,
+                                    response,
+                                )
+                                .await?;
                             }
                             // Now we need to tell Tendermint we've flushed responses
-                            response_sink.send(Response::Flush.into()).await?;
+                            let flush = pb::Response::from(Response::Flush);
+                            metrics::frame_sent(progress.kind, flush.encoded_len());
+                            response_sink.send(flush).await?;
+                            flush_timer.flushed();
+                            metrics::flush(progress.kind, flush_started.elapsed());
+                            continue;
+                        }
+                    };
+                    // Serial consensus and the concurrency settings limit
+                    // the pending requests of a category, like its depth.
+                    let limit = match category {
+                        Category::Consensus if options.serial_consensus => Some(1),
+                        Category::Mempool => options.check_tx_concurrency,
+                        Category::Info => options.query_concurrency,
+                        _ => None,
+                    };
+                    let depth = match (limit, options.pipeline_depth.get(category)) {
+                        (Some(limit), Some(depth)) => Some(limit.min(depth)),
+                        (limit, depth) => limit.or(depth),
+                    };
+                    if let Some(depth) = depth {
+                        // Don't call the service again until enough of its
+                        // previous responses have resolved.
+                        while in_flight.get(category) >= depth.max(1) {
+                            let response = stall
+                                .watch("response", &in_flight, responses.next())
+                                .await?
+                                .expect("in-flight responses are queued");
+                            let pending = in_flight.pop();
+                            send_response(
+                                &mut response_sink,
+                                progress,
+                                &options,
+                                pending,
+                                response,
+                            )
+                            .await?;
+                            flush_timer.buffered();
                         }
                     }
+                    let is_commit = matches!(request, Request::Commit);
+                    let response = match category {
+                        Category::Consensus => {
+                            let request = request.try_into().expect("checked kind");
+                            let ready = self.consensus.ready();
+                            let service = stall
+                                .watch("consensus service readiness", &in_flight, ready)
+                                .await??;
+                            let batched = self.deliver_tx_batch.is_some();
+                            let response = span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || {
+                                        deadline::scope(deadline, || {
+                                            batch::scope(batched, || service.call(request))
+                                        })
+                                    })
+                                })
+                            });
+                            ResponseFuture::Consensus { future: response }
+                        }
+                        Category::Mempool => {
+                            let request: MempoolRequest = request.try_into().expect("checked kind");
+                            let MempoolRequest::CheckTx(check_tx) = &request;
+                            let kind = check_tx.kind;
+                            metrics::check_tx_request(check_tx);
+                            let ready = self.mempool.ready();
+                            let service = stall
+                                .watch("mempool service readiness", &in_flight, ready)
+                                .await??;
+                            let response = span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || {
+                                        deadline::scope(deadline, || service.call(request))
+                                    })
+                                })
+                            });
+                            let response = ResponseFuture::Mempool {
+                                future: response,
+                                kind,
+                                policy: options.error_policy,
+                                check_tx_error: options.check_tx_error.clone(),
+                            };
+                            match options.check_tx_concurrency {
+                                Some(_) => ResponseFuture::Spawned {
+                                    task: task::Scoped::spawn(
+                                        "abci-check-tx",
+                                        response.instrument(span.clone()),
+                                    ),
+                                },
+                                None => response,
+                            }
+                        }
+                        Category::Snapshot => {
+                            let request = request.try_into().expect("checked kind");
+                            let ready = self.snapshot.ready();
+                            let service = stall
+                                .watch("snapshot service readiness", &in_flight, ready)
+                                .await??;
+                            let response = span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || {
+                                        deadline::scope(deadline, || service.call(request))
+                                    })
+                                })
+                            });
+                            ResponseFuture::Snapshot {
+                                future: response,
+                                policy: options.error_policy,
+                                method,
+                            }
+                        }
+                        Category::Info => {
+                            let request = request.try_into().expect("checked kind");
+                            let ready = self.info.ready();
+                            let service = stall
+                                .watch("info service readiness", &in_flight, ready)
+                                .await??;
+                            let response = span.in_scope(|| {
+                                RequestId::scope(Some(id), || {
+                                    raw::scope(frame, || {
+                                        deadline::scope(deadline, || service.call(request))
+                                    })
+                                })
+                            });
+                            let response = ResponseFuture::Info {
+                                future: response,
+                                policy: options.error_policy,
+                                method,
+                            };
+                            match options.query_concurrency {
+                                Some(_) if method == "Query" => ResponseFuture::Spawned {
+                                    task: task::Scoped::spawn(
+                                        "abci-query",
+                                        response.instrument(span.clone()),
+                                    ),
+                                },
+                                _ => response,
+                            }
+                        }
+                    };
+                    if responses.is_empty() {
+                        stall.progress();
+                    }
+                    in_flight.push(Pending {
+                        id,
+                        category,
+                        method,
+                        height,
+                        size,
+                        received,
+                    });
+                    responses.push_back(response.instrument(span));
+                    if is_commit && options.commit_barrier {
+                        // Deliver everything up to and including the Commit
+                        // response before reading any further requests.
+                        tracing::debug!(responses.len = responses.len(), "waiting for commit");
+                        while let Some(response) =
+                            stall.watch("response", &in_flight, responses.next()).await?
+                        {
+                            let pending = in_flight.pop();
+                            send_response(
+                                &mut response_sink,
+                                progress,
+                                &options,
+                                pending,
+                                response,
+                            )
+                            .await?;
+                        }
+                        response_sink.flush().await?;
+                        flush_timer.flushed();
+                    }
                 }
                 rsp = responses.next(), if !responses.is_empty() => {
                     let response = rsp.expect("didn't poll when responses was empty");
-                    // XXX: sometimes we might want to send errors to tendermint
-                    // https://docs.tendermint.com/v0.32/spec/abci/abci.html#errors
-                    tracing::debug!(?response, "sending response");
-                    response_sink.send(response?.into()).await?;
+                    let pending = in_flight.pop();
+                    stall.progress();
+                    idle.activity();
+                    send_response(&mut response_sink, progress, &options, pending, response)
+                        .await?;
+                    // Send the responses that completed behind this one
+                    // without going back through the select.
+                    while let Some(response) = responses.pop_completed() {
+                        let pending = in_flight.pop();
+                        send_response(&mut response_sink, progress, &options, pending, response)
+                            .await?;
+                    }
+                    flush_timer.buffered();
+                }
+                () = flush_timer.expired() => {
+                    tracing::trace!("flushing responses after the flush interval");
+                    response_sink.flush().await?;
+                    flush_timer.flushed();
+                }
+                () = stall.expired(), if !responses.is_empty() => {
+                    stall.stalled("response", &in_flight)?;
+                }
+                // A connection waiting on the application isn't idle; the
+                // stall detection covers it.
+                () = idle.expired(), if responses.is_empty() => {
+                    return Err(idle.error());
+                }
+                Ok(()) = options_watch.changed() => {
+                    shared_options = options_watch.borrow_and_update().clone();
+                    options = shared_options.for_kind(progress.kind).clone();
+                    stall.reconfigure(options.stall_detection);
+                    flush_timer.reconfigure(options.flush_interval);
+                    idle.reconfigure(options.idle_timeout);
+                    apply_codec_options(&mut request_stream, &mut response_sink, &options);
+                    tracing::debug!("applying updated connection options");
+                }
+                () = close.requested(), if !closing => {
+                    // Stop reading requests, but deliver the pending responses.
+                    tracing::debug!(responses.len = responses.len(), "asked to close");
+                    closing = true;
+                }
+            }
+        }
+    }
+
+    /// Calls the batch service with the requests held in `batch`, if any, and
+    /// queues the response of each request of the batch in its place.
+    async fn dispatch_batch(
+        &mut self,
+        batch: &mut Vec<(Request, Pending, tracing::Span)>,
+        stall: &mut StallDetector,
+        in_flight: &mut InFlight,
+        responses: &mut Responses<C::Future, M::Future, I::Future, S::Future>,
+    ) -> Result<(), BoxError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let (requests, mut pending): (Vec<_>, Vec<_>) = batch
+            .drain(..)
+            .map(|(request, pending, span)| (request, (pending, span)))
+            .unzip();
+        let (lead, lead_span) = pending.remove(0);
+        let future = match (lead.method, &mut self.deliver_tx_batch) {
+            ("DeliverTx", Some(batching)) => {
+                let txs = requests
+                    .into_iter()
+                    .filter_map(|request| match request {
+                        Request::DeliverTx(deliver_tx) => Some(deliver_tx),
+                        _ => None,
+                    })
+                    .collect();
+                batching
+                    .call(stall, in_flight, &lead, &lead_span, txs)
+                    .await?
+                    .map_ok(|responses| responses.into_iter().map(Response::DeliverTx).collect())
+                    .boxed()
+            }
+            _ => {
+                let batching = self
+                    .recheck_batch
+                    .as_mut()
+                    .expect("only DeliverTx requests and rechecks are batched");
+                let txs = requests
+                    .into_iter()
+                    .filter_map(|request| match request {
+                        Request::CheckTx(check_tx) => Some(check_tx),
+                        _ => None,
+                    })
+                    .collect();
+                batching
+                    .call(stall, in_flight, &lead, &lead_span, txs)
+                    .await?
+                    .map_ok(|responses| {
+                        responses
+                            .into_iter()
+                            .map(|check_tx| {
+                                metrics::check_tx_response(CheckTxKind::Recheck, &check_tx);
+                                Response::CheckTx(check_tx)
+                            })
+                            .collect()
+                    })
+                    .boxed()
+            }
+        };
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            pending.iter().map(|_| oneshot::channel()).unzip();
+        if responses.is_empty() {
+            stall.progress();
+        }
+        let batch_method = lead.method;
+        in_flight.push(lead);
+        responses.push_back(
+            ResponseFuture::BatchLead {
+                method: batch_method,
+                future,
+                rest: senders,
+            }
+            .instrument(lead_span),
+        );
+        for ((pending, span), receiver) in pending.into_iter().zip(receivers) {
+            in_flight.push(pending);
+            responses.push_back(
+                ResponseFuture::Batched {
+                    method: batch_method,
+                    receiver,
+                }
+                .instrument(span),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The pending responses of a connection, in the order of their requests.
+type Responses<C, M, I, S> =
+    ResponseQueue<tracing::instrument::Instrumented<ResponseFuture<C, M, I, S>>>;
+
+/// The response to a request, from the service of its category.
+///
+/// The futures of the four services have different types, so the responses
+/// queued on a connection are one of these variants rather than boxed.
+#[pin_project(project = ResponseFutureProj)]
+enum ResponseFuture<C, M, I, S> {
+    Consensus {
+        #[pin]
+        future: C,
+    },
+    Mempool {
+        #[pin]
+        future: M,
+        kind: CheckTxKind,
+        policy: ErrorPolicy,
+        check_tx_error: Option<CheckTxError>,
+    },
+    Info {
+        #[pin]
+        future: I,
+        policy: ErrorPolicy,
+        method: &'static str,
+    },
+    Snapshot {
+        #[pin]
+        future: S,
+        policy: ErrorPolicy,
+        method: &'static str,
+    },
+    /// One of the others, running on a task of its own.
+    Spawned {
+        #[pin]
+        task: task::Scoped<Result<Response, BoxError>>,
+    },
+    /// The first request of a batch, which hands the responses to the others
+    /// of the batch to them.
+    BatchLead {
+        method: &'static str,
+        future: BoxFuture<'static, Result<Vec<Response>, BoxError>>,
+        rest: Vec<oneshot::Sender<Response>>,
+    },
+    /// A request of a batch other than the first.
+    Batched {
+        method: &'static str,
+        receiver: oneshot::Receiver<Response>,
+    },
+}
+
+impl<C, M, I, S> Future for ResponseFuture<C, M, I, S>
+where
+    C: Future<Output = Result<ConsensusResponse, BoxError>>,
+    M: Future<Output = Result<MempoolResponse, BoxError>>,
+    I: Future<Output = Result<InfoResponse, BoxError>>,
+    S: Future<Output = Result<SnapshotResponse, BoxError>>,
+{
+    type Output = Result<Response, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let response = match self.project() {
+            ResponseFutureProj::Consensus { future } => ready!(future.poll(cx)).map(Response::from),
+            ResponseFutureProj::Mempool {
+                future,
+                kind,
+                policy,
+                check_tx_error,
+            } => {
+                let response = ready!(future.poll(cx))
+                    .map(Response::from)
+                    .or_else(|e| recover_check_tx(check_tx_error.as_ref(), *policy, e));
+                if let Ok(Response::CheckTx(check_tx)) = &response {
+                    metrics::check_tx_response(*kind, check_tx);
+                }
+                response
+            }
+            ResponseFutureProj::Info {
+                future,
+                policy,
+                method,
+            } => ready!(future.poll(cx))
+                .map(Response::from)
+                .or_else(|e| recover(*policy, method, e)),
+            ResponseFutureProj::Snapshot {
+                future,
+                policy,
+                method,
+            } => ready!(future.poll(cx))
+                .map(Response::from)
+                .or_else(|e| recover(*policy, method, e)),
+            ResponseFutureProj::Spawned { task } => ready!(task.poll(cx))?,
+            ResponseFutureProj::BatchLead {
+                method,
+                future,
+                rest,
+            } => {
+                let responses = ready!(future.as_mut().poll(cx))?;
+                if responses.len() != rest.len() + 1 {
+                    return Poll::Ready(Err(format!(
+                        "the {} batch service returned {} responses to {} requests",
+                        method,
+                        responses.len(),
+                        rest.len() + 1
+                    )
+                    .into()));
+                }
+                let mut responses = responses.into_iter();
+                let first = responses.next().expect("checked length");
+                for (sender, response) in rest.drain(..).zip(responses) {
+                    // The receiver is gone if the connection is closing.
+                    let _ = sender.send(response);
                 }
+                Ok(first)
             }
+            ResponseFutureProj::Batched { method, receiver } => {
+                match ready!(Pin::new(receiver).poll(cx)) {
+                    Ok(response) => Ok(response),
+                    Err(_) => Err(format!("the {} batch failed", method).into()),
+                }
+            }
+        };
+        Poll::Ready(response)
+    }
+}
+
+/// Applies the buffer sizes of `options` that can change while a connection
+/// is open.
+pub(super) fn apply_codec_options<R, W>(
+    requests: &mut DecodeRead<R, pb::Request>,
+    responses: &mut EncodeWrite<W, pb::Response>,
+    options: &ConnectionOptions,
+) {
+    let buffers = options.buffer_sizes;
+    requests.set_max_len(buffers.max_request_len);
+    requests.set_blocking_len(options.blocking_codec_len);
+    requests.set_read_timeout(options.read_timeout);
+    responses.set_capacity(buffers.write_capacity);
+    responses.set_blocking_len(options.blocking_codec_len);
+    responses.set_write_timeout(options.write_timeout);
+}
+
+/// Reads requests up to the first one that isn't a `Flush`, answering the
+/// `Flush` requests before it, which have no responses to wait for.
+async fn read_first_request<R, W>(
+    request_stream: &mut DecodeRead<R, pb::Request>,
+    response_sink: &mut EncodeWrite<W, pb::Response>,
+) -> Result<Option<pb::Request>, BoxError>
+where
+    R: AsyncReadExt + std::marker::Unpin,
+    W: AsyncWriteExt + std::marker::Unpin,
+{
+    while let Some(proto) = request_stream.next().await.transpose()? {
+        if !matches!(proto.value, Some(pb::request::Value::Flush(_))) {
+            return Ok(Some(proto));
+        }
+        response_sink
+            .send(pb::Response::from(Response::Flush))
+            .await?;
+    }
+    Ok(None)
+}
+
+/// The next request of a connection: `first`, if it was read before serving
+/// the connection, and then the requests of the stream.
+async fn next_request<R>(
+    first: &mut Option<pb::Request>,
+    request_stream: &mut DecodeRead<R, pb::Request>,
+) -> Option<Result<pb::Request, BoxError>>
+where
+    R: AsyncReadExt + std::marker::Unpin,
+{
+    match first.take() {
+        Some(proto) => Some(Ok(proto)),
+        None => request_stream.next().await,
+    }
+}
+
+/// Buffers the response to a `pending` request, or returns the error if the
+/// response failed.
+async fn send_response<W>(
+    sink: &mut W,
+    progress: &mut Progress,
+    options: &ConnectionOptions,
+    pending: Pending,
+    response: Result<Response, BoxError>,
+) -> Result<(), BoxError>
+where
+    W: Sink<pb::Response, Error = BoxError> + Unpin,
+{
+    redact::log_response(options.log_level(pending.method), &response);
+    let response = response?;
+    let is_commit = matches!(response, Response::Commit(_));
+    let (code, outcome) = (response.code(), response.outcome());
+    let response = pb::Response::from(response);
+    let size = response.encoded_len();
+    if options.summary_log {
+        summary::record(&pending, code, outcome, size);
+    }
+    metrics::frame_sent(progress.kind, size);
+    // Written when the connection is next flushed.
+    sink.feed(response).await?;
+    if is_commit {
+        let now = Instant::now();
+        if let Some(started) = progress.block_started.take() {
+            metrics::block(now - started);
         }
+        if let Some(started) = progress.commit_started.take() {
+            metrics::commit(now - started);
+        }
+    }
+    Ok(())
+}
+
+/// What a connection has done so far, reported when it closes.
+#[derive(Default)]
+struct Progress {
+    /// The kind of the connection, once detected.
+    kind: Option<Category>,
+    /// The method of the last request read.
+    method: Option<&'static str>,
+    /// The last block height seen in a request.
+    height: Option<block::Height>,
+    /// When the block being executed started, if its `Commit` response has
+    /// not been sent yet.
+    block_started: Option<Instant>,
+    /// When the pending `Commit` request was read.
+    commit_started: Option<Instant>,
+}
+
+/// Applies the error `policy` to an `error` returned by a non-consensus service
+/// while handling a request for `method`.
+fn recover(policy: ErrorPolicy, method: &str, error: BoxError) -> Result<Response, BoxError> {
+    let response = match policy {
+        ErrorPolicy::Disconnect => return Err(error),
+        ErrorPolicy::Exception => None,
+        ErrorPolicy::ErrorResponse => error_response(method, &error),
+    };
+    tracing::warn!(%error, method, "responding to service error");
+    Ok(response.unwrap_or_else(|| Response::Exception(response::Exception::from_error(&*error))))
+}
+
+/// Like [`recover`], but for errors from the mempool service, which are reported
+/// as rejected transactions if `check_tx_error` is set.
+fn recover_check_tx(
+    check_tx_error: Option<&CheckTxError>,
+    policy: ErrorPolicy,
+    error: BoxError,
+) -> Result<Response, BoxError> {
+    match check_tx_error {
+        Some(check_tx_error) => {
+            tracing::debug!(%error, "rejecting tx after mempool service error");
+            Ok(Response::CheckTx(check_tx_error.response(&error)))
+        }
+        None => recover(policy, "CheckTx", error),
+    }
+}
+
+/// A method-appropriate error response to a request for `method`, if there is one.
+fn error_response(method: &str, error: &BoxError) -> Option<Response> {
+    match method {
+        "CheckTx" => Some(Response::CheckTx(response::CheckTx::from_error(
+            ERROR_RESPONSE_CODE,
+            &**error,
+        ))),
+        "Query" => Some(Response::Query(response::Query::from_error(
+            ERROR_RESPONSE_CODE,
+            &**error,
+        ))),
+        "ListSnapshots" => Some(Response::ListSnapshots(Default::default())),
+        "OfferSnapshot" => Some(Response::OfferSnapshot(response::OfferSnapshot::Reject)),
+        "LoadSnapshotChunk" => Some(Response::LoadSnapshotChunk(Default::default())),
+        "ApplySnapshotChunk" => Some(Response::ApplySnapshotChunk(response::ApplySnapshotChunk {
+            result: response::ApplySnapshotChunkResult::RejectSnapshot,
+            ..Default::default()
+        })),
+        _ => None,
     }
 }
@@ -0,0 +1,410 @@
+//! An ABCI conformance suite for applications.
+//!
+//! [`run`] drives an application through the in-memory
+//! [`testing`] harness, over separate consensus, mempool, info
+//! and snapshot connections as a node would, and checks that it behaves as the
+//! ABCI specification requires in the cases applications most often get
+//! wrong:
+//!
+//! - `blocks`: executing the scenario's blocks returns a result per
+//!   transaction, and `Info` reports each committed height and app hash;
+//! - `empty_blocks`: blocks without transactions can be executed and
+//!   committed;
+//! - `determinism`: executing the same blocks on a fresh application yields
+//!   the same app hashes;
+//! - `recheck_after_commit`: the application answers `CheckTx` for new
+//!   transactions and rechecks after each commit, without affecting the app
+//!   hashes;
+//! - `handshake`: a fresh application reports height zero, so the node calls
+//!   `InitChain`, and after a restart the node finds the application up to
+//!   date, as computed by [`handshake::replay`];
+//! - `snapshot_offers`: unknown snapshot formats are not accepted, and, if
+//!   the application lists snapshots, the latest one can be loaded and
+//!   restored on a fresh application.
+//!
+//! The suite is meant to be run from the application's own tests:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn abci_conformance() {
+//!     let suite = Conformance::new(tower_abci::testing::genesis("test-chain"))
+//!         .block(vec![tx1, tx2])
+//!         .block(vec![tx3]);
+//!     conformance::run(|| new_server(), &suite).await.assert_ok();
+//! }
+//! ```
+
+use bytes::Bytes;
+use tendermint::{
+    abci::{response::ApplySnapshotChunkResult, types::Snapshot},
+    v0_37::abci::{
+        request, response, ConsensusRequest, ConsensusResponse, InfoRequest, InfoResponse,
+        MempoolRequest, MempoolResponse, Request, Response, SnapshotRequest, SnapshotResponse,
+    },
+    AppHash,
+};
+use tower::Service;
+
+use super::{
+    server::ABCI_VERSION,
+    testing::{self, BlockDriver, Driver},
+    Server,
+};
+use crate::{
+    handshake::{self, NodeState},
+    testing::{
+        conformance::{ensure, Conformance, Report},
+        expect_response,
+    },
+    BoxError,
+};
+
+/// The number of empty blocks executed by the `empty_blocks` check.
+const EMPTY_BLOCKS: usize = 3;
+
+/// Runs the conformance suite on the application served by the servers
+/// returned by `new_server`, with the given scenario.
+///
+/// Each check uses a new server, whose services must hold a new application
+/// with no state.
+pub async fn run<F, C, M, I, S>(mut new_server: F, suite: &Conformance) -> Report
+where
+    F: FnMut() -> Server<C, M, I, S>,
+    C: Service<ConsensusRequest, Response = ConsensusResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    C::Future: Send + 'static,
+    M: Service<MempoolRequest, Response = MempoolResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    M::Future: Send + 'static,
+    I: Service<InfoRequest, Response = InfoResponse, Error = BoxError> + Send + Clone + 'static,
+    I::Future: Send + 'static,
+    S: Service<SnapshotRequest, Response = SnapshotResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let mut node = || Node::connect(&new_server());
+    let mut report = Report::default();
+
+    let mut reference = node();
+    let app_hashes = match execute(&mut reference, suite).await {
+        Ok(app_hashes) => {
+            report.record("blocks", Ok(()));
+            app_hashes
+        }
+        Err(e) => {
+            // The other checks rely on the scenario's blocks.
+            report.record("blocks", Err(e));
+            return report;
+        }
+    };
+
+    let empty = Conformance::new(suite.genesis.clone());
+    let empty = (0..EMPTY_BLOCKS).fold(empty, |empty, _| empty.block(vec![]));
+    report.record("empty_blocks", execute(&mut node(), &empty).await.map(drop));
+    report.record(
+        "determinism",
+        determinism(&mut node(), suite, &app_hashes).await,
+    );
+    report.record(
+        "recheck_after_commit",
+        recheck_after_commit(&mut node(), suite, &app_hashes).await,
+    );
+    report.record(
+        "handshake",
+        handshake(&mut reference, &mut node(), &app_hashes).await,
+    );
+    report.record(
+        "snapshot_offers",
+        snapshot_offers(&mut reference, &mut node(), suite, &app_hashes).await,
+    );
+    report
+}
+
+/// The connections a node opens to an application.
+struct Node {
+    consensus: BlockDriver,
+    mempool: Driver,
+    info: Driver,
+    snapshot: Driver,
+}
+
+impl Node {
+    fn connect<C, M, I, S>(server: &Server<C, M, I, S>) -> Self
+    where
+        C: Service<ConsensusRequest, Response = ConsensusResponse, Error = BoxError>
+            + Send
+            + Clone
+            + 'static,
+        C::Future: Send + 'static,
+        M: Service<MempoolRequest, Response = MempoolResponse, Error = BoxError>
+            + Send
+            + Clone
+            + 'static,
+        M::Future: Send + 'static,
+        I: Service<InfoRequest, Response = InfoResponse, Error = BoxError> + Send + Clone + 'static,
+        I::Future: Send + 'static,
+        S: Service<SnapshotRequest, Response = SnapshotResponse, Error = BoxError>
+            + Send
+            + Clone
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        Self {
+            consensus: BlockDriver::new(testing::connect(server)),
+            mempool: testing::connect(server),
+            info: testing::connect(server),
+            snapshot: testing::connect(server),
+        }
+    }
+
+    async fn info(&mut self) -> Result<response::Info, BoxError> {
+        let response = self
+            .info
+            .call(Request::Info(request::Info {
+                version: String::new(),
+                block_version: 11,
+                p2p_version: 8,
+                abci_version: ABCI_VERSION.to_string(),
+            }))
+            .await?;
+        Ok(expect_response!(response, Info))
+    }
+
+    async fn check_tx(
+        &mut self,
+        tx: &Bytes,
+        kind: request::CheckTxKind,
+    ) -> Result<response::CheckTx, BoxError> {
+        let response = self
+            .mempool
+            .call(Request::CheckTx(request::CheckTx {
+                tx: tx.clone(),
+                kind,
+            }))
+            .await?;
+        Ok(expect_response!(response, CheckTx))
+    }
+}
+
+/// Initializes the chain and executes and commits the scenario's blocks,
+/// returning the app hash after each block.
+async fn execute(node: &mut Node, suite: &Conformance) -> Result<Vec<AppHash>, BoxError> {
+    node.consensus.init_chain(suite.genesis.clone()).await?;
+    let mut app_hashes = Vec::with_capacity(suite.blocks.len());
+    for txs in &suite.blocks {
+        // The driver fails unless there is a `DeliverTx` response per
+        // transaction.
+        let block = node.consensus.produce_block(txs.clone()).await?;
+        node.consensus.commit().await?;
+        let app_hash = node.consensus.app_hash().cloned().unwrap_or_default();
+
+        let info = node.info().await?;
+        ensure(info.last_block_height == block.height, || {
+            format!(
+                "Info reported height {} after committing height {}",
+                info.last_block_height, block.height
+            )
+        })?;
+        ensure(info.last_block_app_hash == app_hash, || {
+            format!(
+                "Info reported app hash {} at height {}, but the block's app hash is {}",
+                info.last_block_app_hash, block.height, app_hash
+            )
+        })?;
+        app_hashes.push(app_hash);
+    }
+    Ok(app_hashes)
+}
+
+/// Compares the app hashes of a second execution of the scenario.
+fn compare(app_hashes: &[AppHash], expected: &[AppHash]) -> Result<(), BoxError> {
+    for (i, (actual, expected)) in app_hashes.iter().zip(expected).enumerate() {
+        ensure(actual == expected, || {
+            format!(
+                "app hash {} after block {} of the scenario, but {} on the first execution",
+                actual,
+                i + 1,
+                expected
+            )
+        })?;
+    }
+    Ok(())
+}
+
+async fn determinism(
+    node: &mut Node,
+    suite: &Conformance,
+    expected: &[AppHash],
+) -> Result<(), BoxError> {
+    compare(&execute(node, suite).await?, expected)
+}
+
+async fn recheck_after_commit(
+    node: &mut Node,
+    suite: &Conformance,
+    expected: &[AppHash],
+) -> Result<(), BoxError> {
+    node.consensus.init_chain(suite.genesis.clone()).await?;
+    let mut app_hashes = Vec::with_capacity(suite.blocks.len());
+    for (i, txs) in suite.blocks.iter().enumerate() {
+        for tx in txs {
+            node.check_tx(tx, request::CheckTxKind::New).await?;
+        }
+        node.consensus.produce_block(txs.clone()).await?;
+        node.consensus.commit().await?;
+        app_hashes.push(node.consensus.app_hash().cloned().unwrap_or_default());
+        // Like the node, recheck the transactions that remain in the mempool,
+        // here those of the next block.
+        for tx in suite.blocks.get(i + 1).into_iter().flatten() {
+            node.check_tx(tx, request::CheckTxKind::Recheck).await?;
+        }
+    }
+    compare(&app_hashes, expected)
+}
+
+async fn handshake(
+    reference: &mut Node,
+    fresh: &mut Node,
+    app_hashes: &[AppHash],
+) -> Result<(), BoxError> {
+    let info = fresh.info().await?;
+    let genesis = NodeState {
+        store_height: 0u32.into(),
+        state_height: 0u32.into(),
+        app_hash: AppHash::default(),
+    };
+    let replay = handshake::replay(&info, &genesis)?;
+    ensure(replay.init_chain, || {
+        format!(
+            "a fresh application reported height {}, so the node would not call InitChain",
+            info.last_block_height
+        )
+    })?;
+
+    let Some(app_hash) = app_hashes.last() else {
+        return Ok(());
+    };
+    let height = reference
+        .consensus
+        .height()
+        .map(|height| height.value() - 1)
+        .unwrap_or_default();
+    let node = NodeState {
+        store_height: height.try_into()?,
+        state_height: height.try_into()?,
+        app_hash: app_hash.clone(),
+    };
+    let info = reference.info().await?;
+    let replay = handshake::replay(&info, &node)?;
+    ensure(replay.is_up_to_date(), || {
+        format!("after a restart the node would replay {:?}", replay)
+    })
+}
+
+async fn snapshot_offers(
+    reference: &mut Node,
+    fresh: &mut Node,
+    suite: &Conformance,
+    app_hashes: &[AppHash],
+) -> Result<(), BoxError> {
+    let unknown = Snapshot {
+        height: 1u32.into(),
+        format: u32::MAX,
+        chunks: 1,
+        hash: Bytes::from_static(&[0; 32]),
+        metadata: Bytes::new(),
+    };
+    let response = fresh
+        .snapshot
+        .call(Request::OfferSnapshot(request::OfferSnapshot {
+            snapshot: unknown,
+            app_hash: AppHash::default(),
+        }))
+        .await?;
+    let offer = expect_response!(response, OfferSnapshot);
+    ensure(offer != response::OfferSnapshot::Accept, || {
+        "a snapshot of an unknown format was accepted".to_string()
+    })?;
+
+    let response = reference.snapshot.call(Request::ListSnapshots).await?;
+    let snapshots = expect_response!(response, ListSnapshots).snapshots;
+    let Some(snapshot) = snapshots.into_iter().max_by_key(|s| s.height) else {
+        return Ok(());
+    };
+    let height = snapshot.height.value() as usize;
+    let initial = suite.genesis.initial_height.value() as usize;
+    let app_hash = height
+        .checked_sub(initial)
+        .and_then(|i| app_hashes.get(i))
+        .ok_or_else(|| format!("listed a snapshot at unknown height {}", snapshot.height))?
+        .clone();
+
+    let mut chunks = Vec::with_capacity(snapshot.chunks as usize);
+    for chunk in 0..snapshot.chunks {
+        let response = reference
+            .snapshot
+            .call(Request::LoadSnapshotChunk(request::LoadSnapshotChunk {
+                height: snapshot.height,
+                format: snapshot.format,
+                chunk,
+            }))
+            .await?;
+        let response = expect_response!(response, LoadSnapshotChunk);
+        ensure(!response.chunk.is_empty(), || {
+            format!(
+                "chunk {} of the snapshot at height {} is empty",
+                chunk, snapshot.height
+            )
+        })?;
+        chunks.push(response.chunk);
+    }
+
+    let response = fresh
+        .snapshot
+        .call(Request::OfferSnapshot(request::OfferSnapshot {
+            snapshot: snapshot.clone(),
+            app_hash: app_hash.clone(),
+        }))
+        .await?;
+    let offer = expect_response!(response, OfferSnapshot);
+    ensure(offer == response::OfferSnapshot::Accept, || {
+        format!(
+            "its own snapshot at height {} was not accepted: {:?}",
+            snapshot.height, offer
+        )
+    })?;
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let response = fresh
+            .snapshot
+            .call(Request::ApplySnapshotChunk(request::ApplySnapshotChunk {
+                index: index as u32,
+                chunk,
+                sender: String::new(),
+            }))
+            .await?;
+        let result = expect_response!(response, ApplySnapshotChunk).result;
+        ensure(result == ApplySnapshotChunkResult::Accept, || {
+            format!(
+                "chunk {} of its own snapshot at height {} was not accepted: {:?}",
+                index, snapshot.height, result
+            )
+        })?;
+    }
+
+    let info = fresh.info().await?;
+    ensure(
+        info.last_block_height == snapshot.height && info.last_block_app_hash == app_hash,
+        || {
+            format!(
+                "after restoring the snapshot at height {}, Info reported height {} and app hash {}",
+                snapshot.height, info.last_block_height, info.last_block_app_hash
+            )
+        },
+    )
+}
@@ -12,7 +12,7 @@
 //! 4. [`InfoRequest`]s sent to the [`Info`] service.
 //!
 //! The ABCI service can execute these requests synchronously, in
-//! [`Service::call`](tower::Service::call), or asynchronously, by immediately
+//! [`Service::call`], or asynchronously, by immediately
 //! returning a future that will be executed on the caller's task. Or, it can
 //! split the difference and perform some amount of synchronous work and defer
 //! the rest to be performed asynchronously.
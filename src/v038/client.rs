@@ -0,0 +1,103 @@
+//! A client speaking the ABCI socket protocol over any byte stream.
+//!
+//! [`Client`] plays the part of the node over the two halves of a connection
+//! opened by the caller: it encodes requests with the same length-delimited
+//! protobuf framing as the node, and decodes the responses into the domain
+//! types. It does no I/O of its own, so it compiles without the `net`
+//! feature, e.g. to `wasm32-unknown-unknown` for explorers and debuggers
+//! that bridge a WebSocket or a browser stream to an application:
+//!
+//! ```
+//! # use tendermint::v0_38::abci::{request, Request};
+//! # use tower_abci::{v038::client::Client, BoxError};
+//! # async fn example(stream: tokio::io::DuplexStream, info: request::Info) -> Result<(), BoxError> {
+//! let (read, write) = tokio::io::split(stream);
+//! let mut client = Client::new(read, write);
+//! let response = client.call(Request::Info(info)).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Like the node, the client may pipeline several requests with
+//! [`send`](Client::send) before collecting their responses with
+//! [`flush`](Client::flush).
+
+use futures::{SinkExt, StreamExt};
+use tendermint_proto::v0_38::abci as pb;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use super::codec::{Decode, Encode};
+use crate::BoxError;
+use tendermint::v0_38::abci::{Request, Response};
+
+/// The node's end of a connection to an ABCI application.
+pub struct Client<R, W> {
+    requests: FramedWrite<W, Encode<pb::Request>>,
+    responses: FramedRead<R, Decode<pb::Response>>,
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Speaks to the application over the `read` and `write` halves of a
+    /// connection.
+    pub fn new(read: R, write: W) -> Self {
+        Self {
+            requests: FramedWrite::new(write, Encode::default()),
+            responses: FramedRead::new(read, Decode::default()),
+        }
+    }
+
+    /// Sends a request without waiting for its response.
+    ///
+    /// As with a real node, the application may not write the response until
+    /// it receives a `Flush`, so responses should be collected with
+    /// [`flush`](Self::flush).
+    pub async fn send(&mut self, request: Request) -> Result<(), BoxError> {
+        self.requests.send(pb::Request::from(request)).await
+    }
+
+    /// Receives the response to the oldest request sent.
+    ///
+    /// Fails if the application closed the connection.
+    pub async fn recv(&mut self) -> Result<Response, BoxError> {
+        let response = self
+            .responses
+            .next()
+            .await
+            .ok_or("connection closed by the application")??;
+        Ok(Response::try_from(response)?)
+    }
+
+    /// Sends a `Flush` and returns the responses to every request sent since
+    /// the last flush, in order, excluding the `Flush` response itself.
+    pub async fn flush(&mut self) -> Result<Vec<Response>, BoxError> {
+        self.send(Request::Flush).await?;
+        let mut responses = Vec::new();
+        loop {
+            match self.recv().await? {
+                Response::Flush => return Ok(responses),
+                response => responses.push(response),
+            }
+        }
+    }
+
+    /// Sends a request followed by a `Flush`, and returns its response. No
+    /// other requests may be pending.
+    pub async fn call(&mut self, request: Request) -> Result<Response, BoxError> {
+        self.send(request).await?;
+        let mut responses = self.flush().await?;
+        match (responses.pop(), responses.is_empty()) {
+            (Some(response), true) => Ok(response),
+            _ => Err("expected a single response before the flush".into()),
+        }
+    }
+
+    /// Returns the halves of the connection.
+    pub fn into_inner(self) -> (R, W) {
+        (self.responses.into_inner(), self.requests.into_inner())
+    }
+}
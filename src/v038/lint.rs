@@ -0,0 +1,142 @@
+use futures::{sink::SinkExt, stream::StreamExt};
+use tendermint::v0_38::abci::{response, Request, Response};
+use tendermint_proto::v0_38::abci as pb;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tower::ServiceExt;
+use tracing::Instrument;
+
+#[cfg(not(feature = "tracing"))]
+use crate::no_tracing as tracing;
+use crate::v038::codec::{DecodeRead, EncodeWrite};
+use crate::{
+    apps::NoopApp,
+    lint::{ConnectionLint, Linter},
+    metrics,
+    pipeline::Category,
+    request_id, task, BoxError, BufferSizes, RequestExt,
+};
+
+/// A server that answers every request with a minimal valid response, and
+/// checks the requests of its clients against the ABCI 0.38 specification.
+/// See [`lint`](crate::lint) for details.
+#[derive(Clone, Debug)]
+pub struct LintServer {
+    linter: Linter,
+}
+
+impl LintServer {
+    /// Reports the violations of every connection to `linter`.
+    pub fn new(linter: Linter) -> Self {
+        Self { linter }
+    }
+
+    /// Serves a connection opened by the caller over the given halves of a
+    /// stream, on a new task, as if it had been accepted by a listener.
+    pub fn accept(
+        &self,
+        read: impl AsyncReadExt + std::marker::Unpin + Send + 'static,
+        write: impl AsyncWriteExt + std::marker::Unpin + Send + 'static,
+    ) {
+        let id = request_id::next_connection_id();
+        let lint = self.linter.connection(id);
+        let span = tracing::info_span!("abci_lint_connection", id);
+        task::spawn(
+            &format!("abci-lint-connection-{}", id),
+            async move {
+                if let Err(e) = run(lint, read, write).await {
+                    tracing::error!(error = %e, "connection failed");
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    pub async fn listen_unix(self, path: impl AsRef<std::path::Path>) -> Result<(), BoxError> {
+        let listener = tokio::net::UnixListener::bind(path)?;
+        let addr = listener.local_addr()?;
+        tracing::info!(?addr, "ABCI lint server starting on uds");
+
+        loop {
+            match listener.accept().await {
+                Ok((socket, addr)) => {
+                    tracing::debug!(?addr, "accepted new connection");
+                    let (read, write) = socket.into_split();
+                    self.accept(read, write);
+                }
+                Err(e) => {
+                    tracing::error!({ %e }, "error accepting new connection");
+                    metrics::accept_error();
+                }
+            }
+        }
+    }
+
+    pub async fn listen_tcp<A: ToSocketAddrs + std::fmt::Debug>(
+        self,
+        addr: A,
+    ) -> Result<(), BoxError> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        tracing::info!(?addr, "ABCI lint server starting on tcp socket");
+
+        loop {
+            match listener.accept().await {
+                Ok((socket, addr)) => {
+                    tracing::debug!(?addr, "accepted new connection");
+                    let (read, write) = socket.into_split();
+                    self.accept(read, write);
+                }
+                Err(e) => {
+                    tracing::error!({ %e }, "error accepting new connection");
+                    metrics::accept_error();
+                }
+            }
+        }
+    }
+}
+
+/// Answers the requests of a connection until it closes, holding the
+/// responses until the client flushes them.
+async fn run(
+    mut lint: ConnectionLint,
+    read: impl AsyncReadExt + std::marker::Unpin,
+    write: impl AsyncWriteExt + std::marker::Unpin,
+) -> Result<(), BoxError> {
+    let mut requests =
+        DecodeRead::<_, pb::Request>::with_capacity(read, BufferSizes::default().read_capacity);
+    let mut responses = EncodeWrite::<_, pb::Response>::new(write);
+    let result = loop {
+        let proto = match requests.next().await {
+            Some(Ok(proto)) => proto,
+            Some(Err(e)) => {
+                lint.framing(&e);
+                break Err(e);
+            }
+            None => break Ok(()),
+        };
+        let response = match Request::try_from(proto) {
+            Ok(Request::Flush) => {
+                lint.flush();
+                responses.send(Response::Flush.into()).await?;
+                continue;
+            }
+            Ok(request) => {
+                let category = Category::of(&request.kind()).expect("not a Flush");
+                lint.request(request.method(), category, request.height());
+                NoopApp.oneshot(request).await?
+            }
+            Err(e) => {
+                lint.malformed(&e);
+                Response::Exception(response::Exception {
+                    error: format!("malformed request: {e}"),
+                })
+            }
+        };
+        // Written when the client flushes.
+        responses.feed(response.into()).await?;
+    };
+    lint.closed();
+    result
+}
@@ -0,0 +1,699 @@
+//! An in-memory harness for testing applications through the real server.
+//!
+//! [`connect`] opens a connection to a [`Server`] over an in-memory transport
+//! and returns a [`Driver`] that plays the part of the node: it encodes
+//! requests with the same codec as the node, and the server decodes and
+//! dispatches them to the component services exactly as it would for a socket
+//! connection. This lets applications unit-test their full service stack,
+//! including their Tower layers, without a running node:
+//!
+//! ```
+//! # use tendermint::v0_38::abci::{request, Request};
+//! # use tower_abci::{apps::NoopApp, v038::{testing, Server}, BoxError};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), BoxError> {
+//! # let (consensus, mempool, info, snapshot) = (NoopApp, NoopApp, NoopApp, NoopApp);
+//! let server = Server::builder()
+//!     .consensus(consensus)
+//!     .mempool(mempool)
+//!     .info(info)
+//!     .snapshot(snapshot)
+//!     .finish()
+//!     .unwrap();
+//! let mut driver = testing::connect(&server);
+//! let response = driver.call(Request::Echo(request::Echo { message: "hi".into() })).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Like the node, the driver may pipeline several requests with
+//! [`send`](Driver::send) before collecting their responses with
+//! [`flush`](Driver::flush). A [`BlockDriver`] builds on a driver to run a
+//! consensus connection through `InitChain` and a sequence of blocks.
+//!
+//! A driver can also record the session it drives with
+//! [`start_recording`](Driver::start_recording), to be saved and later
+//! [replayed](Driver::replay) against a new build of the application, which
+//! reports every response that changed:
+//!
+//! ```ignore
+//! let differences = driver.replay(&Session::load("tests/sessions/sync.abci")?).await?;
+//! assert!(differences.is_empty(), "{}", differences[0]);
+//! ```
+//!
+//! [`check_sequences`] runs randomly generated block sequences from a
+//! [`Generator`] through the harness, to find nondeterminism in the
+//! application.
+//!
+//! [`Differential`] and [`diff_sequences`] drive two implementations of an
+//! application, a reference and a candidate, with the same requests, and
+//! report every response and app hash of the candidate that differs from the
+//! reference's, e.g. when reimplementing or refactoring consensus-critical
+//! logic:
+//!
+//! ```ignore
+//! diff_sequences(|| connect(&old_server()), || connect(&new_server()), &generator, 0..100)
+//!     .await?;
+//! ```
+//!
+//! Where no real application is at hand, e.g. to test middleware, the
+//! component services can be [`Mock`]s, such as [`MockConsensus`].
+
+use std::{collections::VecDeque, path::Path};
+
+use prost::Message;
+use tendermint_proto::v0_38::abci as pb;
+use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+use tower::Service;
+
+use super::{client::Client, Server};
+use crate::{
+    testing::{
+        diff, expect_response, extended,
+        generate::{self, Generator, Sequence},
+        golden, Chain, Difference, Mock,
+    },
+    BoxError, RequestExt,
+};
+use bytes::Bytes;
+use tendermint::{
+    abci::types::{CommitInfo, Misbehavior},
+    block,
+    v0_38::abci::{
+        request, response, ConsensusRequest, ConsensusResponse, InfoRequest, InfoResponse,
+        MempoolRequest, MempoolResponse, Request, Response, SnapshotRequest, SnapshotResponse,
+    },
+    AppHash,
+};
+
+/// A mock consensus service.
+pub type MockConsensus = Mock<ConsensusRequest, ConsensusResponse>;
+/// A mock mempool service.
+pub type MockMempool = Mock<MempoolRequest, MempoolResponse>;
+/// A mock info service.
+pub type MockInfo = Mock<InfoRequest, InfoResponse>;
+/// A mock snapshot service.
+pub type MockSnapshot = Mock<SnapshotRequest, SnapshotResponse>;
+
+/// The capacity of each direction of the in-memory transport.
+///
+/// The server stops reading requests while it is blocked writing a response,
+/// so a driver that sends more than this many bytes of requests without
+/// reading any responses can deadlock.
+pub const TRANSPORT_CAPACITY: usize = 1 << 20;
+
+/// Opens a connection to `server` over an in-memory transport, served by a new
+/// task as if it had been accepted from a socket.
+pub fn connect<C, M, I, S>(server: &Server<C, M, I, S>) -> Driver
+where
+    C: Service<ConsensusRequest, Response = ConsensusResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    C::Future: Send + 'static,
+    M: Service<MempoolRequest, Response = MempoolResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    M::Future: Send + 'static,
+    I: Service<InfoRequest, Response = InfoResponse, Error = BoxError> + Send + Clone + 'static,
+    I::Future: Send + 'static,
+    S: Service<SnapshotRequest, Response = SnapshotResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let (node, app) = tokio::io::duplex(TRANSPORT_CAPACITY);
+    let (read, write) = tokio::io::split(app);
+    server.spawn_connection(None, read, write);
+    let (read, write) = tokio::io::split(node);
+    Driver {
+        client: Client::new(read, write),
+        pending: VecDeque::new(),
+        session: None,
+    }
+}
+
+/// The node's end of an in-memory connection to a server.
+pub struct Driver {
+    client: Client<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>,
+    /// The requests sent whose responses have not been received, oldest
+    /// first.
+    pending: VecDeque<Request>,
+    /// The session recorded so far, while recording.
+    session: Option<Session>,
+}
+
+impl Driver {
+    /// Sends a request without waiting for its response.
+    ///
+    /// As with a real node, the server may not write the response until it
+    /// receives a `Flush`, so responses should be collected with
+    /// [`flush`](Self::flush).
+    pub async fn send(&mut self, request: Request) -> Result<(), BoxError> {
+        self.client.send(request.clone()).await?;
+        self.pending.push_back(request);
+        Ok(())
+    }
+
+    /// Receives the response to the oldest request sent.
+    ///
+    /// Fails if the server closed the connection, e.g., because a service
+    /// returned an error.
+    pub async fn recv(&mut self) -> Result<Response, BoxError> {
+        let response = self.client.recv().await?;
+        let request = self.pending.pop_front();
+        if let (Some(session), Some(request)) = (self.session.as_mut(), request) {
+            session.exchanges.push(Exchange {
+                request,
+                response: response.clone(),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Sends a `Flush` and returns the responses to every request sent since
+    /// the last flush, in order, excluding the `Flush` response itself.
+    pub async fn flush(&mut self) -> Result<Vec<Response>, BoxError> {
+        self.send(Request::Flush).await?;
+        let mut responses = Vec::with_capacity(self.pending.len());
+        loop {
+            match self.recv().await? {
+                Response::Flush => return Ok(responses),
+                response => responses.push(response),
+            }
+        }
+    }
+
+    /// Sends a request followed by a `Flush`, and returns its response. No
+    /// other requests may be pending.
+    pub async fn call(&mut self, request: Request) -> Result<Response, BoxError> {
+        self.send(request).await?;
+        let mut responses = self.flush().await?;
+        match (responses.pop(), responses.is_empty()) {
+            (Some(response), true) => Ok(response),
+            _ => Err("expected a single response before the flush".into()),
+        }
+    }
+
+    /// Starts recording every request sent and the response received to it,
+    /// discarding any session recorded before.
+    pub fn start_recording(&mut self) {
+        self.session = Some(Session::default());
+    }
+
+    /// Stops recording, and returns the session recorded since
+    /// [`start_recording`](Self::start_recording).
+    ///
+    /// Requests whose responses have not been received yet are not part of
+    /// the session.
+    pub fn stop_recording(&mut self) -> Session {
+        self.session.take().unwrap_or_default()
+    }
+
+    /// Sends the requests of a recorded session, in the same order and with
+    /// the same flushes, and returns the responses that differ from the
+    /// recorded ones.
+    ///
+    /// The application should be in the state it was in when the session was
+    /// recorded, usually a fresh one. Fails if the connection fails, e.g.,
+    /// because a service returned an error.
+    pub async fn replay(
+        &mut self,
+        session: &Session,
+    ) -> Result<Vec<Difference<Response>>, BoxError> {
+        let mut differences = Vec::new();
+        let mut received = 0;
+        let mut compare = |index: usize, actual: Response| {
+            let expected = &session.exchanges[index];
+            if actual != expected.response {
+                differences.push(Difference {
+                    index,
+                    method: expected.request.method(),
+                    expected: expected.response.clone(),
+                    actual,
+                });
+            }
+        };
+        for (sent, exchange) in session.exchanges.iter().enumerate() {
+            self.send(exchange.request.clone()).await?;
+            if let Request::Flush = exchange.request {
+                while received <= sent {
+                    compare(received, self.recv().await?);
+                    received += 1;
+                }
+            }
+        }
+        if received < session.exchanges.len() {
+            // The session ends with requests that were not flushed, but their
+            // responses were recorded, so flush them without comparing the
+            // `Flush` response.
+            for response in self.flush().await? {
+                compare(received, response);
+                received += 1;
+            }
+        }
+        Ok(differences)
+    }
+}
+
+/// A recorded sequence of requests sent over a connection, and of the
+/// responses received to them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    /// The requests and their responses, in the order the requests were sent.
+    pub exchanges: Vec<Exchange>,
+}
+
+/// A request and the response received to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exchange {
+    pub request: Request,
+    pub response: Response,
+}
+
+impl Session {
+    /// Encodes the session as each request followed by its response, in
+    /// order, as protobuf messages length-prefixed with an unsigned varint,
+    /// like on the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for exchange in &self.exchanges {
+            pb::Request::from(exchange.request.clone())
+                .encode_length_delimited(&mut buf)
+                .expect("a Vec grows as needed");
+            pb::Response::from(exchange.response.clone())
+                .encode_length_delimited(&mut buf)
+                .expect("a Vec grows as needed");
+        }
+        buf
+    }
+
+    /// Decodes a session encoded with [`encode`](Self::encode).
+    pub fn decode(mut buf: &[u8]) -> Result<Self, BoxError> {
+        let mut exchanges = Vec::new();
+        while !buf.is_empty() {
+            let request = pb::Request::decode_length_delimited(&mut buf)?;
+            let response = pb::Response::decode_length_delimited(&mut buf)?;
+            exchanges.push(Exchange {
+                request: request.try_into()?,
+                response: response.try_into()?,
+            });
+        }
+        Ok(Self { exchanges })
+    }
+
+    /// Writes the encoded session to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.encode())
+    }
+
+    /// Reads a session saved with [`save`](Self::save).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        Self::decode(&std::fs::read(path)?)
+    }
+}
+
+/// The responses of the application to the requests that executed a block.
+#[derive(Clone, Debug)]
+pub struct Block {
+    /// The height of the block.
+    pub height: block::Height,
+    /// The transactions of the block, as proposed by the application.
+    pub txs: Vec<Bytes>,
+    pub prepare_proposal: response::PrepareProposal,
+    pub process_proposal: response::ProcessProposal,
+    pub finalize_block: response::FinalizeBlock,
+}
+
+/// Drives an application through the lifecycle of a chain, issuing the
+/// consensus requests a node would for each block, so that tests read like
+/// block scenarios:
+///
+/// ```ignore
+/// let mut chain = BlockDriver::new(testing::connect(&server));
+/// chain.init_chain(crate::testing::genesis("test-chain")).await?;
+/// let block = chain.produce_block(vec![tx]).await?;
+/// assert!(block.finalize_block.tx_results[0].code.is_ok());
+/// chain.commit().await?;
+/// ```
+///
+/// The driver acts as the proposer of every block, with an empty validator set
+/// and last commit. Blocks are [`BLOCK_INTERVAL`](crate::testing::BLOCK_INTERVAL)
+/// apart, starting at the genesis time.
+pub struct BlockDriver {
+    driver: Driver,
+    chain: Option<Chain>,
+    /// The app hash returned by the last `FinalizeBlock`, until it is
+    /// committed.
+    finalized: Option<AppHash>,
+}
+
+impl BlockDriver {
+    /// Drives the application over the given connection, which should not be
+    /// used for anything else.
+    pub fn new(driver: Driver) -> Self {
+        Self {
+            driver,
+            chain: None,
+            finalized: None,
+        }
+    }
+
+    /// The underlying connection, for sending other requests between blocks.
+    pub fn driver(&mut self) -> &mut Driver {
+        &mut self.driver
+    }
+
+    /// The height of the next block, once the chain is initialized.
+    pub fn height(&self) -> Option<block::Height> {
+        self.chain.as_ref().map(Chain::height)
+    }
+
+    /// The app hash of the last committed block, or of the genesis state,
+    /// once the chain is initialized.
+    pub fn app_hash(&self) -> Option<&AppHash> {
+        self.chain.as_ref().map(Chain::app_hash)
+    }
+
+    /// Sends `InitChain`, and starts the chain at the genesis height.
+    pub async fn init_chain(
+        &mut self,
+        genesis: request::InitChain,
+    ) -> Result<response::InitChain, BoxError> {
+        let response = self
+            .driver
+            .call(Request::InitChain(genesis.clone()))
+            .await?;
+        let response = expect_response!(response, InitChain);
+        self.chain = Some(Chain::new(&genesis, response.app_hash.clone())?);
+        self.finalized = None;
+        Ok(response)
+    }
+
+    /// Proposes and executes the next block with `txs`, sending
+    /// `PrepareProposal`, `ProcessProposal` and `FinalizeBlock`.
+    ///
+    /// Fails if the application rejects its own proposal. The block must be
+    /// committed with [`commit`](Self::commit) before the next one.
+    pub async fn produce_block(&mut self, txs: Vec<Bytes>) -> Result<Block, BoxError> {
+        let last_commit = CommitInfo {
+            round: block::Round::default(),
+            votes: vec![],
+        };
+        self.produce_block_with(txs, last_commit, vec![]).await
+    }
+
+    /// Like [`produce_block`](Self::produce_block), but with the given votes
+    /// of the previous height's validators and evidence of misbehavior.
+    pub async fn produce_block_with(
+        &mut self,
+        txs: Vec<Bytes>,
+        last_commit: CommitInfo,
+        misbehavior: Vec<Misbehavior>,
+    ) -> Result<Block, BoxError> {
+        let chain = self.chain.as_ref().ok_or("the chain was not initialized")?;
+        let header = chain.next_header()?;
+        let max_tx_bytes = chain.max_block_bytes();
+        let hash = header.hash();
+
+        let prepare_proposal = self
+            .driver
+            .call(Request::PrepareProposal(request::PrepareProposal {
+                max_tx_bytes,
+                txs,
+                local_last_commit: Some(extended(&last_commit)),
+                misbehavior: misbehavior.clone(),
+                height: header.height,
+                time: header.time,
+                next_validators_hash: header.next_validators_hash,
+                proposer_address: header.proposer_address,
+            }))
+            .await?;
+        let prepare_proposal = expect_response!(prepare_proposal, PrepareProposal);
+        let txs = prepare_proposal.txs.clone();
+
+        let process_proposal = self
+            .driver
+            .call(Request::ProcessProposal(request::ProcessProposal {
+                txs: txs.clone(),
+                proposed_last_commit: Some(last_commit.clone()),
+                misbehavior: misbehavior.clone(),
+                hash,
+                height: header.height,
+                time: header.time,
+                next_validators_hash: header.next_validators_hash,
+                proposer_address: header.proposer_address,
+            }))
+            .await?;
+        let process_proposal = expect_response!(process_proposal, ProcessProposal);
+        if process_proposal != response::ProcessProposal::Accept {
+            return Err(format!(
+                "the application did not accept its proposal at height {}",
+                header.height
+            )
+            .into());
+        }
+
+        let finalize_block = self
+            .driver
+            .call(Request::FinalizeBlock(request::FinalizeBlock {
+                txs: txs.clone(),
+                decided_last_commit: last_commit,
+                misbehavior,
+                hash,
+                height: header.height,
+                time: header.time,
+                next_validators_hash: header.next_validators_hash,
+                proposer_address: header.proposer_address,
+            }))
+            .await?;
+        let finalize_block = expect_response!(finalize_block, FinalizeBlock);
+
+        let height = header.height;
+        self.finalized = Some(finalize_block.app_hash.clone());
+        self.chain.as_mut().unwrap().executed(header);
+        Ok(Block {
+            height,
+            txs,
+            prepare_proposal,
+            process_proposal,
+            finalize_block,
+        })
+    }
+
+    /// Sends `Commit` for the block produced last.
+    pub async fn commit(&mut self) -> Result<response::Commit, BoxError> {
+        self.chain
+            .as_ref()
+            .ok_or("the chain was not initialized")?
+            .uncommitted()?;
+        let response = self.driver.call(Request::Commit).await?;
+        let response = expect_response!(response, Commit);
+        let app_hash = self.finalized.take().unwrap_or_default();
+        self.chain.as_mut().unwrap().commit(app_hash)?;
+        Ok(response)
+    }
+}
+
+/// Initializes the chain and executes and commits every block of a generated
+/// sequence, returning the results of each block and the app hash after
+/// committing it.
+pub async fn run_sequence(
+    driver: Driver,
+    sequence: &Sequence,
+) -> Result<Vec<(Block, AppHash)>, BoxError> {
+    let mut chain = BlockDriver::new(driver);
+    chain.init_chain(sequence.genesis.clone()).await?;
+    let mut results = Vec::with_capacity(sequence.blocks.len());
+    for block in &sequence.blocks {
+        let block = chain
+            .produce_block_with(
+                block.txs.clone(),
+                block.last_commit.clone(),
+                block.misbehavior.clone(),
+            )
+            .await?;
+        chain.commit().await?;
+        results.push((block, chain.app_hash().cloned().unwrap_or_default()));
+    }
+    Ok(results)
+}
+
+/// Runs the sequence generated for each seed twice, each time on a fresh
+/// server from `new_server`, and fails with the seed of the first sequence
+/// whose execution fails or differs between the two runs, shrunk to the
+/// simplest sequence that still does.
+///
+/// The sequence first generated for a seed can be reproduced with
+/// [`Generator::generate`] and [`run_sequence`]:
+///
+/// ```ignore
+/// let generator = Generator::new().tx(valid_tx());
+/// check_sequences(|| new_server(), &generator, 0..100).await.unwrap();
+/// ```
+pub async fn check_sequences<F, C, M, I, S>(
+    mut new_server: F,
+    generator: &Generator,
+    seeds: impl IntoIterator<Item = u64>,
+) -> Result<(), BoxError>
+where
+    F: FnMut() -> Server<C, M, I, S>,
+    C: Service<ConsensusRequest, Response = ConsensusResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    C::Future: Send + 'static,
+    M: Service<MempoolRequest, Response = MempoolResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    M::Future: Send + 'static,
+    I: Service<InfoRequest, Response = InfoResponse, Error = BoxError> + Send + Clone + 'static,
+    I::Future: Send + 'static,
+    S: Service<SnapshotRequest, Response = SnapshotResponse, Error = BoxError>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    generate::check_seeds(generator, seeds, |sequence| {
+        let drivers = [connect(&new_server()), connect(&new_server())];
+        async move {
+            let mut runs = Vec::with_capacity(2);
+            for driver in drivers {
+                let results = run_sequence(driver, &sequence)
+                    .await
+                    .map_err(|e| format!("failed: {}", e))?;
+                runs.push(golden::render(&results));
+            }
+            match diff::lines(&runs[0], &runs[1]) {
+                Some(diff) => Err(format!(
+                    "is nondeterministic (-first +second run):\n{}",
+                    diff
+                )),
+                None => Ok(()),
+            }
+        }
+    })
+    .await
+}
+
+/// Drives two applications, a reference and a candidate, with the same
+/// requests, and records every response of the candidate that differs from
+/// the reference's.
+///
+/// ```ignore
+/// let mut diff = Differential::new(connect(&old_server), connect(&new_server));
+/// diff.run(requests).await?;
+/// assert!(diff.differences().is_empty(), "{}", diff.differences()[0]);
+/// ```
+pub struct Differential {
+    reference: Driver,
+    candidate: Driver,
+    /// The number of requests sent to each application so far.
+    sent: usize,
+    differences: Vec<Difference<Response>>,
+}
+
+impl Differential {
+    pub fn new(reference: Driver, candidate: Driver) -> Self {
+        Self {
+            reference,
+            candidate,
+            sent: 0,
+            differences: Vec::new(),
+        }
+    }
+
+    /// Calls both applications with `request`, as [`Driver::call`] does, and
+    /// returns the reference's response. A response of the candidate that
+    /// differs is recorded, as the `actual` response of a [`Difference`].
+    ///
+    /// Fails if either connection fails, e.g., because a service returned an
+    /// error.
+    pub async fn call(&mut self, request: Request) -> Result<Response, BoxError> {
+        let index = self.sent;
+        let method = request.method();
+        let expected = self
+            .reference
+            .call(request.clone())
+            .await
+            .map_err(|e| format!("request {} failed on the reference: {}", index, e))?;
+        let actual = self
+            .candidate
+            .call(request)
+            .await
+            .map_err(|e| format!("request {} failed on the candidate: {}", index, e))?;
+        self.sent += 1;
+        if actual != expected {
+            self.differences.push(Difference {
+                index,
+                method,
+                expected: expected.clone(),
+                actual,
+            });
+        }
+        Ok(expected)
+    }
+
+    /// Calls both applications with each of `requests`, in order. `Flush`
+    /// requests are skipped, since every call is flushed.
+    pub async fn run(
+        &mut self,
+        requests: impl IntoIterator<Item = Request>,
+    ) -> Result<(), BoxError> {
+        for request in requests {
+            if let Request::Flush = request {
+                continue;
+            }
+            self.call(request).await?;
+        }
+        Ok(())
+    }
+
+    /// The responses of the candidate that differed so far, in order.
+    pub fn differences(&self) -> &[Difference<Response>] {
+        &self.differences
+    }
+}
+
+/// Runs the sequence generated for each seed on a fresh reference
+/// application and a fresh candidate, connected by `reference` and
+/// `candidate`, and fails with the seed and the height of the first block
+/// whose responses, or the app hash after committing it, differ, in the
+/// simplest sequence that still diverges.
+///
+/// A diverging sequence can be reproduced with [`Generator::generate`] and
+/// [`run_sequence`], or with a [`Differential`].
+pub async fn diff_sequences(
+    mut reference: impl FnMut() -> Driver,
+    mut candidate: impl FnMut() -> Driver,
+    generator: &Generator,
+    seeds: impl IntoIterator<Item = u64>,
+) -> Result<(), BoxError> {
+    generate::check_seeds(generator, seeds, |sequence| {
+        let (reference, candidate) = (reference(), candidate());
+        async move {
+            let expected = run_sequence(reference, &sequence)
+                .await
+                .map_err(|e| format!("failed on the reference: {}", e))?;
+            let actual = run_sequence(candidate, &sequence)
+                .await
+                .map_err(|e| format!("failed on the candidate: {}", e))?;
+            for (index, (expected, actual)) in expected.iter().zip(&actual).enumerate() {
+                if let Some(diff) = diff::lines(&golden::render(expected), &golden::render(actual))
+                {
+                    return Err(format!(
+                        "diverges at height {} (-reference +candidate):\n{}",
+                        sequence.genesis.initial_height.value() + index as u64,
+                        diff
+                    ));
+                }
+            }
+            Ok(())
+        }
+    })
+    .await
+}
@@ -0,0 +1,282 @@
+//! Limits on the requests a connection has in flight.
+#![cfg(feature = "testing")]
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tendermint::v0_38::abci::{
+    request, response, ConsensusRequest, ConsensusResponse, MempoolRequest, MempoolResponse,
+    Request, Response,
+};
+use tokio::sync::Semaphore;
+use tower::service_fn;
+use tower_abci::{
+    apps::NoopApp,
+    v038::{testing, Server},
+    BoxError, PipelineDepth,
+};
+
+/// Counts the calls to its services, and holds each response until it is
+/// released.
+#[derive(Clone)]
+struct Gate {
+    calls: Arc<AtomicUsize>,
+    released: Arc<Semaphore>,
+}
+
+impl Gate {
+    fn new() -> Self {
+        Self {
+            calls: Arc::new(AtomicUsize::new(0)),
+            released: Arc::new(Semaphore::new(0)),
+        }
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    fn release(&self, responses: usize) {
+        self.released.add_permits(responses);
+    }
+
+    /// Waits for the next response to be released, and returns the number of
+    /// the call it answers.
+    async fn call(self) -> usize {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+        self.released.acquire().await.unwrap().forget();
+        call
+    }
+
+    /// A mempool service answering each transaction with its data.
+    fn mempool(
+        &self,
+    ) -> impl tower::Service<
+        MempoolRequest,
+        Response = MempoolResponse,
+        Error = BoxError,
+        Future: Send + 'static,
+    > + Clone
+           + Send
+           + 'static {
+        let gate = self.clone();
+        service_fn(move |MempoolRequest::CheckTx(check_tx): MempoolRequest| {
+            let gate = gate.clone();
+            async move {
+                gate.call().await;
+                Ok::<_, BoxError>(MempoolResponse::CheckTx(response::CheckTx {
+                    data: check_tx.tx,
+                    ..Default::default()
+                }))
+            }
+        })
+    }
+
+    /// A consensus service answering each `Commit` with the number of its call
+    /// as the height to retain.
+    fn consensus(
+        &self,
+    ) -> impl tower::Service<
+        ConsensusRequest,
+        Response = ConsensusResponse,
+        Error = BoxError,
+        Future: Send + 'static,
+    > + Clone
+           + Send
+           + 'static {
+        let gate = self.clone();
+        service_fn(move |_: ConsensusRequest| {
+            let gate = gate.clone();
+            async move {
+                let call = gate.call().await;
+                Ok::<_, BoxError>(ConsensusResponse::Commit(response::Commit {
+                    retain_height: (call as u32).into(),
+                    ..Default::default()
+                }))
+            }
+        })
+    }
+}
+
+fn check_tx(i: u8) -> Request {
+    Request::CheckTx(request::CheckTx {
+        tx: Bytes::from(vec![i]),
+        kind: request::CheckTxKind::New,
+    })
+}
+
+/// Lets the server read and dispatch what it will.
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+}
+
+/// Sends five transactions, and checks that only two reach the mempool until
+/// their responses are released, and that the five are then answered in
+/// order.
+async fn holds_the_transactions_past_two(driver: &mut testing::Driver, gate: &Gate) {
+    for i in 0..5 {
+        driver.send(check_tx(i)).await.unwrap();
+    }
+    settle().await;
+    assert_eq!(gate.calls(), 2);
+
+    gate.release(5);
+    let responses = driver.flush().await.unwrap();
+    assert_eq!(gate.calls(), 5);
+    let txs: Vec<_> = responses
+        .into_iter()
+        .map(|response| match response {
+            Response::CheckTx(check_tx) => check_tx.data,
+            response => panic!("expected a CheckTx response, got {response:?}"),
+        })
+        .collect();
+    assert_eq!(
+        txs,
+        (0..5).map(|i| Bytes::from(vec![i])).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn max_in_flight_stops_reading_requests() {
+    let gate = Gate::new();
+    let server = Server::builder()
+        .consensus(NoopApp)
+        .mempool(gate.mempool())
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .max_in_flight(2)
+        .finish()
+        .unwrap();
+    let handle = server.handle();
+    let mut driver = testing::connect(&server);
+
+    driver.send(check_tx(0)).await.unwrap();
+    driver.send(check_tx(1)).await.unwrap();
+    settle().await;
+    // The server stopped reading at the limit, so a request sent now waits
+    // unread along with the rest.
+    assert_eq!(handle.connections()[0].in_flight.len(), 2);
+    for i in 2..5 {
+        driver.send(check_tx(i)).await.unwrap();
+    }
+    settle().await;
+    assert_eq!(gate.calls(), 2);
+    assert_eq!(handle.connections()[0].in_flight.len(), 2);
+
+    gate.release(5);
+    let responses = driver.flush().await.unwrap();
+    assert_eq!(responses.len(), 5);
+    assert_eq!(gate.calls(), 5);
+}
+
+#[tokio::test]
+async fn max_in_flight_keeps_responses_in_order() {
+    let gate = Gate::new();
+    let server = Server::builder()
+        .consensus(NoopApp)
+        .mempool(gate.mempool())
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .max_in_flight(2)
+        .finish()
+        .unwrap();
+    let mut driver = testing::connect(&server);
+    holds_the_transactions_past_two(&mut driver, &gate).await;
+}
+
+#[tokio::test]
+async fn pipeline_depth_limits_the_calls_to_a_service() {
+    let gate = Gate::new();
+    let server = Server::builder()
+        .consensus(NoopApp)
+        .mempool(gate.mempool())
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .pipeline_depth(PipelineDepth::default().mempool(2))
+        .finish()
+        .unwrap();
+    let mut driver = testing::connect(&server);
+    holds_the_transactions_past_two(&mut driver, &gate).await;
+}
+
+/// Sends three `Commit` requests, and returns the number of calls made to the
+/// consensus service before any response is released, checking that the
+/// responses are in the order of the calls.
+async fn pipelined_commits(serial_consensus: bool) -> usize {
+    let gate = Gate::new();
+    let server = Server::builder()
+        .consensus(gate.consensus())
+        .mempool(NoopApp)
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .serial_consensus(serial_consensus)
+        .finish()
+        .unwrap();
+    let mut driver = testing::connect(&server);
+    for _ in 0..3 {
+        driver.send(Request::Commit).await.unwrap();
+    }
+    settle().await;
+    let calls = gate.calls();
+
+    gate.release(3);
+    let responses = driver.flush().await.unwrap();
+    let calls_answered: Vec<_> = responses
+        .into_iter()
+        .map(|response| match response {
+            Response::Commit(commit) => commit.retain_height.value(),
+            response => panic!("expected a Commit response, got {response:?}"),
+        })
+        .collect();
+    assert_eq!(calls_answered, [0, 1, 2]);
+    calls
+}
+
+#[tokio::test]
+async fn consensus_requests_are_pipelined_by_default() {
+    assert_eq!(pipelined_commits(false).await, 3);
+}
+
+#[tokio::test]
+async fn serial_consensus_calls_one_request_at_a_time() {
+    assert_eq!(pipelined_commits(true).await, 1);
+}
+
+#[tokio::test]
+async fn commit_barrier_holds_requests_until_the_commit_is_flushed() {
+    let gate = Gate::new();
+    let server = Server::builder()
+        .consensus(gate.consensus())
+        .mempool(NoopApp)
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .commit_barrier(true)
+        .finish()
+        .unwrap();
+    let mut driver = testing::connect(&server);
+    driver.send(Request::Commit).await.unwrap();
+    driver.send(Request::Commit).await.unwrap();
+    settle().await;
+    assert_eq!(gate.calls(), 1);
+
+    // The first response is flushed by the server itself, without waiting
+    // for a `Flush`, and only then is the second `Commit` read.
+    gate.release(1);
+    let response = driver.recv().await.unwrap();
+    assert!(matches!(response, Response::Commit(_)), "{response:?}");
+    settle().await;
+    assert_eq!(gate.calls(), 2);
+
+    gate.release(1);
+    let responses = driver.flush().await.unwrap();
+    assert!(
+        matches!(responses[..], [Response::Commit(_)]),
+        "{responses:?}"
+    );
+}
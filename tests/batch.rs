@@ -0,0 +1,103 @@
+//! Batched requests on a closing connection, and around the consensus layers.
+#![cfg(feature = "testing")]
+
+use bytes::Bytes;
+use tendermint::{
+    v0_34::abci as abci34,
+    v0_38::abci::{request, response, Request, Response},
+};
+use tower::ServiceBuilder;
+use tower_abci::{
+    apps::NoopApp,
+    middleware::halt::HaltLayer,
+    v034,
+    v038::{testing, Server},
+    BoxError,
+};
+
+#[tokio::test]
+async fn close_answers_held_rechecks() {
+    let batch = tower::service_fn(|txs: Vec<request::CheckTx>| async move {
+        Ok::<_, BoxError>(vec![response::CheckTx::default(); txs.len()])
+    });
+    let server = Server::builder()
+        .consensus(NoopApp)
+        .mempool(NoopApp)
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .recheck_batch(10, batch)
+        .finish()
+        .unwrap();
+    let handle = server.handle();
+    let mut driver = testing::connect(&server);
+
+    // Fewer rechecks than a batch, and no Flush: the server holds them.
+    for i in 0..3u8 {
+        let recheck = request::CheckTx {
+            tx: Bytes::from(vec![i]),
+            kind: request::CheckTxKind::Recheck,
+        };
+        driver.send(Request::CheckTx(recheck)).await.unwrap();
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let id = handle.connections()[0].id;
+    assert!(handle.close_connection(id));
+
+    for _ in 0..3 {
+        let response = driver.recv().await.unwrap();
+        assert!(matches!(response, Response::CheckTx(_)), "{response:?}");
+    }
+    assert!(driver.recv().await.is_err());
+}
+
+/// A v0.34 server delivering transactions in batches to a service accepting
+/// them all, with `consensus` as its consensus service.
+fn batching_server<C>(consensus: C) -> v034::Server<C, NoopApp, NoopApp, NoopApp>
+where
+    C: tower::Service<
+            abci34::ConsensusRequest,
+            Response = abci34::ConsensusResponse,
+            Error = BoxError,
+        > + Send
+        + Clone
+        + 'static,
+    C::Future: Send + 'static,
+{
+    let batch = tower::service_fn(|txs: Vec<abci34::request::DeliverTx>| async move {
+        Ok::<_, BoxError>(vec![abci34::response::DeliverTx::default(); txs.len()])
+    });
+    v034::Server::builder()
+        .consensus(consensus)
+        .mempool(NoopApp)
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .deliver_tx_batch(10, batch)
+        .finish()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn halt_fails_consensus_requests_with_batched_transactions() {
+    let halt = HaltLayer::new(100u32.into());
+    let server = batching_server(ServiceBuilder::new().layer(halt).service(NoopApp));
+    let mut driver = v034::testing::connect(&server);
+
+    // The server disconnects on the consensus error, by default.
+    let response = driver.call(abci34::Request::Commit).await;
+    assert!(response.is_err(), "{response:?}");
+}
+
+#[cfg(feature = "net")]
+#[tokio::test]
+async fn wal_fails_consensus_requests_with_batched_transactions() {
+    use tower_abci::middleware::wal::WalLayer;
+
+    let path = std::env::temp_dir().join(format!("tower-abci-batch-{}.wal", std::process::id()));
+    let wal = WalLayer::open(&path).unwrap();
+    let server = batching_server(ServiceBuilder::new().layer(wal).service(NoopApp));
+    let mut driver = v034::testing::connect(&server);
+
+    let response = driver.call(abci34::Request::Commit).await;
+    assert!(response.is_err(), "{response:?}");
+    let _ = std::fs::remove_file(path);
+}
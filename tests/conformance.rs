@@ -0,0 +1,31 @@
+//! The conformance suite against the reference key-value store.
+#![cfg(all(feature = "testing", feature = "kvstore", feature = "split"))]
+
+use bytes::Bytes;
+use tower_abci::{
+    apps::kvstore::KVStore,
+    testing::{self, conformance::Conformance},
+    v038::{conformance, split, Server},
+};
+
+#[tokio::test]
+async fn kvstore_conforms() {
+    let suite = Conformance::new(testing::genesis("test-chain"))
+        .block(vec![Bytes::from("a=1"), Bytes::from("b")])
+        .block(vec![Bytes::from("a=2")]);
+    let report = conformance::run(
+        || {
+            let (consensus, mempool, snapshot, info) = split::service(KVStore::new(), 1);
+            Server::builder()
+                .consensus(consensus)
+                .mempool(mempool)
+                .info(info)
+                .snapshot(snapshot)
+                .finish()
+                .unwrap()
+        },
+        &suite,
+    )
+    .await;
+    report.assert_ok();
+}
@@ -0,0 +1,153 @@
+//! How the server answers the errors of its services, under each error policy.
+#![cfg(feature = "testing")]
+
+use bytes::Bytes;
+use tendermint::v0_38::abci::{
+    request, response, ConsensusRequest, ConsensusResponse, InfoRequest, InfoResponse,
+    MempoolRequest, MempoolResponse, Request, Response, SnapshotRequest, SnapshotResponse,
+};
+use tower::{service_fn, Service};
+use tower_abci::{
+    v038::{testing, Server},
+    BoxError, CheckTxError, ErrorPolicy,
+};
+
+/// A service whose every call fails.
+fn failing<Req, Rsp>() -> impl Service<Req, Response = Rsp, Error = BoxError, Future: Send> + Clone
+where
+    Req: Send + 'static,
+    Rsp: Send + 'static,
+{
+    service_fn(|_: Req| async { Err::<Rsp, BoxError>("the service failed".into()) })
+}
+
+/// Connects to a server of services that always fail, answering their errors
+/// with `policy`, and with `check_tx_error` if set.
+fn connect(policy: ErrorPolicy, check_tx_error: Option<CheckTxError>) -> testing::Driver {
+    let builder = Server::builder()
+        .consensus(failing::<ConsensusRequest, ConsensusResponse>())
+        .mempool(failing::<MempoolRequest, MempoolResponse>())
+        .info(failing::<InfoRequest, InfoResponse>())
+        .snapshot(failing::<SnapshotRequest, SnapshotResponse>())
+        .error_policy(policy);
+    let builder = match check_tx_error {
+        Some(check_tx_error) => builder.check_tx_error(check_tx_error),
+        None => builder,
+    };
+    testing::connect(&builder.finish().unwrap())
+}
+
+fn check_tx() -> Request {
+    Request::CheckTx(request::CheckTx {
+        tx: Bytes::from_static(b"tx"),
+        kind: request::CheckTxKind::New,
+    })
+}
+
+fn info() -> Request {
+    Request::Info(request::Info {
+        version: String::new(),
+        block_version: 0,
+        p2p_version: 0,
+        abci_version: String::new(),
+    })
+}
+
+fn query() -> Request {
+    Request::Query(request::Query {
+        data: Bytes::new(),
+        path: "/store".into(),
+        height: Default::default(),
+        prove: false,
+    })
+}
+
+#[tokio::test]
+async fn disconnect_closes_the_connection_on_a_check_tx_error() {
+    let mut driver = connect(ErrorPolicy::Disconnect, None);
+    assert!(driver.call(check_tx()).await.is_err());
+}
+
+#[tokio::test]
+async fn exception_answers_a_check_tx_error() {
+    let mut driver = connect(ErrorPolicy::Exception, None);
+    let response = driver.call(check_tx()).await.unwrap();
+    assert_eq!(
+        response,
+        Response::Exception(response::Exception {
+            error: "the service failed".into(),
+        })
+    );
+    // The connection is still served.
+    assert!(matches!(
+        driver.call(check_tx()).await,
+        Ok(Response::Exception(_))
+    ));
+}
+
+#[tokio::test]
+async fn error_response_rejects_the_transaction_of_a_check_tx_error() {
+    let mut driver = connect(ErrorPolicy::ErrorResponse, None);
+    let response = driver.call(check_tx()).await.unwrap();
+    let Response::CheckTx(check_tx) = response else {
+        panic!("expected a CheckTx response, got {response:?}");
+    };
+    assert_eq!(check_tx.code.value(), 1);
+    assert_eq!(check_tx.log, "the service failed");
+}
+
+#[tokio::test]
+async fn check_tx_error_overrides_every_policy() {
+    for policy in [
+        ErrorPolicy::Disconnect,
+        ErrorPolicy::Exception,
+        ErrorPolicy::ErrorResponse,
+    ] {
+        let check_tx_error = CheckTxError::new(7).codespace("app").log("rejected");
+        let mut driver = connect(policy, Some(check_tx_error));
+        let response = driver.call(check_tx()).await.unwrap();
+        let Response::CheckTx(check_tx) = response else {
+            panic!("expected a CheckTx response under {policy:?}, got {response:?}");
+        };
+        assert_eq!(check_tx.code.value(), 7, "{policy:?}");
+        assert_eq!(check_tx.codespace, "app", "{policy:?}");
+        assert_eq!(check_tx.log, "rejected", "{policy:?}");
+    }
+}
+
+#[tokio::test]
+async fn every_policy_closes_the_connection_on_a_consensus_error() {
+    for policy in [
+        ErrorPolicy::Disconnect,
+        ErrorPolicy::Exception,
+        ErrorPolicy::ErrorResponse,
+    ] {
+        let mut driver = connect(policy, None);
+        let response = driver.call(Request::Commit).await;
+        assert!(response.is_err(), "{policy:?}: {response:?}");
+    }
+}
+
+#[tokio::test]
+async fn error_response_answers_each_method_appropriately() {
+    let mut driver = connect(ErrorPolicy::ErrorResponse, None);
+
+    let response = driver.call(query()).await.unwrap();
+    let Response::Query(query) = response else {
+        panic!("expected a Query response, got {response:?}");
+    };
+    assert_eq!(query.code.value(), 1);
+    assert_eq!(query.log, "the service failed");
+
+    let response = driver.call(Request::ListSnapshots).await.unwrap();
+    assert_eq!(response, Response::ListSnapshots(Default::default()));
+
+    // `Info` has no error representation.
+    let response = driver.call(info()).await.unwrap();
+    assert_eq!(
+        response,
+        Response::Exception(response::Exception {
+            error: "the service failed".into(),
+        })
+    );
+}
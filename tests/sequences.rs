@@ -0,0 +1,189 @@
+//! Generated block sequences, and the runners checking applications with them.
+#![cfg(feature = "testing")]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use proptest::{
+    collection,
+    prelude::*,
+    test_runner::{Config, TestRunner},
+};
+use tendermint::{
+    abci::types::BlockSignatureInfo,
+    block::BlockIdFlag,
+    v0_38::abci::{ConsensusRequest, ConsensusResponse},
+    AppHash,
+};
+use tower::{service_fn, Service, ServiceExt};
+use tower_abci::{
+    apps::EchoApp,
+    testing::generate::Generator,
+    v038::{testing, Server},
+    BoxError,
+};
+
+fn server() -> Server<EchoApp, EchoApp, EchoApp, EchoApp> {
+    Server::builder()
+        .consensus(EchoApp)
+        .mempool(EchoApp)
+        .info(EchoApp)
+        .snapshot(EchoApp)
+        .finish()
+        .unwrap()
+}
+
+/// The number of app hashes drawn by [`flaky_consensus`] services, across
+/// servers.
+static FLAKY_HASHES: AtomicU64 = AtomicU64::new(0);
+
+/// A consensus service answering as [`EchoApp`], except that blocks with a
+/// transaction containing a byte of `0x80` or more get a different app hash
+/// every time.
+fn flaky_consensus() -> impl Service<
+    ConsensusRequest,
+    Response = ConsensusResponse,
+    Error = BoxError,
+    Future: Send + 'static,
+> + Clone
+       + Send
+       + 'static {
+    service_fn(|request: ConsensusRequest| async move {
+        let flaky = match &request {
+            ConsensusRequest::FinalizeBlock(block) => {
+                block.txs.iter().any(|tx| tx.iter().any(|&b| b >= 0x80))
+            }
+            _ => false,
+        };
+        let mut response = EchoApp.oneshot(request).await?;
+        if let ConsensusResponse::FinalizeBlock(block) = &mut response {
+            if flaky {
+                let hash = FLAKY_HASHES.fetch_add(1, Ordering::SeqCst);
+                block.app_hash = AppHash::try_from(hash.to_be_bytes().to_vec())?;
+            }
+        }
+        Ok(response)
+    })
+}
+
+#[test]
+fn sequences_are_valid() {
+    let generator = Generator::new()
+        .validators(1..=6)
+        .absent_rate(0.5)
+        .evidence_rate(0.5)
+        .validator_update_rate(0.5);
+    let config = Config {
+        failure_persistence: None,
+        ..Config::default()
+    };
+    TestRunner::new(config)
+        .run(&generator.strategy(), |sequence| {
+            let mut keys: Vec<_> = sequence
+                .genesis
+                .validators
+                .iter()
+                .map(|v| v.pub_key)
+                .collect();
+            prop_assert!(!keys.is_empty());
+            keys.sort();
+            keys.dedup();
+            prop_assert_eq!(keys.len(), sequence.genesis.validators.len());
+
+            let initial_height = sequence.genesis.initial_height.value();
+            for (index, block) in sequence.blocks.iter().enumerate().skip(1) {
+                let votes = &block.last_commit.votes;
+                let total: u64 = votes.iter().map(|v| v.validator.power.value()).sum();
+                let signed: u64 = votes
+                    .iter()
+                    .filter(|v| v.sig_info == BlockSignatureInfo::Flag(BlockIdFlag::Commit))
+                    .map(|v| v.validator.power.value())
+                    .sum();
+                prop_assert!(
+                    signed * 3 > total * 2,
+                    "block {}: {}/{}",
+                    index,
+                    signed,
+                    total
+                );
+                for misbehavior in &block.misbehavior {
+                    prop_assert!(misbehavior.height.value() < initial_height + index as u64);
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn the_same_seed_generates_the_same_sequence() {
+    let generator = Generator::new();
+    assert_eq!(
+        format!("{:?}", generator.generate(7)),
+        format!("{:?}", generator.generate(7))
+    );
+    assert_ne!(
+        format!("{:?}", generator.generate(7)),
+        format!("{:?}", generator.generate(8))
+    );
+}
+
+#[tokio::test]
+async fn check_sequences_passes_a_deterministic_application() {
+    testing::check_sequences(server, &Generator::new(), 0..10)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn check_sequences_shrinks_a_nondeterministic_sequence() {
+    let new_server = || {
+        Server::builder()
+            .consensus(flaky_consensus())
+            .mempool(EchoApp)
+            .info(EchoApp)
+            .snapshot(EchoApp)
+            .finish()
+            .unwrap()
+    };
+    let generator = Generator::new()
+        .blocks(3..=5)
+        .txs_per_block(2..=4)
+        .tx(collection::vec(any::<u8>(), 1..=8).prop_map(Bytes::from));
+    let error = testing::check_sequences(new_server, &generator, 0..1)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("is nondeterministic"), "{error}");
+
+    // The simplest sequence that still fails has as few blocks and
+    // transactions as the generator allows, and a single byte of `0x80`.
+    let sequence = error.split_once("shrunk to ").unwrap().1;
+    assert_eq!(sequence.matches("SequenceBlock {").count(), 3, "{sequence}");
+    assert_eq!(sequence.matches("b\"\\x80\"").count(), 1, "{sequence}");
+}
+
+#[tokio::test]
+async fn diff_sequences_reports_a_diverging_candidate() {
+    let candidate = || {
+        testing::connect(
+            &Server::builder()
+                .consensus(flaky_consensus())
+                .mempool(EchoApp)
+                .info(EchoApp)
+                .snapshot(EchoApp)
+                .finish()
+                .unwrap(),
+        )
+    };
+    let error = testing::diff_sequences(
+        || testing::connect(&server()),
+        candidate,
+        &Generator::new(),
+        0..10,
+    )
+    .await
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("diverges at height 1"), "{error}");
+}
@@ -0,0 +1,62 @@
+//! Connections that stop making progress.
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use tendermint::v0_38::abci::{request, MempoolRequest, MempoolResponse, Request};
+use tower_abci::{
+    apps::NoopApp,
+    v038::{testing, Server},
+    BoxError, StallDetection,
+};
+
+fn check_tx() -> Request {
+    Request::CheckTx(request::CheckTx {
+        tx: Bytes::from_static(b"tx"),
+        kind: request::CheckTxKind::New,
+    })
+}
+
+#[tokio::test]
+async fn stall_detection_closes_a_stalled_connection() {
+    let mempool = tower::service_fn(|_: MempoolRequest| {
+        futures::future::pending::<Result<MempoolResponse, BoxError>>()
+    });
+    let server = Server::builder()
+        .consensus(NoopApp)
+        .mempool(mempool)
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .stall_detection(StallDetection::new(Duration::from_millis(20)).close(true))
+        .finish()
+        .unwrap();
+    let handle = server.handle();
+    let mut driver = testing::connect(&server);
+
+    driver.send(check_tx()).await.unwrap();
+    let closed = tokio::time::timeout(Duration::from_secs(5), driver.flush()).await;
+    assert!(matches!(closed, Ok(Err(_))), "{closed:?}");
+    assert!(handle.connections().is_empty());
+}
+
+#[tokio::test]
+async fn idle_timeout_closes_a_silent_connection() {
+    let server = Server::builder()
+        .consensus(NoopApp)
+        .mempool(NoopApp)
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .idle_timeout(Duration::from_millis(20))
+        .finish()
+        .unwrap();
+    let mut driver = testing::connect(&server);
+
+    // Activity keeps the connection open past the timeout.
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        driver.call(check_tx()).await.unwrap();
+    }
+    let closed = tokio::time::timeout(Duration::from_secs(5), driver.recv()).await;
+    assert!(matches!(closed, Ok(Err(_))), "{closed:?}");
+}
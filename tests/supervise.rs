@@ -0,0 +1,53 @@
+//! A supervised service that keeps failing.
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use tendermint::v0_38::abci::{request, MempoolRequest, MempoolResponse, Request, Response};
+use tower_abci::{
+    apps::NoopApp,
+    middleware::supervise::{RestartPolicy, Supervised},
+    v038::{testing, Server},
+    BoxError, ErrorPolicy,
+};
+
+#[tokio::test]
+async fn fatal_call_errors_past_the_policy_close_the_connection() {
+    let mempool = Supervised::new(|| {
+        Ok(tower::service_fn(|_: MempoolRequest| async {
+            Err::<MempoolResponse, BoxError>("storage is gone".into())
+        }))
+    })
+    .unwrap()
+    .policy(RestartPolicy::new(1, Duration::from_secs(60)))
+    .fatal_if(|_| true);
+    let server = Server::builder()
+        .consensus(NoopApp)
+        .mempool(mempool)
+        .info(NoopApp)
+        .snapshot(NoopApp)
+        .error_policy(ErrorPolicy::ErrorResponse)
+        .finish()
+        .unwrap();
+    let mut driver = testing::connect(&server);
+    let check_tx = || {
+        Request::CheckTx(request::CheckTx {
+            tx: Bytes::from_static(b"tx"),
+            kind: request::CheckTxKind::New,
+        })
+    };
+
+    // The first failure recreates the service, and the second exhausts the
+    // policy: both requests are still answered.
+    for _ in 0..2 {
+        match driver.call(check_tx()).await.unwrap() {
+            Response::CheckTx(response) => assert!(response.code.is_err()),
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+    // The next request finds the service unready for good.
+    driver.send(check_tx()).await.unwrap();
+    driver.send(Request::Flush).await.unwrap();
+    assert!(driver.recv().await.is_err());
+}